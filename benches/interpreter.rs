@@ -0,0 +1,31 @@
+//! Times the scripts next to this file, reporting the best of a few runs.
+//! Run with `cargo bench`.
+
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use lox_rs::interpreter::Interpreter;
+
+const SCRIPTS: &[&str] = &["fib", "loop"];
+const RUNS: usize = 3;
+
+fn time(source: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_output(io::sink());
+            let start = Instant::now();
+            interpreter.run(source).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for name in SCRIPTS {
+        let source = fs::read_to_string(format!("benches/{}.lox", name)).unwrap();
+        println!("{:<8} {:>10.2?}", name, time(&source));
+    }
+}
@@ -0,0 +1,127 @@
+//! Times the scripts next to this file that the bytecode VM can run,
+//! reporting the best of a few runs. Compare the representations of values
+//! with `cargo bench --bench vm` and
+//! `cargo bench --bench vm --features nan-boxing`. Every script also runs
+//! without the inline caches of method lookups and without
+//! superinstructions, for what they save, and the instructions dispatched
+//! with and without superinstructions are counted, along with how many
+//! millions of them the VM runs per second.
+//!
+//! With `--features register-vm`, the scripts the register machine can run
+//! also run there, against the stack machine with superinstructions.
+
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use lox_rs::vm::Vm;
+
+const SCRIPTS: &[&str] = &["fib", "loop", "globals", "strings", "binary_trees", "zoo"];
+const RUNS: usize = 3;
+
+fn vm(inline_caches: bool, superinstructions: bool) -> Vm {
+    let mut vm = Vm::new();
+    vm.set_output(io::sink());
+    vm.set_inline_caches(inline_caches);
+    vm.set_superinstructions(superinstructions);
+    vm
+}
+
+fn time(source: &str, inline_caches: bool, superinstructions: bool) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut vm = vm(inline_caches, superinstructions);
+            let start = Instant::now();
+            vm.run(source).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn dispatches(source: &str, superinstructions: bool) -> u64 {
+    let mut vm = vm(true, superinstructions);
+    vm.run(source).unwrap();
+    vm.instructions()
+}
+
+fn main() {
+    let values = if cfg!(feature = "nan-boxing") {
+        "nan-boxed"
+    } else {
+        "enum"
+    };
+    println!("values: {}", values);
+    let sources = SCRIPTS
+        .iter()
+        .map(|name| fs::read_to_string(format!("benches/{}.lox", name)).unwrap())
+        .collect::<Vec<_>>();
+
+    println!(
+        "{:<12} {:>10} {:>10} {:>10}",
+        "", "optimized", "uncached", "unfused"
+    );
+    for (name, source) in SCRIPTS.iter().zip(&sources) {
+        println!(
+            "{:<12} {:>10.2?} {:>10.2?} {:>10.2?}",
+            name,
+            time(source, true, true),
+            time(source, false, true),
+            time(source, true, false)
+        );
+    }
+
+    println!();
+    println!(
+        "{:<12} {:>10} {:>10} {:>6} {:>8}",
+        "dispatches", "fused", "unfused", "saved", "M/s"
+    );
+    for (name, source) in SCRIPTS.iter().zip(&sources) {
+        let fused = dispatches(source, true);
+        let unfused = dispatches(source, false);
+        let saved = 100.0 * (unfused - fused) as f64 / unfused as f64;
+        let per_second = fused as f64 / time(source, true, true).as_secs_f64() / 1e6;
+        println!(
+            "{:<12} {:>10} {:>10} {:>5.1}% {:>8.1}",
+            name, fused, unfused, saved, per_second
+        );
+    }
+
+    #[cfg(feature = "register-vm")]
+    registers(&sources);
+}
+
+#[cfg(feature = "register-vm")]
+fn registers(sources: &[String]) {
+    use lox_rs::register;
+
+    let run = |source: &str| {
+        let mut vm = register::Vm::new();
+        vm.set_output(io::sink());
+        let start = Instant::now();
+        vm.run(source)
+            .map(|()| (start.elapsed(), vm.instructions()))
+    };
+
+    println!();
+    println!(
+        "{:<12} {:>10} {:>10} {:>10} {:>10}",
+        "registers", "stack", "register", "stack ops", "reg ops"
+    );
+    for (name, source) in SCRIPTS.iter().zip(sources) {
+        // what it can't compile fails before running
+        if run(source).is_err() {
+            println!("{:<12} {:>10}", name, "-");
+            continue;
+        }
+        let best = (0..RUNS).map(|_| run(source).unwrap().0).min().unwrap();
+        println!(
+            "{:<12} {:>10.2?} {:>10.2?} {:>10} {:>10}",
+            name,
+            time(source, true, true),
+            best,
+            dispatches(source, true),
+            run(source).unwrap().1
+        );
+    }
+}
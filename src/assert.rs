@@ -0,0 +1,52 @@
+//! The natives tests are written with: `assert(condition, message)`,
+//! `assertEq(actual, expected)` and `fail(message)`.
+//!
+//! They throw an `AssertionError`, the prelude class, at the line they're
+//! called from, so a test runner can catch it and go on with the next test,
+//! while a failure nothing catches stops the program like any `throw`.
+
+use std::rc::Rc;
+
+use crate::function::NativeFunction;
+use crate::interpreter::Interpreter;
+use crate::value::Value;
+
+/// Register the assertion natives with an interpreter.
+pub fn register(interpreter: &mut Interpreter) {
+    let mut assert = NativeFunction::with_callbacks("assert", 2, |interpreter, arguments, span| {
+        if arguments[0].is_truthy() {
+            return Ok(Value::Nil);
+        }
+        let message = match arguments.get(1) {
+            Some(message) => interpreter.stringify(message.clone(), span)?,
+            None => "Assertion failed.".to_string(),
+        };
+        Err(interpreter.throw("AssertionError", &message, span))
+    });
+    // the message is optional
+    assert.arity.min = 1;
+    interpreter.define_global("assert", Value::Native(Rc::new(assert)));
+    let assert_eq =
+        NativeFunction::with_callbacks("assertEq", 2, |interpreter, arguments, span| {
+            let (actual, expected) = (arguments[0].clone(), arguments[1].clone());
+            if interpreter.equal(actual.clone(), expected.clone(), span)? {
+                return Ok(Value::Nil);
+            }
+            let (actual_type, expected_type) = (actual.type_name(), expected.type_name());
+            let mut actual = interpreter.stringify(actual, span)?;
+            let mut expected = interpreter.stringify(expected, span)?;
+            // `1` and `"1"` print the same
+            if actual == expected {
+                actual = format!("{} ({})", actual, actual_type);
+                expected = format!("{} ({})", expected, expected_type);
+            }
+            let message = format!("Expected {} to equal {}.", actual, expected);
+            Err(interpreter.throw("AssertionError", &message, span))
+        });
+    interpreter.define_global("assertEq", Value::Native(Rc::new(assert_eq)));
+    let fail = NativeFunction::with_callbacks("fail", 1, |interpreter, arguments, span| {
+        let message = interpreter.stringify(arguments[0].clone(), span)?;
+        Err(interpreter.throw("AssertionError", &message, span))
+    });
+    interpreter.define_global("fail", Value::Native(Rc::new(fail)));
+}
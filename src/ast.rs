@@ -0,0 +1,267 @@
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::lexer::Span;
+
+/// Identifies a node of the syntax tree. Ids are unique across every tree
+/// parsed by the process, so side tables from different parses never clash.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct NodeId(pub usize);
+
+impl NodeId {
+    pub fn fresh() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        NodeId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Literal {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    /// `~x`, flipping the bits of an integer.
+    BitNot,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    /// Always a float, even for integers: `7 / 2` is `3.5`.
+    Divide,
+    /// `x ~/ y`, the division rounded down, an integer when both are.
+    FloorDivide,
+    /// Remainder of the truncated division, so it takes the sign of the
+    /// dividend: `-7 % 3` is `-1`. Like `/`, `x % 0` doesn't fail but gives NaN.
+    Modulo,
+    /// `start..end`, building a range.
+    Range,
+    /// The bitwise operators work on the numbers as 64-bit integers, failing
+    /// for fractions and floats out of range.
+    BitAnd,
+    BitOr,
+    BitXor,
+    /// `x << n` and `x >> n`, where `n` goes from 0 to 63. Bits shifted out
+    /// on the left are lost, and `>>` keeps the sign.
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Expr {
+    pub id: NodeId,
+    pub span: Span,
+    pub kind: ExprKind,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ExprKind {
+    Literal(Literal),
+    Grouping(Box<Expr>),
+    Unary {
+        op: UnaryOp,
+        right: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+    Logical {
+        left: Box<Expr>,
+        op: LogicalOp,
+        right: Box<Expr>,
+    },
+    Variable(Identifier),
+    Assign {
+        name: Identifier,
+        value: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Identifier,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Identifier,
+        value: Box<Expr>,
+    },
+    List(Vec<Expr>),
+    /// `...list` among the arguments of a call or the elements of a list.
+    Spread(Box<Expr>),
+    /// Key and value expressions, in source order.
+    Map(Vec<(Expr, Expr)>),
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `object[start..end]`, where both bounds are optional.
+    Slice {
+        object: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    This,
+    Super {
+        method: Identifier,
+    },
+    /// `await promise`, only allowed as a whole statement or initializer.
+    Await(Box<Expr>),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self {
+            id: NodeId::fresh(),
+            span,
+            kind,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct FunctionDecl {
+    pub id: NodeId,
+    pub name: Identifier,
+    pub params: Vec<Identifier>,
+    /// The defaults of the last parameters, `name = expression`, evaluated
+    /// when the argument is left out.
+    pub defaults: Vec<Expr>,
+    /// `...name` after the parameters, holding the extra arguments in a list.
+    pub rest: Option<Identifier>,
+    pub body: Vec<Stmt>,
+    /// Declared with `async`, so calling it returns a promise.
+    pub is_async: bool,
+    /// The source of the declaration from its name on, to parse it again in
+    /// another interpreter.
+    pub text: Rc<str>,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ClassDecl {
+    pub name: Identifier,
+    pub superclass: Option<Expr>,
+    pub methods: Vec<Rc<FunctionDecl>>,
+    /// `get name { ... }`, without parameters.
+    pub getters: Vec<Rc<FunctionDecl>>,
+    /// `set name(value) { ... }`, with exactly one parameter.
+    pub setters: Vec<Rc<FunctionDecl>>,
+    /// `class name() { ... }`, called on the class itself.
+    pub class_methods: Vec<Rc<FunctionDecl>>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CatchClause {
+    pub name: Identifier,
+    pub body: Box<Stmt>,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Stmt {
+    pub id: NodeId,
+    pub span: Span,
+    pub kind: StmtKind,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum StmtKind {
+    Expression(Expr),
+    Print(Expr),
+    Var {
+        name: Identifier,
+        initializer: Option<Expr>,
+    },
+    Const {
+        name: Identifier,
+        initializer: Expr,
+    },
+    Block(Vec<Stmt>),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        // the increment of a `for` loop, which `continue` doesn't skip
+        increment: Option<Expr>,
+    },
+    /// `for (name in iterable) body`, with `name` bound anew for every
+    /// element.
+    ForIn {
+        name: Identifier,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+    Break,
+    Continue,
+    Function(Rc<FunctionDecl>),
+    Return(Option<Expr>),
+    /// Hand a value to the caller of a generator's `next`, suspending the
+    /// generator until it's called again.
+    Yield(Option<Expr>),
+    /// Evaluate the expression once the enclosing function returns, in the
+    /// scope of the statement, after the ones deferred later.
+    Defer(Expr),
+    Class(ClassDecl),
+    Throw(Expr),
+    /// Run a module and bind its top-level declarations, the path being
+    /// relative to the importing file.
+    Import(String),
+    /// At least one of `catch` and `finally` is present.
+    Try {
+        body: Box<Stmt>,
+        catch: Option<CatchClause>,
+        finally: Option<Box<Stmt>>,
+    },
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self {
+            id: NodeId::fresh(),
+            span,
+            kind,
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! One API over both engines running programs: the tree-walking
+//! interpreter and the bytecode VM, along with the experimental register
+//! machine with the `register-vm` feature.
+//!
+//! The VM runs what the compiler can compile, and behaves like the
+//! interpreter there. What it can't compile fails with a `SyntaxError`
+//! saying so, before anything runs.
+
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::interpreter::{Interpreter, InterruptHandle};
+#[cfg(feature = "register-vm")]
+use crate::register;
+use crate::value::Value;
+use crate::vm::Vm;
+
+/// The engines programs can run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// The tree-walking interpreter, running everything.
+    #[default]
+    Ast,
+    /// The bytecode VM.
+    Vm,
+    /// The register machine, running less than the VM.
+    #[cfg(feature = "register-vm")]
+    Register,
+}
+
+impl Backend {
+    #[cfg(not(feature = "register-vm"))]
+    pub const ALL: [Backend; 2] = [Backend::Ast, Backend::Vm];
+    #[cfg(feature = "register-vm")]
+    pub const ALL: [Backend; 3] = [Backend::Ast, Backend::Vm, Backend::Register];
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "ast" => Ok(Backend::Ast),
+            "vm" => Ok(Backend::Vm),
+            #[cfg(feature = "register-vm")]
+            "register" => Ok(Backend::Register),
+            _ => {
+                let names = Backend::ALL
+                    .iter()
+                    .map(|backend| format!("'{}'", backend))
+                    .collect::<Vec<_>>();
+                let (last, others) = names.split_last().expect("there are backends");
+                Err(anyhow::anyhow!(
+                    "Unknown backend '{}', expected {} or {}.",
+                    name,
+                    others.join(", "),
+                    last
+                ))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Ast => write!(f, "ast"),
+            Backend::Vm => write!(f, "vm"),
+            #[cfg(feature = "register-vm")]
+            Backend::Register => write!(f, "register"),
+        }
+    }
+}
+
+/// An engine of either backend, keeping its globals from one run to the
+/// next.
+pub enum Engine {
+    Ast(Box<Interpreter>),
+    Vm(Box<Vm>),
+    #[cfg(feature = "register-vm")]
+    Register(Box<register::Vm>),
+}
+
+impl Engine {
+    pub fn new(backend: Backend) -> Self {
+        match backend {
+            Backend::Ast => Engine::Ast(Box::default()),
+            Backend::Vm => Engine::Vm(Box::default()),
+            #[cfg(feature = "register-vm")]
+            Backend::Register => Engine::Register(Box::default()),
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            Engine::Ast(_) => Backend::Ast,
+            Engine::Vm(_) => Backend::Vm,
+            #[cfg(feature = "register-vm")]
+            Engine::Register(_) => Backend::Register,
+        }
+    }
+
+    /// Send everything programs print to `output` instead of stdout.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        match self {
+            Engine::Ast(interpreter) => interpreter.set_output(output),
+            Engine::Vm(vm) => vm.set_output(output),
+            #[cfg(feature = "register-vm")]
+            Engine::Register(vm) => vm.set_output(output),
+        }
+    }
+
+    /// A handle stopping whatever program the engine is running when it's
+    /// interrupted.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        match self {
+            Engine::Ast(interpreter) => interpreter.interrupt_handle(),
+            Engine::Vm(vm) => vm.interrupt_handle(),
+            #[cfg(feature = "register-vm")]
+            Engine::Register(vm) => vm.interrupt_handle(),
+        }
+    }
+
+    /// Run a program, failing with a `SyntaxError` or a `RuntimeError` as
+    /// `Interpreter::run` does.
+    pub fn run(&mut self, source: &str) -> anyhow::Result<()> {
+        match self {
+            Engine::Ast(interpreter) => interpreter.run(source),
+            Engine::Vm(vm) => vm.run(source),
+            #[cfg(feature = "register-vm")]
+            Engine::Register(vm) => vm.run(source),
+        }
+    }
+
+    /// Run a script. Only the interpreter imports modules, relative to the
+    /// directory of the script.
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Engine::Ast(interpreter) = self {
+            return interpreter.run_file(path);
+        }
+        let source = fs::read_to_string(path)
+            .map_err(|error| anyhow::anyhow!("Could not read '{}': {}", path.display(), error))?;
+        self.run(&source)
+    }
+
+    /// Look a global up by name. The VM leaves out the functions only it
+    /// can call.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match self {
+            Engine::Ast(interpreter) => interpreter.get_global(name),
+            Engine::Vm(vm) => vm.get_global(name),
+            #[cfg(feature = "register-vm")]
+            Engine::Register(vm) => vm.get_global(name),
+        }
+    }
+}
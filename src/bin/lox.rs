@@ -0,0 +1,329 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::thread;
+
+use lox_rs::backend::Backend;
+#[cfg(feature = "register-vm")]
+use lox_rs::backend::Engine;
+use lox_rs::cache::Cache;
+use lox_rs::capability::Capability;
+use lox_rs::chunk::{self, Chunk};
+use lox_rs::compiler;
+use lox_rs::diagnostic;
+use lox_rs::interpreter::{self, Interpreter, InterruptHandle};
+use lox_rs::repl;
+use lox_rs::replay::{ReplayLog, ReplayMode};
+use lox_rs::stats::Stats;
+use lox_rs::vm::Vm;
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    // the main thread's stack is too small for deeply nested calls
+    let code = thread::Builder::new()
+        .stack_size(interpreter::STACK_SIZE)
+        .spawn(move || run(&args))
+        .expect("failed to spawn the interpreter thread")
+        .join()
+        .unwrap_or(interpreter::EXIT_RUNTIME_ERROR);
+    process::exit(code);
+}
+
+fn run(args: &[String]) -> i32 {
+    if let [command, rest @ ..] = args {
+        if command == "compile" {
+            return compile_to_file(rest);
+        }
+    }
+
+    let mut interpreter = Interpreter::new();
+
+    let mut stats = None;
+    let mut replay_log = None;
+    let mut disassemble = false;
+    let mut vm = None;
+    let mut backend = None;
+    let mut args = args;
+    loop {
+        match args {
+            [flag, rest @ ..] if flag == "--stats" => {
+                let hook = Rc::new(RefCell::new(Stats::new()));
+                interpreter.set_hook(hook.clone());
+                stats = Some(hook);
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--disassemble" => {
+                disassemble = true;
+                args = rest;
+            }
+            // the collector and tracing flags are about the VM, so they
+            // imply running on it
+            [flag, rest @ ..] if flag == "--vm" => {
+                vm.get_or_insert_with(Vm::new);
+                args = rest;
+            }
+            [flag, rest @ ..] if flag.starts_with("--backend=") => {
+                match flag["--backend=".len()..].parse() {
+                    Ok(Backend::Vm) => {
+                        vm.get_or_insert_with(Vm::new);
+                    }
+                    Ok(other) => backend = Some(other),
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        return 64;
+                    }
+                }
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--gc-stress" => {
+                vm.get_or_insert_with(Vm::new).set_gc_stress(true);
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--gc-log" => {
+                vm.get_or_insert_with(Vm::new).set_gc_log(io::stderr());
+                args = rest;
+            }
+            [flag, rest @ ..] if flag == "--trace-execution" => {
+                vm.get_or_insert_with(Vm::new).set_trace(io::stderr());
+                args = rest;
+            }
+            [flag, capability, rest @ ..] if flag == "--allow" => {
+                match capability.parse::<Capability>() {
+                    Ok(capability) => interpreter.enable(capability),
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        return 64;
+                    }
+                }
+                args = rest;
+            }
+            [flag, seed, rest @ ..] if flag == "--seed" => {
+                match seed.parse() {
+                    Ok(seed) => interpreter.seed_random(seed),
+                    Err(_) => {
+                        eprintln!("Invalid seed '{}', expected a whole number.", seed);
+                        return 64;
+                    }
+                }
+                args = rest;
+            }
+            [flag, log, rest @ ..] if flag == "--replay" => {
+                replay_log = Some(log);
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    // what follows the script is for the script, flags or not
+    let (args, script_args) = args.split_at(args.len().min(1));
+    interpreter.set_args(script_args.to_vec());
+    // the first run records the log, the next ones replay it
+    if let Some(path) = replay_log {
+        if Path::new(path).exists() {
+            match ReplayLog::load(path) {
+                Ok(log) => interpreter.replay(log),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return interpreter::EXIT_NO_INPUT;
+                }
+            }
+        } else {
+            interpreter.record();
+        }
+    }
+
+    if let (Some(backend), Some(_)) = (backend, &vm) {
+        eprintln!("The VM flags can't be used with --backend={}.", backend);
+        return 64;
+    }
+
+    #[cfg(feature = "register-vm")]
+    if let (Some(Backend::Register), [script]) = (backend, args) {
+        return run_on_registers(script);
+    }
+
+    // precompiled scripts only run on the VM
+    if let [script] = args {
+        if vm.is_some() || (is_compiled(script) && !disassemble) {
+            let vm = vm.get_or_insert_with(Vm::new);
+            interrupt_on_sigint(vm.interrupt_handle());
+            return run_bytecode(vm, script);
+        }
+    }
+    // Ctrl-C stops the running program, not the whole process
+    interrupt_on_sigint(interpreter.interrupt_handle());
+    let code = match args {
+        [] => {
+            let stdin = io::stdin();
+            match repl::run(&mut interpreter, stdin.lock(), &mut io::stdout()) {
+                Ok(code) => code,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    1
+                }
+            }
+        }
+        [script] if disassemble => return dump_bytecode(script),
+        [script] => {
+            let result = interpreter.run_file(script);
+            if let (Err(error), false) = (&result, interpreter::exited(&result)) {
+                // test harnesses read errors as jlox prints them, so the
+                // source is only shown to people
+                if io::stderr().is_terminal() {
+                    // read the script again to show where it failed
+                    let source = fs::read_to_string(script).unwrap_or_default();
+                    eprintln!("{}", diagnostic::render(error, &source));
+                } else {
+                    eprintln!("{}", error);
+                }
+            }
+            if let Some(stats) = &stats {
+                eprintln!("{}", stats.borrow());
+            }
+            interpreter::exit_code(&result)
+        }
+        _ => {
+            eprintln!(
+                "Usage: lox [--stats] [--replay log] [--seed n] [--allow fs|env|process] \
+                 [--disassemble] [--backend=ast|vm] [--vm] [--gc-stress] [--gc-log] \
+                 [--trace-execution] [script [args...]]\n       \
+                 lox compile script [-o output]"
+            );
+            return 64;
+        }
+    };
+
+    if let (Some(path), Some(ReplayMode::Record)) = (replay_log, interpreter.replay_mode()) {
+        if let Some(Err(error)) = interpreter.take_replay_log().map(|log| log.save(path)) {
+            eprintln!("{}", error);
+        }
+    }
+    code
+}
+
+// `.loxc` files hold scripts compiled by `lox compile`
+fn is_compiled(script: &str) -> bool {
+    Path::new(script)
+        .extension()
+        .is_some_and(|extension| extension == "loxc")
+}
+
+// compile a script, or load it when it's already compiled
+fn load_chunk(script: &str) -> anyhow::Result<Chunk> {
+    if is_compiled(script) {
+        let bytes = fs::read(script)
+            .map_err(|error| anyhow::anyhow!("Could not read '{}': {}", script, error))?;
+        return chunk::deserialize(&bytes)
+            .map_err(|error| anyhow::anyhow!("Could not load '{}': {}", script, error));
+    }
+    fs::read_to_string(script)
+        .map_err(|error| anyhow::anyhow!("Could not read '{}': {}", script, error))
+        .and_then(|source| compiler::compile(&source))
+}
+
+// save the bytecode of a script, next to it unless `-o` says where
+fn compile_to_file(args: &[String]) -> i32 {
+    let (script, output) = match args {
+        [script] => (script, Path::new(script).with_extension("loxc")),
+        [script, flag, output] if flag == "-o" => (script, PathBuf::from(output)),
+        _ => {
+            eprintln!("Usage: lox compile script [-o output]");
+            return 64;
+        }
+    };
+    let result = load_chunk(script).and_then(|chunk| {
+        fs::write(&output, chunk::serialize(&chunk))
+            .map_err(|error| anyhow::anyhow!("Could not write '{}': {}", output.display(), error))
+    });
+    if let Err(error) = &result {
+        eprintln!("{}", error);
+    }
+    interpreter::exit_code(&result)
+}
+
+// print the bytecode a script compiles to instead of running it
+fn dump_bytecode(script: &str) -> i32 {
+    let result = load_chunk(script);
+    match &result {
+        Ok(chunk) => println!("{}", chunk::disassemble(chunk, script)),
+        Err(error) => eprintln!("{}", error),
+    }
+    interpreter::exit_code(&result.map(|_| ()))
+}
+
+// run a script on the bytecode VM instead of the interpreter, compiling it
+// only when it changed since the last run
+fn run_bytecode(vm: &mut Vm, script: &str) -> i32 {
+    let result = if is_compiled(script) {
+        load_chunk(script).and_then(|chunk| Ok(vm.execute(&chunk)?))
+    } else {
+        if let Some(dir) = cache_dir() {
+            vm.set_cache(Cache::in_dir(dir));
+        }
+        fs::read_to_string(script)
+            .map_err(|error| anyhow::anyhow!("Could not read '{}': {}", script, error))
+            .and_then(|source| vm.run(&source))
+    };
+    if let Err(error) = &result {
+        eprintln!("{}", error);
+    }
+    interpreter::exit_code(&result)
+}
+
+// where compiled scripts are cached: `LOX_CACHE_DIR`, or `lox` in the cache
+// directory of the user, and nowhere when `LOX_CACHE_DIR` is empty
+fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("LOX_CACHE_DIR") {
+        return (!dir.is_empty()).then(|| PathBuf::from(dir));
+    }
+    let cache = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache.join("lox"))
+}
+
+// run a script on the experimental register machine
+#[cfg(feature = "register-vm")]
+fn run_on_registers(script: &str) -> i32 {
+    let mut engine = Engine::new(Backend::Register);
+    interrupt_on_sigint(engine.interrupt_handle());
+    let result = engine.run_file(script);
+    if let Err(error) = &result {
+        eprintln!("{}", error);
+    }
+    interpreter::exit_code(&result)
+}
+
+#[cfg(unix)]
+fn interrupt_on_sigint(handle: InterruptHandle) {
+    use std::sync::OnceLock;
+
+    const SIGINT: i32 = 2;
+    static HANDLE: OnceLock<InterruptHandle> = OnceLock::new();
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    // only sets a flag, which is safe to do from a signal handler
+    extern "C" fn on_sigint(_: i32) {
+        if let Some(handle) = HANDLE.get() {
+            handle.interrupt();
+        }
+    }
+
+    if HANDLE.set(handle).is_ok() {
+        unsafe {
+            signal(SIGINT, on_sigint);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn interrupt_on_sigint(_: InterruptHandle) {}
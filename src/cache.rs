@@ -0,0 +1,156 @@
+//! Compiled chunks kept to run unchanged scripts again without compiling
+//! them.
+//!
+//! Chunks are found by a hash of their source, the options they were
+//! compiled with and the revision of the compiler, so changing any of those
+//! compiles again. The source is kept with its chunk and compared when
+//! loading it, so two sources with the same hash never share a chunk. A
+//! cache lives in memory, for embedders running the same sources over and
+//! over, or in a directory, holding every chunk in a file named after its
+//! hash so it outlives the process: the source, then the chunk as in a
+//! `.loxc` file.
+//!
+//! Files that can't be loaded, broken, from another version of the format
+//! or of another source, are compiled again and replaced. Failing to save a
+//! chunk is no reason to fail the run, it's only left out of the cache.
+
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use crate::chunk::{self, Chunk};
+use crate::compiler::{self, Options};
+
+/// Compiled chunks, by the hash of their source.
+#[derive(Debug, Default)]
+pub struct Cache {
+    // where the chunks are saved, kept in `chunks` otherwise
+    dir: Option<PathBuf>,
+    chunks: HashMap<u64, (String, Chunk)>,
+    hits: usize,
+    misses: usize,
+}
+
+impl Cache {
+    /// A cache for the life of the process.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// A cache saving chunks to `dir`, created when the first one is saved.
+    pub fn in_dir<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: Some(dir.into()),
+            ..Self::default()
+        }
+    }
+
+    /// The chunk `source` compiles to, compiling it only if it isn't cached.
+    pub fn compile(&mut self, source: &str, options: Options) -> anyhow::Result<Chunk> {
+        let key = key(source, options);
+        if let Some(chunk) = self.load(key, source) {
+            self.hits += 1;
+            return Ok(chunk);
+        }
+        self.misses += 1;
+        let chunk = compiler::compile_with(source, options)?;
+        self.save(key, source, &chunk);
+        Ok(chunk)
+    }
+
+    /// The file the chunk of `source` is saved to, for caches in a
+    /// directory.
+    pub fn path(&self, source: &str, options: Options) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(file(dir, key(source, options)))
+    }
+
+    /// How many chunks were found in the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// How many chunks had to be compiled.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    fn load(&self, key: u64, source: &str) -> Option<Chunk> {
+        match &self.dir {
+            Some(dir) => {
+                let bytes = fs::read(file(dir, key)).ok()?;
+                // the chunk follows the source it was compiled from
+                let chunk = bytes
+                    .strip_prefix(&(source.len() as u64).to_le_bytes()[..])?
+                    .strip_prefix(source.as_bytes())?;
+                chunk::deserialize(chunk).ok()
+            }
+            None => match self.chunks.get(&key) {
+                Some((saved, chunk)) if saved == source => Some(chunk.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    fn save(&mut self, key: u64, source: &str, chunk: &Chunk) {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => {
+                self.chunks.insert(key, (source.to_string(), chunk.clone()));
+                return;
+            }
+        };
+        let mut bytes = (source.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(source.as_bytes());
+        bytes.extend(chunk::serialize(chunk));
+        // written next to where it goes, then moved there, so another
+        // process never loads half of it
+        let path = file(dir, key);
+        let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+        let saved = fs::create_dir_all(dir)
+            .and_then(|()| fs::write(&partial, bytes))
+            .and_then(|()| fs::rename(&partial, &path));
+        if saved.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+    }
+}
+
+fn file(dir: &Path, key: u64) -> PathBuf {
+    dir.join(format!("{:016x}.loxcache", key))
+}
+
+// the same on every machine and every run, unlike the hasher of the
+// standard library
+fn key(source: &str, options: Options) -> u64 {
+    let mut hasher = Fnv::default();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    hasher.write(&compiler::REVISION.to_le_bytes());
+    hasher.write(&chunk::FORMAT_VERSION.to_le_bytes());
+    options.hash(&mut hasher);
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+// 64-bit FNV-1a
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,82 @@
+//! What programs may do beyond computing and printing, off unless the
+//! embedder enables it.
+//!
+//! An interpreter starts without any capability, so a program can't write
+//! files, change the environment, run other programs or make requests.
+//! Enabling one with `Interpreter::enable` defines its natives, which don't
+//! exist otherwise: a program calling them fails like with any undefined
+//! variable. The CLI enables them with `--allow <capability>`.
+//!
+//! This is not a sandbox. Without any capability, programs still read:
+//!
+//! - any file with `import`, which takes any path, and reports what it
+//!   couldn't parse;
+//! - the environment variables with `env`, the arguments with `args`, and
+//!   the working directory with `cwd` and `absolutePath`;
+//! - standard input with `input`.
+//!
+//! Embedders running untrusted code must keep it from reaching what they
+//! care about some other way, and limit how long it runs with
+//! `Interpreter::set_limits`.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Reading and writing files, with the natives of `files`.
+    Fs,
+    /// Changing the environment variables of the process, with `setEnv`.
+    Env,
+    /// Running other programs, with `exec` and `spawnProcess`.
+    Process,
+    /// Making HTTP requests, with the natives of `http`.
+    #[cfg(feature = "http")]
+    Net,
+}
+
+impl Capability {
+    #[cfg(not(feature = "http"))]
+    pub const ALL: [Capability; 3] = [Capability::Fs, Capability::Env, Capability::Process];
+    #[cfg(feature = "http")]
+    pub const ALL: [Capability; 4] = [
+        Capability::Fs,
+        Capability::Env,
+        Capability::Process,
+        Capability::Net,
+    ];
+}
+
+impl FromStr for Capability {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        Capability::ALL
+            .iter()
+            .copied()
+            .find(|capability| capability.to_string() == name)
+            .ok_or_else(|| {
+                let names = Capability::ALL
+                    .iter()
+                    .map(|capability| format!("'{}'", capability))
+                    .collect::<Vec<_>>();
+                anyhow::anyhow!(
+                    "Unknown capability '{}', expected {}.",
+                    name,
+                    names.join(" or ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::Fs => write!(f, "fs"),
+            Capability::Env => write!(f, "env"),
+            Capability::Process => write!(f, "process"),
+            #[cfg(feature = "http")]
+            Capability::Net => write!(f, "net"),
+        }
+    }
+}
@@ -0,0 +1,563 @@
+//! Threads, and the channels they send each other messages through.
+//!
+//! `spawn(function)` runs a function on a thread of its own, in a fresh
+//! interpreter: it sees the standard library but none of the globals of the
+//! program that spawned it. Functions using the locals around them can't be
+//! spawned at all. Values can't be shared between threads, so a message is
+//! a deep copy of plain data, which channels themselves are part of.
+//!
+//! The function gets a channel to talk with the thread handle `spawn` hands
+//! back: what one end sends, the other receives. Receiving from the handle
+//! fails once the thread is done without sending anything more.
+//!
+//! The thread runs under the limits of the program that spawned it: the
+//! same maximum call depth and memory limit, and the steps and time its run
+//! has left. What it prints goes to the output of that program, a line at a
+//! time. A run waits for the threads it spawned before it ends, failing
+//! with the error of one that failed unless `join` or `receive` reported it
+//! already, or stops them when it fails.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::function::{LoxFunction, NativeFunction};
+use crate::interpreter::{self, ExecutionLimits, Interpreter, InterruptHandle, RuntimeError};
+use crate::lexer::Span;
+use crate::list;
+use crate::map::{self, MapKey};
+use crate::ordered_map::OrderedMap;
+use crate::range::Range;
+use crate::value::Value;
+
+// how long waiting threads block at most before checking for interrupts
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// One end of a channel, receiving what the other end sends. The ends of a
+/// channel made with `Channel()` are the same, so it receives what it sends.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    incoming: Arc<Queue>,
+    outgoing: Arc<Queue>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    messages: Mutex<VecDeque<Message>>,
+    ready: Condvar,
+}
+
+/// A value copied out of one interpreter, to be rebuilt in another.
+#[derive(Debug, Clone)]
+pub(crate) enum Message {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    List(Vec<Message>),
+    Map(Vec<(Message, Message)>),
+    Range(Range),
+    Channel(Channel),
+}
+
+/// A thread running a spawned function.
+pub struct LoxThread {
+    pub name: String,
+    channel: Channel,
+    // what the function returned, or the error it failed with
+    outcome: Receiver<Result<Message, String>>,
+    finished: Option<Result<Message, String>>,
+    // the program found out how the thread finished
+    reported: bool,
+    handle: Option<JoinHandle<()>>,
+    interrupt: InterruptHandle,
+}
+
+pub type ThreadRef = Rc<RefCell<LoxThread>>;
+
+impl fmt::Debug for LoxThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<thread {}>", self.name)
+    }
+}
+
+/// What a thread takes from the interpreter spawning it.
+pub(crate) struct Inherited {
+    pub(crate) limits: ExecutionLimits,
+    pub(crate) max_call_depth: usize,
+    pub(crate) memory_limit: Option<usize>,
+    pub(crate) interrupt: InterruptHandle,
+    pub(crate) output: Forward,
+}
+
+/// Prints to the output of another thread, a line at a time so the lines
+/// of different threads don't mix.
+pub(crate) struct Forward {
+    line: Vec<u8>,
+    printed: Sender<Vec<u8>>,
+}
+
+/// The threads an interpreter spawned, and what they printed that it has
+/// yet to write to its own output.
+pub(crate) struct Threads {
+    // with the span of the `spawn` call
+    running: Vec<(ThreadRef, Span)>,
+    printed: Sender<Vec<u8>>,
+    received: Receiver<Vec<u8>>,
+}
+
+impl Channel {
+    pub fn new() -> Self {
+        let queue = Arc::new(Queue::default());
+        Self {
+            incoming: queue.clone(),
+            outgoing: queue,
+        }
+    }
+
+    // two ends talking to each other
+    fn pair() -> (Self, Self) {
+        let (left, right) = (Arc::new(Queue::default()), Arc::new(Queue::default()));
+        let near = Self {
+            incoming: left.clone(),
+            outgoing: right.clone(),
+        };
+        let far = Self {
+            incoming: right,
+            outgoing: left,
+        };
+        (near, far)
+    }
+
+    pub fn same(&self, other: &Channel) -> bool {
+        Arc::ptr_eq(&self.incoming, &other.incoming) && Arc::ptr_eq(&self.outgoing, &other.outgoing)
+    }
+
+    fn send(&self, message: Message) {
+        let mut messages = self.outgoing.messages.lock().unwrap();
+        messages.push_back(message);
+        self.outgoing.ready.notify_one();
+    }
+
+    // wait for the next message, checking for interrupts now and then, and
+    // whether the thread at the other end, if any, is done sending
+    fn receive(
+        &self,
+        sender: Option<&ThreadRef>,
+        interpreter: &mut Interpreter,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let mut messages = self.incoming.messages.lock().unwrap();
+        loop {
+            if let Some(message) = messages.pop_front() {
+                return Ok(message.into_value());
+            }
+            // it sends its messages before finishing, they'd be here
+            if let Some(thread) = sender.filter(|thread| poll(thread, Duration::ZERO)) {
+                let mut thread = thread.borrow_mut();
+                thread.reported = true;
+                return Err(match &thread.finished {
+                    Some(Err(error)) => failure(error, span),
+                    _ => RuntimeError::new("Thread finished without sending a value.", span),
+                });
+            }
+            interpreter.check_waiting(span)?;
+            messages = self
+                .incoming
+                .ready
+                .wait_timeout(messages, POLL_INTERVAL)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Look up one of the methods every channel has, bound to `channel`.
+pub fn get_method(channel: &Channel, name: &str) -> Option<Value> {
+    let method = match name {
+        "send" => NativeFunction::new(name, 1, {
+            let channel = channel.clone();
+            move |arguments| {
+                channel.send(Message::new(&arguments[0])?);
+                Ok(Value::Nil)
+            }
+        }),
+        "receive" => NativeFunction::with_callbacks(name, 0, {
+            let channel = channel.clone();
+            move |interpreter, _, span| channel.receive(None, interpreter, span)
+        }),
+        _ => return None,
+    };
+    Some(Value::Native(Rc::new(method)))
+}
+
+/// Look up one of the methods every thread has, bound to `thread`: the ones
+/// of its channel and `join`.
+pub fn get_thread_method(thread: &ThreadRef, name: &str) -> Option<Value> {
+    let method = match name {
+        "join" => NativeFunction::with_callbacks(name, 0, {
+            let thread = thread.clone();
+            move |interpreter, _, span| join(&thread, interpreter, span)
+        }),
+        "receive" => NativeFunction::with_callbacks(name, 0, {
+            let thread = thread.clone();
+            move |interpreter, _, span| {
+                let channel = thread.borrow().channel.clone();
+                channel.receive(Some(&thread), interpreter, span)
+            }
+        }),
+        _ => return get_method(&thread.borrow().channel, name),
+    };
+    Some(Value::Native(Rc::new(method)))
+}
+
+/// Run `function` on a new thread, handing it the far end of a channel
+/// when it takes an argument.
+pub(crate) fn spawn(
+    function: &LoxFunction,
+    interpreter: &mut Interpreter,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    if function.is_bound {
+        return Err(RuntimeError::new("Can't spawn a method.", span));
+    }
+    // the thread declares the function again, without them
+    if interpreter.captures_locals(function) {
+        return Err(RuntimeError::new(
+            "Can't spawn a function using the locals around it.",
+            span,
+        ));
+    }
+    let declaration = &function.declaration;
+    // keep the lines the same, for errors
+    let source = format!(
+        "{}{}fun {}",
+        "\n".repeat(declaration.span.line.saturating_sub(1)),
+        if declaration.is_async { "async " } else { "" },
+        declaration.text
+    );
+    let name = function.name().to_string();
+    let takes_channel = function.arity().accepts(1);
+    if !takes_channel && !function.arity().accepts(0) {
+        return Err(RuntimeError::new(
+            format!(
+                "Can only spawn functions taking 0 or 1 arguments, not {}.",
+                function.arity()
+            ),
+            span,
+        ));
+    }
+
+    let inherited = interpreter.inherited();
+    let interrupt = inherited.interrupt.clone();
+    let (near, far) = Channel::pair();
+    let (finished, outcome) = mpsc::channel();
+    let handle = thread::Builder::new()
+        .name(name.clone())
+        .stack_size(interpreter::STACK_SIZE)
+        .spawn({
+            let name = name.clone();
+            move || {
+                let arguments = if takes_channel {
+                    vec![Value::Channel(far)]
+                } else {
+                    Vec::new()
+                };
+                let result =
+                    run(&source, &name, arguments, inherited).map_err(|error| error.to_string());
+                // the handle may be gone already
+                let _ = finished.send(result);
+            }
+        })
+        .map_err(|error| {
+            RuntimeError::new(format!("Could not spawn a thread: {}.", error), span)
+        })?;
+
+    let thread = Rc::new(RefCell::new(LoxThread {
+        name,
+        channel: near,
+        outcome,
+        finished: None,
+        reported: false,
+        handle: Some(handle),
+        interrupt,
+    }));
+    interpreter.threads().running.push((thread.clone(), span));
+    Ok(Value::Thread(thread))
+}
+
+// declare the function again in a fresh interpreter and call it
+fn run(
+    source: &str,
+    name: &str,
+    arguments: Vec<Value>,
+    inherited: Inherited,
+) -> anyhow::Result<Message> {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(inherited.limits);
+    interpreter.set_max_call_depth(inherited.max_call_depth);
+    interpreter.set_memory_limit(inherited.memory_limit);
+    interpreter.set_interrupt_handle(inherited.interrupt);
+    interpreter.set_output(inherited.output);
+    interpreter.run(source)?;
+    let function = match interpreter.get_global(name) {
+        Some(Value::Function(function)) => function,
+        _ => unreachable!("the spawned function declares itself"),
+    };
+    let span = Span::new(0, 0, function.declaration.span.line);
+    let result = interpreter.call_function(&function, arguments, span);
+    let value = interpreter.end_run(result)?;
+    Ok(Message::new(&value)?)
+}
+
+// wait for the thread to finish, handing back what its function returned
+fn join(
+    thread: &ThreadRef,
+    interpreter: &mut Interpreter,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    while !poll(thread, POLL_INTERVAL) {
+        interpreter.check_waiting(span)?;
+    }
+    let mut thread = thread.borrow_mut();
+    thread.reported = true;
+    match &thread.finished {
+        Some(Ok(message)) => Ok(message.clone().into_value()),
+        Some(Err(error)) => Err(failure(error, span)),
+        None => unreachable!("the thread finished"),
+    }
+}
+
+// whether the thread finished, waiting for it for `timeout` at most, keeping
+// what its function returned
+fn poll(thread: &ThreadRef, timeout: Duration) -> bool {
+    if thread.borrow().finished.is_some() {
+        return true;
+    }
+    let received = thread.borrow().outcome.recv_timeout(timeout);
+    let finished = match received {
+        Ok(finished) => finished,
+        Err(RecvTimeoutError::Timeout) => return false,
+        Err(RecvTimeoutError::Disconnected) => Err("Thread panicked.".to_string()),
+    };
+    let mut thread = thread.borrow_mut();
+    if let Some(handle) = thread.handle.take() {
+        let _ = handle.join();
+    }
+    thread.finished = Some(finished);
+    true
+}
+
+// the error a thread failed with, without its stack trace
+fn failure(error: &str, span: Span) -> RuntimeError {
+    let message = error.lines().next().unwrap_or_default();
+    RuntimeError::new(message, span)
+}
+
+impl Forward {
+    fn send(&mut self, bytes: Vec<u8>) -> io::Result<()> {
+        self.printed
+            .send(bytes)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the spawning thread is gone"))
+    }
+}
+
+impl Write for Forward {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        if let Some(end) = self.line.iter().rposition(|&byte| byte == b'\n') {
+            let rest = self.line.split_off(end + 1);
+            let lines = mem::replace(&mut self.line, rest);
+            self.send(lines)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let line = mem::take(&mut self.line);
+        self.send(line)
+    }
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl Threads {
+    pub(crate) fn new() -> Self {
+        let (printed, received) = mpsc::channel();
+        Self {
+            running: Vec::new(),
+            printed,
+            received,
+        }
+    }
+
+    pub(crate) fn forward(&self) -> Forward {
+        Forward {
+            line: Vec::new(),
+            printed: self.printed.clone(),
+        }
+    }
+
+    /// Write what the threads printed so far to `output`.
+    pub(crate) fn print(&self, output: &mut dyn Write) -> io::Result<()> {
+        for printed in self.received.try_iter() {
+            output.write_all(&printed)?;
+        }
+        Ok(())
+    }
+
+    /// The span of the `spawn` call of a thread still running, forgetting
+    /// the ones that finished. Fails with the error of the first one that
+    /// failed without the program finding out.
+    pub(crate) fn running(&mut self) -> Result<Option<Span>, RuntimeError> {
+        let mut failed = None;
+        self.running.retain(|(thread, span)| {
+            if !poll(thread, Duration::ZERO) {
+                return true;
+            }
+            let mut thread = thread.borrow_mut();
+            if let (Some(Err(error)), false) = (&thread.finished, thread.reported) {
+                failed.get_or_insert_with(|| failure(error, *span));
+                thread.reported = true;
+            }
+            false
+        });
+        match failed {
+            Some(error) => Err(error),
+            None => Ok(self.running.first().map(|(_, span)| *span)),
+        }
+    }
+
+    /// Interrupt the threads still running and wait for them to stop.
+    pub(crate) fn stop(&mut self) {
+        for (thread, _) in self.running.drain(..) {
+            let mut thread = thread.borrow_mut();
+            if let Some(handle) = thread.handle.take() {
+                // until it notices, as starting to run clears interrupts
+                while !handle.is_finished() {
+                    thread.interrupt.interrupt();
+                    thread::sleep(POLL_INTERVAL);
+                }
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+impl Drop for Threads {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Message {
+    /// Copy `value`, failing for the values that belong to their interpreter.
+    pub(crate) fn new(value: &Value) -> Result<Self, RuntimeError> {
+        let mut copying = Vec::new();
+        Self::copy(value, &mut copying)
+    }
+
+    // `copying` holds the collections being copied, which can't hold
+    // themselves
+    fn copy(value: &Value, copying: &mut Vec<*const ()>) -> Result<Self, RuntimeError> {
+        let address = match value {
+            Value::List(list) => Rc::as_ptr(list) as *const (),
+            Value::Map(map) => Rc::as_ptr(map) as *const (),
+            _ => return Self::copy_plain(value),
+        };
+        if copying.contains(&address) {
+            return Err(RuntimeError::msg(format!(
+                "Can't send a {} holding itself.",
+                value.type_name()
+            )));
+        }
+
+        copying.push(address);
+        let message = match value {
+            Value::List(list) => Message::List(
+                list.borrow()
+                    .iter()
+                    .map(|element| Self::copy(element, copying))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Map(map) => Message::Map(
+                map.borrow()
+                    .iter()
+                    .map(|(key, value)| {
+                        Ok((
+                            Self::copy(&key.to_value(), copying)?,
+                            Self::copy(value, copying)?,
+                        ))
+                    })
+                    .collect::<Result<_, RuntimeError>>()?,
+            ),
+            _ => unreachable!("only collections are copied element by element"),
+        };
+        copying.pop();
+        Ok(message)
+    }
+
+    fn copy_plain(value: &Value) -> Result<Self, RuntimeError> {
+        Ok(match value {
+            Value::Nil => Message::Nil,
+            Value::Bool(boolean) => Message::Bool(*boolean),
+            Value::Integer(integer) => Message::Integer(*integer),
+            Value::Number(number) => Message::Number(*number),
+            Value::String(string) => Message::String(string.to_string()),
+            Value::Range(range) => Message::Range(*range),
+            Value::Channel(channel) => Message::Channel(channel.clone()),
+            _ => {
+                return Err(RuntimeError::msg(format!(
+                    "Can't send a {} between threads.",
+                    value.type_name()
+                )))
+            }
+        })
+    }
+
+    pub(crate) fn into_value(self) -> Value {
+        match self {
+            Message::Nil => Value::Nil,
+            Message::Bool(boolean) => Value::Bool(boolean),
+            Message::Integer(integer) => Value::Integer(integer),
+            Message::Number(number) => Value::Number(number),
+            Message::String(string) => Value::String(string.into()),
+            Message::List(elements) => {
+                list::new(elements.into_iter().map(Message::into_value).collect())
+            }
+            Message::Map(entries) => map::new(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let key =
+                            MapKey::new(&key.into_value()).expect("map keys are copied as is");
+                        (key, value.into_value())
+                    })
+                    .collect::<OrderedMap<_, _>>(),
+            ),
+            Message::Range(range) => Value::Range(range),
+            Message::Channel(channel) => Value::Channel(channel),
+        }
+    }
+}
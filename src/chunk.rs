@@ -0,0 +1,711 @@
+//! Bytecode: the instructions the compiler turns programs into.
+//!
+//! A chunk is a sequence of bytes, each instruction being an opcode followed
+//! by its operands. Jumps take two bytes, big-endian, counting from the end
+//! of the jump instruction. Past the first 256 constants, the instructions
+//! using them have a long form taking three bytes, big-endian. Every other
+//! operand takes one byte.
+//!
+//! Chunks are saved to `.loxc` files with `serialize`, and loaded back with
+//! `deserialize`, so scripts are compiled once.
+//!
+//! A chunk also remembers the statements the compiler left out because they
+//! can never run, for coverage tools not to count them as missed.
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::{anyhow, bail};
+
+use crate::lexer::Span;
+use crate::value;
+use crate::verifier;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum OpCode {
+    /// Push the constant at the index given by the operand.
+    Constant,
+    ConstantLong,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Push the local in the stack slot given by the operand, counted from
+    /// the start of the frame.
+    GetLocal,
+    /// Store the top of the stack in a local, leaving it there.
+    SetLocal,
+    /// The operand is the constant holding the name of the global.
+    GetGlobal,
+    GetGlobalLong,
+    /// Pop the top of the stack into a new global.
+    DefineGlobal,
+    DefineGlobalLong,
+    SetGlobal,
+    SetGlobalLong,
+    /// The operand is the index of the upvalue in the running closure.
+    GetUpvalue,
+    SetUpvalue,
+    /// The first operand is the constant holding the name of the property,
+    /// the next two the inline cache remembering the method it found last.
+    GetProperty,
+    /// The operand is the constant holding the name of the field.
+    SetProperty,
+    /// Look a method up in the superclass on top of the stack, binding it to
+    /// the instance below. Operands as for `GetProperty`.
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    FloorDivide,
+    Modulo,
+    Range,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Not,
+    Negate,
+    BitNot,
+    Print,
+    /// Jump forward by the operand.
+    Jump,
+    /// Jump forward by the operand when the top of the stack is falsey,
+    /// leaving it there.
+    JumpIfFalse,
+    /// Jump backward by the operand.
+    Loop,
+    /// Call the value below as many arguments as the operand.
+    Call,
+    /// Make a closure of the function in the constant given by the operand.
+    /// Each of its upvalues follows as two bytes: 1 when it captures a local
+    /// of the enclosing function and 0 when it's an upvalue of it, then the
+    /// slot or index.
+    Closure,
+    ClosureLong,
+    /// Move the local on top of the stack to the upvalues capturing it, then
+    /// pop it.
+    CloseUpvalue,
+    /// Call a method without binding it first: the operands are the constant
+    /// holding its name, the number of arguments and two bytes of inline
+    /// cache.
+    Invoke,
+    /// Call a method of the superclass on top of the stack. Operands as for
+    /// `Invoke`.
+    SuperInvoke,
+    /// The operand is the constant holding the name of the class.
+    Class,
+    /// Copy the methods of the superclass below into the class on top of
+    /// the stack, then pop it.
+    Inherit,
+    /// Add the closure on top of the stack to the class below as the method
+    /// the operand names.
+    Method,
+    /// Push the locals in the slots given by the two operands.
+    GetLocals,
+    /// The superinstructions below do what the two instructions they're
+    /// named after do, with the operand of the first: `SetLocal` and `Pop`.
+    SetLocalPop,
+    SetGlobalPop,
+    /// `Constant` and `Add`.
+    AddConstant,
+    SubtractConstant,
+    LessConstant,
+    Return,
+}
+
+impl OpCode {
+    // in the order of their bytes
+    const ALL: [OpCode; 60] = [
+        OpCode::Constant,
+        OpCode::ConstantLong,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::GetGlobalLong,
+        OpCode::DefineGlobal,
+        OpCode::DefineGlobalLong,
+        OpCode::SetGlobal,
+        OpCode::SetGlobalLong,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::FloorDivide,
+        OpCode::Modulo,
+        OpCode::Range,
+        OpCode::BitAnd,
+        OpCode::BitOr,
+        OpCode::BitXor,
+        OpCode::ShiftLeft,
+        OpCode::ShiftRight,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::BitNot,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::ClosureLong,
+        OpCode::CloseUpvalue,
+        OpCode::Invoke,
+        OpCode::SuperInvoke,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::GetLocals,
+        OpCode::SetLocalPop,
+        OpCode::SetGlobalPop,
+        OpCode::AddConstant,
+        OpCode::SubtractConstant,
+        OpCode::LessConstant,
+        OpCode::Return,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    /// The form of an instruction taking a constant index of three bytes.
+    pub fn long(self) -> Option<Self> {
+        match self {
+            OpCode::Constant => Some(OpCode::ConstantLong),
+            OpCode::GetGlobal => Some(OpCode::GetGlobalLong),
+            OpCode::DefineGlobal => Some(OpCode::DefineGlobalLong),
+            OpCode::SetGlobal => Some(OpCode::SetGlobalLong),
+            OpCode::Closure => Some(OpCode::ClosureLong),
+            _ => None,
+        }
+    }
+
+    /// The superinstruction doing what `self` followed by `next` does, the
+    /// operand of `self` and then the operands of `next` being its own.
+    pub fn fuse(self, next: OpCode) -> Option<Self> {
+        match (self, next) {
+            (OpCode::GetLocal, OpCode::GetLocal) => Some(OpCode::GetLocals),
+            (OpCode::SetLocal, OpCode::Pop) => Some(OpCode::SetLocalPop),
+            (OpCode::SetGlobal, OpCode::Pop) => Some(OpCode::SetGlobalPop),
+            (OpCode::Constant, OpCode::Add) => Some(OpCode::AddConstant),
+            (OpCode::Constant, OpCode::Subtract) => Some(OpCode::SubtractConstant),
+            (OpCode::Constant, OpCode::Less) => Some(OpCode::LessConstant),
+            _ => None,
+        }
+    }
+}
+
+/// The most constants a chunk can hold, as long operands count them.
+pub const MAX_CONSTANTS: usize = 1 << 24;
+
+impl From<OpCode> for u8 {
+    fn from(op: OpCode) -> Self {
+        op as u8
+    }
+}
+
+/// A literal value stored in a chunk.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Constant {
+    Integer(i64),
+    Number(f64),
+    String(String),
+    Function(Rc<Function>),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Integer(integer) => write!(f, "{}", integer),
+            Constant::Number(number) => write!(f, "{}", value::format_number(*number)),
+            Constant::String(string) => write!(f, "{}", string),
+            Constant::Function(function) => write!(f, "<fn {}>", function.name),
+        }
+    }
+}
+
+/// A compiled function, which `Closure` instructions make closures of.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    /// How many upvalues its closures capture.
+    pub upvalues: usize,
+    pub chunk: Chunk,
+}
+
+// constants compare by bits, so `0.0` and `-0.0` stay apart and NaN is
+// stored once
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+enum ConstantKey {
+    Integer(i64),
+    Number(u64),
+    String(String),
+}
+
+impl ConstantKey {
+    // functions are all different
+    fn of(constant: &Constant) -> Option<Self> {
+        match constant {
+            Constant::Integer(integer) => Some(ConstantKey::Integer(*integer)),
+            Constant::Number(number) => Some(ConstantKey::Number(number.to_bits())),
+            Constant::String(string) => Some(ConstantKey::String(string.clone())),
+            Constant::Function(_) => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    // the source line of every byte of code
+    pub lines: Vec<usize>,
+    pub constants: Vec<Constant>,
+    /// How many inline caches its instructions use.
+    pub caches: usize,
+    /// The source of the statements left out as unreachable, in the order
+    /// they were met. Functions have their own.
+    pub eliminated: Vec<Span>,
+    // where each constant is in `constants`
+    indices: HashMap<ConstantKey, usize>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    pub fn write<B: Into<u8>>(&mut self, byte: B, line: usize) {
+        self.code.push(byte.into());
+        self.lines.push(line);
+    }
+
+    /// Add a constant, returning its index, which is the index of the same
+    /// constant added before if there's one.
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        let key = ConstantKey::of(&constant);
+        if let Some(&index) = key.as_ref().and_then(|key| self.indices.get(key)) {
+            return index;
+        }
+        self.constants.push(constant);
+        if let Some(key) = key {
+            self.indices.insert(key, self.constants.len() - 1);
+        }
+        self.constants.len() - 1
+    }
+
+    // add a constant at the next index, even when it's already there, as
+    // when loading a chunk whose instructions know where their constants are
+    fn push_constant(&mut self, constant: Constant) {
+        if let Some(key) = ConstantKey::of(&constant) {
+            self.indices.entry(key).or_insert(self.constants.len());
+        }
+        self.constants.push(constant);
+    }
+}
+
+/// The bytes `.loxc` files start with.
+pub const MAGIC: &[u8; 4] = b"LOXC";
+/// The version of the format of `.loxc` files, changing with the
+/// instructions, so old files are rejected instead of running wrong.
+pub const FORMAT_VERSION: u16 = 3;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_NUMBER: u8 = 1;
+const TAG_STRING: u8 = 2;
+const TAG_FUNCTION: u8 = 3;
+
+/// The bytes of a `.loxc` file holding `chunk`.
+///
+/// After `MAGIC` and `FORMAT_VERSION`, the chunk is its code, its lines as
+/// runs of the same line, its number of inline caches, its constants, each
+/// one tagged with its kind, and the spans of its eliminated statements.
+/// Functions hold their own chunk. Numbers are little-endian, whatever the
+/// machine, and lengths take four bytes.
+pub fn serialize(chunk: &Chunk) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    write_chunk(&mut bytes, chunk);
+    bytes
+}
+
+/// The chunk in the bytes of a `.loxc` file, verified so the VM can run it
+/// safely, see `verifier`.
+pub fn deserialize(bytes: &[u8]) -> anyhow::Result<Chunk> {
+    if !bytes.starts_with(MAGIC) {
+        bail!("Not a compiled Lox file.");
+    }
+    let mut reader = Reader {
+        bytes,
+        offset: MAGIC.len(),
+    };
+    let version = u16::from_le_bytes([reader.byte()?, reader.byte()?]);
+    if version != FORMAT_VERSION {
+        bail!(
+            "Compiled with version {} of the bytecode format, expected {}.",
+            version,
+            FORMAT_VERSION
+        );
+    }
+    let chunk = reader.chunk()?;
+    if reader.offset != bytes.len() {
+        bail!("Unexpected bytes at the end of the compiled file.");
+    }
+    verifier::verify(&chunk)?;
+    Ok(chunk)
+}
+
+fn write_length(bytes: &mut Vec<u8>, length: usize) {
+    let length = u32::try_from(length).expect("lengths fit in four bytes");
+    bytes.extend_from_slice(&length.to_le_bytes());
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    write_length(bytes, string.len());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk: &Chunk) {
+    write_length(bytes, chunk.code.len());
+    bytes.extend_from_slice(&chunk.code);
+
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &line in &chunk.lines {
+        match runs.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => runs.push((line, 1)),
+        }
+    }
+    write_length(bytes, runs.len());
+    for (line, count) in runs {
+        write_length(bytes, line);
+        write_length(bytes, count);
+    }
+
+    write_length(bytes, chunk.caches);
+    write_length(bytes, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Integer(integer) => {
+                bytes.push(TAG_INTEGER);
+                bytes.extend_from_slice(&integer.to_le_bytes());
+            }
+            Constant::Number(number) => {
+                bytes.push(TAG_NUMBER);
+                bytes.extend_from_slice(&number.to_bits().to_le_bytes());
+            }
+            Constant::String(string) => {
+                bytes.push(TAG_STRING);
+                write_string(bytes, string);
+            }
+            Constant::Function(function) => {
+                bytes.push(TAG_FUNCTION);
+                write_string(bytes, &function.name);
+                write_length(bytes, function.arity);
+                write_length(bytes, function.upvalues);
+                write_chunk(bytes, &function.chunk);
+            }
+        }
+    }
+
+    write_length(bytes, chunk.eliminated.len());
+    for span in &chunk.eliminated {
+        write_length(bytes, span.start);
+        write_length(bytes, span.end);
+        write_length(bytes, span.line);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.offset..)
+            .and_then(|rest| rest.get(..count))
+            .ok_or_else(|| anyhow!("The compiled file is truncated."))?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        let bytes = self.take(8)?.try_into().expect("eight bytes were taken");
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn length(&mut self) -> anyhow::Result<usize> {
+        let bytes = self.take(4)?.try_into().expect("four bytes were taken");
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let length = self.length()?;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| anyhow!("The compiled file has a string that isn't UTF-8."))
+    }
+
+    fn chunk(&mut self) -> anyhow::Result<Chunk> {
+        let mut chunk = Chunk::new();
+        let length = self.length()?;
+        chunk.code = self.take(length)?.to_vec();
+
+        for _ in 0..self.length()? {
+            let line = self.length()?;
+            let count = self.length()?;
+            // checked before growing, so a broken count can't take all the
+            // memory
+            if chunk.lines.len() + count > chunk.code.len() {
+                bail!("The compiled file has more lines than code.");
+            }
+            chunk.lines.resize(chunk.lines.len() + count, line);
+        }
+
+        chunk.caches = self.length()?;
+        for _ in 0..self.length()? {
+            let constant = match self.byte()? {
+                TAG_INTEGER => Constant::Integer(self.u64()? as i64),
+                TAG_NUMBER => Constant::Number(f64::from_bits(self.u64()?)),
+                TAG_STRING => Constant::String(self.string()?),
+                TAG_FUNCTION => Constant::Function(Rc::new(Function {
+                    name: self.string()?,
+                    arity: self.length()?,
+                    upvalues: self.length()?,
+                    chunk: self.chunk()?,
+                })),
+                tag => bail!("The compiled file has a constant of unknown kind {}.", tag),
+            };
+            chunk.push_constant(constant);
+        }
+
+        for _ in 0..self.length()? {
+            let span = Span::new(self.length()?, self.length()?, self.length()?);
+            chunk.eliminated.push(span);
+        }
+        Ok(chunk)
+    }
+}
+
+/// A listing of the instructions of `chunk`, one per line under a header
+/// naming it, in the style of clox:
+///
+/// ```text
+/// == script ==
+/// 0000    1 OP_CONSTANT         0 '1'
+/// 0002    | OP_PRINT
+/// ```
+///
+/// Each line gives the offset of the instruction, its source line or `|`
+/// when it's the same as the previous instruction's, the opcode and its
+/// operands, with the values of constants and the targets of jumps. The
+/// statements the compiler eliminated follow, then the functions among the
+/// constants, under their own headers.
+pub fn disassemble(chunk: &Chunk, name: &str) -> String {
+    let mut lines = vec![format!("== {} ==", name)];
+    let mut offset = 0;
+    while offset < chunk.len() {
+        let (line, next) = disassemble_instruction(chunk, offset);
+        lines.push(line);
+        offset = next;
+    }
+    for span in &chunk.eliminated {
+        lines.push(format!(
+            "---- {:4} eliminated, bytes {}..{}",
+            span.line, span.start, span.end
+        ));
+    }
+    for constant in &chunk.constants {
+        if let Constant::Function(function) = constant {
+            lines.push(disassemble(&function.chunk, &function.name));
+        }
+    }
+    lines.join("\n")
+}
+
+/// The listing of the instruction at `offset`, and the offset of the next
+/// one.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    let line = match chunk.lines.get(offset) {
+        Some(line) if offset > 0 && chunk.lines.get(offset - 1) == Some(line) => "   |".to_string(),
+        Some(line) => format!("{:4}", line),
+        None => "   ?".to_string(),
+    };
+    let prefix = format!("{:04} {} ", offset, line);
+
+    let byte = chunk.code[offset];
+    let op = match OpCode::from_byte(byte) {
+        Some(op) => op,
+        None => return (format!("{}Unknown opcode {}", prefix, byte), offset + 1),
+    };
+    let name = op_name(op);
+    let operand = |at: usize| chunk.code.get(offset + at).copied();
+    let (text, size) = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::SetProperty
+        | OpCode::Class
+        | OpCode::Method
+        | OpCode::SetGlobalPop
+        | OpCode::AddConstant
+        | OpCode::SubtractConstant
+        | OpCode::LessConstant => match operand(1) {
+            Some(index) => (constant(chunk, &name, index as usize), 2),
+            None => (format!("{:<16} ?", name), 1),
+        },
+        OpCode::GetProperty | OpCode::GetSuper => match (operand(1), operand(3)) {
+            (Some(index), Some(_)) => (constant(chunk, &name, index as usize), 4),
+            _ => (format!("{:<16} ?", name), 1),
+        },
+        OpCode::Invoke | OpCode::SuperInvoke => match (operand(1), operand(2), operand(4)) {
+            (Some(index), Some(count), Some(_)) => {
+                let value = chunk
+                    .constants
+                    .get(index as usize)
+                    .map_or("?".to_string(), Constant::to_string);
+                let text = format!("{:<16} ({} args) {:4} '{}'", name, count, index, value);
+                (text, 5)
+            }
+            _ => (format!("{:<16} ?", name), 1),
+        },
+        OpCode::ConstantLong
+        | OpCode::GetGlobalLong
+        | OpCode::DefineGlobalLong
+        | OpCode::SetGlobalLong => match (operand(1), operand(2), operand(3)) {
+            (Some(high), Some(middle), Some(low)) => {
+                let index = u32::from_be_bytes([0, high, middle, low]) as usize;
+                (constant(chunk, &name, index), 4)
+            }
+            _ => (format!("{:<16} ?", name), 1),
+        },
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call
+        | OpCode::SetLocalPop => match operand(1) {
+            Some(slot) => (format!("{:<16} {:4}", name, slot), 2),
+            None => (format!("{:<16} ?", name), 1),
+        },
+        OpCode::GetLocals => match (operand(1), operand(2)) {
+            (Some(first), Some(second)) => (format!("{:<16} {:4} {:4}", name, first, second), 3),
+            _ => (format!("{:<16} ?", name), 1),
+        },
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => match (operand(1), operand(2)) {
+            (Some(high), Some(low)) => {
+                let distance = u16::from_be_bytes([high, low]) as usize;
+                let target = match op {
+                    OpCode::Loop => (offset + 3).wrapping_sub(distance),
+                    _ => offset + 3 + distance,
+                };
+                (format!("{:<16} {:4} -> {}", name, offset, target), 3)
+            }
+            _ => (format!("{:<16} ?", name), 1),
+        },
+        OpCode::Closure | OpCode::ClosureLong => {
+            let (index, size) = match op {
+                OpCode::Closure => (operand(1).map(usize::from), 2),
+                _ => match (operand(1), operand(2), operand(3)) {
+                    (Some(high), Some(middle), Some(low)) => {
+                        (Some(u32::from_be_bytes([0, high, middle, low]) as usize), 4)
+                    }
+                    _ => (None, 4),
+                },
+            };
+            match index {
+                Some(index) => {
+                    let (text, upvalues) = closure(chunk, &name, index, offset + size);
+                    (text, size + upvalues)
+                }
+                None => (format!("{:<16} ?", name), 1),
+            }
+        }
+        _ => (name, 1),
+    };
+    (format!("{}{}", prefix, text), offset + size)
+}
+
+// the pairs of bytes describing the upvalues follow the constant, one line
+// each
+fn closure(chunk: &Chunk, name: &str, index: usize, mut offset: usize) -> (String, usize) {
+    let start = offset;
+    let mut text = constant(chunk, name, index);
+    if let Some(Constant::Function(function)) = chunk.constants.get(index) {
+        for _ in 0..function.upvalues {
+            let (is_local, index) = match (chunk.code.get(offset), chunk.code.get(offset + 1)) {
+                (Some(is_local), Some(index)) => (*is_local, *index),
+                _ => break,
+            };
+            let kind = if is_local == 1 { "local" } else { "upvalue" };
+            text.push_str(&format!(
+                "\n{:04}      |                     {} {}",
+                offset, kind, index
+            ));
+            offset += 2;
+        }
+    }
+    (text, offset - start)
+}
+
+fn constant(chunk: &Chunk, name: &str, index: usize) -> String {
+    let value = chunk
+        .constants
+        .get(index)
+        .map_or("?".to_string(), Constant::to_string);
+    format!("{:<16} {:4} '{}'", name, index, value)
+}
+
+// `GetLocal` is listed as `OP_GET_LOCAL`, as in clox
+fn op_name(op: OpCode) -> String {
+    let mut name = String::from("OP");
+    for c in format!("{:?}", op).chars() {
+        if c.is_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
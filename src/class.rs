@@ -0,0 +1,144 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::NodeId;
+use crate::function::{Arity, LoxFunction};
+use crate::gc;
+use crate::ordered_map::OrderedMap;
+use crate::value::Value;
+
+/// What a class declares, functions by name.
+#[derive(Default)]
+pub struct Members {
+    pub methods: HashMap<String, Rc<LoxFunction>>,
+    pub getters: HashMap<String, Rc<LoxFunction>>,
+    pub setters: HashMap<String, Rc<LoxFunction>>,
+    pub metaclass: Option<Rc<LoxClass>>,
+    pub declaration: Option<NodeId>,
+}
+
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    pub methods: HashMap<String, Rc<LoxFunction>>,
+    pub getters: HashMap<String, Rc<LoxFunction>>,
+    pub setters: HashMap<String, Rc<LoxFunction>>,
+    /// The class of the class, holding its class methods. Its superclass is
+    /// the metaclass of the superclass, so class methods are inherited too.
+    pub metaclass: Option<Rc<LoxClass>>,
+    /// The class statement, which its private members are limited to.
+    pub declaration: Option<NodeId>,
+}
+
+impl LoxClass {
+    pub fn new(name: &str, superclass: Option<Rc<LoxClass>>, members: Members) -> Rc<Self> {
+        let class = Rc::new(Self {
+            name: name.to_string(),
+            superclass,
+            methods: members.methods,
+            getters: members.getters,
+            setters: members.setters,
+            metaclass: members.metaclass,
+            declaration: members.declaration,
+        });
+        gc::track(&class);
+        class
+    }
+
+    /// Whether the class is the one declared by `declaration`, or inherits
+    /// from it.
+    pub fn is_declared_by(&self, declaration: NodeId) -> bool {
+        let mut class = Some(self);
+        while let Some(current) = class {
+            if current.declaration == Some(declaration) {
+                return true;
+            }
+            class = current.superclass.as_deref();
+        }
+        false
+    }
+
+    /// Find a method in the class or, failing that, in its superclasses.
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        self.find(name, |class| &class.methods)
+    }
+
+    pub fn find_getter(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        self.find(name, |class| &class.getters)
+    }
+
+    pub fn find_setter(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        self.find(name, |class| &class.setters)
+    }
+
+    fn find<F>(&self, name: &str, members: F) -> Option<Rc<LoxFunction>>
+    where
+        F: Fn(&LoxClass) -> &HashMap<String, Rc<LoxFunction>>,
+    {
+        match members(self).get(name) {
+            Some(function) => Some(function.clone()),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find(name, members)),
+        }
+    }
+
+    /// Calling a class takes the arguments of its initializer.
+    pub fn arity(&self) -> Arity {
+        self.find_method("init")
+            .map(|initializer| initializer.arity())
+            .unwrap_or(Arity::exactly(0))
+    }
+}
+
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    pub fields: OrderedMap<String, Value>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Rc<RefCell<Self>> {
+        let instance = Rc::new(RefCell::new(Self {
+            class,
+            fields: OrderedMap::new(),
+        }));
+        gc::track(&instance);
+        gc::allocate(mem::size_of::<Self>());
+        instance
+    }
+}
+
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+/// Look a class method up on a class, bound to it.
+pub fn get_class_method(class: &Rc<LoxClass>, name: &str) -> Option<Value> {
+    let method = class.metaclass.as_ref()?.find_method(name)?;
+    Some(Value::Function(method.bind(Value::Class(class.clone()))))
+}
+
+/// Look a property up on an instance: fields shadow methods, and methods
+/// come back bound to the instance. Getters are up to the caller, as they
+/// have to be called.
+pub fn get_property(instance: &Rc<RefCell<LoxInstance>>, name: &str) -> Option<Value> {
+    if let Some(value) = instance.borrow().fields.get(name) {
+        return Some(value.clone());
+    }
+
+    let method = instance.borrow().class.find_method(name)?;
+    let bound = method.bind(Value::Instance(instance.clone()));
+    Some(Value::Function(bound))
+}
@@ -0,0 +1,989 @@
+//! Compiling syntax trees to bytecode, the first half of a second backend
+//! next to the tree-walking interpreter.
+//!
+//! Globals are looked up by name, while locals live on the stack: the
+//! compiler keeps track of which slot holds which local, the way the
+//! resolver numbers the slots of environments. Locals that functions
+//! declared inside capture become upvalues of their closures, as in clox.
+//! Programs go through the resolver first, so the compiler only rejects
+//! what bytecode can't express yet.
+//!
+//! Common pairs of instructions are fused into superinstructions as they're
+//! emitted, saving a dispatch each time they run, unless a jump lands
+//! between them.
+//!
+//! Expressions made of literals are worked out while compiling, the way the
+//! interpreter would, along with the local constants they read: `60 * 60`
+//! is a single constant, and the branches a known condition rules out
+//! aren't compiled at all. What would fail, like `1 + nil`, is left to fail
+//! when it runs. Global constants can be declared again by later runs, so
+//! they're looked up as usual.
+//!
+//! Statements that can never run aren't compiled either: those after a
+//! `return`, `break` or `continue` in the same block, and the branches ruled
+//! out. Their spans are kept in `Chunk::eliminated`.
+
+use std::convert::TryFrom;
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::chunk::{self, Chunk, Constant, Function, OpCode};
+use crate::interpreter;
+use crate::lexer::{Lexer, Span, SyntaxError};
+use crate::parser::Parser;
+use crate::resolver;
+use crate::value::{self, Value};
+
+// slots are a byte
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+/// The revision of the code the compiler generates, bumped with every
+/// change to it so `cache` compiles chunks made before again, even when
+/// the format of `.loxc` files stays the same.
+pub const REVISION: u32 = 1;
+
+/// How programs are compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Options {
+    /// Fuse common pairs of instructions into superinstructions.
+    pub superinstructions: bool,
+    /// Work out expressions made of constants, and drop the branches they
+    /// rule out.
+    pub constant_folding: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            superinstructions: true,
+            constant_folding: true,
+        }
+    }
+}
+
+/// Lex, parse, resolve and compile a program.
+pub fn compile(source: &str) -> anyhow::Result<Chunk> {
+    compile_with(source, Options::default())
+}
+
+/// Lex, parse, resolve and compile a program with other options than the
+/// defaults.
+pub fn compile_with(source: &str, options: Options) -> anyhow::Result<Chunk> {
+    let program = Parser::new(Lexer::new(source.to_string()))?.parse()?;
+    resolver::resolve(&program).check()?;
+    Ok(compile_program(&program, options)?)
+}
+
+/// Compile an already resolved program.
+pub fn compile_program(program: &[Stmt], options: Options) -> Result<Chunk, SyntaxError> {
+    let mut compiler = Compiler {
+        superinstructions: options.superinstructions,
+        constant_folding: options.constant_folding,
+        ..Compiler::default()
+    };
+    compiler.statements(program)?;
+    let line = program.last().map_or(1, |statement| statement.span.line);
+    compiler.emit(OpCode::Nil, line);
+    compiler.emit(OpCode::Return, line);
+    Ok(compiler.chunk)
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // whether a closure captures it, so it has to be moved off the stack
+    // when it goes out of scope
+    captured: bool,
+    // the value of a constant known when compiling
+    value: Option<Value>,
+}
+
+struct Upvalue {
+    // the slot of a local of the enclosing function, or the index of one of
+    // its upvalues
+    index: usize,
+    is_local: bool,
+}
+
+// the jumps of `break` and `continue` statements, patched at the end of the
+// loop they're in
+struct Loop {
+    // locals declared outside of the loop
+    locals: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+#[derive(Default, PartialEq, Eq, Clone, Copy)]
+enum FunctionKind {
+    #[default]
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+// compiles the top-level script or a function, nested in the compiler of
+// the function around it
+#[derive(Default)]
+struct Compiler {
+    enclosing: Option<Box<Compiler>>,
+    kind: FunctionKind,
+    chunk: Chunk,
+    // innermost last, the index being the stack slot
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    // the local whose initializer is being compiled, which has no slot yet
+    initializing: Option<String>,
+    superinstructions: bool,
+    constant_folding: bool,
+    // where the last instruction starts, which the next one may be fused
+    // into
+    last: Option<usize>,
+    // the furthest a jump lands, where instructions can't be fused
+    jump_target: usize,
+    // whether the statement just compiled always jumps away, so the ones
+    // after it can't run
+    terminated: bool,
+}
+
+impl Compiler {
+    // compile the statements of a block, leaving out those after one always
+    // jumping away
+    fn statements(&mut self, statements: &[Stmt]) -> Result<(), SyntaxError> {
+        for (index, statement) in statements.iter().enumerate() {
+            self.statement(statement)?;
+            if self.terminated {
+                if let (Some(first), Some(last)) = (statements.get(index + 1), statements.last()) {
+                    self.chunk.eliminated.push(first.span.to(last.span));
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), SyntaxError> {
+        let line = stmt.span.line;
+        self.terminated = false;
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Pop, line);
+            }
+            StmtKind::Print(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Print, line);
+            }
+            StmtKind::Var { name, initializer } => {
+                match initializer {
+                    Some(initializer) => {
+                        if self.scope_depth > 0 {
+                            self.initializing = Some(name.name.clone());
+                        }
+                        let compiled = self.expression(initializer);
+                        self.initializing = None;
+                        compiled?;
+                    }
+                    None => self.emit(OpCode::Nil, line),
+                }
+                self.declare(name)?;
+            }
+            StmtKind::Function(declaration) => {
+                let name = &declaration.name;
+                // declared first, so the function can call itself
+                if self.scope_depth > 0 {
+                    self.add_local(name)?;
+                }
+                self.function(declaration, FunctionKind::Function)?;
+                if self.scope_depth == 0 {
+                    self.emit_global(OpCode::DefineGlobal, name, line)?;
+                }
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.emit_implicit_return_value(line),
+                }
+                self.emit(OpCode::Return, line);
+                self.terminated = true;
+            }
+            StmtKind::Class(class) => self.class(class, stmt.span)?,
+            StmtKind::Const { name, initializer } => {
+                self.expression(initializer)?;
+                self.declare(name)?;
+                if self.scope_depth > 0 {
+                    let value = self.fold(initializer);
+                    if let Some(local) = self.locals.last_mut() {
+                        local.value = value;
+                    }
+                }
+            }
+            StmtKind::Block(statements) => {
+                self.scope_depth += 1;
+                self.statements(statements)?;
+                self.end_scope(line);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                // only the branch a known condition picks is compiled
+                if let Some(condition) = self.fold(condition) {
+                    let (taken, eliminated) = match condition.is_truthy() {
+                        true => (Some(&**then_branch), else_branch.as_deref()),
+                        false => (else_branch.as_deref(), Some(&**then_branch)),
+                    };
+                    if let Some(eliminated) = eliminated {
+                        self.chunk.eliminated.push(eliminated.span);
+                    }
+                    if let Some(taken) = taken {
+                        self.statement(taken)?;
+                    }
+                    return Ok(());
+                }
+                self.expression(condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse, line);
+                self.emit(OpCode::Pop, line);
+                self.statement(then_branch)?;
+                let then_terminated = self.terminated;
+                // nothing falls through a branch jumping away
+                let else_jump = match then_terminated {
+                    true => None,
+                    false => Some(self.emit_jump(OpCode::Jump, line)),
+                };
+                self.patch_jump(then_jump, stmt.span)?;
+                self.emit(OpCode::Pop, line);
+                self.terminated = false;
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.terminated &= then_terminated;
+                if let Some(else_jump) = else_jump {
+                    self.patch_jump(else_jump, stmt.span)?;
+                }
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+            } => self.while_loop(condition, body, increment.as_ref(), stmt.span)?,
+            StmtKind::Break | StmtKind::Continue => {
+                let locals = match self.loops.last() {
+                    Some(innermost) => innermost.locals,
+                    None => {
+                        return Err(SyntaxError::new("Can't jump outside of a loop.", stmt.span))
+                    }
+                };
+                // the locals of the loop body stay known to the compiler
+                for local in (locals..self.locals.len()).rev() {
+                    self.discard(local, line);
+                }
+                let jump = self.emit_jump(OpCode::Jump, line);
+                if let Some(innermost) = self.loops.last_mut() {
+                    match stmt.kind {
+                        StmtKind::Break => innermost.breaks.push(jump),
+                        _ => innermost.continues.push(jump),
+                    }
+                }
+                self.terminated = true;
+            }
+            StmtKind::ForIn { .. } => return Err(unsupported("for-in loops", stmt.span)),
+            StmtKind::Yield(_) => return Err(unsupported("generators", stmt.span)),
+            StmtKind::Defer(_) => return Err(unsupported("defer statements", stmt.span)),
+            StmtKind::Throw(_) | StmtKind::Try { .. } => {
+                return Err(unsupported("exceptions", stmt.span))
+            }
+            StmtKind::Import(_) => return Err(unsupported("imports", stmt.span)),
+        }
+        Ok(())
+    }
+
+    fn while_loop(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+        span: Span,
+    ) -> Result<(), SyntaxError> {
+        let line = span.line;
+        // a loop whose condition is known is never entered, or never left
+        // but by breaking out
+        let known = self.fold(condition);
+        if known
+            .as_ref()
+            .is_some_and(|condition| !condition.is_truthy())
+        {
+            self.chunk.eliminated.push(body.span);
+            return Ok(());
+        }
+        let start = self.chunk.len();
+        self.jump_target = start;
+        let exit = match known {
+            Some(_) => None,
+            None => {
+                self.expression(condition)?;
+                let exit = self.emit_jump(OpCode::JumpIfFalse, line);
+                self.emit(OpCode::Pop, line);
+                Some(exit)
+            }
+        };
+
+        self.loops.push(Loop {
+            locals: self.locals.len(),
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        let body = self.statement(body);
+        let innermost = self.loops.pop().expect("the loop was just pushed");
+        body?;
+
+        for jump in innermost.continues {
+            self.patch_jump(jump, span)?;
+        }
+        if let Some(increment) = increment {
+            self.expression(increment)?;
+            self.emit(OpCode::Pop, line);
+        }
+        self.emit_loop(start, span)?;
+
+        if let Some(exit) = exit {
+            self.patch_jump(exit, span)?;
+            self.emit(OpCode::Pop, line);
+        }
+        // the condition is already popped when breaking out
+        for jump in innermost.breaks {
+            self.patch_jump(jump, span)?;
+        }
+        self.terminated = false;
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), SyntaxError> {
+        let line = expr.span.line;
+        if !matches!(expr.kind, ExprKind::Literal(_)) {
+            if let Some(value) = self.fold(expr) {
+                return self.emit_value(&value, expr.span);
+            }
+        }
+        match &expr.kind {
+            ExprKind::Literal(literal) => match literal {
+                Literal::Nil => self.emit(OpCode::Nil, line),
+                Literal::Bool(true) => self.emit(OpCode::True, line),
+                Literal::Bool(false) => self.emit(OpCode::False, line),
+                Literal::Integer(integer) => {
+                    self.emit_constant(Constant::Integer(*integer), expr.span)?
+                }
+                Literal::Number(number) => {
+                    self.emit_constant(Constant::Number(*number), expr.span)?
+                }
+                Literal::String(string) => {
+                    self.emit_constant(Constant::String(string.clone()), expr.span)?
+                }
+            },
+            ExprKind::Grouping(inner) => self.expression(inner)?,
+            ExprKind::Unary { op, right } => {
+                self.expression(right)?;
+                let op = match op {
+                    UnaryOp::Negate => OpCode::Negate,
+                    UnaryOp::Not => OpCode::Not,
+                    UnaryOp::BitNot => OpCode::BitNot,
+                };
+                self.emit(op, line);
+            }
+            ExprKind::Binary { left, op, right } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.emit(binary(*op), line);
+            }
+            ExprKind::Logical { left, op, right } => {
+                if let Some(value) = self.fold(left) {
+                    // the known left operand is the result when it decides,
+                    // the right one otherwise
+                    let decides = match op {
+                        LogicalOp::And => !value.is_truthy(),
+                        LogicalOp::Or => value.is_truthy(),
+                    };
+                    return if decides {
+                        self.emit_value(&value, left.span)
+                    } else {
+                        self.expression(right)
+                    };
+                }
+                self.expression(left)?;
+                // the left operand is the result when it decides
+                let short_circuit = match op {
+                    LogicalOp::And => self.emit_jump(OpCode::JumpIfFalse, line),
+                    LogicalOp::Or => {
+                        let next = self.emit_jump(OpCode::JumpIfFalse, line);
+                        let end = self.emit_jump(OpCode::Jump, line);
+                        self.patch_jump(next, expr.span)?;
+                        end
+                    }
+                };
+                self.emit(OpCode::Pop, line);
+                self.expression(right)?;
+                self.patch_jump(short_circuit, expr.span)?;
+            }
+            ExprKind::Variable(name) => self.variable(name, line)?,
+            ExprKind::This => self.variable(&keyword("this", expr.span), line)?,
+            ExprKind::Assign { name, value } => {
+                self.expression(value)?;
+                if self.initializing.as_ref() == Some(&name.name) {
+                    // the value is the result, and the definition
+                    // overwrites the local anyway
+                } else if let Some(slot) = self.local(&name.name) {
+                    self.emit(OpCode::SetLocal, line);
+                    self.emit_byte(slot as u8, line);
+                } else if let Some(index) = self.upvalue(name)? {
+                    self.emit(OpCode::SetUpvalue, line);
+                    self.emit_byte(index as u8, line);
+                } else {
+                    self.emit_global(OpCode::SetGlobal, name, line)?;
+                }
+            }
+            // methods are invoked without binding them first
+            ExprKind::Call { callee, arguments } => match &callee.kind {
+                ExprKind::Get { object, name } => {
+                    private(name)?;
+                    self.expression(object)?;
+                    let count = self.arguments(arguments, expr.span)?;
+                    self.emit_with_constant(OpCode::Invoke, identifier(name), name.span, line)?;
+                    self.emit_byte(count, line);
+                    self.emit_cache(name.span)?;
+                }
+                ExprKind::Super { method } => {
+                    private(method)?;
+                    self.variable(&keyword("this", callee.span), line)?;
+                    let count = self.arguments(arguments, expr.span)?;
+                    self.variable(&keyword("super", callee.span), line)?;
+                    let name = identifier(method);
+                    self.emit_with_constant(OpCode::SuperInvoke, name, method.span, line)?;
+                    self.emit_byte(count, line);
+                    self.emit_cache(method.span)?;
+                }
+                _ => {
+                    self.expression(callee)?;
+                    let count = self.arguments(arguments, expr.span)?;
+                    self.emit(OpCode::Call, line);
+                    self.emit_byte(count, line);
+                }
+            },
+            ExprKind::Get { object, name } => {
+                private(name)?;
+                self.expression(object)?;
+                self.emit_with_constant(OpCode::GetProperty, identifier(name), name.span, line)?;
+                self.emit_cache(name.span)?;
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                private(name)?;
+                self.expression(object)?;
+                self.expression(value)?;
+                self.emit_with_constant(OpCode::SetProperty, identifier(name), name.span, line)?;
+            }
+            ExprKind::Super { method } => {
+                private(method)?;
+                self.variable(&keyword("this", expr.span), line)?;
+                self.variable(&keyword("super", expr.span), line)?;
+                let name = identifier(method);
+                self.emit_with_constant(OpCode::GetSuper, name, method.span, line)?;
+                self.emit_cache(method.span)?;
+            }
+            ExprKind::List(_) | ExprKind::Spread(_) => return Err(unsupported("lists", expr.span)),
+            ExprKind::Map(_) => return Err(unsupported("maps", expr.span)),
+            ExprKind::Index { .. } | ExprKind::Slice { .. } | ExprKind::SetIndex { .. } => {
+                return Err(unsupported("indexing", expr.span))
+            }
+            ExprKind::Await(_) => return Err(unsupported("async functions", expr.span)),
+        }
+        Ok(())
+    }
+
+    fn variable(&mut self, name: &Identifier, line: usize) -> Result<(), SyntaxError> {
+        if let Some(slot) = self.local(&name.name) {
+            self.emit(OpCode::GetLocal, line);
+            self.emit_byte(slot as u8, line);
+        } else if let Some(index) = self.upvalue(name)? {
+            self.emit(OpCode::GetUpvalue, line);
+            self.emit_byte(index as u8, line);
+        } else {
+            self.emit_global(OpCode::GetGlobal, name, line)?;
+        }
+        Ok(())
+    }
+
+    // the value of an expression known without running it, as the
+    // interpreter would work it out: `None` for the rest, and for what would
+    // fail, so it fails when it runs
+    fn fold(&self, expr: &Expr) -> Option<Value> {
+        if !self.constant_folding {
+            return None;
+        }
+        match &expr.kind {
+            ExprKind::Literal(literal) => Some(match literal {
+                Literal::Nil => Value::Nil,
+                Literal::Bool(b) => Value::Bool(*b),
+                Literal::Integer(integer) => Value::Integer(*integer),
+                Literal::Number(number) => Value::Number(*number),
+                Literal::String(string) => Value::from(string.as_str()),
+            }),
+            ExprKind::Grouping(inner) => self.fold(inner),
+            ExprKind::Unary { op, right } => match (op, self.fold(right)?) {
+                (UnaryOp::Not, right) => Some(Value::Bool(!right.is_truthy())),
+                // only -i64::MIN doesn't fit
+                (UnaryOp::Negate, Value::Integer(integer)) => Some(
+                    integer
+                        .checked_neg()
+                        .map_or(Value::Number(-(integer as f64)), Value::Integer),
+                ),
+                (UnaryOp::Negate, Value::Number(number)) => Some(Value::Number(-number)),
+                (UnaryOp::BitNot, Value::Integer(integer)) => Some(Value::Integer(!integer)),
+                (UnaryOp::BitNot, Value::Number(number)) => {
+                    value::exact_integer(number).map(|integer| Value::Integer(!integer))
+                }
+                _ => None,
+            },
+            ExprKind::Binary { left, op, right } => {
+                let (left, right) = (self.fold(left)?, self.fold(right)?);
+                match (op, &left, &right) {
+                    (BinaryOp::Equal, _, _) => Some(Value::Bool(left == right)),
+                    (BinaryOp::NotEqual, _, _) => Some(Value::Bool(left != right)),
+                    (BinaryOp::Add, Value::String(left), Value::String(right)) => {
+                        Some(Value::from(format!("{}{}", left, right).as_str()))
+                    }
+                    (BinaryOp::Range, _, _) => None,
+                    _ => interpreter::arithmetic(*op, &left, &right, expr.span).ok(),
+                }
+            }
+            ExprKind::Logical { left, op, right } => {
+                let left = self.fold(left)?;
+                match (op, left.is_truthy()) {
+                    (LogicalOp::And, false) | (LogicalOp::Or, true) => Some(left),
+                    _ => self.fold(right),
+                }
+            }
+            ExprKind::Variable(name) => {
+                let slot = self.local(&name.name)?;
+                self.locals[slot].value.clone()
+            }
+            _ => None,
+        }
+    }
+
+    // compile the arguments of a call, returning how many there are
+    fn arguments(&mut self, arguments: &[Expr], span: Span) -> Result<u8, SyntaxError> {
+        let count = u8::try_from(arguments.len())
+            .map_err(|_| SyntaxError::new("Can't have more than 255 arguments.", span))?;
+        for argument in arguments {
+            self.expression(argument)?;
+        }
+        Ok(count)
+    }
+
+    fn class(&mut self, class: &ClassDecl, span: Span) -> Result<(), SyntaxError> {
+        if !class.getters.is_empty() {
+            return Err(unsupported("getters", span));
+        }
+        if !class.setters.is_empty() {
+            return Err(unsupported("setters", span));
+        }
+        if !class.class_methods.is_empty() {
+            return Err(unsupported("class methods", span));
+        }
+        let name = &class.name;
+        let line = span.line;
+        self.emit_with_constant(OpCode::Class, identifier(name), name.span, line)?;
+        self.declare(name)?;
+
+        // methods of subclasses capture the superclass as `super`, a local
+        // of a scope around them
+        if let Some(superclass) = &class.superclass {
+            self.expression(superclass)?;
+            self.scope_depth += 1;
+            self.add_local(&keyword("super", superclass.span))?;
+            self.variable(name, line)?;
+            self.emit(OpCode::Inherit, line);
+        }
+
+        self.variable(name, line)?;
+        for method in &class.methods {
+            private(&method.name)?;
+            let kind = match method.name.name.as_str() {
+                "init" => FunctionKind::Initializer,
+                _ => FunctionKind::Method,
+            };
+            self.function(method, kind)?;
+            let method_name = identifier(&method.name);
+            self.emit_with_constant(OpCode::Method, method_name, method.name.span, line)?;
+        }
+        self.emit(OpCode::Pop, line);
+
+        if class.superclass.is_some() {
+            self.end_scope(line);
+        }
+        Ok(())
+    }
+
+    // compile a function in a compiler of its own, leaving a closure of it
+    // on the stack
+    fn function(
+        &mut self,
+        declaration: &FunctionDecl,
+        kind: FunctionKind,
+    ) -> Result<(), SyntaxError> {
+        let span = declaration.span;
+        if !declaration.defaults.is_empty() {
+            return Err(unsupported("default parameters", span));
+        }
+        if declaration.rest.is_some() {
+            return Err(unsupported("rest parameters", span));
+        }
+        if declaration.is_async {
+            return Err(unsupported("async functions", span));
+        }
+
+        let enclosing = mem::take(self);
+        self.superinstructions = enclosing.superinstructions;
+        self.constant_folding = enclosing.constant_folding;
+        self.enclosing = Some(Box::new(enclosing));
+        self.kind = kind;
+        let body = self.function_body(declaration);
+        let enclosing = self
+            .enclosing
+            .take()
+            .expect("the enclosing compiler was just set");
+        let compiled = mem::replace(self, *enclosing);
+        body?;
+
+        let function = Function {
+            name: declaration.name.name.clone(),
+            arity: declaration.params.len(),
+            upvalues: compiled.upvalues.len(),
+            chunk: compiled.chunk,
+        };
+        let constant = Constant::Function(Rc::new(function));
+        self.emit_with_constant(OpCode::Closure, constant, span, span.line)?;
+        for upvalue in &compiled.upvalues {
+            self.emit_byte(upvalue.is_local as u8, span.line);
+            self.emit_byte(upvalue.index as u8, span.line);
+        }
+        Ok(())
+    }
+
+    fn function_body(&mut self, declaration: &FunctionDecl) -> Result<(), SyntaxError> {
+        self.scope_depth = 1;
+        // the first slot holds the function being called, or the instance
+        // methods are called on
+        let name = match self.kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => "",
+        };
+        self.locals.push(Local {
+            name: name.to_string(),
+            depth: 0,
+            captured: false,
+            value: None,
+        });
+        for param in &declaration.params {
+            self.add_local(param)?;
+        }
+        self.statements(&declaration.body)?;
+        if self.terminated {
+            return Ok(());
+        }
+        let line = declaration
+            .body
+            .last()
+            .map_or(declaration.span.line, |statement| statement.span.line);
+        self.emit_implicit_return_value(line);
+        self.emit(OpCode::Return, line);
+        Ok(())
+    }
+
+    // initializers always hand back the instance
+    fn emit_implicit_return_value(&mut self, line: usize) {
+        if self.kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal, line);
+            self.emit_byte(0, line);
+        } else {
+            self.emit(OpCode::Nil, line);
+        }
+    }
+
+    // the value of the declaration is on top of the stack
+    fn declare(&mut self, name: &Identifier) -> Result<(), SyntaxError> {
+        if self.scope_depth == 0 {
+            return self.emit_global(OpCode::DefineGlobal, name, name.span.line);
+        }
+        self.add_local(name)
+    }
+
+    fn add_local(&mut self, name: &Identifier) -> Result<(), SyntaxError> {
+        if self.locals.len() == MAX_LOCALS {
+            return Err(SyntaxError::new(
+                "Too many local variables in function.",
+                name.span,
+            ));
+        }
+        self.locals.push(Local {
+            name: name.name.clone(),
+            depth: self.scope_depth,
+            captured: false,
+            value: None,
+        });
+        Ok(())
+    }
+
+    fn local(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|local| local.name == name)
+    }
+
+    // the index of the upvalue of the function being compiled capturing a
+    // local of an enclosing function, adding it if it's new
+    fn upvalue(&mut self, name: &Identifier) -> Result<Option<usize>, SyntaxError> {
+        let enclosing = match &mut self.enclosing {
+            Some(enclosing) => enclosing,
+            None => return Ok(None),
+        };
+        if let Some(slot) = enclosing.local(&name.name) {
+            enclosing.locals[slot].captured = true;
+            return self.add_upvalue(slot, true, name.span).map(Some);
+        }
+        match enclosing.upvalue(name)? {
+            Some(index) => self.add_upvalue(index, false, name.span).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        index: usize,
+        is_local: bool,
+        span: Span,
+    ) -> Result<usize, SyntaxError> {
+        let existing = self
+            .upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local);
+        if let Some(existing) = existing {
+            return Ok(existing);
+        }
+        if self.upvalues.len() == MAX_UPVALUES {
+            return Err(SyntaxError::new(
+                "Too many closure variables in function.",
+                span,
+            ));
+        }
+        self.upvalues.push(Upvalue { index, is_local });
+        Ok(self.upvalues.len() - 1)
+    }
+
+    fn end_scope(&mut self, line: usize) {
+        self.scope_depth -= 1;
+        while self
+            .locals
+            .last()
+            .is_some_and(|local| local.depth > self.scope_depth)
+        {
+            // after jumping away, nothing is left on the stack to discard
+            if !self.terminated {
+                self.discard(self.locals.len() - 1, line);
+            }
+            self.locals.pop();
+        }
+    }
+
+    // emit what takes the local in `slot` off the stack
+    fn discard(&mut self, slot: usize, line: usize) {
+        if self.locals[slot].captured {
+            self.emit(OpCode::CloseUpvalue, line);
+        } else {
+            self.emit(OpCode::Pop, line);
+        }
+    }
+
+    fn emit(&mut self, op: OpCode, line: usize) {
+        if let Some(last) = self.fusable() {
+            if let Some(fused) =
+                OpCode::from_byte(self.chunk.code[last]).and_then(|last| last.fuse(op))
+            {
+                self.chunk.code[last] = fused.into();
+                self.chunk.lines[last] = line;
+                // the operands of `op` follow
+                return;
+            }
+        }
+        self.last = Some(self.chunk.len());
+        self.chunk.write(op, line);
+    }
+
+    // the last instruction, when the next one can be fused into it: it
+    // takes one operand, and no jump lands after it
+    fn fusable(&self) -> Option<usize> {
+        let last = self.last.filter(|_| self.superinstructions)?;
+        let end = self.chunk.len();
+        (end == last + 2 && self.jump_target < end).then_some(last)
+    }
+
+    fn emit_byte(&mut self, byte: u8, line: usize) {
+        self.chunk.write(byte, line);
+    }
+
+    fn emit_constant(&mut self, constant: Constant, span: Span) -> Result<(), SyntaxError> {
+        self.emit_with_constant(OpCode::Constant, constant, span, span.line)
+    }
+
+    // a value `fold` worked out
+    fn emit_value(&mut self, value: &Value, span: Span) -> Result<(), SyntaxError> {
+        match value {
+            Value::Nil => self.emit(OpCode::Nil, span.line),
+            Value::Bool(true) => self.emit(OpCode::True, span.line),
+            Value::Bool(false) => self.emit(OpCode::False, span.line),
+            Value::Integer(integer) => self.emit_constant(Constant::Integer(*integer), span)?,
+            Value::Number(number) => self.emit_constant(Constant::Number(*number), span)?,
+            Value::String(string) => {
+                self.emit_constant(Constant::String(string.to_string()), span)?
+            }
+            _ => unreachable!("only literals are folded"),
+        }
+        Ok(())
+    }
+
+    fn emit_global(
+        &mut self,
+        op: OpCode,
+        name: &Identifier,
+        line: usize,
+    ) -> Result<(), SyntaxError> {
+        self.emit_with_constant(op, identifier(name), name.span, line)
+    }
+
+    // emit an instruction taking a constant, in its long form when the
+    // index doesn't fit in a byte
+    fn emit_with_constant(
+        &mut self,
+        op: OpCode,
+        constant: Constant,
+        span: Span,
+        line: usize,
+    ) -> Result<(), SyntaxError> {
+        let index = self.chunk.add_constant(constant);
+        if index >= chunk::MAX_CONSTANTS {
+            return Err(SyntaxError::new("Too many constants in one chunk.", span));
+        }
+        match u8::try_from(index) {
+            Ok(index) => {
+                self.emit(op, line);
+                self.emit_byte(index, line);
+            }
+            Err(_) => {
+                let long = op
+                    .long()
+                    .ok_or_else(|| SyntaxError::new("Too many constants in one chunk.", span))?;
+                self.emit(long, line);
+                for byte in &(index as u32).to_be_bytes()[1..] {
+                    self.emit_byte(*byte, line);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // give the instruction just emitted an inline cache of its own
+    fn emit_cache(&mut self, span: Span) -> Result<(), SyntaxError> {
+        let index = u16::try_from(self.chunk.caches)
+            .map_err(|_| SyntaxError::new("Too many property accesses in one function.", span))?;
+        self.chunk.caches += 1;
+        for byte in index.to_be_bytes() {
+            self.emit_byte(byte, span.line);
+        }
+        Ok(())
+    }
+
+    // emit a jump to patch once the code it jumps over is compiled,
+    // returning where its operand is
+    fn emit_jump(&mut self, op: OpCode, line: usize) -> usize {
+        self.emit(op, line);
+        self.emit_byte(0xff, line);
+        self.emit_byte(0xff, line);
+        self.chunk.len() - 2
+    }
+
+    fn patch_jump(&mut self, operand: usize, span: Span) -> Result<(), SyntaxError> {
+        let distance = self.chunk.len() - operand - 2;
+        if distance > u16::MAX as usize {
+            return Err(SyntaxError::new("Too much code to jump over.", span));
+        }
+        self.chunk.code[operand..operand + 2].copy_from_slice(&(distance as u16).to_be_bytes());
+        self.jump_target = self.chunk.len();
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, start: usize, span: Span) -> Result<(), SyntaxError> {
+        self.emit(OpCode::Loop, span.line);
+        let distance = self.chunk.len() + 2 - start;
+        if distance > u16::MAX as usize {
+            return Err(SyntaxError::new("Loop body too large.", span));
+        }
+        for byte in (distance as u16).to_be_bytes() {
+            self.emit_byte(byte, span.line);
+        }
+        Ok(())
+    }
+}
+
+fn binary(op: BinaryOp) -> OpCode {
+    match op {
+        BinaryOp::Add => OpCode::Add,
+        BinaryOp::Subtract => OpCode::Subtract,
+        BinaryOp::Multiply => OpCode::Multiply,
+        BinaryOp::Divide => OpCode::Divide,
+        BinaryOp::FloorDivide => OpCode::FloorDivide,
+        BinaryOp::Modulo => OpCode::Modulo,
+        BinaryOp::Range => OpCode::Range,
+        BinaryOp::BitAnd => OpCode::BitAnd,
+        BinaryOp::BitOr => OpCode::BitOr,
+        BinaryOp::BitXor => OpCode::BitXor,
+        BinaryOp::ShiftLeft => OpCode::ShiftLeft,
+        BinaryOp::ShiftRight => OpCode::ShiftRight,
+        BinaryOp::Equal => OpCode::Equal,
+        BinaryOp::NotEqual => OpCode::NotEqual,
+        BinaryOp::Greater => OpCode::Greater,
+        BinaryOp::GreaterEqual => OpCode::GreaterEqual,
+        BinaryOp::Less => OpCode::Less,
+        BinaryOp::LessEqual => OpCode::LessEqual,
+    }
+}
+
+fn identifier(name: &Identifier) -> Constant {
+    Constant::String(name.name.clone())
+}
+
+// `this` and `super` are looked up like variables
+fn keyword(name: &str, span: Span) -> Identifier {
+    Identifier {
+        name: name.to_string(),
+        span,
+    }
+}
+
+// the VM doesn't check who accesses private members
+fn private(name: &Identifier) -> Result<(), SyntaxError> {
+    if name.name.starts_with('_') {
+        return Err(unsupported("private members", name.span));
+    }
+    Ok(())
+}
+
+fn unsupported(what: &str, span: Span) -> SyntaxError {
+    SyntaxError::new(format!("Can't compile {} to bytecode yet.", what), span)
+}
@@ -0,0 +1,69 @@
+//! Showing errors along with the source they point at.
+//!
+//! Under the message comes the line the error happened on, with carets
+//! under the offending span, then the stack trace of runtime errors:
+//!
+//! ```text
+//! Operands must be numbers.
+//!  2 | print count < nil;
+//!    |       ^^^^^^^^^^^
+//! [line 2] in script
+//! ```
+
+use crate::interpreter::RuntimeError;
+use crate::lexer::{Span, SyntaxError};
+
+/// Render `error`, raised running `source`. Errors that aren't syntax or
+/// runtime errors are shown as they are.
+pub fn render(error: &anyhow::Error, source: &str) -> String {
+    let mut lines = Vec::new();
+    if let Some(error) = error.downcast_ref::<RuntimeError>() {
+        lines.push(error.message.clone());
+        lines.extend(snippet(source, error.span));
+        lines.extend(error.trace_lines());
+    } else if let Some(error) = error.downcast_ref::<SyntaxError>() {
+        lines.push(error.to_string());
+        lines.extend(snippet(source, error.span));
+    } else {
+        lines.push(error.to_string());
+    }
+    lines.join("\n")
+}
+
+/// The line `span` starts on, with carets under the span, or nothing when
+/// the span doesn't point into `source`.
+pub fn snippet(source: &str, span: Span) -> Vec<String> {
+    // errors raised in imported modules point into other files, and some
+    // have no place at all
+    if span.line == 0 || span.start > source.len() || !source.is_char_boundary(span.start) {
+        return Vec::new();
+    }
+    let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    if 1 + source[..line_start].matches('\n').count() != span.line {
+        return Vec::new();
+    }
+    let line_end = source[span.start..]
+        .find('\n')
+        .map_or(source.len(), |i| span.start + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+
+    // only the first line of spans going over several, and at least a caret
+    let end = span.end.min(line_start + line.len()).max(span.start);
+    let carets = source[span.start..end].chars().count().max(1);
+    // tabs are kept so the carets line up
+    let indent = source[line_start..span.start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+
+    let number = span.line.to_string();
+    vec![
+        format!(" {} | {}", number, line),
+        format!(
+            " {} | {}{}",
+            " ".repeat(number.len()),
+            indent,
+            "^".repeat(carets)
+        ),
+    ]
+}
@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::gc;
+use crate::value::Value;
+
+pub type EnvRef = Rc<RefCell<Environment>>;
+
+/// A scope of variables, linked to the scope enclosing it.
+///
+/// Only the global scope looks variables up by name. Locals live in slots,
+/// numbered by the resolver in the order their scope declares them, which is
+/// also the order they get defined in while running.
+#[derive(Debug, Default)]
+pub struct Environment {
+    globals: HashMap<String, Value>,
+    // globals declared with `const`
+    constants: HashSet<String>,
+    slots: Vec<Value>,
+    // the names of the slots, and whether they're constants, for `bindings`
+    names: Vec<(String, bool)>,
+    enclosing: Option<EnvRef>,
+}
+
+/// A variable and its value, as debuggers and the REPL show them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub value: Value,
+    pub constant: bool,
+}
+
+impl Environment {
+    pub fn new() -> EnvRef {
+        let environment = Rc::new(RefCell::new(Self::default()));
+        gc::track(&environment);
+        environment
+    }
+
+    pub fn with_enclosing(enclosing: EnvRef) -> EnvRef {
+        let environment = Rc::new(RefCell::new(Self {
+            globals: HashMap::new(),
+            constants: HashSet::new(),
+            slots: Vec::new(),
+            names: Vec::new(),
+            enclosing: Some(enclosing),
+        }));
+        gc::track(&environment);
+        environment
+    }
+
+    pub fn enclosing(&self) -> Option<EnvRef> {
+        self.enclosing.clone()
+    }
+
+    /// Define a variable: by name in the global scope, in the next slot
+    /// anywhere else.
+    pub fn define(&mut self, name: &str, value: Value) {
+        if self.enclosing.is_none() {
+            self.constants.remove(name);
+            self.globals.insert(name.to_string(), value);
+        } else {
+            self.slots.push(value);
+            self.names.push((name.to_string(), false));
+        }
+    }
+
+    /// Define a variable that can't be assigned to. The resolver rejects
+    /// assignments to local constants, so only globals are marked.
+    pub fn define_constant(&mut self, name: &str, value: Value) {
+        self.define(name, value);
+        if self.enclosing.is_none() {
+            self.constants.insert(name.to_string());
+        } else if let Some((_, constant)) = self.names.last_mut() {
+            *constant = true;
+        }
+    }
+
+    /// Whether a global was declared with `const`.
+    pub fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains(name)
+    }
+
+    /// Every global, in no particular order.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.globals
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// The variables of this scope: locals in the order they were defined,
+    /// globals sorted by name.
+    pub fn bindings(&self) -> Vec<Binding> {
+        if self.enclosing.is_none() {
+            let mut bindings = self
+                .globals
+                .iter()
+                .map(|(name, value)| Binding {
+                    name: name.clone(),
+                    value: value.clone(),
+                    constant: self.constants.contains(name),
+                })
+                .collect::<Vec<_>>();
+            bindings.sort_by(|a, b| a.name.cmp(&b.name));
+            return bindings;
+        }
+        self.names
+            .iter()
+            .zip(&self.slots)
+            .map(|((name, constant), value)| Binding {
+                name: name.clone(),
+                value: value.clone(),
+                constant: *constant,
+            })
+            .collect()
+    }
+
+    /// The variables of `env` and of every scope enclosing it, innermost
+    /// first, so the globals come last.
+    pub fn scopes(env: &EnvRef) -> Vec<Vec<Binding>> {
+        let mut scopes = vec![env.borrow().bindings()];
+        let mut env = env.borrow().enclosing.clone();
+        while let Some(scope) = env {
+            scopes.push(scope.borrow().bindings());
+            env = scope.borrow().enclosing.clone();
+        }
+        scopes
+    }
+
+    /// A copy of the globals and which of them are constants.
+    pub(crate) fn copy_globals(&self) -> (HashMap<String, Value>, HashSet<String>) {
+        (self.globals.clone(), self.constants.clone())
+    }
+
+    /// Replace the globals with a copy from `copy_globals`.
+    pub(crate) fn set_globals(
+        &mut self,
+        globals: HashMap<String, Value>,
+        constants: HashSet<String>,
+    ) {
+        self.globals = globals;
+        self.constants = constants;
+    }
+
+    /// Look a global up by name.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).cloned()
+    }
+
+    /// Assign to an existing global, returning whether it was found.
+    pub fn assign(&mut self, name: &str, value: Value) -> bool {
+        match self.globals.get_mut(name) {
+            Some(global) => {
+                *global = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Look a local up in the slot of the scope `depth` scopes away, as
+    /// found by the resolver.
+    pub fn get_at(env: &EnvRef, depth: usize, slot: usize) -> Option<Value> {
+        if depth == 0 {
+            return env.borrow().slots.get(slot).cloned();
+        }
+        Self::ancestor(env, depth).borrow().slots.get(slot).cloned()
+    }
+
+    pub fn assign_at(env: &EnvRef, depth: usize, slot: usize, value: Value) {
+        let env = Self::ancestor(env, depth);
+        let mut env = env.borrow_mut();
+        // the slot is missing when assigning a variable in its own
+        // initializer, whose value the definition overwrites anyway
+        if let Some(local) = env.slots.get_mut(slot) {
+            *local = value;
+        }
+    }
+
+    /// Visit the value of every variable of this scope.
+    pub(crate) fn trace<F: FnMut(&Value)>(&self, mut visit: F) {
+        self.globals.values().for_each(&mut visit);
+        self.slots.iter().for_each(visit);
+    }
+
+    /// Empty the scope, unlinking it from the enclosing one, and hand back
+    /// the values it held.
+    pub(crate) fn clear(&mut self) -> Vec<Value> {
+        self.enclosing = None;
+        let globals = std::mem::take(&mut self.globals);
+        self.names.clear();
+        let mut values = std::mem::take(&mut self.slots);
+        values.extend(globals.into_values());
+        values
+    }
+
+    fn ancestor(env: &EnvRef, depth: usize) -> EnvRef {
+        let mut env = env.clone();
+        for _ in 0..depth {
+            let enclosing = env
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("resolved scope depth is deeper than the environment chain");
+            env = enclosing;
+        }
+        env
+    }
+}
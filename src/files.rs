@@ -0,0 +1,79 @@
+//! The natives of the `fs` capability: `readFile`, `writeFile`,
+//! `appendFile`, `exists` and `listDir`.
+//!
+//! Paths are relative to the working directory of the process, not to the
+//! script. Files are read and written as UTF-8 text, and failing to is a
+//! runtime error with the reason the system gave.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::list;
+use crate::value::Value;
+
+/// Register the file natives with an interpreter, see
+/// `Interpreter::enable`.
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.define_native("appendFile", 2, |arguments| {
+        let (path, text) = (path(&arguments[0])?, text(&arguments[1])?);
+        let appended = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(text.as_bytes()));
+        appended.map_err(|error| failed("write", path, error))?;
+        Ok(Value::Nil)
+    });
+    interpreter.define_native("exists", 1, |arguments| {
+        let path = path(&arguments[0])?;
+        Ok(Value::Bool(fs::metadata(path).is_ok()))
+    });
+    interpreter.define_native("listDir", 1, |arguments| {
+        let path = path(&arguments[0])?;
+        let mut names = fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(|error| failed("list", path, error))?;
+        // in the same order everywhere, unlike the system's
+        names.sort();
+        Ok(list::new(names.into_iter().map(Value::from).collect()))
+    });
+    interpreter.define_native("readFile", 1, |arguments| {
+        let path = path(&arguments[0])?;
+        let text = fs::read_to_string(path).map_err(|error| failed("read", path, error))?;
+        Ok(Value::from(text))
+    });
+    interpreter.define_native("writeFile", 2, |arguments| {
+        let (path, text) = (path(&arguments[0])?, text(&arguments[1])?);
+        fs::write(path, text).map_err(|error| failed("write", path, error))?;
+        Ok(Value::Nil)
+    });
+}
+
+pub(crate) fn path(value: &Value) -> Result<&str, RuntimeError> {
+    match value {
+        Value::String(path) => Ok(path),
+        _ => Err(RuntimeError::msg(format!(
+            "Expected a path but got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+fn text(value: &Value) -> Result<&str, RuntimeError> {
+    match value {
+        Value::String(text) => Ok(text),
+        _ => Err(RuntimeError::msg(format!(
+            "Expected a string but got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+fn failed(action: &str, path: &str, error: io::Error) -> RuntimeError {
+    RuntimeError::msg(format!("Could not {} '{}': {}.", action, path, error))
+}
@@ -0,0 +1,200 @@
+//! `format(template, ...)`, filling the placeholders of a template with the
+//! arguments that follow it.
+//!
+//! `{}` takes the next argument and `{1}` a given one, counting from 0. After
+//! a `:` comes how to lay the value out: an alignment (`<`, `>` or `^`), a
+//! `0` to pad numbers with zeros, a minimum width, and for numbers a
+//! precision, the digits kept after the point: `{:>8.2}`. Numbers are
+//! aligned right by default, everything else left. `{{` and `}}` stand for
+//! the braces themselves.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::lexer::Span;
+use crate::value::{self, Value};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+// what goes between the braces of a placeholder
+#[derive(Debug, Default)]
+struct Spec {
+    index: Option<usize>,
+    align: Option<Align>,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+pub(crate) fn format(
+    interpreter: &mut Interpreter,
+    arguments: &[Value],
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    let template = match &arguments[0] {
+        Value::String(template) => template.clone(),
+        other => {
+            return Err(RuntimeError::new(
+                format!("Expected a format string but got {}.", other.type_name()),
+                span,
+            ))
+        }
+    };
+    let arguments = &arguments[1..];
+
+    let mut output = String::new();
+    let mut chars = template.chars().peekable();
+    let mut next = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '{' => {
+                let spec = placeholder(&mut chars, span)?;
+                let index = spec.index.unwrap_or_else(|| {
+                    next += 1;
+                    next - 1
+                });
+                let value = arguments.get(index).ok_or_else(|| {
+                    RuntimeError::new(
+                        format!(
+                            "Format string needs argument {} but got only {}.",
+                            index,
+                            arguments.len()
+                        ),
+                        span,
+                    )
+                })?;
+                let text = render(interpreter, value, &spec, span)?;
+                output.push_str(&text);
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '}' => return Err(RuntimeError::new("Unmatched '}' in format string.", span)),
+            c => output.push(c),
+        }
+    }
+    Ok(Value::from(output))
+}
+
+// read a placeholder up to its closing brace, the opening one already read
+fn placeholder(chars: &mut Peekable<Chars>, span: Span) -> Result<Spec, RuntimeError> {
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) => text.push(c),
+            None => return Err(RuntimeError::new("Unmatched '{' in format string.", span)),
+        }
+    }
+    parse(&text).ok_or_else(|| {
+        RuntimeError::new(format!("Invalid format placeholder '{{{}}}'.", text), span)
+    })
+}
+
+fn parse(text: &str) -> Option<Spec> {
+    let (index, layout) = text.split_once(':').unwrap_or((text, ""));
+    let mut spec = Spec {
+        index: match index {
+            "" => None,
+            index => Some(digits(index)?),
+        },
+        ..Spec::default()
+    };
+
+    let mut rest = layout;
+    spec.align = match rest.chars().next() {
+        Some('<') => Some(Align::Left),
+        Some('>') => Some(Align::Right),
+        Some('^') => Some(Align::Center),
+        _ => None,
+    };
+    if spec.align.is_some() {
+        rest = &rest[1..];
+    }
+    if let Some(after) = rest.strip_prefix('0') {
+        spec.zero = true;
+        rest = after;
+    }
+    let (width, precision) = rest
+        .split_once('.')
+        .map_or((rest, None), |(width, precision)| (width, Some(precision)));
+    if !width.is_empty() {
+        spec.width = digits(width)?;
+    }
+    if let Some(precision) = precision {
+        spec.precision = Some(digits(precision)?);
+    }
+    Some(spec)
+}
+
+fn digits(text: &str) -> Option<usize> {
+    if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+fn render(
+    interpreter: &mut Interpreter,
+    value: &Value,
+    spec: &Spec,
+    span: Span,
+) -> Result<String, RuntimeError> {
+    let number = value.as_number();
+    let text = match (spec.precision, number) {
+        (Some(precision), Some(number)) if number.is_finite() => {
+            format!("{:.*}", precision, number)
+        }
+        (Some(_), Some(number)) => value::format_number(number),
+        (Some(_), None) => {
+            return Err(RuntimeError::new(
+                format!(
+                    "Can only give a precision to numbers, not {}.",
+                    value.type_name()
+                ),
+                span,
+            ))
+        }
+        (None, _) => interpreter.stringify(value.clone(), span)?,
+    };
+
+    let padding = spec.width.saturating_sub(text.chars().count());
+    if padding == 0 {
+        return Ok(text);
+    }
+    // zeros go between the sign and the digits
+    if spec.zero && number.is_some() {
+        let (sign, digits) = match text.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", text.as_str()),
+        };
+        return Ok(format!("{}{}{}", sign, "0".repeat(padding), digits));
+    }
+    let default = if number.is_some() {
+        Align::Right
+    } else {
+        Align::Left
+    };
+    let (before, after) = match spec.align.unwrap_or(default) {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+    Ok(format!(
+        "{}{}{}",
+        " ".repeat(before),
+        text,
+        " ".repeat(after)
+    ))
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::FunctionDecl;
+use crate::environment::{EnvRef, Environment};
+use crate::gc;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::lexer::Span;
+use crate::value::Value;
+
+/// A function declared in Lox, along with the environment it closes over.
+pub struct LoxFunction {
+    pub declaration: Rc<FunctionDecl>,
+    pub closure: EnvRef,
+    pub is_initializer: bool,
+    // whether `this` is bound, in the scope right inside the closure
+    pub is_bound: bool,
+}
+
+impl LoxFunction {
+    pub fn new(declaration: Rc<FunctionDecl>, closure: EnvRef) -> Rc<Self> {
+        Self::track(Self {
+            declaration,
+            closure,
+            is_initializer: false,
+            is_bound: false,
+        })
+    }
+
+    pub fn method(declaration: Rc<FunctionDecl>, closure: EnvRef) -> Rc<Self> {
+        let is_initializer = declaration.name.name == "init";
+        Self::track(Self {
+            declaration,
+            closure,
+            is_initializer,
+            is_bound: false,
+        })
+    }
+
+    /// A copy of the method whose closure has `this` bound to `instance`.
+    pub fn bind(&self, instance: Value) -> Rc<Self> {
+        let environment = Environment::with_enclosing(self.closure.clone());
+        environment.borrow_mut().define("this", instance);
+        Self::track(Self {
+            declaration: self.declaration.clone(),
+            closure: environment,
+            is_initializer: self.is_initializer,
+            is_bound: true,
+        })
+    }
+
+    /// The instance or class `this` is bound to, for methods taken from one.
+    pub fn receiver(&self) -> Option<Value> {
+        if !self.is_bound {
+            return None;
+        }
+        Environment::get_at(&self.closure, 0, 0)
+    }
+
+    /// Whether both are the same function, or the same method bound to the
+    /// same receiver, like `a.f == a.f`.
+    pub fn same(self: &Rc<Self>, other: &Rc<Self>) -> bool {
+        if Rc::ptr_eq(self, other) {
+            return true;
+        }
+        let (left, right) = match (self.receiver(), other.receiver()) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return false,
+        };
+        // the scope binding `this` encloses the one the method was declared in
+        let declared_in = |function: &Self| function.closure.borrow().enclosing();
+        let same_scope = match (declared_in(self), declared_in(other)) {
+            (Some(left), Some(right)) => Rc::ptr_eq(&left, &right),
+            _ => false,
+        };
+        // instances and classes are only equal to themselves
+        Rc::ptr_eq(&self.declaration, &other.declaration) && same_scope && left == right
+    }
+
+    // closures can end up in the environment they close over
+    fn track(function: Self) -> Rc<Self> {
+        let function = Rc::new(function);
+        gc::track(&function);
+        function
+    }
+
+    pub fn name(&self) -> &str {
+        &self.declaration.name.name
+    }
+
+    pub fn arity(&self) -> Arity {
+        let declaration = &self.declaration;
+        let params = declaration.params.len();
+        Arity {
+            min: params - declaration.defaults.len(),
+            max: Some(params).filter(|_| declaration.rest.is_none()),
+        }
+    }
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+/// How many arguments a function takes, `max` being `None` when it has a
+/// rest parameter.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exactly(count: usize) -> Self {
+        Self {
+            min: count,
+            max: Some(count),
+        }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, RuntimeError>;
+
+/// A native calling back into Lox code, given where it was called from.
+pub(crate) type CallbackFn =
+    dyn Fn(&mut Interpreter, &[Value], Span) -> Result<Value, RuntimeError>;
+
+/// A function implemented in Rust and exposed to Lox.
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: Arity,
+    /// Whether its results are recorded and replayed, see `replay`.
+    pub nondeterministic: bool,
+    function: Native,
+}
+
+enum Native {
+    Plain(Box<NativeFn>),
+    Callback(Box<CallbackFn>),
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity: Arity::exactly(arity),
+            nondeterministic: false,
+            function: Native::Plain(Box::new(function)),
+        }
+    }
+
+    /// A native that can call Lox functions and methods. Unlike the others,
+    /// it places its own errors, which may come from deep inside Lox code.
+    pub(crate) fn with_callbacks<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&mut Interpreter, &[Value], Span) -> Result<Value, RuntimeError> + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity: Arity::exactly(arity),
+            nondeterministic: false,
+            function: Native::Callback(Box::new(function)),
+        }
+    }
+
+    /// Take any number of arguments past `arity`.
+    pub fn variadic(mut self) -> Self {
+        self.arity.max = None;
+        self
+    }
+
+    /// Mark a native whose results change from one run to the next.
+    pub fn nondeterministic(mut self) -> Self {
+        self.nondeterministic = true;
+        self
+    }
+
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        match &self.function {
+            Native::Plain(function) => function(arguments).map_err(|error| error.at(span)),
+            Native::Callback(function) => function(interpreter, arguments, span),
+        }
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
@@ -0,0 +1,557 @@
+//! Collection of reference cycles.
+//!
+//! Runtime objects are reference counted, which frees them as soon as nothing
+//! points to them, except when they point to each other: a closure stored in
+//! the environment it closes over, or an instance holding itself in a field.
+//! Every object that can be part of a cycle registers itself here, and
+//! `collect` finds the ones only kept alive by other registered objects.
+//!
+//! There are no roots to scan. Like CPython's collector, it subtracts the
+//! references objects hold to each other from their reference counts: what is
+//! left comes from outside, like the interpreter itself or a value on the
+//! Rust stack, and keeps the object and everything it points to alive. The
+//! rest is unreachable, and clearing the environments and instances among it
+//! breaks the cycles so reference counting frees it all.
+//!
+//! Weak references don't count, so what they point to is freed all the same.
+//!
+//! Objects are registered per thread, so every interpreter of a thread shares
+//! the collector.
+//!
+//! The collector also keeps rough count of the memory objects take, for
+//! `Interpreter::set_memory_limit`: measuring walks every registered object,
+//! so allocations are counted as they happen to tell when it's worth it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::class::{LoxClass, LoxInstance};
+use crate::environment::{EnvRef, Environment};
+use crate::function::LoxFunction;
+use crate::generator::{Generator, GeneratorRef};
+use crate::list::ListRef;
+use crate::map::{MapKey, MapRef};
+use crate::ordered_map::OrderedMap;
+use crate::promise::{Promise, PromiseRef, State, Waiter};
+use crate::value::Value;
+
+// collecting right after a few allocations would be a waste of time
+const INITIAL_THRESHOLD: usize = 10_000;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap {
+            objects: Vec::new(),
+            allocated: 0,
+            threshold: INITIAL_THRESHOLD,
+            measured: 0,
+            allocated_bytes: 0,
+            total_objects: 0,
+            total_bytes: 0,
+        })
+    };
+}
+
+struct Heap {
+    objects: Vec<WeakObject>,
+    // objects registered since the last collection
+    allocated: usize,
+    threshold: usize,
+    // bytes in use at the last measurement, and allocated since
+    measured: usize,
+    allocated_bytes: usize,
+    // ever, for `allocation_totals`
+    total_objects: u64,
+    total_bytes: u64,
+}
+
+/// A registered object, which doesn't keep it alive.
+pub(crate) enum WeakObject {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    Function(Weak<LoxFunction>),
+    Class(Weak<LoxClass>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<OrderedMap<MapKey, Value>>>),
+    Generator(Weak<RefCell<Generator>>),
+    Promise(Weak<RefCell<Promise>>),
+}
+
+impl From<&EnvRef> for WeakObject {
+    fn from(environment: &EnvRef) -> Self {
+        WeakObject::Environment(Rc::downgrade(environment))
+    }
+}
+
+impl From<&Rc<RefCell<LoxInstance>>> for WeakObject {
+    fn from(instance: &Rc<RefCell<LoxInstance>>) -> Self {
+        WeakObject::Instance(Rc::downgrade(instance))
+    }
+}
+
+impl From<&Rc<LoxFunction>> for WeakObject {
+    fn from(function: &Rc<LoxFunction>) -> Self {
+        WeakObject::Function(Rc::downgrade(function))
+    }
+}
+
+impl From<&Rc<LoxClass>> for WeakObject {
+    fn from(class: &Rc<LoxClass>) -> Self {
+        WeakObject::Class(Rc::downgrade(class))
+    }
+}
+
+impl From<&ListRef> for WeakObject {
+    fn from(list: &ListRef) -> Self {
+        WeakObject::List(Rc::downgrade(list))
+    }
+}
+
+impl From<&MapRef> for WeakObject {
+    fn from(map: &MapRef) -> Self {
+        WeakObject::Map(Rc::downgrade(map))
+    }
+}
+
+impl From<&GeneratorRef> for WeakObject {
+    fn from(generator: &GeneratorRef) -> Self {
+        WeakObject::Generator(Rc::downgrade(generator))
+    }
+}
+
+impl From<&PromiseRef> for WeakObject {
+    fn from(promise: &PromiseRef) -> Self {
+        WeakObject::Promise(Rc::downgrade(promise))
+    }
+}
+
+impl WeakObject {
+    fn upgrade(&self) -> Option<Object> {
+        Some(match self {
+            WeakObject::Environment(weak) => Object::Environment(weak.upgrade()?),
+            WeakObject::Instance(weak) => Object::Instance(weak.upgrade()?),
+            WeakObject::Function(weak) => Object::Function(weak.upgrade()?),
+            WeakObject::Class(weak) => Object::Class(weak.upgrade()?),
+            WeakObject::List(weak) => Object::List(weak.upgrade()?),
+            WeakObject::Map(weak) => Object::Map(weak.upgrade()?),
+            WeakObject::Generator(weak) => Object::Generator(weak.upgrade()?),
+            WeakObject::Promise(weak) => Object::Promise(weak.upgrade()?),
+        })
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            WeakObject::Environment(weak) => weak.strong_count() > 0,
+            WeakObject::Instance(weak) => weak.strong_count() > 0,
+            WeakObject::Function(weak) => weak.strong_count() > 0,
+            WeakObject::Class(weak) => weak.strong_count() > 0,
+            WeakObject::List(weak) => weak.strong_count() > 0,
+            WeakObject::Map(weak) => weak.strong_count() > 0,
+            WeakObject::Generator(weak) => weak.strong_count() > 0,
+            WeakObject::Promise(weak) => weak.strong_count() > 0,
+        }
+    }
+}
+
+enum Object {
+    Environment(EnvRef),
+    Instance(Rc<RefCell<LoxInstance>>),
+    Function(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
+    List(ListRef),
+    Map(MapRef),
+    Generator(GeneratorRef),
+    Promise(PromiseRef),
+}
+
+impl Object {
+    fn address(&self) -> usize {
+        match self {
+            Object::Environment(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Instance(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Function(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Class(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::List(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Map(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Generator(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Promise(rc) => Rc::as_ptr(rc) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(rc) => Rc::strong_count(rc),
+            Object::Instance(rc) => Rc::strong_count(rc),
+            Object::Function(rc) => Rc::strong_count(rc),
+            Object::Class(rc) => Rc::strong_count(rc),
+            Object::List(rc) => Rc::strong_count(rc),
+            Object::Map(rc) => Rc::strong_count(rc),
+            Object::Generator(rc) => Rc::strong_count(rc),
+            Object::Promise(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    /// The addresses of the objects this one holds a reference to, one for
+    /// each reference. `None` when it's borrowed, meaning it's in use.
+    fn references(&self) -> Option<Vec<usize>> {
+        let mut references = Vec::new();
+        match self {
+            Object::Environment(environment) => {
+                let environment = environment.try_borrow().ok()?;
+                environment.trace(|value| references.extend(address_of(value)));
+                if let Some(enclosing) = environment.enclosing() {
+                    references.push(Rc::as_ptr(&enclosing) as *const () as usize);
+                }
+            }
+            Object::Instance(instance) => {
+                let instance = instance.try_borrow().ok()?;
+                references.push(Rc::as_ptr(&instance.class) as *const () as usize);
+                references.extend(instance.fields.values().filter_map(address_of));
+            }
+            Object::Function(function) => {
+                references.push(Rc::as_ptr(&function.closure) as *const () as usize);
+            }
+            Object::Class(class) => {
+                let classes = class.superclass.iter().chain(&class.metaclass);
+                references.extend(classes.map(|class| Rc::as_ptr(class) as *const () as usize));
+                references.extend(
+                    class
+                        .methods
+                        .values()
+                        .chain(class.getters.values())
+                        .chain(class.setters.values())
+                        .map(|method| Rc::as_ptr(method) as *const () as usize),
+                );
+            }
+            Object::List(list) => {
+                let list = list.try_borrow().ok()?;
+                references.extend(list.iter().filter_map(address_of));
+            }
+            Object::Map(map) => {
+                let map = map.try_borrow().ok()?;
+                for (key, value) in map.iter() {
+                    if let MapKey::Instance(key) = key {
+                        references.push(Rc::as_ptr(&key.instance) as *const () as usize);
+                    }
+                    references.extend(address_of(value));
+                }
+            }
+            Object::Generator(generator) => {
+                let generator = generator.try_borrow().ok()?;
+                references.extend(
+                    generator
+                        .environments()
+                        .map(|environment| Rc::as_ptr(environment) as *const () as usize),
+                );
+                generator.trace(|value| references.extend(address_of(value)));
+            }
+            Object::Promise(promise) => match &promise.try_borrow().ok()?.state {
+                State::Pending(waiters) => {
+                    for waiter in waiters {
+                        let (coroutine, promise) = match waiter {
+                            Waiter::Resume {
+                                coroutine, promise, ..
+                            } => (Some(coroutine), promise),
+                            Waiter::Forward(promise) => (None, promise),
+                        };
+                        references.extend(
+                            coroutine.map(|coroutine| Rc::as_ptr(coroutine) as *const () as usize),
+                        );
+                        references.push(Rc::as_ptr(promise) as *const () as usize);
+                    }
+                }
+                State::Fulfilled(value) | State::Rejected(value) => {
+                    references.extend(address_of(value))
+                }
+            },
+        }
+        Some(references)
+    }
+
+    /// Drop what the object holds, returning it so it's freed once nothing
+    /// is borrowed anymore.
+    fn clear(&self) -> Vec<Value> {
+        match self {
+            Object::Environment(environment) => environment.borrow_mut().clear(),
+            Object::Instance(instance) => {
+                let fields = mem::take(&mut instance.borrow_mut().fields);
+                fields.into_iter().map(|(_, value)| value).collect()
+            }
+            Object::List(list) => mem::take(&mut *list.borrow_mut()),
+            // what's left of the body won't run anymore, and the scope of
+            // the generator gets cleared on its own
+            Object::Generator(generator) => {
+                generator.borrow_mut().cursors.clear();
+                Vec::new()
+            }
+            // nothing waiting on it will ever resume
+            Object::Promise(promise) => {
+                let state =
+                    mem::replace(&mut promise.borrow_mut().state, State::Pending(Vec::new()));
+                match state {
+                    State::Pending(waiters) => waiters
+                        .into_iter()
+                        .flat_map(|waiter| match waiter {
+                            Waiter::Resume {
+                                coroutine, promise, ..
+                            } => vec![Value::Generator(coroutine), Value::Promise(promise)],
+                            Waiter::Forward(promise) => vec![Value::Promise(promise)],
+                        })
+                        .collect(),
+                    State::Fulfilled(value) | State::Rejected(value) => vec![value],
+                }
+            }
+            Object::Map(map) => mem::take(&mut *map.borrow_mut())
+                .into_iter()
+                .flat_map(|(key, value)| [key.to_value(), value])
+                .collect(),
+            // immutable, every cycle through them also goes through an
+            // environment
+            Object::Function(_) | Object::Class(_) => Vec::new(),
+        }
+    }
+}
+
+impl Object {
+    /// Roughly how many bytes the object takes, the strings it holds
+    /// included. Only its own size when it's borrowed.
+    fn size(&self) -> usize {
+        match self {
+            Object::Environment(environment) => {
+                let mut size = mem::size_of::<Environment>();
+                if let Ok(environment) = environment.try_borrow() {
+                    environment.trace(|value| size += value_size(value));
+                    size += environment
+                        .globals()
+                        .map(|(name, _)| name.len())
+                        .sum::<usize>();
+                }
+                size
+            }
+            Object::Instance(instance) => {
+                let mut size = mem::size_of::<LoxInstance>();
+                if let Ok(instance) = instance.try_borrow() {
+                    size += instance
+                        .fields
+                        .iter()
+                        .map(|(name, value)| name.len() + value_size(value))
+                        .sum::<usize>();
+                }
+                size
+            }
+            Object::List(list) => {
+                let mut size = mem::size_of::<Vec<Value>>();
+                if let Ok(list) = list.try_borrow() {
+                    size += (list.capacity() - list.len()) * mem::size_of::<Value>();
+                    size += list.iter().map(value_size).sum::<usize>();
+                }
+                size
+            }
+            Object::Map(map) => {
+                let mut size = mem::size_of::<OrderedMap<MapKey, Value>>();
+                if let Ok(map) = map.try_borrow() {
+                    size += map.capacity() * mem::size_of::<MapKey>();
+                    size += (map.capacity() - map.len()) * mem::size_of::<Value>();
+                    size += map
+                        .iter()
+                        .map(|(key, value)| {
+                            let key = match key {
+                                MapKey::String(string) => string.len(),
+                                _ => 0,
+                            };
+                            key + value_size(value)
+                        })
+                        .sum::<usize>();
+                }
+                size
+            }
+            Object::Function(_) => mem::size_of::<LoxFunction>(),
+            Object::Class(_) => mem::size_of::<LoxClass>(),
+            Object::Generator(_) => mem::size_of::<Generator>(),
+            Object::Promise(_) => mem::size_of::<Promise>(),
+        }
+    }
+}
+
+// a value in a slot, with the string it holds
+fn value_size(value: &Value) -> usize {
+    match value {
+        Value::String(string) => mem::size_of::<Value>() + string.len(),
+        _ => mem::size_of::<Value>(),
+    }
+}
+
+fn address_of(value: &Value) -> Option<usize> {
+    match value {
+        Value::Function(function) => Some(Rc::as_ptr(function) as *const () as usize),
+        Value::Class(class) => Some(Rc::as_ptr(class) as *const () as usize),
+        Value::Instance(instance) => Some(Rc::as_ptr(instance) as *const () as usize),
+        Value::List(list) => Some(Rc::as_ptr(list) as *const () as usize),
+        Value::Map(map) => Some(Rc::as_ptr(map) as *const () as usize),
+        Value::Generator(generator) => Some(Rc::as_ptr(generator) as *const () as usize),
+        Value::Promise(promise) => Some(Rc::as_ptr(promise) as *const () as usize),
+        _ => None,
+    }
+}
+
+/// Register an object that may end up in a reference cycle.
+pub(crate) fn track<O: Into<WeakObject>>(object: O) {
+    let object = object.into();
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.push(object);
+        heap.allocated += 1;
+        heap.total_objects += 1;
+    });
+}
+
+/// Count `bytes` allocated for a new string, element or field.
+pub(crate) fn allocate(bytes: usize) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.allocated_bytes += bytes;
+        heap.total_bytes += bytes as u64;
+    });
+}
+
+/// How many objects were registered on this thread so far, and how many
+/// bytes counted for strings, elements and fields.
+pub fn allocation_totals() -> (u64, u64) {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        (heap.total_objects, heap.total_bytes)
+    })
+}
+
+/// Whether the objects of this thread may take more than `limit` bytes, from
+/// what was allocated since they were last measured. Nothing allocated means
+/// nothing grew, so a program over the limit can still let go of objects.
+pub(crate) fn may_exceed(limit: usize) -> bool {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.allocated_bytes > 0 && heap.measured + heap.allocated_bytes > limit
+    })
+}
+
+/// Roughly how many bytes the objects of this thread take, counting the
+/// strings they hold but not the ones only on the stack of the interpreter.
+pub fn memory_used() -> usize {
+    let objects = HEAP.with(|heap| {
+        heap.borrow()
+            .objects
+            .iter()
+            .filter_map(WeakObject::upgrade)
+            .collect::<Vec<_>>()
+    });
+    let used = objects.iter().map(Object::size).sum();
+    drop(objects);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.measured = used;
+        heap.allocated_bytes = 0;
+    });
+    used
+}
+
+/// Whether enough objects were registered since the last collection to make
+/// another one worth it.
+pub fn should_collect() -> bool {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.allocated >= heap.threshold
+    })
+}
+
+/// Free the objects of this thread only kept alive by reference cycles,
+/// returning how many there were.
+pub fn collect() -> usize {
+    let objects = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.retain(WeakObject::is_alive);
+        heap.objects
+            .iter()
+            .filter_map(WeakObject::upgrade)
+            .collect::<Vec<_>>()
+    });
+
+    let index = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (object.address(), i))
+        .collect::<HashMap<_, _>>();
+
+    // references from outside the registered objects, not counting the ones
+    // just taken above
+    let mut external = objects
+        .iter()
+        .map(|object| object.strong_count() - 1)
+        .collect::<Vec<_>>();
+    let mut reachable = vec![false; objects.len()];
+    let mut edges = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        let references = match object.references() {
+            Some(references) => references,
+            None => {
+                reachable[i] = true;
+                Vec::new()
+            }
+        };
+        let references = references
+            .iter()
+            .filter_map(|address| index.get(address).copied())
+            .collect::<Vec<_>>();
+        for &reference in &references {
+            external[reference] = external[reference].saturating_sub(1);
+        }
+        edges.push(references);
+    }
+
+    // anything an externally referenced object points to is alive too
+    let mut pending = (0..objects.len())
+        .filter(|&i| external[i] > 0 || reachable[i])
+        .collect::<Vec<_>>();
+    for &i in &pending {
+        reachable[i] = true;
+    }
+    while let Some(i) = pending.pop() {
+        for &reference in &edges[i] {
+            if !reachable[reference] {
+                reachable[reference] = true;
+                pending.push(reference);
+            }
+        }
+    }
+
+    let garbage = objects
+        .iter()
+        .zip(&reachable)
+        .filter(|(_, reachable)| !**reachable)
+        .flat_map(|(object, _)| object.clear())
+        .collect::<Vec<_>>();
+    let freed = reachable.iter().filter(|reachable| !**reachable).count();
+    drop(garbage);
+    drop(objects);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.retain(WeakObject::is_alive);
+        heap.allocated = 0;
+        // survivors make the next collection slower, so wait longer for it
+        heap.threshold = INITIAL_THRESHOLD.max(heap.objects.len());
+    });
+    freed
+}
+
+/// How many registered objects of this thread are still alive.
+pub fn tracked() -> usize {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.objects
+            .iter()
+            .filter(|object| object.is_alive())
+            .count()
+    })
+}
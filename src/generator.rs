@@ -0,0 +1,186 @@
+//! Generators, made by calling a function with `yield` in its body.
+//!
+//! Calling such a function binds its arguments without running it. Each call
+//! to the `next` method of the generator it returns runs the body up to the
+//! next `yield`, returning the value yielded, or `nil` once the body is done.
+//!
+//! In between, the generator remembers where it's suspended as a stack of
+//! cursors: one for the body, then one for each block, branch or loop the
+//! `yield` is inside of. The interpreter walks them back down the syntax tree
+//! to resume, running everything without a `yield` inside as usual.
+//!
+//! Async functions run the same way, suspending at each `await` instead,
+//! which can also be inside the body, `catch` or `finally` of a try
+//! statement.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::slice;
+
+use crate::ast::{Expr, FunctionDecl, Stmt, StmtKind};
+use crate::environment::EnvRef;
+use crate::function::NativeFunction;
+use crate::gc;
+use crate::interpreter::{ControlFlow, Iteration};
+use crate::value::Value;
+
+pub type GeneratorRef = Rc<RefCell<Generator>>;
+
+pub struct Generator {
+    pub declaration: Rc<FunctionDecl>,
+    /// The scope of the body, holding the arguments.
+    pub environment: EnvRef,
+    // empty once the body is done
+    pub(crate) cursors: Vec<Cursor>,
+    // what the body deferred so far, to run once it's done
+    pub(crate) deferred: Vec<(Expr, EnvRef)>,
+    // `next` was called from inside the body
+    pub(crate) running: bool,
+}
+
+/// A position in a list of statements: the body of the generator or of a
+/// block, or the single statement of a branch or of a loop.
+pub(crate) struct Cursor {
+    /// The next statement to run.
+    pub index: usize,
+    /// The scope of the statements, when they get their own.
+    pub environment: Option<EnvRef>,
+    /// What's running inside the statement before `index`, if the generator
+    /// is suspended in there.
+    pub inside: Option<Inside>,
+}
+
+pub(crate) enum Inside {
+    Block,
+    Then,
+    Else,
+    While,
+    ForIn(Iteration),
+    Try,
+    Catch,
+    // with how the statements before it were left, to carry on with once
+    // it's done, and the exception that was being thrown
+    Finally(Option<ControlFlow>, Option<Value>),
+}
+
+impl Cursor {
+    pub fn new(environment: Option<EnvRef>) -> Self {
+        Self {
+            index: 0,
+            environment,
+            inside: None,
+        }
+    }
+}
+
+pub fn new(declaration: Rc<FunctionDecl>, environment: EnvRef) -> GeneratorRef {
+    let generator = Rc::new(RefCell::new(Generator {
+        declaration,
+        environment,
+        cursors: vec![Cursor::new(None)],
+        deferred: Vec::new(),
+        running: false,
+    }));
+    // its scope can hold the generator itself
+    gc::track(&generator);
+    generator
+}
+
+/// Look up one of the methods every generator has, bound to `generator`.
+pub fn get_method(generator: &GeneratorRef, name: &str) -> Option<Value> {
+    let method = match name {
+        "next" => NativeFunction::with_callbacks(name, 0, {
+            let generator = generator.clone();
+            move |interpreter, _, span| {
+                let value = interpreter.resume(&generator, span)?;
+                Ok(value.unwrap_or(Value::Nil))
+            }
+        }),
+        _ => return None,
+    };
+    Some(Value::Native(Rc::new(method)))
+}
+
+/// The statements the last of `cursors` moves through, found by following
+/// the others down from the body.
+pub(crate) fn statements<'a>(body: &'a [Stmt], cursors: &[Cursor]) -> &'a [Stmt] {
+    let mut statements = body;
+    for cursor in &cursors[..cursors.len().saturating_sub(1)] {
+        let owner = &statements[cursor.index - 1];
+        statements = match (&owner.kind, &cursor.inside) {
+            (StmtKind::Block(inner), Some(Inside::Block)) => inner,
+            (StmtKind::If { then_branch, .. }, Some(Inside::Then)) => slice::from_ref(then_branch),
+            (
+                StmtKind::If {
+                    else_branch: Some(else_branch),
+                    ..
+                },
+                Some(Inside::Else),
+            ) => slice::from_ref(else_branch),
+            (StmtKind::While { body, .. }, Some(Inside::While))
+            | (StmtKind::ForIn { body, .. }, Some(Inside::ForIn(_)))
+            | (StmtKind::Try { body, .. }, Some(Inside::Try)) => slice::from_ref(body),
+            (
+                StmtKind::Try {
+                    catch: Some(catch), ..
+                },
+                Some(Inside::Catch),
+            ) => slice::from_ref(&catch.body),
+            (
+                StmtKind::Try {
+                    finally: Some(finally),
+                    ..
+                },
+                Some(Inside::Finally(..)),
+            ) => slice::from_ref(finally),
+            _ => unreachable!("generator cursors out of step with the body"),
+        };
+    }
+    statements
+}
+
+/// The scope the statements of the last of `cursors` run in.
+pub(crate) fn environment(generator_scope: &EnvRef, cursors: &[Cursor]) -> EnvRef {
+    cursors
+        .iter()
+        .rev()
+        .find_map(|cursor| cursor.environment.clone())
+        .unwrap_or_else(|| generator_scope.clone())
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<generator {}>", self.declaration.name.name)
+    }
+}
+
+impl Generator {
+    /// Visit the values the suspended generator holds on to.
+    pub(crate) fn trace<F: FnMut(&Value)>(&self, mut visit: F) {
+        for cursor in &self.cursors {
+            match &cursor.inside {
+                Some(Inside::ForIn(iteration)) => iteration.trace(&mut visit),
+                Some(Inside::Finally(pending, thrown)) => {
+                    if let Some(ControlFlow::Return(value)) = pending {
+                        visit(value);
+                    }
+                    if let Some(thrown) = thrown {
+                        visit(thrown);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The scopes the suspended generator runs in.
+    pub(crate) fn environments(&self) -> impl Iterator<Item = &EnvRef> {
+        let cursors = self.cursors.iter();
+        let scopes = cursors.filter_map(|cursor| cursor.environment.as_ref());
+        let deferred = self.deferred.iter().map(|(_, environment)| environment);
+        std::iter::once(&self.environment)
+            .chain(scopes)
+            .chain(deferred)
+    }
+}
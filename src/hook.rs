@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ast::Stmt;
+use crate::environment::EnvRef;
+use crate::lexer::Span;
+use crate::value::Value;
+
+/// Callbacks into the execution of a program, for tools like debuggers,
+/// tracers or coverage, set with `Interpreter::set_hook`. They all do
+/// nothing by default.
+///
+/// While a hook is set tail calls aren't optimized, so every call that
+/// returns has its own `on_return`.
+pub trait InterpreterHook {
+    /// Before executing a statement, in the scope it runs in, whose
+    /// variables `Environment::scopes` lists. A step debugger can block
+    /// here until told to go on.
+    fn on_statement(&mut self, _statement: &Stmt, _environment: &EnvRef) {}
+
+    /// Before calling a function, native function or class.
+    fn on_call(&mut self, _callee: &Value, _arguments: &[Value], _span: Span) {}
+
+    /// After a call returned a value, as opposed to failing.
+    fn on_return(&mut self, _callee: &Value, _value: &Value) {}
+}
+
+// lets the host keep a handle to read the hook after running
+impl<H: InterpreterHook> InterpreterHook for Rc<RefCell<H>> {
+    fn on_statement(&mut self, statement: &Stmt, environment: &EnvRef) {
+        self.borrow_mut().on_statement(statement, environment);
+    }
+
+    fn on_call(&mut self, callee: &Value, arguments: &[Value], span: Span) {
+        self.borrow_mut().on_call(callee, arguments, span);
+    }
+
+    fn on_return(&mut self, callee: &Value, value: &Value) {
+        self.borrow_mut().on_return(callee, value);
+    }
+}
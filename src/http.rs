@@ -0,0 +1,112 @@
+//! The HTTP natives of the `net` capability, with the `http` feature:
+//! `httpGet(url)` and `httpPost(url, body, headers)`.
+//!
+//! Both wait for the whole response and give a map of its `status`, its
+//! `headers` by lowercase name, and its `body` as a string. A status
+//! telling of an error is still a response, only failing to get one is an
+//! error. Requests give up after `TIMEOUT`. Responses can't be recorded for
+//! replay, so the natives aren't, like the file natives.
+
+use std::rc::Rc;
+use std::time::Duration;
+
+use ureq::{AgentBuilder, Error, Response};
+
+use crate::function::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::map::{self, MapKey};
+use crate::ordered_map::OrderedMap;
+use crate::string::string;
+use crate::value::Value;
+
+/// How long a request may take, from connecting to reading the body.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Register the HTTP natives, see `Interpreter::enable`.
+pub fn register(interpreter: &mut Interpreter) {
+    let agent = AgentBuilder::new().timeout(TIMEOUT).build();
+    interpreter.define_native("httpGet", 1, {
+        let agent = agent.clone();
+        move |arguments| {
+            let url = string(&arguments[0])?;
+            send(url, agent.get(url).call())
+        }
+    });
+    let mut post = NativeFunction::new("httpPost", 3, move |arguments| {
+        let (url, body) = (string(&arguments[0])?, string(&arguments[1])?);
+        let mut request = agent.post(url);
+        if let Some(headers) = arguments.get(2) {
+            for (name, value) in headers_of(headers)? {
+                request = request.set(&name, &value);
+            }
+        }
+        send(url, request.send_string(body))
+    });
+    // the headers are optional
+    post.arity.min = 2;
+    interpreter.define_global("httpPost", Value::Native(Rc::new(post)));
+}
+
+fn send(url: &str, result: Result<Response, Error>) -> Result<Value, RuntimeError> {
+    let response = match result {
+        Ok(response) | Err(Error::Status(_, response)) => response,
+        Err(Error::Transport(error)) => {
+            return Err(RuntimeError::msg(format!(
+                "Could not reach '{}': {}.",
+                url, error
+            )))
+        }
+    };
+
+    let status = Value::Integer(i64::from(response.status()));
+    let mut headers = OrderedMap::new();
+    for name in response.headers_names() {
+        // repeated headers are one list of values
+        let values = response.all(&name).join(", ");
+        headers.insert(key(&name.to_lowercase()), Value::from(values));
+    }
+    let body = response.into_string().map_err(|error| {
+        RuntimeError::msg(format!(
+            "Could not read the response of '{}': {}.",
+            url, error
+        ))
+    })?;
+
+    let mut entries = OrderedMap::new();
+    entries.insert(key("status"), status);
+    entries.insert(key("headers"), map::new(headers));
+    entries.insert(key("body"), Value::from(body));
+    Ok(map::new(entries))
+}
+
+fn headers_of(headers: &Value) -> Result<Vec<(String, String)>, RuntimeError> {
+    let headers = match headers {
+        Value::Map(headers) => headers.borrow(),
+        other => {
+            return Err(RuntimeError::msg(format!(
+                "Expected a map of headers but got {}.",
+                other.type_name()
+            )))
+        }
+    };
+    headers
+        .iter()
+        .map(|(name, value)| match (name, value) {
+            (MapKey::String(name), Value::String(value)) => {
+                Ok((name.to_string(), value.to_string()))
+            }
+            (MapKey::String(_), other) => Err(RuntimeError::msg(format!(
+                "Header values must be strings, not {}.",
+                other.type_name()
+            ))),
+            (other, _) => Err(RuntimeError::msg(format!(
+                "Header names must be strings, not {}.",
+                other.to_value().type_name()
+            ))),
+        })
+        .collect()
+}
+
+fn key(name: &str) -> MapKey {
+    MapKey::String(Rc::from(name))
+}
@@ -0,0 +1,3029 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::iter;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::ast::*;
+use crate::capability::Capability;
+use crate::channel::{self, Inherited, Threads};
+use crate::class::{self, LoxClass, LoxInstance, Members};
+use crate::environment::{Binding, EnvRef, Environment};
+use crate::files;
+use crate::function::{Arity, LoxFunction, NativeFunction};
+use crate::gc;
+use crate::generator::{self, Cursor, GeneratorRef, Inside};
+use crate::hook::InterpreterHook;
+#[cfg(feature = "http")]
+use crate::http;
+use crate::lexer::{Lexer, Span, SyntaxError};
+use crate::list::{self, ListRef};
+use crate::map::{self, InstanceKey, MapKey, MapRef};
+use crate::ordered_map::OrderedMap;
+use crate::parser::Parser;
+use crate::process;
+use crate::promise::{self, PromiseRef, State, Waiter};
+use crate::random::Random;
+use crate::range::{self, Range};
+use crate::replay::{ReplayLog, ReplayMode};
+use crate::resolver::{self, Resolution, SemanticModel};
+use crate::stack;
+use crate::stdlib;
+use crate::string;
+use crate::value::{self, Value};
+use crate::weak;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RuntimeErrorKind {
+    /// Raised by the program itself, like an undefined variable.
+    Error,
+    /// The program used up the budget set by `Interpreter::set_limits`.
+    /// Unlike the other kinds, `catch` can't stop it.
+    ExecutionLimitExceeded,
+    /// A value thrown with `throw` that nothing caught.
+    Thrown,
+    /// The host asked the program to stop through an `InterruptHandle`.
+    /// Like running out of budget, it can't be caught.
+    Interrupted,
+    /// The program called `exit` with this status. It can't be caught
+    /// either, but `finally` blocks and deferred expressions still run on
+    /// the way out.
+    Exit(i32),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    pub span: Span,
+    // innermost frame first, filled in once the error leaves the frame it
+    // was raised in
+    pub trace: Vec<TraceFrame>,
+}
+
+/// A function that was running when an error happened, along with the line
+/// it was executing.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TraceFrame {
+    // `None` for the top-level script
+    pub function: Option<String>,
+    pub line: usize,
+}
+
+impl RuntimeError {
+    pub fn new<M: Into<String>>(message: M, span: Span) -> Self {
+        Self {
+            kind: RuntimeErrorKind::Error,
+            message: message.into(),
+            span,
+            trace: Vec::new(),
+        }
+    }
+
+    /// An error without a location yet, like the ones raised by natives,
+    /// which get the span of their call site.
+    pub fn msg<M: Into<String>>(message: M) -> Self {
+        Self::new(message, Span::default())
+    }
+
+    /// Place an error raised without a location, see `msg`.
+    pub fn at(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn with_kind(mut self, kind: RuntimeErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// The stack trace shown under the message, innermost frame first, with
+    /// the middle of deep ones left out.
+    pub fn trace_lines(&self) -> Vec<String> {
+        if self.trace.is_empty() {
+            return vec![format!("[line {}]", self.span.line)];
+        }
+        if self.trace.len() <= 2 * TRACE_EDGE {
+            return self.trace.iter().map(TraceFrame::to_string).collect();
+        }
+
+        let omitted = self.trace.len() - 2 * TRACE_EDGE;
+        let mut lines = self.trace[..TRACE_EDGE]
+            .iter()
+            .map(TraceFrame::to_string)
+            .collect::<Vec<_>>();
+        lines.push(format!("[{} more frames]", omitted));
+        lines.extend(
+            self.trace[TRACE_EDGE + omitted..]
+                .iter()
+                .map(TraceFrame::to_string),
+        );
+        lines
+    }
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "[line {}] in {}()", self.line, function),
+            None => write!(f, "[line {}] in script", self.line),
+        }
+    }
+}
+
+impl RuntimeErrorKind {
+    /// Whether the error stops the program, skipping `catch` and `finally`.
+    pub fn is_abort(&self) -> bool {
+        matches!(
+            self,
+            RuntimeErrorKind::ExecutionLimitExceeded | RuntimeErrorKind::Interrupted
+        )
+    }
+}
+
+/// Stops the program an interpreter or a VM is running, from any thread, or
+/// from a signal handler as it only sets a flag. The program fails with an
+/// `Interrupted` error at its next step.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    // an interrupt only stops the run it was meant for
+    pub(crate) fn clear(&self) {
+        self.interrupted.store(false, Ordering::Relaxed);
+    }
+
+    // whether the program was interrupted, which it only fails for once
+    pub(crate) fn take(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed) && self.interrupted.swap(false, Ordering::Relaxed)
+    }
+}
+
+// how many frames are shown at each end of a long stack trace
+const TRACE_EDGE: usize = 10;
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for line in self.trace_lines() {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Why the execution of statements stopped before reaching their end.
+pub(crate) enum ControlFlow {
+    Return(Value),
+    /// Return the result of calling the callee, which the caller does in
+    /// place of the returning function instead of nesting a Rust call.
+    TailCall(Value, Vec<Value>, Span),
+    Break,
+    Continue,
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for ControlFlow {
+    fn from(error: RuntimeError) -> Self {
+        ControlFlow::Error(error)
+    }
+}
+
+/// Budget for a single run of the interpreter, so untrusted scripts can't
+/// loop forever. Every executed statement and evaluated expression is a step.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct ExecutionLimits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+// checking the clock on every step would be too slow
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+// how long the event loop sleeps at most before checking for interrupts
+const TIMER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How many calls can be nested before the program fails with a stack
+/// overflow, unless changed with `Interpreter::set_max_call_depth`. Calls
+/// also fail that way once the native stack of the thread is nearly used
+/// up, so a smaller stack only means fewer calls.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// A native stack big enough for `DEFAULT_MAX_CALL_DEPTH` nested calls, even
+/// in debug builds. On the main thread and the threads Rust spawns by
+/// default, programs run out of stack long before that in debug builds, so
+/// hosts wanting the whole depth should run interpreters on a thread with
+/// this stack size.
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// The exit codes of jlox, from the BSD `sysexits.h`, see `exit_code`: a
+/// script that doesn't compile is bad input data, one that fails running is
+/// an internal error of the program.
+pub const EXIT_COMPILE_ERROR: i32 = 65;
+pub const EXIT_NO_INPUT: i32 = 66;
+pub const EXIT_RUNTIME_ERROR: i32 = 70;
+/// Like a shell, for a program stopped with Ctrl-C.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// The exit code of a process running a script with `run_file`: 0 when it
+/// succeeded, `EXIT_COMPILE_ERROR` for lexing, parsing and resolving
+/// errors, `EXIT_RUNTIME_ERROR` when it failed running and `EXIT_NO_INPUT`
+/// when it couldn't be read, unless the script called `exit`.
+pub fn exit_code(result: &anyhow::Result<()>) -> i32 {
+    let error = match result {
+        Ok(()) => return 0,
+        Err(error) => error,
+    };
+    if error.is::<SyntaxError>() {
+        return EXIT_COMPILE_ERROR;
+    }
+    match error.downcast_ref::<RuntimeError>() {
+        Some(error) if error.kind == RuntimeErrorKind::Interrupted => EXIT_INTERRUPTED,
+        Some(RuntimeError {
+            kind: RuntimeErrorKind::Exit(code),
+            ..
+        }) => *code,
+        Some(_) => EXIT_RUNTIME_ERROR,
+        None => EXIT_NO_INPUT,
+    }
+}
+
+/// Whether a run ended with the program calling `exit`, which is how it
+/// meant to stop rather than an error to report.
+pub fn exited(result: &anyhow::Result<()>) -> bool {
+    let error = match result {
+        Ok(()) => return false,
+        Err(error) => error,
+    };
+    matches!(
+        error.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError {
+            kind: RuntimeErrorKind::Exit(_),
+            ..
+        })
+    )
+}
+
+/// The globals of an interpreter at some point, to go back to with
+/// `Interpreter::restore`.
+///
+/// It holds the values of the globals, not copies of them: a list a global
+/// held is the same list after restoring, with whatever was pushed since.
+#[derive(Clone)]
+pub struct Snapshot {
+    globals: HashMap<String, Value>,
+    constants: HashSet<String>,
+    builtins: Vec<(String, Value)>,
+    modules: HashMap<PathBuf, Module>,
+}
+
+impl Snapshot {
+    pub fn get_global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+}
+
+// a module imported so far
+#[derive(Clone)]
+struct Module {
+    // as first imported, to show in errors
+    path: PathBuf,
+    declarations: Vec<(String, Value)>,
+    // the global scopes it was imported into, which reloading rebinds
+    importers: Vec<Weak<RefCell<Environment>>>,
+    modified: Option<SystemTime>,
+}
+
+// a file being run, either the script or one of the modules it imports
+struct SourceFile {
+    // as given, to show in errors
+    path: PathBuf,
+    canonical: PathBuf,
+}
+
+// resumes an async function, see `Interpreter::run_event_loop`
+struct Task {
+    coroutine: GeneratorRef,
+    promise: PromiseRef,
+    // how the promise it was suspended on settled, `None` to start it
+    settled: Option<Result<Value, Value>>,
+    span: Span,
+}
+
+struct Timer {
+    deadline: Instant,
+    id: i64,
+    action: TimerAction,
+    span: Span,
+}
+
+// what happens when a timer is due
+enum TimerAction {
+    // `sleepAsync` fulfilling its promise
+    Settle(PromiseRef),
+    // `setTimeout` calling its callback
+    Call(Value),
+}
+
+// where running the body of a generator or async function stopped
+enum Suspension {
+    Yielded(Value),
+    Returned(Value),
+}
+
+struct CallFrame {
+    function: String,
+    // where the function was called from
+    call_span: Span,
+    // `defer` expressions to evaluate once the function is done, along with
+    // their scope
+    deferred: Vec<(Expr, EnvRef)>,
+}
+
+/// Runs Lox programs, keeping globals from one run to the next.
+///
+/// An interpreter stays on the thread that created it, but interpreters
+/// share no state, so every thread can have its own. See `isolate` for
+/// running programs concurrently from a single thread.
+pub struct Interpreter {
+    globals: EnvRef,
+    environment: EnvRef,
+    // how many scopes away each resolved local lives, and its slot there
+    locals: HashMap<NodeId, (usize, usize)>,
+    tail_calls: HashSet<NodeId>,
+    // private member accesses -> the class declaration around them
+    private_accesses: HashMap<NodeId, NodeId>,
+    generators: HashSet<NodeId>,
+    yielding: HashSet<NodeId>,
+    // functions using the locals around them
+    captures: HashSet<NodeId>,
+    frames: Vec<CallFrame>,
+    max_call_depth: usize,
+    memory_limit: Option<usize>,
+    limits: ExecutionLimits,
+    interrupt: InterruptHandle,
+    steps: u64,
+    deadline: Option<Instant>,
+    output: Box<dyn Write>,
+    // stdin when `None`, read a line at a time without holding its lock
+    input: Option<Box<dyn BufRead>>,
+    string_coercion: bool,
+    // the value of the `throw` being propagated, errors can't hold values
+    thrown: Option<Value>,
+    catch_runtime_errors: bool,
+    // globals every module starts with: the standard library and the ones
+    // defined by the host
+    builtins: Vec<(String, Value)>,
+    // every module imported so far, by canonical path
+    modules: HashMap<PathBuf, Module>,
+    // innermost last
+    files: Vec<SourceFile>,
+    hook: Option<Box<dyn InterpreterHook>>,
+    replay: Option<(ReplayMode, ReplayLog)>,
+    random: Random,
+    args: Vec<String>,
+    tasks: VecDeque<Task>,
+    // soonest first
+    timers: Vec<Timer>,
+    last_timer_id: i64,
+    // promises of async functions that threw, with the error to report if
+    // nothing awaits them
+    rejections: Vec<(PromiseRef, RuntimeError)>,
+    threads: Threads,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let globals = Environment::new();
+        let mut interpreter = Self {
+            environment: globals.clone(),
+            globals,
+            locals: HashMap::new(),
+            tail_calls: HashSet::new(),
+            private_accesses: HashMap::new(),
+            generators: HashSet::new(),
+            yielding: HashSet::new(),
+            captures: HashSet::new(),
+            frames: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            memory_limit: None,
+            limits: ExecutionLimits::default(),
+            interrupt: InterruptHandle::default(),
+            steps: 0,
+            deadline: None,
+            output: Box::new(io::stdout()),
+            input: None,
+            string_coercion: false,
+            thrown: None,
+            catch_runtime_errors: false,
+            builtins: Vec::new(),
+            modules: HashMap::new(),
+            files: Vec::new(),
+            hook: None,
+            replay: None,
+            random: Random::unseeded(),
+            args: Vec::new(),
+            tasks: VecDeque::new(),
+            timers: Vec::new(),
+            last_timer_id: 0,
+            rejections: Vec::new(),
+            threads: Threads::new(),
+        };
+        stdlib::register(&mut interpreter);
+        interpreter.builtins = interpreter
+            .globals
+            .borrow()
+            .globals()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        interpreter
+    }
+
+    /// Lex, parse, resolve and execute a program.
+    pub fn run(&mut self, source: &str) -> anyhow::Result<()> {
+        self.eval(source)?;
+        Ok(())
+    }
+
+    /// Run a script, importing modules relative to its directory. It fails
+    /// with a `SyntaxError` when the script doesn't compile and with a
+    /// `RuntimeError` when it fails running; `exit_code` tells them apart.
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let read = |error| anyhow::anyhow!("Could not read '{}': {}", path.display(), error);
+        let source = fs::read_to_string(path).map_err(read)?;
+        let canonical = path.canonicalize().map_err(read)?;
+
+        self.files.push(SourceFile {
+            path: path.to_path_buf(),
+            canonical,
+        });
+        let result = self.run(&source);
+        self.files.pop();
+        result
+    }
+
+    /// Like `run`, but hands back the value of the last statement when it is
+    /// a bare expression.
+    pub fn eval(&mut self, source: &str) -> anyhow::Result<Option<Value>> {
+        let program = Parser::new(Lexer::new(source.to_string()))?.parse()?;
+        let model = resolver::resolve(&program);
+        model.check()?;
+
+        Ok(self.interpret_program(&program, &model)?)
+    }
+
+    /// Execute an already resolved program.
+    pub fn interpret(
+        &mut self,
+        program: &[Stmt],
+        model: &SemanticModel,
+    ) -> Result<(), RuntimeError> {
+        self.interpret_program(program, model)?;
+        Ok(())
+    }
+
+    fn interpret_program(
+        &mut self,
+        program: &[Stmt],
+        model: &SemanticModel,
+    ) -> Result<Option<Value>, RuntimeError> {
+        self.resolve(model);
+        self.start_run();
+        let result = self
+            .execute_program(program)
+            .and_then(|last| self.run_event_loop().map(|_| last));
+        self.end_run(result)
+    }
+
+    fn start_run(&mut self) {
+        self.steps = 0;
+        self.interrupt.clear();
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Wait for the threads the run spawned, or stop them when it failed,
+    /// printing what they printed.
+    pub(crate) fn end_run<T>(
+        &mut self,
+        result: Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        let result = result.and_then(|value| self.join_threads().map(|_| value));
+        if result.is_err() {
+            self.stop_run();
+        }
+        // the run already failed, or it printed nothing since
+        let _ = self.threads.print(&mut self.output);
+        result
+    }
+
+    // drop the work left by a run that failed
+    fn stop_run(&mut self) {
+        self.tasks.clear();
+        self.timers.clear();
+        self.rejections.clear();
+        self.threads.stop();
+    }
+
+    fn join_threads(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            let span = match self.threads.running() {
+                Ok(Some(span)) => span,
+                Ok(None) => return Ok(()),
+                Err(error) => return Err(self.with_trace(error)),
+            };
+            self.check_waiting(span)
+                .map_err(|error| self.with_trace(error))?;
+            thread::sleep(TIMER_POLL_INTERVAL);
+        }
+    }
+
+    // write what the threads the program spawned printed, before it prints
+    // anything else
+    fn print_threads(&mut self, span: Span) -> Result<(), RuntimeError> {
+        self.threads
+            .print(&mut self.output)
+            .map_err(|error| RuntimeError::new(format!("Could not print: {}.", error), span))
+    }
+
+    /// What the threads the program spawns take from it: its limits, with
+    /// the steps and time its run has left, and its output.
+    pub(crate) fn inherited(&self) -> Inherited {
+        let max_steps = self
+            .limits
+            .max_steps
+            .map(|max_steps| max_steps.saturating_sub(self.steps));
+        let timeout = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        Inherited {
+            limits: ExecutionLimits { max_steps, timeout },
+            max_call_depth: self.max_call_depth,
+            memory_limit: self.memory_limit,
+            interrupt: InterruptHandle::default(),
+            output: self.threads.forward(),
+        }
+    }
+
+    pub(crate) fn threads(&mut self) -> &mut Threads {
+        &mut self.threads
+    }
+
+    fn execute_program(&mut self, program: &[Stmt]) -> Result<Option<Value>, RuntimeError> {
+        let mut last = None;
+        for statement in program {
+            let result = match &statement.kind {
+                StmtKind::Expression(expr) => self
+                    .enter_statement(statement)
+                    .and_then(|_| self.evaluate(expr))
+                    .map(Some)
+                    .map_err(Into::into),
+                _ => self.execute(statement).map(|_| None),
+            };
+            match result {
+                Ok(value) => last = value,
+                Err(ControlFlow::Error(error)) => {
+                    self.thrown = None;
+                    return Err(self.with_trace(error));
+                }
+                Err(
+                    ControlFlow::Return(_)
+                    | ControlFlow::TailCall(..)
+                    | ControlFlow::Break
+                    | ControlFlow::Continue,
+                ) => {
+                    unreachable!("control flow statement outside of its construct")
+                }
+            }
+        }
+        Ok(last)
+    }
+
+    /// Limit how long each following run can take. A run going over the
+    /// limits fails with `RuntimeErrorKind::ExecutionLimitExceeded`.
+    pub fn set_limits(&mut self, limits: ExecutionLimits) {
+        self.limits = limits;
+    }
+
+    /// A handle stopping whatever program the interpreter is running when
+    /// it's interrupted.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub(crate) fn set_interrupt_handle(&mut self, interrupt: InterruptHandle) {
+        self.interrupt = interrupt;
+    }
+
+    /// Make calls nested deeper than `depth` fail with a "Stack overflow."
+    /// error, which programs can catch, as they do running out of native
+    /// stack first. Tail calls don't nest.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Make the program throw an `OutOfMemoryError`, which it can catch,
+    /// once the objects of its thread take more than `limit` bytes, as
+    /// roughly counted by `gc::memory_used`. Cycles are collected first.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /// Record the results of nondeterministic natives like `clock` from now
+    /// on, to be taken with `take_replay_log`.
+    pub fn record(&mut self) {
+        self.replay = Some((ReplayMode::Record, ReplayLog::new()));
+    }
+
+    /// Have nondeterministic natives return the results of `log` instead,
+    /// in order. Calling them in another order than they were recorded in
+    /// is an error.
+    pub fn replay(&mut self, log: ReplayLog) {
+        self.replay = Some((ReplayMode::Replay, log));
+    }
+
+    /// Whether natives are being recorded or replayed.
+    pub fn replay_mode(&self) -> Option<ReplayMode> {
+        self.replay.as_ref().map(|(mode, _)| *mode)
+    }
+
+    /// Stop recording or replaying, giving back the log: what was recorded,
+    /// or what's left to replay.
+    pub fn take_replay_log(&mut self) -> Option<ReplayLog> {
+        self.replay.take().map(|(_, log)| log)
+    }
+
+    /// Have `random` and `randomInt` give the numbers of `seed` from now
+    /// on, the same on every run, as `seedRandom` does.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Random::seeded(seed);
+    }
+
+    pub(crate) fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    /// Set what `args()` gives the program, the arguments after the script
+    /// on the command line.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub(crate) fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Send everything the program prints to `output` instead of stdout.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+
+    /// Have `input` read lines from `input` instead of stdin.
+    pub fn set_input<R: BufRead + 'static>(&mut self, input: R) {
+        self.input = Some(Box::new(input));
+    }
+
+    /// When enabled, `+` with a string on either side turns the other operand
+    /// into a string instead of failing, so `"n = " + 1` gives `"n = 1"`.
+    pub fn set_string_coercion(&mut self, enabled: bool) {
+        self.string_coercion = enabled;
+    }
+
+    /// When enabled, `catch` also catches the errors raised by the
+    /// interpreter itself, like an undefined variable, as instances of the
+    /// global `Error` class with the error message in their `message` field.
+    pub fn set_catch_runtime_errors(&mut self, enabled: bool) {
+        self.catch_runtime_errors = enabled;
+    }
+
+    /// Have `hook` notified as the following runs execute statements and
+    /// call functions, replacing the previous hook.
+    pub fn set_hook<H: InterpreterHook + 'static>(&mut self, hook: H) {
+        self.hook = Some(Box::new(hook));
+    }
+
+    pub fn take_hook(&mut self) -> Option<Box<dyn InterpreterHook>> {
+        self.hook.take()
+    }
+
+    /// Take a snapshot of the globals, the ones of the host included, and of
+    /// the modules imported so far.
+    pub fn snapshot(&self) -> Snapshot {
+        let (globals, constants) = self.globals.borrow().copy_globals();
+        Snapshot {
+            globals,
+            constants,
+            builtins: self.builtins.clone(),
+            modules: self.modules.clone(),
+        }
+    }
+
+    /// Go back to the globals of `snapshot`: the ones defined since are
+    /// gone, and the others get their value back. Taking a snapshot of a
+    /// fresh interpreter lets it start over without running the standard
+    /// library again.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let Snapshot {
+            globals,
+            constants,
+            builtins,
+            modules,
+        } = snapshot.clone();
+        self.globals.borrow_mut().set_globals(globals, constants);
+        self.builtins = builtins;
+        self.modules = modules;
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().get(name)
+    }
+
+    /// The variables in scope where the program is, innermost scope first
+    /// and the globals last. Between runs, only the globals are.
+    pub fn scopes(&self) -> Vec<Vec<Binding>> {
+        Environment::scopes(&self.environment)
+    }
+
+    /// Define a global, which modules imported later also see.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().define(name, value.clone());
+        self.builtins.retain(|(builtin, _)| builtin != name);
+        self.builtins.push((name.to_string(), value));
+    }
+
+    /// Let programs do more than compute and print, by defining the
+    /// natives of `capability`. None is enabled by default, which doesn't
+    /// make programs harmless, see `capability`.
+    pub fn enable(&mut self, capability: Capability) {
+        match capability {
+            Capability::Fs => files::register(self),
+            Capability::Env => process::register_env(self),
+            Capability::Process => process::register_process(self),
+            #[cfg(feature = "http")]
+            Capability::Net => http::register(self),
+        }
+    }
+
+    /// Expose a Rust function to Lox code as a global. Errors returned by
+    /// the function are reported at the call site.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let native = NativeFunction::new(name, arity, function);
+        self.define_global(name, Value::Native(Rc::new(native)));
+    }
+
+    fn resolve(&mut self, model: &SemanticModel) {
+        for reference in model.references() {
+            if let Some(Resolution::Local { depth, slot, .. }) = model.resolution(reference.node) {
+                self.locals.insert(reference.node, (depth, slot));
+            }
+        }
+        self.tail_calls.extend(model.tail_calls());
+        self.private_accesses.extend(model.private_accesses());
+        self.generators.extend(model.generators());
+        self.yielding.extend(model.yielding_statements());
+        self.captures.extend(model.captures());
+    }
+
+    /// Whether `function` uses the locals of the functions or blocks around
+    /// it, which can't go along to another thread.
+    pub(crate) fn captures_locals(&self, function: &LoxFunction) -> bool {
+        self.captures.contains(&function.declaration.id)
+    }
+
+    // Statements
+
+    // what happens before running any statement
+    fn enter_statement(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.step(stmt.span)?;
+        if gc::should_collect() {
+            gc::collect();
+        }
+        if let Some(hook) = &mut self.hook {
+            hook.on_statement(stmt, &self.environment);
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), ControlFlow> {
+        self.enter_statement(stmt)?;
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                self.evaluate(expr)?;
+            }
+            StmtKind::Print(expr) => {
+                let value = self.evaluate(expr)?;
+                let text = self.stringify(value, expr.span)?;
+                self.print_threads(stmt.span)?;
+                writeln!(self.output, "{}", text).map_err(|error| {
+                    RuntimeError::new(format!("Could not print: {}.", error), stmt.span)
+                })?;
+            }
+            StmtKind::Var { name, initializer } => {
+                let value = match initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => Value::Nil,
+                };
+                self.environment.borrow_mut().define(&name.name, value);
+            }
+            StmtKind::Const { name, initializer } => {
+                let value = self.evaluate(initializer)?;
+                self.environment
+                    .borrow_mut()
+                    .define_constant(&name.name, value);
+            }
+            StmtKind::Block(statements) => {
+                let environment = Environment::with_enclosing(self.environment.clone());
+                self.execute_block(statements, environment)?;
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    self.execute(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)?;
+                }
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+            } => {
+                while self.evaluate(condition)?.is_truthy() {
+                    match self.execute(body) {
+                        Ok(()) | Err(ControlFlow::Continue) => {}
+                        Err(ControlFlow::Break) => break,
+                        Err(flow) => return Err(flow),
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(increment)?;
+                    }
+                }
+            }
+            StmtKind::ForIn {
+                name,
+                iterable,
+                body,
+            } => {
+                let iterable = self.evaluate(iterable)?;
+                let mut iterator = self.iterate(iterable, stmt.span)?;
+                while let Some(element) = iterator.next(self, stmt.span)? {
+                    let environment = Environment::with_enclosing(self.environment.clone());
+                    environment.borrow_mut().define(&name.name, element);
+                    match self.execute_block(std::slice::from_ref(body), environment) {
+                        Ok(()) | Err(ControlFlow::Continue) => {}
+                        Err(ControlFlow::Break) => break,
+                        Err(flow) => return Err(flow),
+                    }
+                }
+            }
+            StmtKind::Yield(_) => unreachable!("yield outside of a generator"),
+            StmtKind::Defer(expr) => {
+                let frame = self
+                    .frames
+                    .last_mut()
+                    .expect("'defer' resolved outside of a function");
+                frame
+                    .deferred
+                    .push((expr.clone(), self.environment.clone()));
+            }
+            StmtKind::Break => return Err(ControlFlow::Break),
+            StmtKind::Continue => return Err(ControlFlow::Continue),
+            StmtKind::Function(declaration) => {
+                let function = LoxFunction::new(declaration.clone(), self.environment.clone());
+                self.environment
+                    .borrow_mut()
+                    .define(&declaration.name.name, Value::Function(function));
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(Expr {
+                        id,
+                        span,
+                        kind: ExprKind::Call { callee, arguments },
+                    }) if self.tail_calls.contains(id) && self.hook.is_none() => {
+                        let callee = self.evaluate(callee)?;
+                        let arguments = self.evaluate_arguments(arguments)?;
+                        return Err(ControlFlow::TailCall(callee, arguments, *span));
+                    }
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
+                };
+                return Err(ControlFlow::Return(value));
+            }
+            StmtKind::Class(class) => self.execute_class(stmt.id, class)?,
+            StmtKind::Throw(value) => {
+                let value = self.evaluate(value)?;
+                let message = format!("Uncaught exception: {}.", describe_exception(&value));
+                self.thrown = Some(value);
+                return Err(RuntimeError::new(message, stmt.span)
+                    .with_kind(RuntimeErrorKind::Thrown)
+                    .into());
+            }
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => return self.execute_try(body, catch.as_ref(), finally.as_deref()),
+            StmtKind::Import(path) => self.execute_import(path, stmt.span)?,
+        }
+        Ok(())
+    }
+
+    fn execute_import(&mut self, path: &str, span: Span) -> Result<(), RuntimeError> {
+        let path = match self.files.last() {
+            Some(importer) => importer.path.with_file_name(path),
+            None => PathBuf::from(path),
+        };
+        let not_found = |error| {
+            RuntimeError::new(
+                format!("Could not import '{}': {}.", path.display(), error),
+                span,
+            )
+        };
+        let canonical = path.canonicalize().map_err(not_found)?;
+
+        if let Some(start) = self
+            .files
+            .iter()
+            .position(|file| file.canonical == canonical)
+        {
+            let chain = self.files[start..]
+                .iter()
+                .map(|file| &file.path)
+                .chain(iter::once(&path))
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+            return Err(RuntimeError::new(
+                format!("Circular import: {}.", chain.join(" -> ")),
+                span,
+            ));
+        }
+
+        if !self.modules.contains_key(&canonical) {
+            let modified = modified(&canonical);
+            let source = fs::read_to_string(&path).map_err(not_found)?;
+            self.files.push(SourceFile {
+                path: path.clone(),
+                canonical: canonical.clone(),
+            });
+            let result = self.load_module(&source);
+            self.files.pop();
+
+            let declarations = result.map_err(|error| match error.downcast::<RuntimeError>() {
+                Ok(error) => error,
+                Err(error) => RuntimeError::new(
+                    format!("Could not import '{}': {}", path.display(), error),
+                    span,
+                ),
+            })?;
+            let module = Module {
+                path,
+                declarations,
+                importers: Vec::new(),
+                modified,
+            };
+            self.modules.insert(canonical.clone(), module);
+        }
+
+        let module = self
+            .modules
+            .get_mut(&canonical)
+            .expect("the module was just loaded");
+        // imports into blocks live in slots, which can't be rebound by name
+        if self.environment.borrow().enclosing().is_none() {
+            let importer = Rc::downgrade(&self.environment);
+            if !module.importers.iter().any(|other| other.ptr_eq(&importer)) {
+                module.importers.push(importer);
+            }
+        }
+        for (name, value) in &module.declarations {
+            self.environment.borrow_mut().define(name, value.clone());
+        }
+        Ok(())
+    }
+
+    /// Import a module again after its file changed, running its top-level
+    /// code again. The global scopes that imported it get its new functions,
+    /// classes and variables, unless they defined the same names themselves,
+    /// so new calls run the new code. Instances of its old classes keep the
+    /// methods they had. When the module no longer compiles or fails running,
+    /// the old one stays.
+    pub fn reload_module<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let read = |error| anyhow::anyhow!("Could not read '{}': {}", path.display(), error);
+        let canonical = path.canonicalize().map_err(read)?;
+        let module_path = match self.modules.get(&canonical) {
+            Some(module) => module.path.clone(),
+            None => anyhow::bail!("Module '{}' was never imported.", path.display()),
+        };
+        let modified = modified(&canonical);
+        let source = fs::read_to_string(&canonical).map_err(read)?;
+
+        self.start_run();
+        self.files.push(SourceFile {
+            path: module_path,
+            canonical: canonical.clone(),
+        });
+        let result = self.load_module(&source);
+        self.files.pop();
+        let declarations = match result.and_then(|declarations| {
+            self.run_event_loop()?;
+            Ok(declarations)
+        }) {
+            Ok(declarations) => declarations,
+            Err(error) => {
+                self.stop_run();
+                return Err(error);
+            }
+        };
+
+        let module = self
+            .modules
+            .get_mut(&canonical)
+            .expect("the module was imported");
+        let old = &module.declarations;
+        let importers = &mut module.importers;
+        importers.retain(|importer| match importer.upgrade() {
+            Some(importer) => {
+                rebind(&mut importer.borrow_mut(), old, &declarations);
+                true
+            }
+            None => false,
+        });
+        module.declarations = declarations;
+        module.modified = modified;
+        Ok(())
+    }
+
+    /// Reload every module whose file changed since it was last loaded, see
+    /// `reload_module`, returning their paths.
+    pub fn reload_changed_modules(&mut self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut changed = self
+            .modules
+            .iter()
+            .filter(|(canonical, module)| modified(canonical) != module.modified)
+            .map(|(canonical, _)| canonical.clone())
+            .collect::<Vec<_>>();
+        changed.sort();
+        for canonical in &changed {
+            self.reload_module(canonical)?;
+        }
+        Ok(changed)
+    }
+
+    /// Run a module with globals of its own, returning the values of its
+    /// top-level declarations.
+    fn load_module(&mut self, source: &str) -> anyhow::Result<Vec<(String, Value)>> {
+        let program = Parser::new(Lexer::new(source.to_string()))?.parse()?;
+        let model = resolver::resolve(&program);
+        model.check()?;
+        self.resolve(&model);
+
+        let globals = Environment::new();
+        for (name, value) in &self.builtins {
+            globals.borrow_mut().define(name, value.clone());
+        }
+        let importer = mem::replace(&mut self.globals, globals.clone());
+        let environment = mem::replace(&mut self.environment, globals.clone());
+        let result = self.execute_program(&program);
+        self.globals = importer;
+        self.environment = environment;
+        result?;
+
+        let globals = globals.borrow();
+        let declarations = model
+            .global_scope()
+            .definitions
+            .iter()
+            .map(|id| model.definition(*id).name.as_str())
+            .filter_map(|name| Some((name.to_string(), globals.get(name)?)))
+            .collect();
+        Ok(declarations)
+    }
+
+    fn execute_try(
+        &mut self,
+        body: &Stmt,
+        catch: Option<&CatchClause>,
+        finally: Option<&Stmt>,
+    ) -> Result<(), ControlFlow> {
+        let mut result = self.execute(body);
+
+        if let (Some(catch), Err(ControlFlow::Error(error))) = (catch, &result) {
+            if let Some(exception) = self.catch_exception(error) {
+                let environment = Environment::with_enclosing(self.environment.clone());
+                environment.borrow_mut().define(&catch.name.name, exception);
+                result = self.execute_block(std::slice::from_ref(&catch.body), environment);
+            }
+        }
+
+        // running out of budget aborts the program, cleanup included
+        let aborted = matches!(
+            &result,
+            Err(ControlFlow::Error(error)) if error.kind.is_abort()
+        );
+        if let (Some(finally), false) = (finally, aborted) {
+            // a `throw` on its way out survives the ones caught inside `finally`
+            let pending = self.thrown.take();
+            self.execute(finally)?;
+            self.thrown = pending;
+        }
+        result
+    }
+
+    /// The value a `catch` binds for an error, if it can catch it at all.
+    /// Start iterating over the elements of a list, the keys of a map, the
+    /// characters of a string, the numbers of a range, or what the `next` method of the object
+    /// returned by an instance's `iter` method returns until it's `nil`.
+    fn iterate(&mut self, iterable: Value, span: Span) -> Result<Iteration, RuntimeError> {
+        Ok(match iterable {
+            Value::List(list) => Iteration::List(list, 0),
+            Value::Range(range) => Iteration::Range(range.start, range.end),
+            Value::Generator(generator) => Iteration::Generator(generator),
+            Value::Map(map) => {
+                let keys = map
+                    .borrow()
+                    .keys()
+                    .map(MapKey::to_value)
+                    .collect::<Vec<_>>();
+                Iteration::Values(keys.into_iter())
+            }
+            Value::String(string) => {
+                let characters = string.chars().map(|c| Value::from(c.to_string()));
+                Iteration::Values(characters.collect::<Vec<_>>().into_iter())
+            }
+            Value::Instance(instance) => {
+                let iter = class::get_property(&instance, "iter")
+                    .ok_or_else(|| RuntimeError::new("Undefined property 'iter'.", span))?;
+                let iterator = match self.call(iter, Vec::new(), span)? {
+                    Value::Instance(iterator) => iterator,
+                    _ => return Err(RuntimeError::new("Iterators must be instances.", span)),
+                };
+                let next = class::get_property(&iterator, "next")
+                    .ok_or_else(|| RuntimeError::new("Undefined property 'next'.", span))?;
+                Iteration::Object(next)
+            }
+            _ => {
+                return Err(RuntimeError::new(
+                    format!("Can't iterate over a {}.", iterable.type_name()),
+                    span,
+                ))
+            }
+        })
+    }
+
+    fn catch_exception(&mut self, error: &RuntimeError) -> Option<Value> {
+        match error.kind {
+            RuntimeErrorKind::Thrown => self.thrown.take(),
+            RuntimeErrorKind::Error if self.catch_runtime_errors => {
+                Some(self.exception("Error", &error.message))
+            }
+            _ => None,
+        }
+    }
+
+    /// Throw an instance of the prelude class `class` with `message` from a
+    /// native, as `throw` does, so the program can catch it.
+    pub(crate) fn throw(&mut self, class: &str, message: &str, span: Span) -> RuntimeError {
+        let exception = self.exception(class, message);
+        // messages of natives end in a period already
+        let description = describe_exception(&exception);
+        let message = format!(
+            "Uncaught exception: {}.",
+            description.strip_suffix('.').unwrap_or(&description)
+        );
+        self.thrown = Some(exception);
+        RuntimeError::new(message, span).with_kind(RuntimeErrorKind::Thrown)
+    }
+
+    /// An instance of the prelude class `class` with `message`, or just the
+    /// message when the program replaced the class.
+    fn exception(&self, class: &str, message: &str) -> Value {
+        let message = Value::from(message);
+        match self.get_global(class) {
+            Some(Value::Class(class)) => {
+                let instance = LoxInstance::new(class);
+                instance
+                    .borrow_mut()
+                    .fields
+                    .insert("message".to_string(), message);
+                Value::Instance(instance)
+            }
+            _ => message,
+        }
+    }
+
+    fn execute_class(&mut self, id: NodeId, class: &ClassDecl) -> Result<(), RuntimeError> {
+        let superclass = match &class.superclass {
+            Some(expr) => match self.evaluate(expr)? {
+                Value::Class(superclass) => Some(superclass),
+                _ => return Err(RuntimeError::new("Superclass must be a class.", expr.span)),
+            },
+            None => None,
+        };
+
+        // methods of subclasses close over a scope holding `super`
+        let environment = match &superclass {
+            Some(superclass) => {
+                let environment = Environment::with_enclosing(self.environment.clone());
+                environment
+                    .borrow_mut()
+                    .define("super", Value::Class(superclass.clone()));
+                environment
+            }
+            None => self.environment.clone(),
+        };
+
+        let functions = |declarations: &[Rc<FunctionDecl>]| {
+            declarations
+                .iter()
+                .map(|method| {
+                    let function = LoxFunction::method(method.clone(), environment.clone());
+                    (method.name.name.clone(), function)
+                })
+                .collect()
+        };
+
+        let metaclass = LoxClass::new(
+            &format!("{} metaclass", class.name.name),
+            superclass
+                .as_ref()
+                .and_then(|superclass| superclass.metaclass.clone()),
+            Members {
+                methods: functions(&class.class_methods),
+                declaration: Some(id),
+                ..Members::default()
+            },
+        );
+        let class_value = LoxClass::new(
+            &class.name.name,
+            superclass,
+            Members {
+                methods: functions(&class.methods),
+                getters: functions(&class.getters),
+                setters: functions(&class.setters),
+                metaclass: Some(metaclass),
+                declaration: Some(id),
+            },
+        );
+        self.environment
+            .borrow_mut()
+            .define(&class.name.name, Value::Class(class_value));
+        Ok(())
+    }
+
+    /// Execute statements in the given environment, restoring the current one
+    /// afterwards even if execution fails.
+    pub(crate) fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: EnvRef,
+    ) -> Result<(), ControlFlow> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = statements.iter().try_for_each(|stmt| self.execute(stmt));
+        self.environment = previous;
+        result
+    }
+
+    // Expressions
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        self.step(expr.span)?;
+        match &expr.kind {
+            ExprKind::Literal(literal) => Ok(match literal {
+                Literal::Nil => Value::Nil,
+                Literal::Bool(boolean) => Value::Bool(*boolean),
+                Literal::Integer(integer) => Value::Integer(*integer),
+                Literal::Number(number) => Value::Number(*number),
+                Literal::String(string) => Value::from(string.as_str()),
+            }),
+            ExprKind::Grouping(inner) => self.evaluate(inner),
+            ExprKind::Unary { op, right } => {
+                let right = self.evaluate(right)?;
+                match op {
+                    UnaryOp::Not => Ok(Value::Bool(!right.is_truthy())),
+                    UnaryOp::Negate => match right {
+                        // only -i64::MIN doesn't fit
+                        Value::Integer(integer) => Ok(integer
+                            .checked_neg()
+                            .map_or(Value::Number(-(integer as f64)), Value::Integer)),
+                        Value::Number(number) => Ok(Value::Number(-number)),
+                        Value::Instance(instance) => {
+                            self.call_operator(&instance, "neg", "-", Vec::new(), expr.span)
+                        }
+                        _ => Err(RuntimeError::new("Operand must be a number.", expr.span)),
+                    },
+                    UnaryOp::BitNot => match right {
+                        Value::Integer(integer) => Ok(Value::Integer(!integer)),
+                        Value::Number(number) => Ok(Value::Integer(!integer(number, expr.span)?)),
+                        _ => Err(RuntimeError::new("Operand must be a number.", expr.span)),
+                    },
+                }
+            }
+            ExprKind::Binary { left, op, right } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                self.binary(*op, left, right, expr.span)
+            }
+            ExprKind::Logical { left, op, right } => {
+                let left = self.evaluate(left)?;
+                match op {
+                    LogicalOp::Or if left.is_truthy() => Ok(left),
+                    LogicalOp::And if !left.is_truthy() => Ok(left),
+                    _ => self.evaluate(right),
+                }
+            }
+            ExprKind::Variable(name) => self.look_up_variable(expr.id, name),
+            ExprKind::Assign { name, value } => {
+                let value = self.evaluate(value)?;
+                match self.locals.get(&expr.id) {
+                    Some(&(depth, slot)) => {
+                        Environment::assign_at(&self.environment, depth, slot, value.clone())
+                    }
+                    None => {
+                        let mut globals = self.globals.borrow_mut();
+                        // constants from earlier runs aren't known to the resolver
+                        if globals.is_constant(&name.name) {
+                            return Err(RuntimeError::new(
+                                format!("Can't assign to constant '{}'.", name.name),
+                                name.span,
+                            ));
+                        }
+                        if !globals.assign(&name.name, value.clone()) {
+                            return Err(undefined_variable(name));
+                        }
+                    }
+                }
+                Ok(value)
+            }
+            ExprKind::Call { callee, arguments } => {
+                let callee = self.evaluate(callee)?;
+                let arguments = self.evaluate_arguments(arguments)?;
+                self.call(callee, arguments, expr.span)
+            }
+            ExprKind::Get { object, name } => match self.evaluate(object)? {
+                object if !self.can_access(expr.id, &object, name) => Err(private_member(name)),
+                Value::Instance(instance) => {
+                    // getters shadow fields, even inside the getter itself
+                    let getter = instance.borrow().class.find_getter(&name.name);
+                    if let Some(getter) = getter {
+                        let getter = getter.bind(Value::Instance(instance));
+                        return self.call(Value::Function(getter), Vec::new(), name.span);
+                    }
+                    class::get_property(&instance, &name.name)
+                        .ok_or_else(|| undefined_property(name))
+                }
+                Value::List(list) => {
+                    list::get_method(&list, &name.name).ok_or_else(|| undefined_property(name))
+                }
+                Value::Map(map) => {
+                    map::get_method(&map, &name.name).ok_or_else(|| undefined_property(name))
+                }
+                Value::String(string) => {
+                    string::get_method(&string, &name.name).ok_or_else(|| undefined_property(name))
+                }
+                Value::Range(range) => {
+                    range::get_method(range, &name.name).ok_or_else(|| undefined_property(name))
+                }
+                Value::Class(class) => class::get_class_method(&class, &name.name)
+                    .ok_or_else(|| undefined_property(name)),
+                Value::Generator(generator) => generator::get_method(&generator, &name.name)
+                    .ok_or_else(|| undefined_property(name)),
+                Value::Channel(channel) => channel::get_method(&channel, &name.name)
+                    .ok_or_else(|| undefined_property(name)),
+                Value::Thread(thread) => channel::get_thread_method(&thread, &name.name)
+                    .ok_or_else(|| undefined_property(name)),
+                Value::WeakRef(weak) => {
+                    weak::get_method(&weak, &name.name).ok_or_else(|| undefined_property(name))
+                }
+                _ => Err(RuntimeError::new(
+                    "Only instances have properties.",
+                    name.span,
+                )),
+            },
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => match self.evaluate(object)? {
+                object if !self.can_access(expr.id, &object, name) => Err(private_member(name)),
+                Value::Instance(instance) => {
+                    let value = self.evaluate(value)?;
+                    let class = instance.borrow().class.clone();
+                    if let Some(setter) = class.find_setter(&name.name) {
+                        let setter = setter.bind(Value::Instance(instance));
+                        self.call(Value::Function(setter), vec![value.clone()], name.span)?;
+                        return Ok(value);
+                    }
+                    // the field would be hidden behind the getter
+                    if class.find_getter(&name.name).is_some() {
+                        return Err(RuntimeError::new(
+                            format!("Property '{}' has a getter but no setter.", name.name),
+                            name.span,
+                        ));
+                    }
+                    gc::allocate(name.name.len() + mem::size_of::<Value>());
+                    instance
+                        .borrow_mut()
+                        .fields
+                        .insert(name.name.clone(), value.clone());
+                    Ok(value)
+                }
+                _ => Err(RuntimeError::new("Only instances have fields.", name.span)),
+            },
+            ExprKind::List(elements) => Ok(list::new(self.evaluate_arguments(elements)?)),
+            ExprKind::Spread(_) => unreachable!("spread outside of arguments or elements"),
+            ExprKind::Map(entries) => {
+                let map = Rc::new(RefCell::new(OrderedMap::new()));
+                for (key, value) in entries {
+                    let key = self.evaluate(key)?;
+                    let key = self.map_key(&map, &key, expr.span)?;
+                    let value = self.evaluate(value)?;
+                    map.borrow_mut().insert(key, value);
+                }
+                Ok(map::new(map.take()))
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                if let Value::Instance(instance) = &object {
+                    return self.call_operator(instance, "index", "[]", vec![index], expr.span);
+                }
+                if let (Value::Map(map), Value::Instance(_)) = (&object, &index) {
+                    let key = self.map_key(map, &index, expr.span)?;
+                    let value = map.borrow().get(&key).cloned();
+                    return value.ok_or_else(|| map::undefined_key(&key).at(expr.span));
+                }
+                get_index(object, &index).map_err(|error| error.at(expr.span))
+            }
+            ExprKind::Slice { object, start, end } => {
+                let object = self.evaluate(object)?;
+                let start = start
+                    .as_ref()
+                    .map(|start| self.evaluate(start))
+                    .transpose()?;
+                let end = end.as_ref().map(|end| self.evaluate(end)).transpose()?;
+                slice(object, start.as_ref(), end.as_ref()).map_err(|error| error.at(expr.span))
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let value = self.evaluate(value)?;
+                if let Value::Instance(instance) = &object {
+                    let arguments = vec![index, value.clone()];
+                    self.call_operator(instance, "setIndex", "[]=", arguments, expr.span)?;
+                    return Ok(value);
+                }
+                if let (Value::Map(map), Value::Instance(_)) = (&object, &index) {
+                    let key = self.map_key(map, &index, expr.span)?;
+                    gc::allocate(map::ENTRY_SIZE);
+                    map.borrow_mut().insert(key, value.clone());
+                    return Ok(value);
+                }
+                set_index(object, &index, value.clone()).map_err(|error| error.at(expr.span))?;
+                Ok(value)
+            }
+            ExprKind::This => {
+                let this = Identifier {
+                    name: "this".to_string(),
+                    span: expr.span,
+                };
+                self.look_up_variable(expr.id, &this)
+            }
+            ExprKind::Super { method } => {
+                // `this` lives in the scope right inside the one holding `super`
+                let (depth, _) = self.locals[&expr.id];
+                let superclass = Environment::get_at(&self.environment, depth, 0);
+                let this = Environment::get_at(&self.environment, depth - 1, 0);
+
+                match (superclass, this) {
+                    (Some(Value::Class(superclass)), Some(this)) => {
+                        // in class methods, `super` looks up class methods
+                        let superclass = match (&this, &superclass.metaclass) {
+                            (Value::Class(_), Some(metaclass)) => metaclass.clone(),
+                            _ => superclass,
+                        };
+                        match superclass.find_method(&method.name) {
+                            Some(function) => Ok(Value::Function(function.bind(this))),
+                            None => Err(undefined_property(method)),
+                        }
+                    }
+                    _ => unreachable!("'super' resolved outside of a subclass"),
+                }
+            }
+            // the async function suspends at the statement instead
+            ExprKind::Await(_) => unreachable!("'await' resolved outside of its statement"),
+        }
+    }
+
+    fn evaluate_arguments(&mut self, arguments: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+        let mut values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            match &argument.kind {
+                ExprKind::Spread(list) => match self.evaluate(list)? {
+                    Value::List(list) => values.extend(list.borrow().iter().cloned()),
+                    other => {
+                        return Err(RuntimeError::new(
+                            format!("Can only spread lists, not {}.", other.type_name()),
+                            argument.span,
+                        ))
+                    }
+                },
+                _ => values.push(self.evaluate(argument)?),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Call `callee` as a call expression would, for natives calling back
+    /// into Lox code.
+    pub(crate) fn call(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let hook = match &mut self.hook {
+            Some(hook) => hook,
+            None => return self.call_value(callee, arguments, span),
+        };
+
+        hook.on_call(&callee, &arguments, span);
+        let result = self.call_value(callee.clone(), arguments, span);
+        if let (Some(hook), Ok(value)) = (&mut self.hook, &result) {
+            hook.on_return(&callee, value);
+        }
+        result
+    }
+
+    fn call_value(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::Function(function) => {
+                check_arity(function.arity(), arguments.len(), span)?;
+                self.call_function(&function, arguments, span)
+            }
+            Value::Native(native) => {
+                check_arity(native.arity, arguments.len(), span)?;
+                if native.nondeterministic {
+                    return self.call_nondeterministic(&native, &arguments, span);
+                }
+                native.call(self, &arguments, span)
+            }
+            Value::Class(class) => {
+                check_arity(class.arity(), arguments.len(), span)?;
+                let instance = LoxInstance::new(class.clone());
+                if let Some(initializer) = class.find_method("init") {
+                    let initializer = initializer.bind(Value::Instance(instance.clone()));
+                    self.call_function(&initializer, arguments, span)?;
+                }
+                Ok(Value::Instance(instance))
+            }
+            _ => Err(RuntimeError::new(
+                "Can only call functions and classes.",
+                span,
+            )),
+        }
+    }
+
+    fn call_nondeterministic(
+        &mut self,
+        native: &NativeFunction,
+        arguments: &[Value],
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        self.nondeterministic(&native.name, span, |interpreter| {
+            native.call(interpreter, arguments, span)
+        })
+    }
+
+    /// The result of `run`, recorded or replayed under the name `native`
+    /// when the interpreter is recording or replaying.
+    pub(crate) fn nondeterministic<F>(
+        &mut self,
+        native: &str,
+        span: Span,
+        run: F,
+    ) -> Result<Value, RuntimeError>
+    where
+        F: FnOnce(&mut Self) -> Result<Value, RuntimeError>,
+    {
+        match &mut self.replay {
+            Some((ReplayMode::Replay, log)) => log
+                .pop(native)
+                .map_err(|message| RuntimeError::new(message, span)),
+            Some((ReplayMode::Record, _)) => {
+                let value = run(self)?;
+                if let Some((_, log)) = &mut self.replay {
+                    log.push(native, value.clone())
+                        .map_err(|message| RuntimeError::new(message, span))?;
+                }
+                Ok(value)
+            }
+            None => run(self),
+        }
+    }
+
+    /// The next line of the input without its line ending, or nil once
+    /// it's all read, after writing `prompt` where the program prints.
+    pub(crate) fn read_line(&mut self, prompt: &str, span: Span) -> Result<Value, RuntimeError> {
+        write!(self.output, "{}", prompt)
+            .and_then(|()| self.output.flush())
+            .map_err(|error| RuntimeError::new(format!("Could not print: {}.", error), span))?;
+        self.nondeterministic("input", span, |interpreter| {
+            let mut line = String::new();
+            let read = match &mut interpreter.input {
+                Some(input) => input.read_line(&mut line),
+                None => io::stdin().read_line(&mut line),
+            };
+            match read {
+                Ok(0) => Ok(Value::Nil),
+                Ok(_) => {
+                    if line.ends_with('\n') {
+                        line.pop();
+                        if line.ends_with('\r') {
+                            line.pop();
+                        }
+                    }
+                    Ok(Value::from(line))
+                }
+                Err(error) => Err(RuntimeError::new(
+                    format!("Could not read input: {}.", error),
+                    span,
+                )),
+            }
+        })
+    }
+
+    pub(crate) fn call_function(
+        &mut self,
+        function: &LoxFunction,
+        arguments: Vec<Value>,
+        call_span: Span,
+    ) -> Result<Value, RuntimeError> {
+        if self.frames.len() >= self.max_call_depth || stack::exhausted() {
+            return Err(RuntimeError::new("Stack overflow.", call_span));
+        }
+        self.frames.push(CallFrame {
+            function: function.name().to_string(),
+            call_span,
+            deferred: Vec::new(),
+        });
+        let result = self.run_function(function, arguments);
+        self.frames.pop();
+        result
+    }
+
+    /// Run the body of a function in the current frame. Tail calls to other
+    /// Lox functions reuse the frame, so they don't grow the Rust stack nor
+    /// show up in stack traces.
+    fn run_function(
+        &mut self,
+        function: &LoxFunction,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let mut tail_callee;
+        let mut function = function;
+        let mut arguments = arguments;
+
+        loop {
+            let environment = Environment::with_enclosing(function.closure.clone());
+            self.bind_arguments(&function.declaration, arguments, &environment)
+                .map_err(|error| self.with_trace(error))?;
+            if self.generators.contains(&function.declaration.id) {
+                let generator = generator::new(function.declaration.clone(), environment);
+                return Ok(Value::Generator(generator));
+            }
+            if function.declaration.is_async {
+                let span = self.frames.last().map(|frame| frame.call_span);
+                return Ok(self.start_async(&function.declaration, environment, span));
+            }
+
+            let mut result = self.execute_block(&function.declaration.body, environment);
+            if self
+                .frames
+                .last()
+                .is_some_and(|frame| !frame.deferred.is_empty())
+            {
+                // the deferred expressions run after the tail call
+                if let Err(ControlFlow::TailCall(callee, arguments, span)) = result {
+                    result = Err(match self.call(callee, arguments, span) {
+                        Ok(value) => ControlFlow::Return(value),
+                        Err(error) => ControlFlow::Error(self.with_trace(error)),
+                    });
+                }
+                result = self.run_deferred(result);
+            }
+
+            let value = match result {
+                Ok(()) => Value::Nil,
+                Err(ControlFlow::Return(value)) => value,
+                Err(ControlFlow::TailCall(Value::Function(callee), next_arguments, _))
+                    if callee.arity().accepts(next_arguments.len()) =>
+                {
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.function = callee.name().to_string();
+                    }
+                    tail_callee = callee;
+                    function = &tail_callee;
+                    arguments = next_arguments;
+                    continue;
+                }
+                Err(ControlFlow::TailCall(callee, arguments, span)) => self
+                    .call(callee, arguments, span)
+                    .map_err(|error| self.with_trace(error))?,
+                Err(ControlFlow::Error(error)) => return Err(self.with_trace(error)),
+                Err(ControlFlow::Break | ControlFlow::Continue) => {
+                    unreachable!("loop control outside of a loop")
+                }
+            };
+
+            // initializers always hand back the instance
+            return if function.is_initializer {
+                Ok(Environment::get_at(&function.closure, 0, 0).unwrap_or(Value::Nil))
+            } else {
+                Ok(value)
+            };
+        }
+    }
+
+    // evaluate what the current frame deferred, last first. An error in one
+    // takes over from how the function ended, but the others still run.
+    fn run_deferred(&mut self, result: Result<(), ControlFlow>) -> Result<(), ControlFlow> {
+        let mut deferred = match self.frames.last_mut() {
+            Some(frame) => mem::take(&mut frame.deferred),
+            None => return result,
+        };
+        if matches!(&result, Err(ControlFlow::Error(error)) if error.kind.is_abort()) {
+            return result;
+        }
+
+        let mut result = result;
+        let pending = self.thrown.take();
+        let mut replaced = false;
+        while let Some((expr, environment)) = deferred.pop() {
+            let previous = mem::replace(&mut self.environment, environment);
+            let value = self.evaluate(&expr);
+            self.environment = previous;
+            if let Err(error) = value {
+                let abort = error.kind.is_abort();
+                result = Err(ControlFlow::Error(error));
+                replaced = true;
+                if abort {
+                    break;
+                }
+            }
+        }
+        if !replaced {
+            self.thrown = pending;
+        }
+        result
+    }
+
+    /// Run a generator up to its next `yield`, returning the value yielded,
+    /// or `None` once its body is done.
+    pub(crate) fn resume(
+        &mut self,
+        generator: &GeneratorRef,
+        span: Span,
+    ) -> Result<Option<Value>, RuntimeError> {
+        Ok(match self.resume_body(generator, None, span)? {
+            Suspension::Yielded(value) => Some(value),
+            Suspension::Returned(_) => None,
+        })
+    }
+
+    // run a generator or async function until it suspends again, first
+    // finishing the `await` it's suspended at with how the promise settled
+    fn resume_body(
+        &mut self,
+        generator: &GeneratorRef,
+        settled: Option<Result<Value, Value>>,
+        span: Span,
+    ) -> Result<Suspension, RuntimeError> {
+        let (declaration, scope, mut cursors, deferred) = {
+            let mut generator = generator.borrow_mut();
+            if generator.running {
+                return Err(RuntimeError::new("Generator is already running.", span));
+            }
+            generator.running = true;
+            let cursors = mem::take(&mut generator.cursors);
+            let deferred = mem::take(&mut generator.deferred);
+            (
+                generator.declaration.clone(),
+                generator.environment.clone(),
+                cursors,
+                deferred,
+            )
+        };
+
+        let (result, deferred) = if self.frames.len() >= self.max_call_depth || stack::exhausted() {
+            (Err(RuntimeError::new("Stack overflow.", span)), Vec::new())
+        } else {
+            self.frames.push(CallFrame {
+                function: declaration.name.name.clone(),
+                call_span: span,
+                deferred,
+            });
+            let previous = self.environment.clone();
+            let result = match self.run_generator(&declaration.body, &scope, &mut cursors, settled)
+            {
+                Ok(Suspension::Yielded(value)) => Ok(Suspension::Yielded(value)),
+                // the body is done, one way or another
+                Ok(Suspension::Returned(value)) => {
+                    self.finish_body(Err(ControlFlow::Return(value)))
+                }
+                Err(error) => self.finish_body(Err(ControlFlow::Error(error))),
+            };
+            self.environment = previous;
+            let result = result.map_err(|error| self.with_trace(error));
+            let frame = self.frames.pop().expect("the frame was pushed above");
+            (result, frame.deferred)
+        };
+
+        let mut generator = generator.borrow_mut();
+        generator.running = false;
+        // a generator that failed is done too
+        if let Ok(Suspension::Yielded(_)) = result {
+            generator.cursors = cursors;
+            generator.deferred = deferred;
+        }
+        result
+    }
+
+    // run what the body of a generator or async function deferred once it's
+    // done
+    fn finish_body(&mut self, result: Result<(), ControlFlow>) -> Result<Suspension, RuntimeError> {
+        match self.run_deferred(result) {
+            Err(ControlFlow::Return(value)) => Ok(Suspension::Returned(value)),
+            Err(ControlFlow::Error(error)) => Err(error),
+            _ => unreachable!("the body of a generator ends with a return or an error"),
+        }
+    }
+
+    // deliver the value of the promise awaited by the statement the last
+    // cursor just ran, or throw why it was rejected from there
+    fn finish_await(
+        &mut self,
+        body: &[Stmt],
+        scope: &EnvRef,
+        cursors: &[Cursor],
+        settled: Result<Value, Value>,
+    ) -> Result<(), RuntimeError> {
+        self.environment = generator::environment(scope, cursors);
+        let index = cursors.last().map_or(0, |cursor| cursor.index);
+        let stmt = &generator::statements(body, cursors)[index - 1];
+        match settled {
+            Ok(value) => {
+                if let StmtKind::Var { name, .. } = &stmt.kind {
+                    self.environment.borrow_mut().define(&name.name, value);
+                }
+                Ok(())
+            }
+            Err(reason) => {
+                let message = format!("Uncaught exception: {}.", describe_exception(&reason));
+                self.thrown = Some(reason);
+                Err(RuntimeError::new(message, stmt.span).with_kind(RuntimeErrorKind::Thrown))
+            }
+        }
+    }
+
+    fn run_generator(
+        &mut self,
+        body: &[Stmt],
+        scope: &EnvRef,
+        cursors: &mut Vec<Cursor>,
+        settled: Option<Result<Value, Value>>,
+    ) -> Result<Suspension, RuntimeError> {
+        let mut leaving = match settled {
+            Some(settled) => self
+                .finish_await(body, scope, cursors, settled)
+                .err()
+                .map(ControlFlow::Error),
+            None => None,
+        };
+        loop {
+            let flow = match leaving.take() {
+                Some(flow) => flow,
+                None if cursors.is_empty() => return Ok(Suspension::Returned(Value::Nil)),
+                None => match self.advance(body, scope, cursors) {
+                    Ok(Some(suspension)) => return Ok(suspension),
+                    Ok(None) => continue,
+                    Err(flow) => flow,
+                },
+            };
+            if let Some(suspension) = self.leave(body, scope, cursors, flow)? {
+                return Ok(suspension);
+            }
+        }
+    }
+
+    // run the next statement of the last cursor, or step out of the
+    // statements it moves through once they're done
+    fn advance(
+        &mut self,
+        body: &[Stmt],
+        scope: &EnvRef,
+        cursors: &mut Vec<Cursor>,
+    ) -> Result<Option<Suspension>, ControlFlow> {
+        let depth = cursors.len() - 1;
+        self.environment = generator::environment(scope, cursors);
+        let statements = generator::statements(body, cursors);
+        let stmt = match statements.get(cursors[depth].index) {
+            Some(stmt) => stmt,
+            None => {
+                cursors.pop();
+                self.resume_after_inner(body, scope, cursors)?;
+                return Ok(None);
+            }
+        };
+        cursors[depth].index += 1;
+
+        if !self.yielding.contains(&stmt.id) {
+            return match self.execute(stmt) {
+                Ok(()) => Ok(None),
+                Err(ControlFlow::TailCall(callee, arguments, span)) => {
+                    let value = self.call(callee, arguments, span)?;
+                    Err(ControlFlow::Return(value))
+                }
+                Err(flow) => Err(flow),
+            };
+        }
+
+        self.enter_statement(stmt)?;
+        let (inside, environment) = match &stmt.kind {
+            StmtKind::Yield(value) => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
+                };
+                return Ok(Some(Suspension::Yielded(value)));
+            }
+            StmtKind::Expression(Expr {
+                kind: ExprKind::Await(promise),
+                ..
+            })
+            | StmtKind::Var {
+                initializer:
+                    Some(Expr {
+                        kind: ExprKind::Await(promise),
+                        ..
+                    }),
+                ..
+            } => return Ok(Some(Suspension::Yielded(self.evaluate(promise)?))),
+            StmtKind::Block(_) => {
+                let environment = Environment::with_enclosing(self.environment.clone());
+                (Inside::Block, Some(environment))
+            }
+            StmtKind::If {
+                condition,
+                else_branch,
+                ..
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    (Inside::Then, None)
+                } else if else_branch.is_some() {
+                    (Inside::Else, None)
+                } else {
+                    return Ok(None);
+                }
+            }
+            StmtKind::While { condition, .. } => {
+                if !self.evaluate(condition)?.is_truthy() {
+                    return Ok(None);
+                }
+                (Inside::While, None)
+            }
+            StmtKind::ForIn { name, iterable, .. } => {
+                let iterable = self.evaluate(iterable)?;
+                let mut iteration = self.iterate(iterable, stmt.span)?;
+                let element = match iteration.next(self, stmt.span)? {
+                    Some(element) => element,
+                    None => return Ok(None),
+                };
+                let environment = Environment::with_enclosing(self.environment.clone());
+                environment.borrow_mut().define(&name.name, element);
+                (Inside::ForIn(iteration), Some(environment))
+            }
+            StmtKind::Try { .. } => (Inside::Try, None),
+            _ => unreachable!("only blocks, branches, loops and try statements can hold a yield"),
+        };
+        cursors[depth].inside = Some(inside);
+        cursors.push(Cursor::new(environment));
+        Ok(None)
+    }
+
+    // carry `flow` out of the statements the generator is suspended in, up
+    // to the loop it breaks out of or the try statement handling it, as
+    // `execute` would; what gets out of the body ends the run
+    fn leave(
+        &mut self,
+        body: &[Stmt],
+        scope: &EnvRef,
+        cursors: &mut Vec<Cursor>,
+        mut flow: ControlFlow,
+    ) -> Result<Option<Suspension>, RuntimeError> {
+        loop {
+            // running out of budget aborts the program, cleanup included
+            let aborted = matches!(&flow, ControlFlow::Error(error) if error.kind.is_abort());
+            let depth = match cursors.len().checked_sub(1) {
+                Some(depth) if !aborted => depth,
+                _ => {
+                    return match flow {
+                        ControlFlow::Return(value) => Ok(Some(Suspension::Returned(value))),
+                        ControlFlow::Error(error) => Err(error),
+                        _ => unreachable!("control flow statement outside of its construct"),
+                    }
+                }
+            };
+            self.environment = generator::environment(scope, cursors);
+
+            let looping = matches!(
+                cursors[depth].inside,
+                Some(Inside::While | Inside::ForIn(_))
+            );
+            match flow {
+                ControlFlow::Break if looping => {
+                    cursors[depth].inside = None;
+                    return Ok(None);
+                }
+                ControlFlow::Continue if looping => {
+                    match self.resume_after_inner(body, scope, cursors) {
+                        Ok(()) => return Ok(None),
+                        Err(next) => flow = next,
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            if let Some(Inside::Try | Inside::Catch) = cursors[depth].inside {
+                let owner = &generator::statements(body, cursors)[cursors[depth].index - 1];
+                let (catch, finally) = match &owner.kind {
+                    StmtKind::Try { catch, finally, .. } => (catch, finally),
+                    _ => unreachable!("generator cursors out of step with the body"),
+                };
+                if let (Some(Inside::Try), Some(catch), ControlFlow::Error(error)) =
+                    (&cursors[depth].inside, catch, &flow)
+                {
+                    if let Some(exception) = self.catch_exception(error) {
+                        let environment = Environment::with_enclosing(self.environment.clone());
+                        environment.borrow_mut().define(&catch.name.name, exception);
+                        cursors[depth].inside = Some(Inside::Catch);
+                        cursors.push(Cursor::new(Some(environment)));
+                        return Ok(None);
+                    }
+                }
+                if finally.is_some() {
+                    enter_finally(cursors, Some(flow), self.thrown.take());
+                    return Ok(None);
+                }
+            }
+            cursors.pop();
+        }
+    }
+
+    // the body of an async function starts running from the event loop,
+    // once the code calling it is done
+    fn start_async(
+        &mut self,
+        declaration: &Rc<FunctionDecl>,
+        environment: EnvRef,
+        span: Option<Span>,
+    ) -> Value {
+        let promise = promise::new();
+        self.tasks.push_back(Task {
+            coroutine: generator::new(declaration.clone(), environment),
+            promise: promise.clone(),
+            settled: None,
+            span: span.unwrap_or(declaration.name.span),
+        });
+        Value::Promise(promise)
+    }
+
+    /// A promise fulfilled with `nil` once `duration` has passed.
+    pub(crate) fn sleep_async(&mut self, duration: Duration, span: Span) -> Value {
+        let promise = promise::new();
+        self.add_timer(duration, TimerAction::Settle(promise.clone()), span);
+        Value::Promise(promise)
+    }
+
+    /// Have the event loop call `callback` once `duration` has passed,
+    /// giving the id `clear_timeout` takes.
+    pub(crate) fn set_timeout(&mut self, callback: Value, duration: Duration, span: Span) -> i64 {
+        self.add_timer(duration, TimerAction::Call(callback), span)
+    }
+
+    /// Cancel a timeout that hasn't run yet, giving whether there was one.
+    pub(crate) fn clear_timeout(&mut self, id: i64) -> bool {
+        let index = self
+            .timers
+            .iter()
+            .position(|timer| timer.id == id && matches!(timer.action, TimerAction::Call(_)));
+        index.map(|index| self.timers.remove(index)).is_some()
+    }
+
+    /// Block for `duration`, unless the program is interrupted or runs out
+    /// of time first.
+    pub(crate) fn sleep(&mut self, duration: Duration, span: Span) -> Result<(), RuntimeError> {
+        self.wait_until(Instant::now() + duration, span)
+    }
+
+    // timers due at the same time go off in the order they were set
+    fn add_timer(&mut self, duration: Duration, action: TimerAction, span: Span) -> i64 {
+        self.last_timer_id += 1;
+        let deadline = Instant::now() + duration;
+        let index = self
+            .timers
+            .partition_point(|timer| timer.deadline <= deadline);
+        let timer = Timer {
+            deadline,
+            id: self.last_timer_id,
+            action,
+            span,
+        };
+        self.timers.insert(index, timer);
+        self.last_timer_id
+    }
+
+    fn wait_until(&mut self, deadline: Instant, span: Span) -> Result<(), RuntimeError> {
+        // wake up now and then to notice interrupts and timeouts
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            self.check_waiting(span)?;
+            thread::sleep(left.min(TIMER_POLL_INTERVAL));
+        }
+        Ok(())
+    }
+
+    /// Run the async functions the program started, resuming them as the
+    /// promises they await settle, until nothing is left to wait for.
+    fn run_event_loop(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            while let Some(task) = self.tasks.pop_front() {
+                self.run_task(task)?;
+            }
+            let (deadline, span) = match self.timers.first() {
+                Some(timer) => (timer.deadline, timer.span),
+                None => break,
+            };
+            self.wait_until(deadline, span)?;
+            let timer = self.timers.remove(0);
+            match timer.action {
+                TimerAction::Settle(promise) => self.settle(&promise, Ok(Value::Nil)),
+                TimerAction::Call(callback) => {
+                    self.call(callback, Vec::new(), timer.span)?;
+                }
+            }
+        }
+
+        let rejections = mem::take(&mut self.rejections);
+        match rejections
+            .into_iter()
+            .find(|(promise, _)| !promise.borrow().handled)
+        {
+            Some((_, error)) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Fail if the program was interrupted or ran out of time while blocked,
+    /// as it takes no steps then.
+    pub(crate) fn check_waiting(&mut self, span: Span) -> Result<(), RuntimeError> {
+        self.step(span)?;
+        self.print_threads(span)?;
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(RuntimeError::new("Execution limit exceeded.", span)
+                .with_kind(RuntimeErrorKind::ExecutionLimitExceeded));
+        }
+        Ok(())
+    }
+
+    fn run_task(&mut self, task: Task) -> Result<(), RuntimeError> {
+        let Task {
+            coroutine,
+            promise,
+            settled,
+            span,
+        } = task;
+        match self.resume_body(&coroutine, settled, span) {
+            Ok(Suspension::Yielded(awaited)) => {
+                let waiter = Waiter::Resume {
+                    coroutine,
+                    promise,
+                    span,
+                };
+                self.await_value(awaited, waiter);
+            }
+            // returning a promise settles with it
+            Ok(Suspension::Returned(value)) => self.await_value(value, Waiter::Forward(promise)),
+            Err(error) if error.kind == RuntimeErrorKind::Thrown => {
+                let reason = self.thrown.take().unwrap_or(Value::Nil);
+                self.settle(&promise, Err(reason));
+                self.rejections.push((promise, error));
+            }
+            Err(error) => return Err(error),
+        }
+        Ok(())
+    }
+
+    // have `waiter` wait for `value`, right away unless it's a pending promise
+    fn await_value(&mut self, value: Value, waiter: Waiter) {
+        let awaited = match value {
+            Value::Promise(awaited) => awaited,
+            value => return self.wake(waiter, Ok(value)),
+        };
+        let mut awaited = awaited.borrow_mut();
+        awaited.handled = true;
+        let settled = match &mut awaited.state {
+            State::Pending(waiters) => return waiters.push(waiter),
+            State::Fulfilled(value) => Ok(value.clone()),
+            State::Rejected(reason) => Err(reason.clone()),
+        };
+        drop(awaited);
+        self.wake(waiter, settled);
+    }
+
+    fn wake(&mut self, waiter: Waiter, settled: Result<Value, Value>) {
+        match waiter {
+            Waiter::Resume {
+                coroutine,
+                promise,
+                span,
+            } => self.tasks.push_back(Task {
+                coroutine,
+                promise,
+                settled: Some(settled),
+                span,
+            }),
+            Waiter::Forward(promise) => self.settle(&promise, settled),
+        }
+    }
+
+    fn settle(&mut self, promise: &PromiseRef, settled: Result<Value, Value>) {
+        let state = match &settled {
+            Ok(value) => State::Fulfilled(value.clone()),
+            Err(reason) => State::Rejected(reason.clone()),
+        };
+        let waiters = match mem::replace(&mut promise.borrow_mut().state, state) {
+            State::Pending(waiters) => waiters,
+            _ => unreachable!("promise settled twice"),
+        };
+        for waiter in waiters {
+            self.wake(waiter, settled.clone());
+        }
+    }
+
+    // once the statements inside the one the last cursor is at are done,
+    // loop again or move past it
+    fn resume_after_inner(
+        &mut self,
+        body: &[Stmt],
+        scope: &EnvRef,
+        cursors: &mut Vec<Cursor>,
+    ) -> Result<(), ControlFlow> {
+        let depth = match cursors.len().checked_sub(1) {
+            Some(depth) => depth,
+            None => return Ok(()),
+        };
+        self.environment = generator::environment(scope, cursors);
+        let owner = &generator::statements(body, cursors)[cursors[depth].index - 1];
+
+        if let Some(Inside::Try | Inside::Catch | Inside::Finally(..)) = cursors[depth].inside {
+            return match (cursors[depth].inside.take(), &owner.kind) {
+                (Some(Inside::Finally(pending, thrown)), _) => {
+                    self.thrown = thrown;
+                    pending.map_or(Ok(()), Err)
+                }
+                (
+                    _,
+                    StmtKind::Try {
+                        finally: Some(_), ..
+                    },
+                ) => {
+                    enter_finally(cursors, None, self.thrown.take());
+                    Ok(())
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let environment = match (&owner.kind, &mut cursors[depth].inside) {
+            (
+                StmtKind::While {
+                    condition,
+                    increment,
+                    ..
+                },
+                Some(Inside::While),
+            ) => {
+                if let Some(increment) = increment {
+                    self.evaluate(increment)?;
+                }
+                self.evaluate(condition)?.is_truthy().then_some(None)
+            }
+            (StmtKind::ForIn { name, .. }, Some(Inside::ForIn(iteration))) => {
+                iteration.next(self, owner.span)?.map(|element| {
+                    let environment = Environment::with_enclosing(self.environment.clone());
+                    environment.borrow_mut().define(&name.name, element);
+                    Some(environment)
+                })
+            }
+            _ => None,
+        };
+        match environment {
+            Some(environment) => cursors.push(Cursor::new(environment)),
+            None => cursors[depth].inside = None,
+        }
+        Ok(())
+    }
+
+    /// Define the parameters of a call in `environment`, evaluating the
+    /// defaults of the missing arguments in it as it fills up.
+    fn bind_arguments(
+        &mut self,
+        declaration: &FunctionDecl,
+        arguments: Vec<Value>,
+        environment: &EnvRef,
+    ) -> Result<(), RuntimeError> {
+        let required = declaration.params.len() - declaration.defaults.len();
+        let mut arguments = arguments.into_iter();
+        for (i, param) in declaration.params.iter().enumerate() {
+            let value = match arguments.next() {
+                Some(argument) => argument,
+                None => {
+                    let default = &declaration.defaults[i - required];
+                    let previous = mem::replace(&mut self.environment, environment.clone());
+                    let value = self.evaluate(default);
+                    self.environment = previous;
+                    value?
+                }
+            };
+            environment.borrow_mut().define(&param.name, value);
+        }
+        if let Some(rest) = &declaration.rest {
+            environment
+                .borrow_mut()
+                .define(&rest.name, list::new(arguments.collect()));
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, span: Span) -> Result<(), RuntimeError> {
+        self.steps += 1;
+
+        if self.interrupt.take() {
+            return Err(
+                RuntimeError::new("Interrupted.", span).with_kind(RuntimeErrorKind::Interrupted)
+            );
+        }
+
+        if let Some(limit) = self.memory_limit {
+            if gc::may_exceed(limit) && self.out_of_memory(limit) {
+                self.thrown = Some(self.exception("OutOfMemoryError", "Out of memory."));
+                // measure again with the exception, or it'd count as growth
+                // and keep the program from running what lets go of memory
+                gc::memory_used();
+                return Err(
+                    RuntimeError::new("Uncaught exception: Out of memory.", span)
+                        .with_kind(RuntimeErrorKind::Thrown),
+                );
+            }
+        }
+
+        let out_of_steps =
+            matches!(self.limits.max_steps, Some(max_steps) if self.steps > max_steps);
+        let out_of_time = match self.deadline {
+            Some(deadline) if self.steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) => {
+                Instant::now() >= deadline
+            }
+            _ => false,
+        };
+
+        if out_of_steps || out_of_time {
+            Err(RuntimeError::new("Execution limit exceeded.", span)
+                .with_kind(RuntimeErrorKind::ExecutionLimitExceeded))
+        } else {
+            Ok(())
+        }
+    }
+
+    // whether the objects take more than `limit` bytes, even without cycles
+    fn out_of_memory(&self, limit: usize) -> bool {
+        if gc::memory_used() <= limit {
+            return false;
+        }
+        gc::collect();
+        gc::memory_used() > limit
+    }
+
+    /// Record the call stack in an error raised by the innermost frame.
+    fn with_trace(&self, mut error: RuntimeError) -> RuntimeError {
+        if !error.trace.is_empty() {
+            return error;
+        }
+
+        // each frame is executing the line where the next one was called
+        let mut line = error.span.line;
+        for frame in self.frames.iter().rev() {
+            error.trace.push(TraceFrame {
+                function: Some(frame.function.clone()),
+                line,
+            });
+            line = frame.call_span.line;
+        }
+        error.trace.push(TraceFrame {
+            function: None,
+            line,
+        });
+        error
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        left: Value,
+        right: Value,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        if let (Value::Instance(instance), Some((name, symbol))) = (&left, overload(op)) {
+            let overloaded = instance.borrow().class.find_method(name).is_some();
+            // without `eq`, instances are only equal to themselves, and
+            // without `plus` they can still be coerced into strings
+            let coerced =
+                op == BinaryOp::Add && self.string_coercion && matches!(right, Value::String(_));
+            let fallback = matches!(op, BinaryOp::Equal | BinaryOp::NotEqual) || coerced;
+            if overloaded || !fallback {
+                let result = self.call_operator(instance, name, symbol, vec![right], span)?;
+                return match op {
+                    BinaryOp::Add
+                    | BinaryOp::Subtract
+                    | BinaryOp::Multiply
+                    | BinaryOp::Divide
+                    | BinaryOp::FloorDivide
+                    | BinaryOp::Modulo => Ok(result),
+                    BinaryOp::NotEqual => Ok(Value::Bool(!as_boolean(&result, name, span)?)),
+                    _ => as_boolean(&result, name, span).map(Value::Bool),
+                };
+            }
+        }
+
+        match op {
+            BinaryOp::Equal => return Ok(Value::Bool(left == right)),
+            BinaryOp::NotEqual => return Ok(Value::Bool(left != right)),
+            BinaryOp::Add => return self.add(left, right, span),
+            BinaryOp::Range => {
+                let range = Range::new(&left, &right).map_err(|error| error.at(span))?;
+                return Ok(Value::Range(range));
+            }
+            _ => {}
+        }
+        arithmetic(op, &left, &right, span)
+    }
+
+    /// Whether the property `name` of `object` can be used by the `access`
+    /// expression: private members, starting with `_`, only from inside the
+    /// class of the object or one of its superclasses.
+    fn can_access(&self, access: NodeId, object: &Value, name: &Identifier) -> bool {
+        if !name.name.starts_with('_') {
+            return true;
+        }
+        let class = match object {
+            Value::Instance(instance) => instance.borrow().class.clone(),
+            Value::Class(class) => class.clone(),
+            _ => return true,
+        };
+        match self.private_accesses.get(&access) {
+            Some(&declaration) => class.is_declared_by(declaration),
+            None => false,
+        }
+    }
+
+    /// The key `value` is stored under in `map`.
+    ///
+    /// Instances are hashed with their `hash` method, and looked up among the
+    /// keys with the same hash using their `eq` method, so equal instances
+    /// share a key.
+    pub(crate) fn map_key(
+        &mut self,
+        map: &MapRef,
+        value: &Value,
+        span: Span,
+    ) -> Result<MapKey, RuntimeError> {
+        let instance = match value {
+            Value::Instance(instance) if instance.borrow().class.find_method("hash").is_some() => {
+                instance
+            }
+            _ => return MapKey::new(value).map_err(|error| error.at(span)),
+        };
+        // integers hash like the floats they're equal to
+        let hash = match self.call_operator(instance, "hash", "hash", Vec::new(), span)? {
+            hash @ (Value::Integer(_) | Value::Number(_)) => {
+                (hash.as_number().unwrap_or_default() + 0.0).to_bits()
+            }
+            other => {
+                return Err(RuntimeError::new(
+                    format!(
+                        "Method 'hash' must return a number, not {}.",
+                        other.type_name()
+                    ),
+                    span,
+                ))
+            }
+        };
+
+        let mut key = InstanceKey {
+            hash,
+            collision: 0,
+            instance: instance.clone(),
+        };
+        loop {
+            // `eq` may change the map, so it can't stay borrowed
+            let existing = map
+                .borrow()
+                .get_key_value(&MapKey::Instance(key.clone()))
+                .map(|(existing, _)| existing.clone());
+            let existing = match existing {
+                Some(existing) => existing,
+                None => return Ok(MapKey::Instance(key)),
+            };
+            let equal = self.binary(BinaryOp::Equal, value.clone(), existing.to_value(), span)?;
+            if equal == Value::Bool(true) {
+                return Ok(existing);
+            }
+            key.collision += 1;
+        }
+    }
+
+    /// Whether `left` and `right` are equal, using `==` and so the `eq`
+    /// method of instances.
+    pub(crate) fn equal(
+        &mut self,
+        left: Value,
+        right: Value,
+        span: Span,
+    ) -> Result<bool, RuntimeError> {
+        let result = self.binary(BinaryOp::Equal, left, right, span)?;
+        Ok(result == Value::Bool(true))
+    }
+
+    /// Whether `left` sorts before `right`, using `<` and so the `lt` method
+    /// of instances.
+    pub(crate) fn less(
+        &mut self,
+        left: Value,
+        right: Value,
+        span: Span,
+    ) -> Result<bool, RuntimeError> {
+        let result = self.binary(BinaryOp::Less, left, right, span)?;
+        Ok(result == Value::Bool(true))
+    }
+
+    /// Call the method overloading `symbol` for `instance`.
+    fn call_operator(
+        &mut self,
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &str,
+        symbol: &str,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let method = instance.borrow().class.find_method(name);
+        let method = method.ok_or_else(|| missing_operator(instance, name, symbol, span))?;
+        let method = method.bind(Value::Instance(instance.clone()));
+        self.call(Value::Function(method), arguments, span)
+    }
+
+    /// The text `print` and string coercion show for `value`, which is what
+    /// the `toString` or `str` method returns for instances defining one.
+    pub(crate) fn stringify(&mut self, value: Value, span: Span) -> Result<String, RuntimeError> {
+        let (instance, name) = match &value {
+            Value::Instance(instance) => match string_method(instance) {
+                Some(name) => (instance.clone(), name),
+                None => return Ok(value.to_string()),
+            },
+            _ => return Ok(value.to_string()),
+        };
+        match self.call_operator(&instance, name, "print", Vec::new(), span)? {
+            Value::String(string) => Ok(string.to_string()),
+            other => Err(RuntimeError::new(
+                format!(
+                    "Method '{}' must return a string, not {}.",
+                    name,
+                    other.type_name()
+                ),
+                span,
+            )),
+        }
+    }
+
+    fn add(&mut self, left: Value, right: Value, span: Span) -> Result<Value, RuntimeError> {
+        match (left, right) {
+            (left, right) if left.as_number().is_some() && right.as_number().is_some() => {
+                arithmetic(BinaryOp::Add, &left, &right, span)
+            }
+            (Value::String(left), Value::String(right)) => {
+                Ok(Value::from(format!("{}{}", left, right)))
+            }
+            (left @ Value::String(_), right) | (left, right @ Value::String(_))
+                if self.string_coercion =>
+            {
+                let left = self.stringify(left, span)?;
+                let right = self.stringify(right, span)?;
+                Ok(Value::from(left + &right))
+            }
+            (left, right) if is_string_and_number(&left, &right) => Err(RuntimeError::new(
+                format!(
+                    "Can't add {} and {}, operands must be two numbers or two strings.",
+                    left.type_name(),
+                    right.type_name()
+                ),
+                span,
+            )),
+            _ => Err(RuntimeError::new(
+                "Operands must be two numbers or two strings.",
+                span,
+            )),
+        }
+    }
+
+    fn look_up_variable(&self, id: NodeId, name: &Identifier) -> Result<Value, RuntimeError> {
+        let value = match self.locals.get(&id) {
+            Some(&(depth, slot)) => Environment::get_at(&self.environment, depth, slot),
+            None => self.globals.borrow().get(&name.name),
+        };
+        value.ok_or_else(|| undefined_variable(name))
+    }
+}
+
+fn is_string_and_number(left: &Value, right: &Value) -> bool {
+    matches!(
+        (left, right),
+        (Value::String(_), Value::Integer(_) | Value::Number(_))
+            | (Value::Integer(_) | Value::Number(_), Value::String(_))
+    )
+}
+
+// instances carrying a message, like the ones of `Error`, are shown by it
+fn describe_exception(value: &Value) -> String {
+    if let Value::Instance(instance) = value {
+        let instance = instance.borrow();
+        if let Some(message) = instance.fields.get("message") {
+            return format!("{}: {}", instance.class.name, message);
+        }
+    }
+    value.to_string()
+}
+
+// when a file was last modified, if the system can tell
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// give an importer the new declarations of a module, leaving alone the
+// names it bound to something else since importing the old ones
+fn rebind(importer: &mut Environment, old: &[(String, Value)], new: &[(String, Value)]) {
+    for (name, value) in new {
+        let previous = old
+            .iter()
+            .find(|(old, _)| old == name)
+            .map(|(_, value)| value);
+        let unchanged = match (importer.get(name), previous) {
+            (Some(current), Some(previous)) => current.is_identical(previous),
+            (current, _) => current.is_none(),
+        };
+        if unchanged {
+            importer.define(name, value.clone());
+        }
+    }
+}
+
+fn check_arity(arity: Arity, got: usize, span: Span) -> Result<(), RuntimeError> {
+    if arity.accepts(got) {
+        Ok(())
+    } else {
+        Err(RuntimeError::new(
+            format!("Expected {} arguments but got {}.", arity, got),
+            span,
+        ))
+    }
+}
+
+fn undefined_property(name: &Identifier) -> RuntimeError {
+    RuntimeError::new(format!("Undefined property '{}'.", name.name), name.span)
+}
+
+/// The method turning an instance into a string, if its class has one.
+fn string_method(instance: &Rc<RefCell<LoxInstance>>) -> Option<&'static str> {
+    let class = instance.borrow().class.clone();
+    ["toString", "str"]
+        .iter()
+        .copied()
+        .find(|name| class.find_method(name).is_some())
+}
+
+// run the `finally` of the try statement the last cursor is at, then carry
+// on with `pending`
+fn enter_finally(cursors: &mut Vec<Cursor>, pending: Option<ControlFlow>, thrown: Option<Value>) {
+    if let Some(cursor) = cursors.last_mut() {
+        cursor.inside = Some(Inside::Finally(pending, thrown));
+    }
+    cursors.push(Cursor::new(None));
+}
+
+fn private_member(name: &Identifier) -> RuntimeError {
+    RuntimeError::new(
+        format!(
+            "Can't access private property '{}' outside of its class.",
+            name.name
+        ),
+        name.span,
+    )
+}
+
+fn undefined_variable(name: &Identifier) -> RuntimeError {
+    RuntimeError::new(format!("Undefined variable '{}'.", name.name), name.span)
+}
+
+/// The method overloading a binary operator for instances, and how the
+/// operator is written.
+fn overload(op: BinaryOp) -> Option<(&'static str, &'static str)> {
+    Some(match op {
+        BinaryOp::Add => ("plus", "+"),
+        BinaryOp::Subtract => ("minus", "-"),
+        BinaryOp::Multiply => ("times", "*"),
+        BinaryOp::Divide => ("div", "/"),
+        BinaryOp::FloorDivide => ("floorDiv", "~/"),
+        BinaryOp::Modulo => ("mod", "%"),
+        BinaryOp::Equal => ("eq", "=="),
+        BinaryOp::NotEqual => ("eq", "!="),
+        BinaryOp::Greater => ("gt", ">"),
+        BinaryOp::GreaterEqual => ("ge", ">="),
+        BinaryOp::Less => ("lt", "<"),
+        BinaryOp::LessEqual => ("le", "<="),
+        BinaryOp::Range
+        | BinaryOp::BitAnd
+        | BinaryOp::BitOr
+        | BinaryOp::BitXor
+        | BinaryOp::ShiftLeft
+        | BinaryOp::ShiftRight => return None,
+    })
+}
+
+// a binary operator applied to two numbers, exactly for integers unless the
+// result doesn't fit, and as floats when either is a float
+pub(crate) fn arithmetic(
+    op: BinaryOp,
+    left: &Value,
+    right: &Value,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    if let (Value::Integer(left), Value::Integer(right)) = (left, right) {
+        let (left, right) = (*left, *right);
+        let exact = match op {
+            BinaryOp::Add => left.checked_add(right),
+            BinaryOp::Subtract => left.checked_sub(right),
+            BinaryOp::Multiply => left.checked_mul(right),
+            BinaryOp::FloorDivide => floor_divide(left, right),
+            BinaryOp::Modulo => left.checked_rem(right),
+            BinaryOp::Greater => return Ok(Value::Bool(left > right)),
+            BinaryOp::GreaterEqual => return Ok(Value::Bool(left >= right)),
+            BinaryOp::Less => return Ok(Value::Bool(left < right)),
+            BinaryOp::LessEqual => return Ok(Value::Bool(left <= right)),
+            BinaryOp::BitAnd => Some(left & right),
+            BinaryOp::BitOr => Some(left | right),
+            BinaryOp::BitXor => Some(left ^ right),
+            BinaryOp::ShiftLeft => Some(left << shift_count(right, span)?),
+            BinaryOp::ShiftRight => Some(left >> shift_count(right, span)?),
+            _ => None,
+        };
+        if let Some(exact) = exact {
+            return Ok(Value::Integer(exact));
+        }
+    }
+
+    let (left, right) = match (left.as_number(), right.as_number()) {
+        (Some(left), Some(right)) => (left, right),
+        _ => return Err(RuntimeError::new("Operands must be numbers.", span)),
+    };
+
+    Ok(match op {
+        BinaryOp::Add => Value::Number(left + right),
+        BinaryOp::Subtract => Value::Number(left - right),
+        BinaryOp::Multiply => Value::Number(left * right),
+        BinaryOp::Divide => Value::Number(left / right),
+        BinaryOp::FloorDivide => Value::Number((left / right).floor()),
+        BinaryOp::Modulo => Value::Number(left % right),
+        BinaryOp::Greater => Value::Bool(left > right),
+        BinaryOp::GreaterEqual => Value::Bool(left >= right),
+        BinaryOp::Less => Value::Bool(left < right),
+        BinaryOp::LessEqual => Value::Bool(left <= right),
+        BinaryOp::BitAnd => Value::Integer(integer(left, span)? & integer(right, span)?),
+        BinaryOp::BitOr => Value::Integer(integer(left, span)? | integer(right, span)?),
+        BinaryOp::BitXor => Value::Integer(integer(left, span)? ^ integer(right, span)?),
+        BinaryOp::ShiftLeft => {
+            Value::Integer(integer(left, span)? << shift_count(integer(right, span)?, span)?)
+        }
+        BinaryOp::ShiftRight => {
+            Value::Integer(integer(left, span)? >> shift_count(integer(right, span)?, span)?)
+        }
+        BinaryOp::Equal | BinaryOp::NotEqual | BinaryOp::Range => unreachable!(),
+    })
+}
+
+// `None` when dividing by zero or overflowing, left to floats
+fn floor_divide(left: i64, right: i64) -> Option<i64> {
+    let quotient = left.checked_div(right)?;
+    if left % right != 0 && (left < 0) != (right < 0) {
+        Some(quotient - 1)
+    } else {
+        Some(quotient)
+    }
+}
+
+// the integer a bitwise operand stands for
+pub(crate) fn integer(number: f64, span: Span) -> Result<i64, RuntimeError> {
+    value::exact_integer(number)
+        .ok_or_else(|| RuntimeError::new("Bitwise operands must be integers.", span))
+}
+
+fn shift_count(count: i64, span: Span) -> Result<u32, RuntimeError> {
+    match count {
+        0..=63 => Ok(count as u32),
+        _ => Err(RuntimeError::new(
+            "Shift count must be between 0 and 63.",
+            span,
+        )),
+    }
+}
+
+fn missing_operator(
+    instance: &Rc<RefCell<LoxInstance>>,
+    name: &str,
+    symbol: &str,
+    span: Span,
+) -> RuntimeError {
+    RuntimeError::new(
+        format!(
+            "Can't use '{}' on a {} instance without a '{}' method.",
+            symbol,
+            instance.borrow().class.name,
+            name
+        ),
+        span,
+    )
+}
+
+fn as_boolean(value: &Value, name: &str, span: Span) -> Result<bool, RuntimeError> {
+    match value {
+        Value::Bool(boolean) => Ok(*boolean),
+        _ => Err(RuntimeError::new(
+            format!(
+                "Method '{}' must return a boolean, not {}.",
+                name,
+                value.type_name()
+            ),
+            span,
+        )),
+    }
+}
+
+fn get_index(object: Value, index: &Value) -> Result<Value, RuntimeError> {
+    // `list[start..end]` slices
+    if let Value::Range(range) = index {
+        let (start, end) = (Value::from(range.start), Value::from(range.end));
+        return slice(object, Some(&start), Some(&end));
+    }
+    match object {
+        Value::List(list) => {
+            let list = list.borrow();
+            Ok(list[list::index(index, list.len())?].clone())
+        }
+        Value::Map(map) => {
+            let key = MapKey::new(index)?;
+            let value = map.borrow().get(&key).cloned();
+            value.ok_or_else(|| map::undefined_key(&key))
+        }
+        Value::String(string) => string::index(&string, index),
+        _ => Err(RuntimeError::msg(
+            "Only lists, maps and strings can be indexed.",
+        )),
+    }
+}
+
+fn slice(object: Value, start: Option<&Value>, end: Option<&Value>) -> Result<Value, RuntimeError> {
+    match object {
+        Value::List(list) => {
+            let list = list.borrow();
+            let (start, end) = list::slice(start, end, list.len())?;
+            Ok(list::new(list[start..end].to_vec()))
+        }
+        Value::String(string) => string::slice(&string, start, end),
+        _ => Err(RuntimeError::msg("Only lists and strings can be sliced.")),
+    }
+}
+
+fn set_index(object: Value, index: &Value, value: Value) -> Result<(), RuntimeError> {
+    match object {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let index = list::index(index, list.len())?;
+            list[index] = value;
+        }
+        Value::Map(map) => {
+            gc::allocate(map::ENTRY_SIZE);
+            map.borrow_mut().insert(MapKey::new(index)?, value);
+        }
+        _ => return Err(RuntimeError::msg("Only lists and maps can be indexed.")),
+    }
+    Ok(())
+}
+
+/// The state of a `for (x in ...)` loop.
+pub(crate) enum Iteration {
+    // lists are read one element at a time, so changes show up mid-loop
+    List(ListRef, usize),
+    // the next number and the end of the range
+    Range(i64, i64),
+    Values(std::vec::IntoIter<Value>),
+    Object(Value),
+    Generator(GeneratorRef),
+}
+
+impl Iteration {
+    pub(crate) fn next(
+        &mut self,
+        interpreter: &mut Interpreter,
+        span: Span,
+    ) -> Result<Option<Value>, RuntimeError> {
+        match self {
+            Iteration::List(list, index) => {
+                let element = list.borrow().get(*index).cloned();
+                *index += 1;
+                Ok(element)
+            }
+            Iteration::Range(next, end) if *next < *end => {
+                *next += 1;
+                Ok(Some(Value::Integer(*next - 1)))
+            }
+            Iteration::Range(..) => Ok(None),
+            Iteration::Values(values) => Ok(values.next()),
+            Iteration::Object(next) => match interpreter.call(next.clone(), Vec::new(), span)? {
+                Value::Nil => Ok(None),
+                element => Ok(Some(element)),
+            },
+            Iteration::Generator(generator) => interpreter.resume(generator, span),
+        }
+    }
+
+    /// Visit the values the loop holds on to.
+    pub(crate) fn trace<F: FnMut(&Value)>(&self, mut visit: F) {
+        match self {
+            Iteration::List(list, _) => visit(&Value::List(list.clone())),
+            Iteration::Range(..) => {}
+            Iteration::Values(values) => values.as_slice().iter().for_each(visit),
+            Iteration::Object(next) => visit(next),
+            Iteration::Generator(generator) => visit(&Value::Generator(generator.clone())),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Interpreters running on threads of their own.
+//!
+//! Values are reference counted without atomics, so an `Interpreter` and
+//! everything it creates stay on the thread that created it. Interpreters
+//! share nothing, so each thread can run its own. An `Isolate` does that for
+//! the host: it owns a thread with an interpreter, and its handle can be
+//! sent and shared between threads. Only sources, errors and plain data
+//! cross over, the values of the program never leave the isolate's thread.
+
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::interpreter::{self, Interpreter};
+
+type Job = Box<dyn FnOnce(&mut Interpreter) + Send>;
+
+pub struct Isolate {
+    // `None` once dropped, so the thread sees the channel close
+    jobs: Option<Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Default for Isolate {
+    fn default() -> Self {
+        Self::spawn()
+    }
+}
+
+impl Isolate {
+    pub fn spawn() -> Self {
+        Self::spawn_with(|_| {})
+    }
+
+    /// Start an isolate whose interpreter is first handed to `setup`, to
+    /// define natives or redirect its output.
+    pub fn spawn_with<F>(setup: F) -> Self
+    where
+        F: FnOnce(&mut Interpreter) + Send + 'static,
+    {
+        let (jobs, received) = mpsc::channel::<Job>();
+        let thread = thread::Builder::new()
+            .stack_size(interpreter::STACK_SIZE)
+            .spawn(move || {
+                let mut interpreter = Interpreter::new();
+                setup(&mut interpreter);
+                for job in received {
+                    job(&mut interpreter);
+                }
+            })
+            .expect("failed to spawn the isolate thread");
+
+        Self {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        }
+    }
+
+    /// Run `f` with the interpreter of the isolate, waiting for the result.
+    /// Jobs run one at a time, in the order they were sent.
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Interpreter) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result, received) = mpsc::channel();
+        let job: Job = Box::new(move |interpreter| {
+            // the caller may have stopped waiting
+            let _ = result.send(f(interpreter));
+        });
+
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .expect("the isolate thread stopped");
+        received.recv().expect("the isolate thread panicked")
+    }
+
+    pub fn run(&self, source: &str) -> anyhow::Result<()> {
+        let source = source.to_string();
+        self.with(move |interpreter| interpreter.run(&source))
+    }
+
+    /// Like `Interpreter::eval`, with the value printed as Lox would.
+    pub fn eval(&self, source: &str) -> anyhow::Result<Option<String>> {
+        let source = source.to_string();
+        self.with(move |interpreter| {
+            let value = interpreter.eval(&source)?;
+            Ok(value.map(|value| value.to_string()))
+        })
+    }
+}
+
+impl Drop for Isolate {
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
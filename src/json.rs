@@ -0,0 +1,474 @@
+//! The JSON natives, `jsonParse(text)` and `jsonStringify(value, pretty)`.
+//!
+//! Objects become maps, keeping the order of their keys, and arrays lists.
+//! Numbers without a fraction or an exponent become integers when they fit
+//! in one, floats otherwise. Malformed JSON throws a `JsonError`, the
+//! prelude class, with the line and column where it stops making sense in
+//! its message, so scripts can catch it.
+//!
+//! Going back, map keys that aren't strings are written as they print, as
+//! JSON only has string keys. Instances, functions and the other values
+//! JSON has no place for are an error, as are NaN, the infinities and
+//! lists or maps holding themselves.
+
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::function::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::list;
+use crate::map::{self, MapKey};
+use crate::ordered_map::OrderedMap;
+use crate::value::{self, Value};
+
+// deeper than any document written for people, and shallow enough to parse
+// on the stack
+const MAX_DEPTH: usize = 512;
+
+/// Register the JSON natives with an interpreter.
+pub fn register(interpreter: &mut Interpreter) {
+    let parse = NativeFunction::with_callbacks("jsonParse", 1, |interpreter, arguments, span| {
+        match &arguments[0] {
+            Value::String(text) => self::parse(text)
+                .map_err(|error| interpreter.throw("JsonError", &error.message, span)),
+            other => Err(RuntimeError::new(
+                format!("Expected a string but got {}.", other.type_name()),
+                span,
+            )),
+        }
+    });
+    interpreter.define_global("jsonParse", Value::Native(Rc::new(parse)));
+    let mut stringify = NativeFunction::new("jsonStringify", 1, |arguments| {
+        let pretty = match arguments.get(1) {
+            None => false,
+            Some(Value::Bool(pretty)) => *pretty,
+            Some(other) => {
+                return Err(RuntimeError::msg(format!(
+                    "Expected a boolean but got {}.",
+                    other.type_name()
+                )))
+            }
+        };
+        Ok(Value::from(self::stringify(&arguments[0], pretty)?))
+    });
+    // whether to indent is optional
+    stringify.arity.max = Some(2);
+    interpreter.define_global("jsonStringify", Value::Native(Rc::new(stringify)));
+}
+
+/// The value of a JSON text.
+pub fn parse(text: &str) -> Result<Value, RuntimeError> {
+    let mut parser = Parser {
+        text,
+        at: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.at < text.len() {
+        return Err(parser.unexpected());
+    }
+    Ok(value)
+}
+
+/// The JSON text of a value, indented by two spaces a level when `pretty`.
+pub fn stringify(value: &Value, pretty: bool) -> Result<String, RuntimeError> {
+    let mut writer = Writer {
+        text: String::new(),
+        pretty,
+        containers: Vec::new(),
+    };
+    writer.value(value)?;
+    Ok(writer.text)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    // a byte offset, always on a character boundary
+    at: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value, RuntimeError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(Value::from(self.string()?)),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Nil),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Value, RuntimeError>,
+    ) -> Result<Value, RuntimeError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("it's nested too deeply"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Value, RuntimeError> {
+        self.at += 1;
+        let mut entries = OrderedMap::new();
+        self.skip_whitespace();
+        if self.eat(b'}') {
+            return Ok(map::new(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.expected("a string key"));
+            }
+            let key = Value::from(self.string()?);
+            self.skip_whitespace();
+            if !self.eat(b':') {
+                return Err(self.expected("':' after the key"));
+            }
+            let value = self.value()?;
+            entries.insert(MapKey::new(&key)?, value);
+            self.skip_whitespace();
+            if self.eat(b'}') {
+                return Ok(map::new(entries));
+            }
+            if !self.eat(b',') {
+                return Err(self.expected("',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, RuntimeError> {
+        self.at += 1;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.eat(b']') {
+            return Ok(list::new(elements));
+        }
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat(b']') {
+                return Ok(list::new(elements));
+            }
+            if !self.eat(b',') {
+                return Err(self.expected("',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, RuntimeError> {
+        self.at += 1;
+        let mut string = String::new();
+        loop {
+            // everything up to the next quote, escape or control character
+            // as it is
+            let rest = &self.text[self.at..];
+            let run = rest
+                .find(|c: char| c == '"' || c == '\\' || c < ' ')
+                .unwrap_or(rest.len());
+            string.push_str(&rest[..run]);
+            self.at += run;
+            match self.peek() {
+                Some(b'"') => {
+                    self.at += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    let escape = self.at;
+                    self.at += 1;
+                    let c = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.at += 1;
+                            let c = self.unicode_escape(escape)?;
+                            string.push(c);
+                            continue;
+                        }
+                        _ => {
+                            self.at = escape;
+                            return Err(self.error("invalid escape"));
+                        }
+                    };
+                    self.at += 1;
+                    string.push(c);
+                }
+                None => return Err(self.error("the string isn't closed")),
+                Some(_) => return Err(self.error("control characters must be escaped in strings")),
+            }
+        }
+    }
+
+    // the character of a `\u` escape starting at `escape`, with the one
+    // after it when they're a surrogate pair
+    fn unicode_escape(&mut self, escape: usize) -> Result<char, RuntimeError> {
+        let invalid = |parser: &mut Self| {
+            parser.at = escape;
+            parser.error("invalid unicode escape")
+        };
+        let first = match self.hex() {
+            Some(first) => first,
+            None => return Err(invalid(self)),
+        };
+        let code = if (0xd800..0xdc00).contains(&first) {
+            let second = match self.text[self.at..].strip_prefix("\\u") {
+                Some(_) => {
+                    self.at += 2;
+                    self.hex()
+                }
+                None => None,
+            };
+            match second {
+                Some(second) if (0xdc00..0xe000).contains(&second) => {
+                    0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00)
+                }
+                _ => return Err(invalid(self)),
+            }
+        } else {
+            first
+        };
+        char::from_u32(code).ok_or_else(|| invalid(self))
+    }
+
+    fn hex(&mut self) -> Option<u32> {
+        let digits = self.text.get(self.at..self.at + 4)?;
+        if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        self.at += 4;
+        u32::from_str_radix(digits, 16).ok()
+    }
+
+    fn number(&mut self) -> Result<Value, RuntimeError> {
+        let start = self.at;
+        self.eat(b'-');
+        // no leading zeros
+        if !self.eat(b'0') && self.digits() == 0 {
+            return Err(self.error("invalid number"));
+        }
+        let mut integer = true;
+        if self.eat(b'.') {
+            integer = false;
+            if self.digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            integer = false;
+            let _ = self.eat(b'+') || self.eat(b'-');
+            if self.digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        let number = &self.text[start..self.at];
+        if integer {
+            if let Ok(integer) = number.parse() {
+                return Ok(Value::Integer(integer));
+            }
+        }
+        // the grammar above is a subset of what floats parse
+        Ok(Value::Number(number.parse().unwrap_or_default()))
+    }
+
+    fn digits(&mut self) -> usize {
+        let count = self.text[self.at..]
+            .bytes()
+            .take_while(u8::is_ascii_digit)
+            .count();
+        self.at += count;
+        count
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value, RuntimeError> {
+        if !self.text[self.at..].starts_with(literal) {
+            return Err(self.unexpected());
+        }
+        self.at += literal.len();
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.at..];
+        self.at += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.at).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn unexpected(&self) -> RuntimeError {
+        match self.text[self.at..].chars().next() {
+            Some(c) => self.error(&format!("unexpected {:?}", c)),
+            None => self.error("unexpected end of the text"),
+        }
+    }
+
+    fn expected(&self, what: &str) -> RuntimeError {
+        match self.text[self.at..].chars().next() {
+            Some(c) => self.error(&format!("expected {} but got {:?}", what, c)),
+            None => self.error(&format!("expected {} but the text ended", what)),
+        }
+    }
+
+    // lines and columns count from one, columns in characters
+    fn error(&self, problem: &str) -> RuntimeError {
+        let before = &self.text[..self.at];
+        let line = before.matches('\n').count() + 1;
+        let column = before[before.rfind('\n').map_or(0, |newline| newline + 1)..]
+            .chars()
+            .count()
+            + 1;
+        RuntimeError::msg(format!(
+            "Invalid JSON at line {}, column {}: {}.",
+            line, column, problem
+        ))
+    }
+}
+
+struct Writer {
+    text: String,
+    pretty: bool,
+    // the lists and maps being written, to catch the ones holding
+    // themselves
+    containers: Vec<*const ()>,
+}
+
+impl Writer {
+    fn value(&mut self, value: &Value) -> Result<(), RuntimeError> {
+        match value {
+            Value::Nil => self.text.push_str("null"),
+            Value::Bool(boolean) => {
+                let _ = write!(self.text, "{}", boolean);
+            }
+            Value::Integer(integer) => {
+                let _ = write!(self.text, "{}", integer);
+            }
+            Value::Number(number) if number.is_finite() => {
+                self.text.push_str(&value::format_number(*number));
+            }
+            Value::Number(number) => {
+                return Err(RuntimeError::msg(format!(
+                    "Can't convert {} to JSON.",
+                    value::format_number(*number)
+                )))
+            }
+            Value::String(string) => self.string(string),
+            Value::List(list) => {
+                self.enter(Rc::as_ptr(list) as *const ())?;
+                let list = list.borrow();
+                self.text.push('[');
+                for (i, element) in list.iter().enumerate() {
+                    self.separate(i);
+                    self.value(element)?;
+                }
+                self.close(list.is_empty(), ']');
+            }
+            Value::Map(map) => {
+                self.enter(Rc::as_ptr(map) as *const ())?;
+                let map = map.borrow();
+                self.text.push('{');
+                for (i, (key, value)) in map.iter().enumerate() {
+                    self.separate(i);
+                    match key {
+                        MapKey::String(key) => self.string(key),
+                        MapKey::Instance(_) => {
+                            return Err(RuntimeError::msg("Can't convert instance keys to JSON."))
+                        }
+                        other => self.string(&other.to_string()),
+                    }
+                    self.text.push(':');
+                    if self.pretty {
+                        self.text.push(' ');
+                    }
+                    self.value(value)?;
+                }
+                self.close(map.is_empty(), '}');
+            }
+            other => {
+                return Err(RuntimeError::msg(format!(
+                    "Can't convert {} to JSON.",
+                    other.type_name()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, container: *const ()) -> Result<(), RuntimeError> {
+        if self.containers.contains(&container) {
+            return Err(RuntimeError::msg(
+                "Can't convert a value holding itself to JSON.",
+            ));
+        }
+        self.containers.push(container);
+        Ok(())
+    }
+
+    // before the element at `index`, on a line of its own when pretty
+    fn separate(&mut self, index: usize) {
+        if index > 0 {
+            self.text.push(',');
+        }
+        self.newline(self.containers.len());
+    }
+
+    fn close(&mut self, empty: bool, bracket: char) {
+        self.containers.pop();
+        if !empty {
+            self.newline(self.containers.len());
+        }
+        self.text.push(bracket);
+    }
+
+    fn newline(&mut self, depth: usize) {
+        if self.pretty {
+            self.text.push('\n');
+            for _ in 0..depth {
+                self.text.push_str("  ");
+            }
+        }
+    }
+
+    fn string(&mut self, string: &str) {
+        self.text.push('"');
+        for c in string.chars() {
+            match c {
+                '"' => self.text.push_str("\\\""),
+                '\\' => self.text.push_str("\\\\"),
+                '\n' => self.text.push_str("\\n"),
+                '\r' => self.text.push_str("\\r"),
+                '\t' => self.text.push_str("\\t"),
+                '\u{8}' => self.text.push_str("\\b"),
+                '\u{c}' => self.text.push_str("\\f"),
+                c if c < ' ' => {
+                    let _ = write!(self.text, "\\u{:04x}", c as u32);
+                }
+                c => self.text.push(c),
+            }
+        }
+        self.text.push('"');
+    }
+}
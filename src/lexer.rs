@@ -1,24 +1,36 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum TokenKind {
     // Single-character tokens
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
+    DotDot,
+    DotDotDot,
     Minus,
     Plus,
     SemiColon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
 
     // One or two character tokens
+    Tilde,
+    TildeSlash,
     Bang,
     BangEqual,
     Equal,
@@ -27,37 +39,126 @@ pub enum TokenKind {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
 
     // Literals
     Identifier(String),
     String(String),
     Number(i64),
+    Float(f64),
 
     // Keywords
     And,
+    Async,
+    Await,
+    Break,
+    Catch,
     Class,
+    Const,
+    Continue,
+    Defer,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
+    Yield,
 
     Unknown,
 }
 
+/// A byte range in the source buffer, along with the line it starts on.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize) -> Self {
+        Self { start, end, line }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+            line: self.line.min(other.line),
+        }
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// An error found while turning source text into tokens or syntax trees.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Span,
+    // where the error happened, like `'='` or `end`
+    pub location: Option<String>,
+}
+
+impl SyntaxError {
+    pub fn new<M: Into<String>>(message: M, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            location: None,
+        }
+    }
+
+    pub fn at<L: Into<String>>(mut self, location: L) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(
+                f,
+                "[line {}] Error at {}: {}",
+                self.span.line, location, self.message
+            ),
+            None => write!(f, "[line {}] Error: {}", self.span.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
 #[derive(Clone, Debug)]
 pub struct Lexer {
     pub buffer: String,
     position: usize,
+    line: usize,
 }
 
 impl Lexer {
@@ -65,6 +166,7 @@ impl Lexer {
         Self {
             buffer,
             position: 0,
+            line: 1,
         }
     }
 
@@ -76,17 +178,54 @@ impl Lexer {
         let mut buffer = String::with_capacity((source_len) as usize + 1);
 
         // write to the buffer without reallocations
-        source
-            .take(source_len)
-            .read_to_end(unsafe { buffer.as_mut_vec() })?;
+        source.take(source_len).read_to_string(&mut buffer)?;
 
         let scanner = Lexer::new(buffer);
 
         Ok(scanner)
     }
 
+    /// Scan the next token, skipping whitespaces and comments.
+    pub fn next_token(&mut self) -> anyhow::Result<Option<Token>> {
+        self.skip_trivia();
+
+        let start = self.position;
+        let line = self.line;
+        match self.tokenize_next()? {
+            Some((kind, length)) => {
+                self.advance(length);
+                Ok(Some(Token {
+                    kind,
+                    span: Span::new(start, start + length, line),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Scan the whole buffer, stopping at the first error.
+    pub fn tokenize(mut self) -> anyhow::Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        while let Some(token) = self.next_token()? {
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    /// The span right after the last character of the buffer.
+    pub fn eof_span(&self) -> Span {
+        let line = 1 + self.buffer.matches('\n').count();
+        Span::new(self.buffer.len(), self.buffer.len(), line)
+    }
+
+    fn advance(&mut self, length: usize) {
+        let end = self.position + length;
+        self.line += self.buffer[self.position..end].matches('\n').count();
+        self.position = end;
+    }
+
     fn tokenize_next(&mut self) -> anyhow::Result<Option<(TokenKind, usize)>> {
-        let mut next_chars = self.buffer.chars().skip(self.position);
+        let mut next_chars = self.buffer[self.position..].chars();
         if let (Some(current), next) = (next_chars.next(), next_chars.next()) {
             match current {
                 // Single-character tokens
@@ -94,15 +233,37 @@ impl Lexer {
                 ')' => Ok(Some((TokenKind::RightParen, 1))),
                 '{' => Ok(Some((TokenKind::LeftBrace, 1))),
                 '}' => Ok(Some((TokenKind::RightBrace, 1))),
+                '[' => Ok(Some((TokenKind::LeftBracket, 1))),
+                ']' => Ok(Some((TokenKind::RightBracket, 1))),
+                ':' => Ok(Some((TokenKind::Colon, 1))),
                 ',' => Ok(Some((TokenKind::Comma, 1))),
-                '.' => Ok(Some((TokenKind::Dot, 1))),
                 '-' => Ok(Some((TokenKind::Minus, 1))),
                 '+' => Ok(Some((TokenKind::Plus, 1))),
                 ';' => Ok(Some((TokenKind::SemiColon, 1))),
                 '/' => Ok(Some((TokenKind::Slash, 1))),
                 '*' => Ok(Some((TokenKind::Star, 1))),
+                '%' => Ok(Some((TokenKind::Percent, 1))),
+                '&' => Ok(Some((TokenKind::Ampersand, 1))),
+                '|' => Ok(Some((TokenKind::Pipe, 1))),
+                '^' => Ok(Some((TokenKind::Caret, 1))),
 
                 // One or two character tokens
+                '~' => {
+                    if let Some('/') = next {
+                        Ok(Some((TokenKind::TildeSlash, 2)))
+                    } else {
+                        Ok(Some((TokenKind::Tilde, 1)))
+                    }
+                }
+                '.' => {
+                    if self.buffer[self.position..].starts_with("...") {
+                        Ok(Some((TokenKind::DotDotDot, 3)))
+                    } else if let Some('.') = next {
+                        Ok(Some((TokenKind::DotDot, 2)))
+                    } else {
+                        Ok(Some((TokenKind::Dot, 1)))
+                    }
+                }
                 '!' => {
                     if let Some('=') = next {
                         Ok(Some((TokenKind::BangEqual, 2)))
@@ -120,6 +281,8 @@ impl Lexer {
                 '>' => {
                     if let Some('=') = next {
                         Ok(Some((TokenKind::GreaterEqual, 2)))
+                    } else if let Some('>') = next {
+                        Ok(Some((TokenKind::GreaterGreater, 2)))
                     } else {
                         Ok(Some((TokenKind::Greater, 1)))
                     }
@@ -127,6 +290,8 @@ impl Lexer {
                 '<' => {
                     if let Some('=') = next {
                         Ok(Some((TokenKind::LessEqual, 2)))
+                    } else if let Some('<') = next {
+                        Ok(Some((TokenKind::LessLess, 2)))
                     } else {
                         Ok(Some((TokenKind::Less, 1)))
                     }
@@ -134,7 +299,7 @@ impl Lexer {
 
                 // Literals
                 '"' => self.tokenize_next_string(),
-                ch @ '_' | ch if ch.is_alphabetic() => {
+                ch if ch == '_' || ch.is_alphabetic() => {
                     let (ident, length) = self.tokenize_next_identifier()?.unwrap();
                     if let TokenKind::Identifier(ident_str) = &ident {
                         // check if the identifier is a keyword
@@ -147,13 +312,14 @@ impl Lexer {
                         unreachable!()
                     }
                 }
-                ch if ch.is_digit(10) => self.tokenize_next_number(),
+                ch if ch.is_ascii_digit() => self.tokenize_next_number(),
 
-                // Unknwon character
-                _ => Err(anyhow::Error::msg(format!(
-                    "Unknown token: {}",
-                    &self.buffer[self.position..self.position + 10]
-                ))),
+                // Unknown character
+                ch => Err(SyntaxError::new(
+                    format!("Unexpected character '{}'.", ch),
+                    Span::new(self.position, self.position + ch.len_utf8(), self.line),
+                )
+                .into()),
             }
         } else {
             // EOF
@@ -174,23 +340,57 @@ impl Lexer {
         skipped
     }
 
+    fn skip_trivia(&mut self) {
+        loop {
+            let skipped = self.skip_whitespaces();
+            self.advance(skipped);
+
+            // line comments run until the end of the line
+            if self.buffer[self.position..].starts_with("//") {
+                let (_, comment) = self.take_all_next(|ch| ch != '\n');
+                self.advance(comment);
+            } else {
+                break;
+            }
+        }
+    }
+
     fn tokenize_next_identifier(&self) -> anyhow::Result<Option<(TokenKind, usize)>> {
-        let (ident, length) = self.take_all_next(|ch| ch.is_alphanumeric());
+        let (ident, length) = self.take_all_next(|ch| ch == '_' || ch.is_alphanumeric());
         Ok(Some((TokenKind::Identifier(ident.to_string()), length)))
     }
     fn tokenize_next_string(&self) -> anyhow::Result<Option<(TokenKind, usize)>> {
         let (string, length) = take_all(&self.buffer[self.position + 1..], |ch| ch != '"');
-        if self.buffer.chars().nth(self.position + length + 1) == Some('"') {
+        if self.buffer[self.position + length + 1..].starts_with('"') {
             Ok(Some((TokenKind::String(string.to_string()), length + 2)))
         } else {
-            Err(anyhow::Error::msg("Unmatched string quotes"))
+            Err(SyntaxError::new(
+                "Unterminated string.",
+                Span::new(self.position, self.position + length + 1, self.line),
+            )
+            .into())
         }
     }
     fn tokenize_next_number(&self) -> anyhow::Result<Option<(TokenKind, usize)>> {
-        let (number, length) = self.take_all_next(|ch| ch.is_digit(10));
-        let number_parsed = number.parse()?;
+        let (number, length) = self.take_all_next(|ch| ch.is_ascii_digit());
 
-        Ok(Some((TokenKind::Number(number_parsed), length)))
+        // a fractional part needs at least one digit after the dot
+        let rest = &self.buffer[self.position + length..];
+        let mut rest_chars = rest.chars();
+        if let (Some('.'), Some(digit)) = (rest_chars.next(), rest_chars.next()) {
+            if digit.is_ascii_digit() {
+                let (fraction, fraction_length) = take_all(&rest[1..], |ch| ch.is_ascii_digit());
+                let float = format!("{}.{}", number, fraction);
+                let length = length + 1 + fraction_length;
+                return Ok(Some((TokenKind::Float(float.parse()?), length)));
+            }
+        }
+
+        // integers too large for an i64 are still valid numbers
+        match number.parse() {
+            Ok(number_parsed) => Ok(Some((TokenKind::Number(number_parsed), length))),
+            Err(_) => Ok(Some((TokenKind::Float(number.parse()?), length))),
+        }
     }
 }
 
@@ -198,18 +398,11 @@ impl Iterator for Lexer {
     type Item = TokenKind;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.position += self.skip_whitespaces();
-
-        if let Some((token, length)) = self.tokenize_next().unwrap().take() {
-            self.position += length;
-            Some(token)
-        } else {
-            None
-        }
+        self.next_token().unwrap().map(|token| token.kind)
     }
 }
 
-fn take_all<'a, F>(data: &'a str, matcher: F) -> (&'a str, usize)
+fn take_all<F>(data: &str, matcher: F) -> (&str, usize)
 where
     F: Fn(char) -> bool,
 {
@@ -226,27 +419,40 @@ where
     (data, index)
 }
 
-fn is_keyword<'a>(data: &'a str) -> Option<TokenKind> {
+fn is_keyword(data: &str) -> Option<TokenKind> {
     let keywords: HashMap<&'static str, TokenKind> = vec![
         ("and", TokenKind::And),
+        ("async", TokenKind::Async),
+        ("await", TokenKind::Await),
+        ("break", TokenKind::Break),
+        ("catch", TokenKind::Catch),
         ("class", TokenKind::Class),
+        ("const", TokenKind::Const),
+        ("continue", TokenKind::Continue),
+        ("defer", TokenKind::Defer),
         ("else", TokenKind::Else),
         ("false", TokenKind::False),
+        ("finally", TokenKind::Finally),
         ("fun", TokenKind::Fun),
         ("for", TokenKind::For),
         ("if", TokenKind::If),
+        ("import", TokenKind::Import),
+        ("in", TokenKind::In),
         ("nil", TokenKind::Nil),
         ("or", TokenKind::Or),
         ("print", TokenKind::Print),
         ("return", TokenKind::Return),
         ("super", TokenKind::Super),
         ("this", TokenKind::This),
+        ("throw", TokenKind::Throw),
         ("true", TokenKind::True),
+        ("try", TokenKind::Try),
         ("var", TokenKind::Var),
         ("while", TokenKind::While),
+        ("yield", TokenKind::Yield),
     ]
     .into_iter()
     .collect();
 
-    keywords.get(data).and_then(|token| Some(token.clone()))
+    keywords.get(data).cloned()
 }
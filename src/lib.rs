@@ -1 +1,52 @@
+pub mod assert;
+pub mod ast;
+pub mod backend;
+pub mod cache;
+pub mod capability;
+pub mod channel;
+pub mod chunk;
+pub mod class;
+pub mod compiler;
+pub mod diagnostic;
+pub mod environment;
+pub mod files;
+pub mod format;
+pub mod function;
+pub mod gc;
+pub mod generator;
+pub mod hook;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod interpreter;
+pub mod isolate;
+pub mod json;
 pub mod lexer;
+pub mod list;
+pub mod map;
+pub mod math;
+pub mod number;
+pub(crate) mod object;
+pub mod ordered_map;
+pub mod parser;
+pub mod path;
+pub mod process;
+pub mod promise;
+pub mod random;
+pub mod range;
+#[cfg(feature = "regex")]
+pub mod regexp;
+#[cfg(feature = "register-vm")]
+pub mod register;
+pub mod repl;
+pub mod replay;
+pub mod resolver;
+pub(crate) mod stack;
+pub mod stats;
+pub mod stdlib;
+pub mod string;
+pub mod time;
+pub mod value;
+pub mod verifier;
+pub mod vm;
+pub(crate) mod vm_value;
+pub mod weak;
@@ -0,0 +1,230 @@
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use crate::function::NativeFunction;
+use crate::gc;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::lexer::Span;
+use crate::value::Value;
+
+pub type ListRef = Rc<RefCell<Vec<Value>>>;
+
+pub fn new(elements: Vec<Value>) -> Value {
+    gc::allocate(elements.len() * mem::size_of::<Value>());
+    let list = Rc::new(RefCell::new(elements));
+    // a list can hold itself
+    gc::track(&list);
+    Value::List(list)
+}
+
+/// Check that `index` is a number pointing to one of `len` elements.
+pub fn index(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    position(index, len, "List")
+}
+
+/// Check that `start..end` are numbers delimiting some of `len` elements,
+/// defaulting to the first and the last one.
+pub fn slice(
+    start: Option<&Value>,
+    end: Option<&Value>,
+    len: usize,
+) -> Result<(usize, usize), RuntimeError> {
+    bounds(start, end, len, "List")
+}
+
+pub(crate) fn position(index: &Value, len: usize, kind: &str) -> Result<usize, RuntimeError> {
+    let index = integer(index, kind)?;
+    if index < 0.0 || index >= len as f64 {
+        return Err(out_of_bounds(index, len, kind));
+    }
+    Ok(index as usize)
+}
+
+pub(crate) fn bounds(
+    start: Option<&Value>,
+    end: Option<&Value>,
+    len: usize,
+    kind: &str,
+) -> Result<(usize, usize), RuntimeError> {
+    let start = start.map_or(Ok(0.0), |start| integer(start, kind))?;
+    let end = end.map_or(Ok(len as f64), |end| integer(end, kind))?;
+    if start < 0.0 || start > end || end > len as f64 {
+        return Err(RuntimeError::msg(format!(
+            "{} slice {}..{} out of bounds for length {}.",
+            kind, start, end, len
+        )));
+    }
+    Ok((start as usize, end as usize))
+}
+
+/// Look up one of the methods every list has, bound to `list`.
+pub fn get_method(list: &ListRef, name: &str) -> Option<Value> {
+    let method = match name {
+        "push" => bind(list, name, 1, |list, arguments| {
+            gc::allocate(mem::size_of::<Value>());
+            list.push(arguments[0].clone());
+            Ok(Value::Nil)
+        }),
+        "pop" => bind(list, name, 0, |list, _| {
+            list.pop()
+                .ok_or_else(|| RuntimeError::msg("Can't pop from an empty list."))
+        }),
+        "insert" => bind(list, name, 2, |list, arguments| {
+            // inserting right after the last element is fine
+            let index = integer(&arguments[0], "List")?;
+            if index < 0.0 || index > list.len() as f64 {
+                return Err(out_of_bounds(index, list.len(), "List"));
+            }
+            gc::allocate(mem::size_of::<Value>());
+            list.insert(index as usize, arguments[1].clone());
+            Ok(Value::Nil)
+        }),
+        "remove" => bind(list, name, 1, |list, arguments| {
+            let index = self::index(&arguments[0], list.len())?;
+            Ok(list.remove(index))
+        }),
+        // whether an element is `==` to the argument
+        "contains" => bind_callback(list, name, 1, |interpreter, elements, arguments, span| {
+            for element in elements {
+                if interpreter.equal(element, arguments[0].clone(), span)? {
+                    return Ok(Value::Bool(true));
+                }
+            }
+            Ok(Value::Bool(false))
+        }),
+        "filter" => bind_callback(list, name, 1, |interpreter, elements, arguments, span| {
+            let mut kept = Vec::new();
+            for element in elements {
+                let keep = interpreter.call(arguments[0].clone(), vec![element.clone()], span)?;
+                if keep.is_truthy() {
+                    kept.push(element);
+                }
+            }
+            Ok(new(kept))
+        }),
+        "map" => bind_callback(list, name, 1, |interpreter, elements, arguments, span| {
+            let mut mapped = Vec::with_capacity(elements.len());
+            for element in elements {
+                mapped.push(interpreter.call(arguments[0].clone(), vec![element], span)?);
+            }
+            Ok(new(mapped))
+        }),
+        // folds from the first element, starting with the second argument
+        "reduce" => bind_callback(list, name, 2, |interpreter, elements, arguments, span| {
+            let mut accumulator = arguments[1].clone();
+            for element in elements {
+                accumulator =
+                    interpreter.call(arguments[0].clone(), vec![accumulator, element], span)?;
+            }
+            Ok(accumulator)
+        }),
+        // in place and stable, comparing with `<`, or with a function giving
+        // a negative number when its first argument goes first, zero when
+        // they're equal and a positive number otherwise
+        "sort" => {
+            let mut sort = NativeFunction::with_callbacks(name, 1, {
+                let list = list.clone();
+                move |interpreter, arguments, span| {
+                    // comparing may run Lox code, which can change the list
+                    let elements = list.borrow().clone();
+                    let sorted = match arguments.first() {
+                        Some(compare) => merge_sort(elements, &mut |left, right| {
+                            let arguments = vec![left.clone(), right.clone()];
+                            match interpreter.call(compare.clone(), arguments, span)? {
+                                Value::Integer(order) => Ok(order < 0),
+                                Value::Number(order) => Ok(order < 0.0),
+                                other => Err(RuntimeError::new(
+                                    format!(
+                                        "Comparison functions must return a number, not {}.",
+                                        other.type_name()
+                                    ),
+                                    span,
+                                )),
+                            }
+                        })?,
+                        None => merge_sort(elements, &mut |left, right| {
+                            interpreter.less(left.clone(), right.clone(), span)
+                        })?,
+                    };
+                    *list.borrow_mut() = sorted;
+                    Ok(Value::Nil)
+                }
+            });
+            sort.arity.min = 0;
+            sort
+        }
+        _ => return None,
+    };
+    Some(Value::Native(Rc::new(method)))
+}
+
+fn bind<F>(list: &ListRef, name: &str, arity: usize, method: F) -> NativeFunction
+where
+    F: Fn(&mut Vec<Value>, &[Value]) -> Result<Value, RuntimeError> + 'static,
+{
+    let list = list.clone();
+    NativeFunction::new(name, arity, move |arguments| {
+        method(&mut list.borrow_mut(), arguments)
+    })
+}
+
+/// A method calling back into Lox code, given the elements the list had
+/// when it was called, as the code can change the list.
+fn bind_callback<F>(list: &ListRef, name: &str, arity: usize, method: F) -> NativeFunction
+where
+    F: Fn(&mut Interpreter, Vec<Value>, &[Value], Span) -> Result<Value, RuntimeError> + 'static,
+{
+    let list = list.clone();
+    NativeFunction::with_callbacks(name, arity, move |interpreter, arguments, span| {
+        let elements = list.borrow().clone();
+        method(interpreter, elements, arguments, span)
+    })
+}
+
+/// A merge sort that stops at the first failed comparison, unlike the one of
+/// the standard library.
+fn merge_sort<F>(mut elements: Vec<Value>, less: &mut F) -> Result<Vec<Value>, RuntimeError>
+where
+    F: FnMut(&Value, &Value) -> Result<bool, RuntimeError>,
+{
+    if elements.len() < 2 {
+        return Ok(elements);
+    }
+    let right = elements.split_off(elements.len() / 2);
+    let left = merge_sort(elements, less)?;
+    let right = merge_sort(right, less)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // equal elements keep their order
+        if less(r, l)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn integer(index: &Value, kind: &str) -> Result<f64, RuntimeError> {
+    match index {
+        Value::Integer(index) => Ok(*index as f64),
+        Value::Number(index) if index.fract() == 0.0 => Ok(*index),
+        _ => Err(RuntimeError::msg(format!(
+            "{} index must be an integer.",
+            kind
+        ))),
+    }
+}
+
+fn out_of_bounds(index: f64, len: usize, kind: &str) -> RuntimeError {
+    RuntimeError::msg(format!(
+        "{} index {} out of bounds for length {}.",
+        kind, index, len
+    ))
+}
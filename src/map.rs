@@ -0,0 +1,190 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+
+use crate::class::LoxInstance;
+use crate::function::NativeFunction;
+use crate::gc;
+use crate::interpreter::RuntimeError;
+use crate::list;
+use crate::ordered_map::OrderedMap;
+use crate::value::Value;
+
+// the integers up to 2^53 are floats exactly as well
+const MAX_EXACT: u64 = 1 << 53;
+
+/// Roughly how many bytes an entry takes, for `gc::allocate`.
+pub(crate) const ENTRY_SIZE: usize = mem::size_of::<(MapKey, Value)>();
+
+pub type MapRef = Rc<RefCell<OrderedMap<MapKey, Value>>>;
+
+/// The values that can be used as map keys, compared by value.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum MapKey {
+    Bool(bool),
+    // the bits of the number, `NaN` excluded and `-0` stored as `0`; integers
+    // equal to a float share its key
+    Number(u64),
+    // integers too large to be a float exactly
+    Integer(i64),
+    String(Rc<str>),
+    Instance(InstanceKey),
+}
+
+/// An instance used as a map key.
+///
+/// Instances define their own hashing and equality with the `hash` and `eq`
+/// methods, which only the interpreter can call. So the key is what `hash`
+/// returned, along with a position among the keys of the map that have the
+/// same hash but aren't equal; see `Interpreter::map_key`.
+#[derive(Debug, Clone)]
+pub struct InstanceKey {
+    pub hash: u64,
+    pub collision: usize,
+    pub instance: Rc<RefCell<LoxInstance>>,
+}
+
+impl PartialEq for InstanceKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.collision == other.collision
+    }
+}
+
+impl Eq for InstanceKey {}
+
+impl Hash for InstanceKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash.hash(state);
+        self.collision.hash(state);
+    }
+}
+
+impl MapKey {
+    /// The key for any value but an instance, see `Interpreter::map_key`.
+    pub fn new(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Bool(boolean) => Ok(MapKey::Bool(*boolean)),
+            Value::Number(number) if number.is_nan() => {
+                Err(RuntimeError::msg("Map keys can't be NaN."))
+            }
+            Value::Number(number) => Ok(MapKey::Number((number + 0.0).to_bits())),
+            Value::Integer(integer) if integer.unsigned_abs() <= MAX_EXACT => {
+                Ok(MapKey::Number((*integer as f64).to_bits()))
+            }
+            Value::Integer(integer) => Ok(MapKey::Integer(*integer)),
+            Value::String(string) => Ok(MapKey::String(string.clone())),
+            Value::Instance(_) => Err(RuntimeError::msg(
+                "Instances need a 'hash' method to be map keys.",
+            )),
+            _ => Err(RuntimeError::msg(format!(
+                "Map keys must be strings, numbers or booleans, not {}.",
+                value.type_name()
+            ))),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Bool(boolean) => Value::Bool(*boolean),
+            MapKey::Number(bits) => {
+                let number = f64::from_bits(*bits);
+                if number.fract() == 0.0 && number.abs() <= MAX_EXACT as f64 {
+                    Value::Integer(number as i64)
+                } else {
+                    Value::Number(number)
+                }
+            }
+            MapKey::Integer(integer) => Value::Integer(*integer),
+            MapKey::String(string) => Value::String(string.clone()),
+            MapKey::Instance(key) => Value::Instance(key.instance.clone()),
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_value())
+    }
+}
+
+// instance keys only hash and compare what `hash` returned, never the
+// instance itself
+#[allow(clippy::mutable_key_type)]
+pub fn new(entries: OrderedMap<MapKey, Value>) -> Value {
+    gc::allocate(entries.len() * ENTRY_SIZE);
+    let map = Rc::new(RefCell::new(entries));
+    gc::track(&map);
+    Value::Map(map)
+}
+
+/// Remove the entry for `key`, keeping the keys sharing its hash next to
+/// each other.
+pub fn remove(map: &MapRef, key: &MapKey) -> Option<Value> {
+    let mut map = map.borrow_mut();
+    let removed = map.remove(key)?;
+    if let MapKey::Instance(key) = key {
+        // the last key with the same hash takes the freed position
+        let mut last = key.clone();
+        loop {
+            last.collision += 1;
+            if !map.contains_key(&MapKey::Instance(last.clone())) {
+                break;
+            }
+        }
+        last.collision -= 1;
+        if last.collision > key.collision {
+            let last = MapKey::Instance(last);
+            if let Some((MapKey::Instance(moved), _)) = map.get_key_value(&last) {
+                let mut moved = moved.clone();
+                moved.collision = key.collision;
+                map.replace_key(&last, MapKey::Instance(moved));
+            }
+        }
+    }
+    Some(removed)
+}
+
+pub fn undefined_key(key: &MapKey) -> RuntimeError {
+    RuntimeError::msg(format!("Undefined key '{}'.", key))
+}
+
+/// Look up one of the methods every map has, bound to `map`.
+pub fn get_method(map: &MapRef, name: &str) -> Option<Value> {
+    let method = match name {
+        "has" => NativeFunction::with_callbacks(name, 1, {
+            let map = map.clone();
+            move |interpreter, arguments, span| {
+                let key = interpreter.map_key(&map, &arguments[0], span)?;
+                Ok(Value::Bool(map.borrow().contains_key(&key)))
+            }
+        }),
+        // whether there was something to delete
+        "delete" => NativeFunction::with_callbacks(name, 1, {
+            let map = map.clone();
+            move |interpreter, arguments, span| {
+                let key = interpreter.map_key(&map, &arguments[0], span)?;
+                Ok(Value::Bool(remove(&map, &key).is_some()))
+            }
+        }),
+        "keys" => bind(map, name, 0, |map, _| {
+            let keys = map.borrow().keys().map(MapKey::to_value).collect();
+            Ok(list::new(keys))
+        }),
+        "values" => bind(map, name, 0, |map, _| {
+            let values = map.borrow().values().cloned().collect();
+            Ok(list::new(values))
+        }),
+        _ => return None,
+    };
+    Some(Value::Native(Rc::new(method)))
+}
+
+fn bind<F>(map: &MapRef, name: &str, arity: usize, method: F) -> NativeFunction
+where
+    F: Fn(&MapRef, &[Value]) -> Result<Value, RuntimeError> + 'static,
+{
+    let map = map.clone();
+    NativeFunction::new(name, arity, move |arguments| method(&map, arguments))
+}
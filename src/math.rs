@@ -0,0 +1,156 @@
+//! The math natives: `sqrt`, `abs`, `floor`, `ceil`, `round`, `pow`, `min`,
+//! `max`, the trigonometric functions and the `pi` constant.
+//!
+//! They work on the values of the interpreter, and the VMs convert their
+//! numbers to call them, as they do for arithmetic. Integers stay integers
+//! where the result is one, so `abs(-3)` and `floor(2.5)` can index lists,
+//! and become floats where they would overflow, like operators do.
+
+use std::convert::TryFrom;
+use std::f64::consts::PI;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::value::{self, Value};
+
+type Native = fn(&[Value]) -> Result<Value, RuntimeError>;
+
+// by name, with their arities
+const NATIVES: &[(&str, usize, Native)] = &[
+    ("abs", 1, abs),
+    ("acos", 1, acos),
+    ("asin", 1, asin),
+    ("atan", 1, atan),
+    ("atan2", 2, atan2),
+    ("ceil", 1, ceil),
+    ("cos", 1, cos),
+    ("floor", 1, floor),
+    ("max", 2, max),
+    ("min", 2, min),
+    ("pow", 2, pow),
+    ("round", 1, round),
+    ("sin", 1, sin),
+    ("sqrt", 1, sqrt),
+    ("tan", 1, tan),
+];
+
+/// Register the math natives and `pi` with an interpreter.
+pub fn register(interpreter: &mut Interpreter) {
+    for &(name, arity, native) in NATIVES {
+        interpreter.define_native(name, arity, native);
+    }
+    interpreter.define_global("pi", Value::Number(PI));
+}
+
+pub(crate) fn abs(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Integer(integer) => Ok(match integer.checked_abs() {
+            Some(abs) => Value::Integer(abs),
+            None => Value::Number((*integer as f64).abs()),
+        }),
+        other => Ok(Value::Number(number(other)?.abs())),
+    }
+}
+
+pub(crate) fn floor(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    whole(&arguments[0], f64::floor)
+}
+
+pub(crate) fn ceil(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    whole(&arguments[0], f64::ceil)
+}
+
+/// Halfway cases round away from zero.
+pub(crate) fn round(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    whole(&arguments[0], f64::round)
+}
+
+pub(crate) fn pow(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    if let (Value::Integer(base), Value::Integer(exponent)) = (&arguments[0], &arguments[1]) {
+        let power = u32::try_from(*exponent)
+            .ok()
+            .and_then(|exponent| base.checked_pow(exponent));
+        if let Some(power) = power {
+            return Ok(Value::Integer(power));
+        }
+    }
+    Ok(Value::Number(
+        number(&arguments[0])?.powf(number(&arguments[1])?),
+    ))
+}
+
+/// The smaller argument as it is, or NaN if either is.
+pub(crate) fn min(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    pick(arguments, |left, right| right < left)
+}
+
+/// The larger argument as it is, or NaN if either is.
+pub(crate) fn max(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    pick(arguments, |left, right| right > left)
+}
+
+pub(crate) fn sqrt(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    float(&arguments[0], f64::sqrt)
+}
+
+pub(crate) fn sin(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    float(&arguments[0], f64::sin)
+}
+
+pub(crate) fn cos(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    float(&arguments[0], f64::cos)
+}
+
+pub(crate) fn tan(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    float(&arguments[0], f64::tan)
+}
+
+pub(crate) fn asin(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    float(&arguments[0], f64::asin)
+}
+
+pub(crate) fn acos(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    float(&arguments[0], f64::acos)
+}
+
+pub(crate) fn atan(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    float(&arguments[0], f64::atan)
+}
+
+/// The angle of the point `(x, y)`, taking `y` first.
+pub(crate) fn atan2(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let (y, x) = (number(&arguments[0])?, number(&arguments[1])?);
+    Ok(Value::Number(y.atan2(x)))
+}
+
+pub(crate) fn number(value: &Value) -> Result<f64, RuntimeError> {
+    value.as_number().ok_or_else(|| {
+        RuntimeError::msg(format!("Expected a number but got {}.", value.type_name()))
+    })
+}
+
+fn float(value: &Value, function: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(function(number(value)?)))
+}
+
+// rounded to an integer, unless it's too big for one or not a number
+fn whole(value: &Value, function: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    if let Value::Integer(integer) = value {
+        return Ok(Value::Integer(*integer));
+    }
+    let rounded = function(number(value)?);
+    Ok(match value::exact_integer(rounded) {
+        Some(integer) => Value::Integer(integer),
+        None => Value::Number(rounded),
+    })
+}
+
+fn pick(arguments: &[Value], replaces: fn(f64, f64) -> bool) -> Result<Value, RuntimeError> {
+    let (left, right) = (number(&arguments[0])?, number(&arguments[1])?);
+    if left.is_nan() || right.is_nan() {
+        return Ok(Value::Number(f64::NAN));
+    }
+    match replaces(left, right) {
+        true => Ok(arguments[1].clone()),
+        false => Ok(arguments[0].clone()),
+    }
+}
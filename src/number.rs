@@ -0,0 +1,125 @@
+//! The natives turning numbers into text and back: `parseNumber(text)`,
+//! `toFixed(number, digits)`, `toHex(integer)` and
+//! `numberToString(number, radix)`.
+//!
+//! `parseNumber` reads what `print` writes, `1.0E7`, `NaN` and `Infinity`
+//! included, with an optional exponent, and gives `nil` for anything else
+//! rather than failing, so it can check input. Whole numbers without a point
+//! or exponent are integers. With a radix other than 10 it reads integers
+//! in it, the inverse of `numberToString` and `toHex`, whose digits past 9
+//! are lowercase letters.
+
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::function::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::math::number;
+use crate::random::integer;
+use crate::string::string;
+use crate::value::{self, Value};
+
+// more digits than a float holds, but that's what fixed notation is for
+const MAX_FIXED_DIGITS: i64 = 100;
+
+/// Register the number natives with an interpreter.
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.define_native("numberToString", 2, |arguments| {
+        let radix = radix(&arguments[1])?;
+        if radix == 10 {
+            number(&arguments[0])?;
+            return Ok(Value::from(arguments[0].to_string()));
+        }
+        Ok(Value::from(to_radix(integer(&arguments[0])?, radix)))
+    });
+    let mut parse = NativeFunction::new("parseNumber", 2, |arguments| {
+        let text = string(&arguments[0])?.trim();
+        let radix = arguments.get(1).map_or(Ok(10), radix)?;
+        Ok(match radix {
+            10 => parse_decimal(text),
+            radix => i64::from_str_radix(text, radix).ok().map(Value::Integer),
+        }
+        .unwrap_or(Value::Nil))
+    });
+    // reading decimals by default
+    parse.arity.min = 1;
+    interpreter.define_global("parseNumber", Value::Native(Rc::new(parse)));
+    interpreter.define_native("toFixed", 2, |arguments| {
+        let number = number(&arguments[0])?;
+        let digits = integer(&arguments[1])?;
+        if !(0..=MAX_FIXED_DIGITS).contains(&digits) {
+            return Err(RuntimeError::msg(format!(
+                "Digits must be between 0 and {} but got {}.",
+                MAX_FIXED_DIGITS, digits
+            )));
+        }
+        if !number.is_finite() {
+            return Ok(Value::from(value::format_number(number)));
+        }
+        Ok(Value::from(format!("{:.*}", digits as usize, number)))
+    });
+    interpreter.define_native("toHex", 1, |arguments| {
+        Ok(Value::from(to_radix(integer(&arguments[0])?, 16)))
+    });
+}
+
+fn parse_decimal(text: &str) -> Option<Value> {
+    match text {
+        "NaN" => return Some(Value::Number(f64::NAN)),
+        "Infinity" => return Some(Value::Number(f64::INFINITY)),
+        "-Infinity" => return Some(Value::Number(f64::NEG_INFINITY)),
+        _ => {}
+    }
+    // a sign, digits, then maybe a point followed by digits and an exponent,
+    // leaving out what Rust reads but Lox doesn't write, like `.5` or `inf`
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(at) => (&unsigned[..at], Some(&unsigned[at + 1..])),
+        None => (unsigned, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let exponent_digits = |part: &str| digits(part.strip_prefix(['-', '+']).unwrap_or(part));
+    let valid =
+        digits(whole) && fraction.is_none_or(digits) && exponent.is_none_or(exponent_digits);
+    if !valid {
+        return None;
+    }
+    if fraction.is_none() && exponent.is_none() {
+        if let Ok(integer) = text.parse() {
+            return Some(Value::Integer(integer));
+        }
+    }
+    text.parse().ok().map(Value::Number)
+}
+
+fn to_radix(integer: i64, radix: u32) -> String {
+    let mut magnitude = integer.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        let digit = (magnitude % u64::from(radix)) as u32;
+        digits.push(std::char::from_digit(digit, radix).expect("a digit of the radix"));
+        magnitude /= u64::from(radix);
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if integer < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+fn radix(value: &Value) -> Result<u32, RuntimeError> {
+    let radix = integer(value)?;
+    match u32::try_from(radix) {
+        Ok(radix) if (2..=36).contains(&radix) => Ok(radix),
+        _ => Err(RuntimeError::msg(format!(
+            "Radix must be between 2 and 36 but got {}.",
+            radix
+        ))),
+    }
+}
@@ -0,0 +1,548 @@
+//! The objects of the bytecode VM, which its values point to, and the heap
+//! collecting them.
+//!
+//! The heap owns every object. Values hold plain pointers to them, which
+//! stay valid as long as the objects are reachable from the VM: collecting
+//! is marking what the VM hands over as roots and everything they point to,
+//! then freeing the rest, as clox does. Nothing outside the crate gets
+//! hold of those pointers, only the VM does, so it is the one keeping them
+//! reachable for as long as it uses them.
+//!
+//! Strings are interned: the heap holds a single string of any contents, so
+//! comparing strings is comparing pointers. The table of strings doesn't
+//! keep them alive, those only it points to are removed when collecting.
+//!
+//! When the heap collects, and how big it may get, is set with a `GcConfig`.
+//! Collecting less often trades memory for throughput, more often shortens
+//! the pauses; `GcStats` tells how it's going.
+
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::io::Write;
+use std::mem;
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::chunk::Function;
+use crate::vm::Vm;
+use crate::vm_value::Value;
+
+/// When the heap collects, and how big it may get.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    /// How many times what survived a collection the heap may grow to
+    /// before the next one.
+    pub growth_factor: f64,
+    /// The bytes allocated before the first collection, and the least the
+    /// heap grows to before any other.
+    pub initial_threshold: usize,
+    /// The bytes the heap may not outgrow once collected, running out of
+    /// memory otherwise.
+    pub max_heap: Option<usize>,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            growth_factor: 2.0,
+            // collecting right after a few allocations would be a waste of
+            // time
+            initial_threshold: 1024 * 1024,
+            max_heap: None,
+        }
+    }
+}
+
+/// What the collector did so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GcStats {
+    pub collections: u64,
+    pub objects_freed: u64,
+    pub bytes_freed: u64,
+    /// The time the program was stopped collecting, in all.
+    pub total_pause: Duration,
+    /// The longest collection.
+    pub max_pause: Duration,
+}
+
+pub enum Obj {
+    String(String),
+    /// An integer too big to fit in a NaN-boxed value.
+    Integer(i64),
+    /// A compiled function, along with its constants as values and the
+    /// inline caches of its instructions.
+    Function {
+        function: Rc<Function>,
+        constants: Vec<Value>,
+        caches: Vec<Cell<Option<Cached>>>,
+        /// The slots of the globals its instructions name, by the constant
+        /// holding the name, found the first time one runs.
+        globals: Vec<Cell<Option<usize>>>,
+    },
+    Closure {
+        /// The `Obj::Function` it's a closure of.
+        function: ObjRef,
+        /// The `Obj::Upvalue`s holding the variables it captures.
+        upvalues: Vec<ObjRef>,
+    },
+    Upvalue(Cell<Upvalue>),
+    Class {
+        /// The interned string of its name.
+        name: ObjRef,
+        /// Closures by their interned names, including the inherited ones.
+        methods: RefCell<ObjMap<ObjRef>>,
+    },
+    Instance {
+        class: ObjRef,
+        /// By their interned names.
+        fields: RefCell<ObjMap<Value>>,
+    },
+    /// A method taken off an instance, to call later.
+    BoundMethod {
+        receiver: Value,
+        /// The closure of the method.
+        method: ObjRef,
+    },
+    /// A function written in Rust, failing with an error message.
+    Native {
+        arity: usize,
+        function: fn(&mut Vm, &[Value]) -> Result<Value, String>,
+    },
+}
+
+/// What an inline cache remembers: the method an instruction found last,
+/// and the class it found it in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cached {
+    pub class: ObjRef,
+    pub method: ObjRef,
+}
+
+/// A variable a closure captures.
+#[derive(Debug, Clone, Copy)]
+pub enum Upvalue {
+    /// Still a local on the stack, in this slot.
+    Open(usize),
+    /// Moved off the stack once the local went out of scope.
+    Closed(Value),
+}
+
+impl Obj {
+    fn kind(&self) -> &'static str {
+        match self {
+            Obj::String(_) => "string",
+            Obj::Integer(_) => "integer",
+            Obj::Function { .. } => "function",
+            Obj::Closure { .. } => "closure",
+            Obj::Upvalue(_) => "upvalue",
+            Obj::Class { .. } => "class",
+            Obj::Instance { .. } => "instance",
+            Obj::BoundMethod { .. } => "bound method",
+            Obj::Native { .. } => "native",
+        }
+    }
+
+    // roughly the bytes the object takes, counting what it owns
+    fn size(&self) -> usize {
+        let owned = match self {
+            Obj::String(string) => string.capacity(),
+            Obj::Function {
+                constants,
+                caches,
+                globals,
+                ..
+            } => {
+                constants.capacity() * mem::size_of::<Value>()
+                    + caches.capacity() * mem::size_of::<Option<Cached>>()
+                    + globals.capacity() * mem::size_of::<Option<usize>>()
+            }
+            Obj::Closure { upvalues, .. } => upvalues.capacity() * mem::size_of::<ObjRef>(),
+            Obj::Class { methods, .. } => {
+                methods.borrow().capacity() * 2 * mem::size_of::<ObjRef>()
+            }
+            Obj::Instance { fields, .. } => {
+                fields.borrow().capacity() * (mem::size_of::<ObjRef>() + mem::size_of::<Value>())
+            }
+            Obj::Integer(_) | Obj::Upvalue(_) | Obj::BoundMethod { .. } | Obj::Native { .. } => 0,
+        };
+        mem::size_of::<Object>() + owned
+    }
+
+    // the objects this one points to
+    fn references(&self) -> Vec<ObjRef> {
+        match self {
+            Obj::String(_) | Obj::Integer(_) | Obj::Native { .. } => Vec::new(),
+            // what the caches remember stays alive, so they never point to
+            // freed objects
+            Obj::Function {
+                constants, caches, ..
+            } => constants
+                .iter()
+                .filter_map(|constant| constant.as_object())
+                .chain(
+                    caches
+                        .iter()
+                        .filter_map(Cell::get)
+                        .flat_map(|cached| vec![cached.class, cached.method]),
+                )
+                .collect(),
+            Obj::Closure { function, upvalues } => {
+                let mut references = vec![*function];
+                references.extend(upvalues);
+                references
+            }
+            Obj::Upvalue(upvalue) => match upvalue.get() {
+                Upvalue::Open(_) => Vec::new(),
+                Upvalue::Closed(value) => value.as_object().into_iter().collect(),
+            },
+            Obj::Class { name, methods } => {
+                let mut references = vec![*name];
+                for (name, method) in methods.borrow().iter() {
+                    references.push(*name);
+                    references.push(*method);
+                }
+                references
+            }
+            Obj::Instance { class, fields } => {
+                let mut references = vec![*class];
+                for (name, value) in fields.borrow().iter() {
+                    references.push(*name);
+                    references.extend(value.as_object());
+                }
+                references
+            }
+            Obj::BoundMethod { receiver, method } => {
+                let mut references = vec![*method];
+                references.extend(receiver.as_object());
+                references
+            }
+        }
+    }
+}
+
+struct Object {
+    marked: Cell<bool>,
+    // the bytes counted for it, which grow along with instances and classes
+    size: Cell<usize>,
+    obj: Obj,
+}
+
+/// A pointer to an object of a `Heap`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(NonNull<Object>);
+
+impl ObjRef {
+    pub fn get(&self) -> &Obj {
+        // the heap frees objects only when they can't be reached from the
+        // VM holding the values
+        unsafe { &self.0.as_ref().obj }
+    }
+
+    fn object(&self) -> &Object {
+        unsafe { self.0.as_ref() }
+    }
+
+    /// The contents of a string, or `""` for other objects.
+    pub fn as_str(&self) -> &str {
+        match self.get() {
+            Obj::String(string) => string,
+            _ => "",
+        }
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    pub(crate) fn address(self) -> u64 {
+        self.0.as_ptr() as u64
+    }
+
+    /// # Safety
+    ///
+    /// `address` must come from `address` on an object that is still alive.
+    #[cfg(feature = "nan-boxing")]
+    pub(crate) unsafe fn from_address(address: u64) -> Self {
+        ObjRef(NonNull::new_unchecked(address as *mut Object))
+    }
+}
+
+impl fmt::Debug for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Obj::String(string) => write!(f, "{:?}", string),
+            Obj::Integer(integer) => write!(f, "{}", integer),
+            Obj::Function { function, .. } => write!(f, "<fn {}>", function.name),
+            Obj::Closure { function, .. } => write!(f, "{:?}", function),
+            Obj::Upvalue(_) => write!(f, "upvalue"),
+            Obj::Class { name, .. } => write!(f, "{}", name.as_str()),
+            Obj::Instance { class, .. } => write!(f, "{:?} instance", class),
+            Obj::BoundMethod { method, .. } => write!(f, "{:?}", method),
+            Obj::Native { .. } => write!(f, "<native fn>"),
+        }
+    }
+}
+
+/// A map keyed by objects, mostly interned strings, hashing their
+/// addresses: it's faster than the default hasher, and has nothing to fear
+/// from keys picked to collide.
+pub type ObjMap<V> = HashMap<ObjRef, V, BuildHasherDefault<AddressHasher>>;
+
+#[derive(Default)]
+pub struct AddressHasher(u64);
+
+impl Hasher for AddressHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(u64::from(byte));
+        }
+    }
+
+    fn write_usize(&mut self, address: usize) {
+        self.write_u64(address as u64);
+    }
+
+    // spreads the bits of the address, whose low ones are always zeros
+    fn write_u64(&mut self, value: u64) {
+        self.0 = (self.0.rotate_left(5) ^ value).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+// a string of the table, found by its contents
+struct Interned(ObjRef);
+
+impl Borrow<str> for Interned {
+    fn borrow(&self) -> &str {
+        match self.0.get() {
+            Obj::String(string) => string,
+            _ => unreachable!("only strings are interned"),
+        }
+    }
+}
+
+impl PartialEq for Interned {
+    fn eq(&self, other: &Self) -> bool {
+        Borrow::<str>::borrow(self) == Borrow::<str>::borrow(other)
+    }
+}
+
+impl Eq for Interned {}
+
+// as the string, for lookups by `&str`
+impl Hash for Interned {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Borrow::<str>::borrow(self).hash(state);
+    }
+}
+
+pub struct Heap {
+    // boxed so objects don't move when the vector grows
+    #[allow(clippy::vec_box)]
+    objects: Vec<Box<Object>>,
+    strings: HashSet<Interned>,
+    bytes_allocated: usize,
+    next_collection: usize,
+    // objects marked but not traced yet
+    gray: Vec<ObjRef>,
+    stress: bool,
+    log: Option<Box<dyn Write>>,
+    config: GcConfig,
+    stats: GcStats,
+    // when the collection going on started
+    started: Option<Instant>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            strings: HashSet::new(),
+            bytes_allocated: 0,
+            next_collection: GcConfig::default().initial_threshold,
+            gray: Vec::new(),
+            stress: false,
+            log: None,
+            config: GcConfig::default(),
+            stats: GcStats::default(),
+            started: None,
+        }
+    }
+
+    /// Collect as `config` says from now on.
+    pub fn set_config(&mut self, config: GcConfig) {
+        self.config = config;
+        self.next_collection = self.next_threshold();
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Whether the heap outgrew its maximum size, garbage included.
+    pub fn over_limit(&self) -> bool {
+        self.config
+            .max_heap
+            .is_some_and(|max| self.bytes_allocated > max)
+    }
+
+    // a collection once the heap outgrows the maximum gets the garbage out
+    // of the way before running out of memory
+    fn next_threshold(&self) -> usize {
+        let grown = (self.bytes_allocated as f64 * self.config.growth_factor) as usize;
+        let threshold = self.config.initial_threshold.max(grown);
+        match self.config.max_heap {
+            Some(max) => threshold.min(max),
+            None => threshold,
+        }
+    }
+
+    /// Collect before every allocation, to find objects the VM forgets to
+    /// hand over as roots.
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    /// Trace allocations and collections to `log`, in the format of clox.
+    pub fn set_log<W: Write + 'static>(&mut self, log: W) {
+        self.log = Some(Box::new(log));
+    }
+
+    /// Whether to collect before the next allocation.
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_collection
+    }
+
+    /// The string of the heap with these contents, if there's one.
+    pub fn find_string(&self, string: &str) -> Option<ObjRef> {
+        self.strings.get(string).map(|interned| interned.0)
+    }
+
+    /// Allocate an object. It isn't a root, so it's up to the caller to
+    /// store it somewhere the next collection will find it.
+    ///
+    /// Strings must not be on the heap already, see `find_string`.
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        let size = obj.size();
+        let kind = obj.kind();
+        let mut object = Box::new(Object {
+            marked: Cell::new(false),
+            size: Cell::new(size),
+            obj,
+        });
+        let pointer = ObjRef(NonNull::from(&mut *object));
+        self.objects.push(object);
+        if let Obj::String(_) = pointer.get() {
+            let added = self.strings.insert(Interned(pointer));
+            debug_assert!(added, "strings are interned");
+        }
+        self.bytes_allocated += size;
+        self.trace(|| format!("{:p} allocate {} for {}", pointer.0, size, kind));
+        pointer
+    }
+
+    /// Count `bytes` more for an object that grew, like an instance getting
+    /// a new field.
+    pub fn grow(&mut self, object: ObjRef, bytes: usize) {
+        let size = &object.object().size;
+        size.set(size.get() + bytes);
+        self.bytes_allocated += bytes;
+    }
+
+    /// How many objects are alive.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Start a collection, marking `roots`. More can be marked with `mark`
+    /// before calling `sweep`.
+    pub fn mark_roots<I: IntoIterator<Item = ObjRef>>(&mut self, roots: I) {
+        self.trace(|| "-- gc begin".to_string());
+        self.started = Some(Instant::now());
+        for root in roots {
+            self.mark(root);
+        }
+    }
+
+    pub fn mark(&mut self, object: ObjRef) {
+        if object.object().marked.replace(true) {
+            return;
+        }
+        self.trace(|| format!("{:p} mark {:?}", object.0, object));
+        self.gray.push(object);
+    }
+
+    /// Trace what the marked objects point to, then free every object that
+    /// wasn't marked, returning how many there were.
+    pub fn sweep(&mut self) -> usize {
+        while let Some(object) = self.gray.pop() {
+            self.trace(|| format!("{:p} blacken {:?}", object.0, object));
+            for reference in object.get().references() {
+                self.mark(reference);
+            }
+        }
+        // the table would point to freed strings otherwise
+        self.strings
+            .retain(|interned| interned.0.object().marked.get());
+
+        let before = self.bytes_allocated;
+        let count = self.objects.len();
+        let mut freed = Vec::new();
+        self.objects.retain(|object| {
+            let marked = object.marked.replace(false);
+            if !marked {
+                freed.push((
+                    &**object as *const Object,
+                    object.size.get(),
+                    object.obj.kind(),
+                ));
+            }
+            marked
+        });
+        for (address, size, kind) in &freed {
+            self.bytes_allocated -= size;
+            self.trace(|| format!("{:p} free type {}", *address, kind));
+        }
+        self.next_collection = self.next_threshold();
+
+        let after = self.bytes_allocated;
+        let pause = self
+            .started
+            .take()
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        self.stats.collections += 1;
+        self.stats.objects_freed += freed.len() as u64;
+        self.stats.bytes_freed += (before - after) as u64;
+        self.stats.total_pause += pause;
+        self.stats.max_pause = self.stats.max_pause.max(pause);
+        let next = self.next_collection;
+        self.trace(|| "-- gc end".to_string());
+        self.trace(|| {
+            format!(
+                "   collected {} bytes (from {} to {}) next at {}",
+                before - after,
+                before,
+                after,
+                next
+            )
+        });
+        count - self.objects.len()
+    }
+
+    fn trace<F: FnOnce() -> String>(&mut self, line: F) {
+        if let Some(log) = &mut self.log {
+            // the log is for debugging, losing it is no reason to stop
+            let _ = writeln!(log, "{}", line());
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! A hash map remembering the order its keys were inserted in, which is the
+//! order maps and the fields of instances are iterated and printed in, so
+//! programs behave the same from one run to the next.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::iter::FromIterator;
+
+/// Entries in insertion order. Inserting a key that's already there keeps
+/// its position, and removing one keeps the order of the others.
+#[derive(Clone)]
+pub struct OrderedMap<K, V> {
+    // removed entries leave a hole behind, until there are as many holes as
+    // entries
+    slots: Vec<Option<(K, V)>>,
+    positions: HashMap<K, usize>,
+}
+
+impl<K, V> Default for OrderedMap<K, V> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> OrderedMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// How many entries fit without allocating, holes included.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let position = *self.positions.get(key)?;
+        self.slots[position].as_ref().map(|(_, value)| value)
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let position = *self.positions.get(key)?;
+        self.slots[position]
+            .as_ref()
+            .map(|(key, value)| (key, value))
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.positions.contains_key(key)
+    }
+
+    /// Set the value of `key`, handing back the one it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(&position) = self.positions.get(&key) {
+            let (_, old) = self.slots[position].replace((key, value))?;
+            return Some(old);
+        }
+        self.positions.insert(key.clone(), self.slots.len());
+        self.slots.push(Some((key, value)));
+        None
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let position = self.positions.remove(key)?;
+        let (_, value) = self.slots[position].take()?;
+        if self.slots.len() > 2 * self.positions.len() {
+            self.compact();
+        }
+        Some(value)
+    }
+
+    /// Replace the key of an entry by an equivalent one, keeping its place.
+    pub fn replace_key(&mut self, old: &K, new: K) {
+        if let Some(position) = self.positions.remove(old) {
+            if let Some((key, _)) = &mut self.slots[position] {
+                *key = new.clone();
+            }
+            self.positions.insert(new, position);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.slots.iter().flatten().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    // close the holes left by removed entries
+    fn compact(&mut self) {
+        self.slots.retain(Option::is_some);
+        for (position, (key, _)) in self.slots.iter().flatten().enumerate() {
+            if let Some(slot) = self.positions.get_mut(key) {
+                *slot = position;
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> FromIterator<(K, V)> for OrderedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Self {
+        let mut map = Self::new();
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl<K, V> IntoIterator for OrderedMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = std::iter::Flatten<std::vec::IntoIter<Option<(K, V)>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.slots.into_iter().flatten()
+    }
+}
+
+// maps with the same entries are equal, whatever their order
+impl<K: Hash + Eq + Clone, V: PartialEq> PartialEq for OrderedMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for OrderedMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.slots.iter().flatten().map(|(key, value)| (key, value)))
+            .finish()
+    }
+}
@@ -0,0 +1,1046 @@
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::lexer::{Lexer, Span, SyntaxError, Token, TokenKind};
+
+const MAX_ARGUMENTS: usize = 255;
+// how deeply statements and expressions nest, which the resolver, the
+// interpreter and the compiler recurse into as well
+const MAX_NESTING: usize = 1024;
+
+pub struct Parser {
+    source: String,
+    tokens: Vec<Token>,
+    current: usize,
+    eof: Span,
+    depth: usize,
+}
+
+impl Parser {
+    pub fn new(lexer: Lexer) -> anyhow::Result<Self> {
+        let eof = lexer.eof_span();
+        let source = lexer.buffer.clone();
+        let tokens = lexer.tokenize()?;
+
+        Ok(Self {
+            source,
+            tokens,
+            current: 0,
+            eof,
+            depth: 0,
+        })
+    }
+
+    /// Parse a whole program, stopping at the first syntax error.
+    pub fn parse(&mut self) -> anyhow::Result<Vec<Stmt>> {
+        let mut statements = Vec::new();
+        while !self.is_at_end() {
+            statements.push(self.declaration()?);
+        }
+        Ok(statements)
+    }
+
+    // Declarations
+
+    fn declaration(&mut self) -> anyhow::Result<Stmt> {
+        self.nest()?;
+        let declaration = if self.check(&TokenKind::Class) {
+            self.class_declaration()
+        } else if self.check(&TokenKind::Fun) {
+            let start = self.advance().span;
+            let function = self.function("function")?;
+            let span = start.to(function.span);
+            Ok(Stmt::new(StmtKind::Function(Rc::new(function)), span))
+        } else if self.check(&TokenKind::Async) {
+            let start = self.advance().span;
+            self.consume(&TokenKind::Fun, "Expect 'fun' after 'async'.")?;
+            let function = self.async_function("function")?;
+            let span = start.to(function.span);
+            Ok(Stmt::new(StmtKind::Function(Rc::new(function)), span))
+        } else if self.check(&TokenKind::Var) {
+            self.var_declaration()
+        } else if self.check(&TokenKind::Const) {
+            self.const_declaration()
+        } else {
+            self.statement()
+        };
+        self.depth -= 1;
+        declaration
+    }
+
+    fn class_declaration(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let name = self.consume_identifier("Expect class name.")?;
+
+        let superclass = if self.matches(&TokenKind::Less) {
+            let superclass = self.consume_identifier("Expect superclass name.")?;
+            let span = superclass.span;
+            Some(Expr::new(ExprKind::Variable(superclass), span))
+        } else {
+            None
+        };
+
+        self.consume(&TokenKind::LeftBrace, "Expect '{' before class body.")?;
+        let mut methods = Vec::new();
+        let mut getters = Vec::new();
+        let mut setters = Vec::new();
+        let mut class_methods = Vec::new();
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
+            if self.matches(&TokenKind::Class) {
+                class_methods.push(Rc::new(self.function("method")?));
+                continue;
+            }
+            if self.matches(&TokenKind::Async) {
+                methods.push(Rc::new(self.async_function("method")?));
+                continue;
+            }
+            match self.accessor() {
+                Some("get") => {
+                    self.advance();
+                    getters.push(Rc::new(self.getter()?));
+                }
+                Some(_) => {
+                    self.advance();
+                    setters.push(Rc::new(self.setter()?));
+                }
+                None => methods.push(Rc::new(self.function("method")?)),
+            }
+        }
+        let end = self.consume(&TokenKind::RightBrace, "Expect '}' after class body.")?;
+
+        Ok(Stmt::new(
+            StmtKind::Class(ClassDecl {
+                name,
+                superclass,
+                methods,
+                getters,
+                setters,
+                class_methods,
+            }),
+            start.to(end),
+        ))
+    }
+
+    // `get` and `set` only introduce accessors when a name follows them, so
+    // they still work as method names
+    fn accessor(&self) -> Option<&'static str> {
+        let word = match self.peek_kind() {
+            Some(TokenKind::Identifier(word)) if word == "get" => "get",
+            Some(TokenKind::Identifier(word)) if word == "set" => "set",
+            _ => return None,
+        };
+        match self.tokens.get(self.current + 1) {
+            Some(Token {
+                kind: TokenKind::Identifier(_),
+                ..
+            }) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn getter(&mut self) -> anyhow::Result<FunctionDecl> {
+        let name = self.consume_identifier("Expect getter name.")?;
+        self.consume(&TokenKind::LeftBrace, "Expect '{' before getter body.")?;
+        let (body, end) = self.block()?;
+
+        let span = name.span.to(end);
+        Ok(FunctionDecl {
+            id: NodeId::fresh(),
+            text: self.source[span.start..span.end].into(),
+            span,
+            name,
+            params: Vec::new(),
+            defaults: Vec::new(),
+            rest: None,
+            body,
+            is_async: false,
+        })
+    }
+
+    fn setter(&mut self) -> anyhow::Result<FunctionDecl> {
+        let setter = self.function("setter")?;
+        if setter.params.len() != 1 {
+            let error = SyntaxError::new(
+                "A setter must have exactly one parameter.",
+                setter.name.span,
+            )
+            .at(format!("'{}'", setter.name.name));
+            return Err(error.into());
+        }
+        Ok(setter)
+    }
+
+    fn function(&mut self, kind: &str) -> anyhow::Result<FunctionDecl> {
+        let name = self.consume_identifier(&format!("Expect {} name.", kind))?;
+        self.consume(
+            &TokenKind::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+
+        let mut params = Vec::new();
+        let mut defaults = Vec::new();
+        let mut rest = None;
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    return Err(self.error_at_current("Can't have more than 255 parameters."));
+                }
+                if self.matches(&TokenKind::DotDotDot) {
+                    rest = Some(self.consume_identifier("Expect parameter name.")?);
+                    break;
+                }
+                params.push(self.consume_identifier("Expect parameter name.")?);
+                if self.matches(&TokenKind::Equal) {
+                    defaults.push(self.expression()?);
+                } else if !defaults.is_empty() {
+                    let param = &self.tokens[self.current - 1];
+                    return Err(
+                        self.error_at(param, "Expect a default value, like the parameters before.")
+                    );
+                }
+                if !self.matches(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        let message = match rest {
+            Some(_) => "Expect ')' after rest parameter.",
+            None => "Expect ')' after parameters.",
+        };
+        self.consume(&TokenKind::RightParen, message)?;
+
+        self.consume(
+            &TokenKind::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        let (body, end) = self.block()?;
+
+        let span = name.span.to(end);
+        Ok(FunctionDecl {
+            id: NodeId::fresh(),
+            text: self.source[span.start..span.end].into(),
+            span,
+            name,
+            params,
+            defaults,
+            rest,
+            body,
+            is_async: false,
+        })
+    }
+
+    fn async_function(&mut self, kind: &str) -> anyhow::Result<FunctionDecl> {
+        let mut function = self.function(kind)?;
+        function.is_async = true;
+        Ok(function)
+    }
+
+    fn var_declaration(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let name = self.consume_identifier("Expect variable name.")?;
+
+        let initializer = if self.matches(&TokenKind::Equal) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        let end = self.consume(
+            &TokenKind::SemiColon,
+            "Expect ';' after variable declaration.",
+        )?;
+
+        Ok(Stmt::new(
+            StmtKind::Var { name, initializer },
+            start.to(end),
+        ))
+    }
+
+    fn const_declaration(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let name = self.consume_identifier("Expect constant name.")?;
+        self.consume(&TokenKind::Equal, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+        let end = self.consume(
+            &TokenKind::SemiColon,
+            "Expect ';' after constant declaration.",
+        )?;
+
+        Ok(Stmt::new(
+            StmtKind::Const { name, initializer },
+            start.to(end),
+        ))
+    }
+
+    // Statements
+
+    fn statement(&mut self) -> anyhow::Result<Stmt> {
+        self.nest()?;
+        let statement = match self.peek_kind() {
+            Some(TokenKind::For) => self.for_statement(),
+            Some(TokenKind::If) => self.if_statement(),
+            Some(TokenKind::Print) => self.print_statement(),
+            Some(TokenKind::Return) => self.return_statement(),
+            Some(TokenKind::Throw) => self.throw_statement(),
+            Some(TokenKind::Try) => self.try_statement(),
+            Some(TokenKind::Import) => self.import_statement(),
+            Some(TokenKind::While) => self.while_statement(),
+            Some(TokenKind::Yield) => self.yield_statement(),
+            Some(TokenKind::Defer) => self.defer_statement(),
+            Some(TokenKind::Break) => {
+                let start = self.advance().span;
+                let end = self.consume(&TokenKind::SemiColon, "Expect ';' after 'break'.")?;
+                Ok(Stmt::new(StmtKind::Break, start.to(end)))
+            }
+            Some(TokenKind::Continue) => {
+                let start = self.advance().span;
+                let end = self.consume(&TokenKind::SemiColon, "Expect ';' after 'continue'.")?;
+                Ok(Stmt::new(StmtKind::Continue, start.to(end)))
+            }
+            Some(TokenKind::LeftBrace) => {
+                let start = self.advance().span;
+                let (statements, end) = self.block()?;
+                Ok(Stmt::new(StmtKind::Block(statements), start.to(end)))
+            }
+            _ => self.expression_statement(),
+        };
+        self.depth -= 1;
+        statement
+    }
+
+    fn for_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'for'.")?;
+
+        let is_for_in = matches!(self.peek_kind(), Some(TokenKind::Identifier(_)))
+            && matches!(self.tokens.get(self.current + 1), Some(token) if token.kind == TokenKind::In);
+        if is_for_in {
+            let name = self.consume_identifier("Expect variable name.")?;
+            self.advance();
+            let iterable = self.expression()?;
+            self.consume(&TokenKind::RightParen, "Expect ')' after for clauses.")?;
+            let body = self.statement()?;
+            let span = start.to(body.span);
+            return Ok(Stmt::new(
+                StmtKind::ForIn {
+                    name,
+                    iterable,
+                    body: Box::new(body),
+                },
+                span,
+            ));
+        }
+
+        let initializer = if self.matches(&TokenKind::SemiColon) {
+            None
+        } else if self.check(&TokenKind::Var) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(&TokenKind::SemiColon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        let condition_end =
+            self.consume(&TokenKind::SemiColon, "Expect ';' after loop condition.")?;
+
+        let increment = if self.check(&TokenKind::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&TokenKind::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.statement()?;
+        let span = start.to(body.span);
+
+        // desugar into a while loop
+        let condition = condition
+            .unwrap_or_else(|| Expr::new(ExprKind::Literal(Literal::Bool(true)), condition_end));
+        let mut body = Stmt::new(
+            StmtKind::While {
+                condition,
+                body: Box::new(body),
+                increment,
+            },
+            span,
+        );
+
+        if let Some(initializer) = initializer {
+            body = Stmt::new(StmtKind::Block(vec![initializer, body]), span);
+        }
+
+        Ok(body)
+    }
+
+    fn if_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(&TokenKind::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.matches(&TokenKind::Else) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        let end = else_branch.as_ref().unwrap_or(&then_branch).span;
+        Ok(Stmt::new(
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            },
+            start.to(end),
+        ))
+    }
+
+    fn print_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let value = self.expression()?;
+        let end = self.consume(&TokenKind::SemiColon, "Expect ';' after value.")?;
+        Ok(Stmt::new(StmtKind::Print(value), start.to(end)))
+    }
+
+    fn return_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let value = if self.check(&TokenKind::SemiColon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        let end = self.consume(&TokenKind::SemiColon, "Expect ';' after return value.")?;
+        Ok(Stmt::new(StmtKind::Return(value), start.to(end)))
+    }
+
+    fn yield_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let value = if self.check(&TokenKind::SemiColon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        let end = self.consume(&TokenKind::SemiColon, "Expect ';' after yield value.")?;
+        Ok(Stmt::new(StmtKind::Yield(value), start.to(end)))
+    }
+
+    fn defer_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let expr = self.expression()?;
+        let end = self.consume(
+            &TokenKind::SemiColon,
+            "Expect ';' after deferred expression.",
+        )?;
+        Ok(Stmt::new(StmtKind::Defer(expr), start.to(end)))
+    }
+
+    fn import_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let path = match self.peek_kind() {
+            Some(TokenKind::String(path)) => path.clone(),
+            _ => return Err(self.error_at_current("Expect module path after 'import'.")),
+        };
+        self.advance();
+        let end = self.consume(&TokenKind::SemiColon, "Expect ';' after module path.")?;
+        Ok(Stmt::new(StmtKind::Import(path), start.to(end)))
+    }
+
+    fn throw_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let value = self.expression()?;
+        let end = self.consume(&TokenKind::SemiColon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::new(StmtKind::Throw(value), start.to(end)))
+    }
+
+    fn try_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        let body = Box::new(self.block_statement("Expect '{' after 'try'.")?);
+        let mut end = body.span;
+
+        let catch = if self.check(&TokenKind::Catch) {
+            let catch_start = self.advance().span;
+            self.consume(&TokenKind::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume_identifier("Expect exception name.")?;
+            self.consume(&TokenKind::RightParen, "Expect ')' after exception name.")?;
+            let body = Box::new(self.block_statement("Expect '{' before catch body.")?);
+            end = body.span;
+            Some(CatchClause {
+                name,
+                span: catch_start.to(body.span),
+                body,
+            })
+        } else {
+            None
+        };
+
+        let finally = if self.matches(&TokenKind::Finally) {
+            let body = self.block_statement("Expect '{' after 'finally'.")?;
+            end = body.span;
+            Some(Box::new(body))
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error_at_current("Expect 'catch' or 'finally' after try block."));
+        }
+
+        Ok(Stmt::new(
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            },
+            start.to(end),
+        ))
+    }
+
+    fn while_statement(&mut self) -> anyhow::Result<Stmt> {
+        let start = self.advance().span;
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(&TokenKind::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.statement()?);
+
+        let span = start.to(body.span);
+        Ok(Stmt::new(
+            StmtKind::While {
+                condition,
+                body,
+                increment: None,
+            },
+            span,
+        ))
+    }
+
+    /// Parse the declarations of a block whose `{` was already consumed,
+    /// returning them along with the span of the closing `}`.
+    fn block(&mut self) -> anyhow::Result<(Vec<Stmt>, Span)> {
+        let mut statements = Vec::new();
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
+            statements.push(self.declaration()?);
+        }
+        let end = self.consume(&TokenKind::RightBrace, "Expect '}' after block.")?;
+        Ok((statements, end))
+    }
+
+    /// Parse a block, braces included, as a statement.
+    fn block_statement(&mut self, message: &str) -> anyhow::Result<Stmt> {
+        let start = self.consume(&TokenKind::LeftBrace, message)?;
+        let (statements, end) = self.block()?;
+        Ok(Stmt::new(StmtKind::Block(statements), start.to(end)))
+    }
+
+    fn expression_statement(&mut self) -> anyhow::Result<Stmt> {
+        let expr = self.expression()?;
+        let end = self.consume(&TokenKind::SemiColon, "Expect ';' after expression.")?;
+        let span = expr.span.to(end);
+        Ok(Stmt::new(StmtKind::Expression(expr), span))
+    }
+
+    // Expressions
+
+    pub(crate) fn expression(&mut self) -> anyhow::Result<Expr> {
+        self.nest()?;
+        let expr = self.assignment();
+        self.depth -= 1;
+        expr
+    }
+
+    fn assignment(&mut self) -> anyhow::Result<Expr> {
+        let expr = self.or()?;
+
+        if self.check(&TokenKind::Equal) {
+            let equals = self.advance().clone();
+            let value = Box::new(self.expression()?);
+            let span = expr.span.to(value.span);
+
+            return match expr.kind {
+                ExprKind::Variable(name) => Ok(Expr::new(ExprKind::Assign { name, value }, span)),
+                ExprKind::Get { object, name } => Ok(Expr::new(
+                    ExprKind::Set {
+                        object,
+                        name,
+                        value,
+                    },
+                    span,
+                )),
+                ExprKind::Index { object, index } => Ok(Expr::new(
+                    ExprKind::SetIndex {
+                        object,
+                        index,
+                        value,
+                    },
+                    span,
+                )),
+                _ => Err(self.error_at(&equals, "Invalid assignment target.")),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut expr = self.and()?;
+        while self.matches(&TokenKind::Or) {
+            self.nest()?;
+            let right = self.and()?;
+            expr = logical(expr, LogicalOp::Or, right);
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut expr = self.equality()?;
+        while self.matches(&TokenKind::And) {
+            self.nest()?;
+            let right = self.equality()?;
+            expr = logical(expr, LogicalOp::And, right);
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut expr = self.comparison()?;
+        loop {
+            let op = match self.peek_kind() {
+                Some(TokenKind::BangEqual) => BinaryOp::NotEqual,
+                Some(TokenKind::EqualEqual) => BinaryOp::Equal,
+                _ => break,
+            };
+            self.advance();
+            self.nest()?;
+            let right = self.comparison()?;
+            expr = binary(expr, op, right);
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut expr = self.bit_or()?;
+        loop {
+            let op = match self.peek_kind() {
+                Some(TokenKind::Greater) => BinaryOp::Greater,
+                Some(TokenKind::GreaterEqual) => BinaryOp::GreaterEqual,
+                Some(TokenKind::Less) => BinaryOp::Less,
+                Some(TokenKind::LessEqual) => BinaryOp::LessEqual,
+                _ => break,
+            };
+            self.advance();
+            self.nest()?;
+            let right = self.bit_or()?;
+            expr = binary(expr, op, right);
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn bit_or(&mut self) -> anyhow::Result<Expr> {
+        self.left_associative(&[(TokenKind::Pipe, BinaryOp::BitOr)], Self::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> anyhow::Result<Expr> {
+        self.left_associative(&[(TokenKind::Caret, BinaryOp::BitXor)], Self::bit_and)
+    }
+
+    fn bit_and(&mut self) -> anyhow::Result<Expr> {
+        self.left_associative(&[(TokenKind::Ampersand, BinaryOp::BitAnd)], Self::shift)
+    }
+
+    fn shift(&mut self) -> anyhow::Result<Expr> {
+        let ops = [
+            (TokenKind::LessLess, BinaryOp::ShiftLeft),
+            (TokenKind::GreaterGreater, BinaryOp::ShiftRight),
+        ];
+        self.left_associative(&ops, Self::range)
+    }
+
+    // operands parsed with `operand`, separated by any of `ops`
+    fn left_associative(
+        &mut self,
+        ops: &[(TokenKind, BinaryOp)],
+        operand: fn(&mut Self) -> anyhow::Result<Expr>,
+    ) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut expr = operand(self)?;
+        while let Some(op) = ops
+            .iter()
+            .find(|(kind, _)| self.check(kind))
+            .map(|(_, op)| *op)
+        {
+            self.advance();
+            self.nest()?;
+            let right = operand(self)?;
+            expr = binary(expr, op, right);
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn range(&mut self) -> anyhow::Result<Expr> {
+        let expr = self.term()?;
+        // `list[start..]` slices, leaving the `..` to the index
+        let open = matches!(self.tokens.get(self.current + 1), Some(token) if token.kind == TokenKind::RightBracket);
+        if !self.check(&TokenKind::DotDot) || open {
+            return Ok(expr);
+        }
+        self.advance();
+        let right = self.term()?;
+        Ok(binary(expr, BinaryOp::Range, right))
+    }
+
+    fn term(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut expr = self.factor()?;
+        loop {
+            let op = match self.peek_kind() {
+                Some(TokenKind::Minus) => BinaryOp::Subtract,
+                Some(TokenKind::Plus) => BinaryOp::Add,
+                _ => break,
+            };
+            self.advance();
+            self.nest()?;
+            let right = self.factor()?;
+            expr = binary(expr, op, right);
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut expr = self.unary()?;
+        loop {
+            let op = match self.peek_kind() {
+                Some(TokenKind::Slash) => BinaryOp::Divide,
+                Some(TokenKind::TildeSlash) => BinaryOp::FloorDivide,
+                Some(TokenKind::Star) => BinaryOp::Multiply,
+                Some(TokenKind::Percent) => BinaryOp::Modulo,
+                _ => break,
+            };
+            self.advance();
+            self.nest()?;
+            let right = self.unary()?;
+            expr = binary(expr, op, right);
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        let op = match self.peek_kind() {
+            Some(TokenKind::Bang) => UnaryOp::Not,
+            Some(TokenKind::Minus) => UnaryOp::Negate,
+            Some(TokenKind::Tilde) => UnaryOp::BitNot,
+            Some(TokenKind::Await) => {
+                let start = self.advance().span;
+                self.nest()?;
+                let promise = Box::new(self.unary()?);
+                self.depth -= 1;
+                let span = start.to(promise.span);
+                return Ok(Expr::new(ExprKind::Await(promise), span));
+            }
+            _ => return self.call(),
+        };
+        let start = self.advance().span;
+        self.nest()?;
+        let right = Box::new(self.unary()?);
+        self.depth -= 1;
+        let span = start.to(right.span);
+        Ok(Expr::new(ExprKind::Unary { op, right }, span))
+    }
+
+    fn call(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut expr = self.primary()?;
+        loop {
+            if self.matches(&TokenKind::LeftParen) {
+                expr = self.finish_call(expr)?;
+            } else if self.matches(&TokenKind::Dot) {
+                let name = self.consume_identifier("Expect property name after '.'.")?;
+                let span = expr.span.to(name.span);
+                expr = Expr::new(
+                    ExprKind::Get {
+                        object: Box::new(expr),
+                        name,
+                    },
+                    span,
+                );
+            } else if self.matches(&TokenKind::LeftBracket) {
+                expr = self.finish_index(expr)?;
+            } else {
+                break;
+            }
+            self.nest()?;
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn finish_index(&mut self, object: Expr) -> anyhow::Result<Expr> {
+        if self.matches(&TokenKind::DotDot) {
+            return self.finish_slice(object, None);
+        }
+        let index = self.expression()?;
+        if self.matches(&TokenKind::DotDot) {
+            return self.finish_slice(object, Some(index));
+        }
+
+        let end = self.consume(&TokenKind::RightBracket, "Expect ']' after index.")?;
+        let span = object.span.to(end);
+        Ok(Expr::new(
+            ExprKind::Index {
+                object: Box::new(object),
+                index: Box::new(index),
+            },
+            span,
+        ))
+    }
+
+    fn finish_slice(&mut self, object: Expr, start: Option<Expr>) -> anyhow::Result<Expr> {
+        let end = if self.check(&TokenKind::RightBracket) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+        let close = self.consume(&TokenKind::RightBracket, "Expect ']' after slice.")?;
+        let span = object.span.to(close);
+        Ok(Expr::new(
+            ExprKind::Slice {
+                object: Box::new(object),
+                start: start.map(Box::new),
+                end,
+            },
+            span,
+        ))
+    }
+
+    // an expression, or `...expression` spreading a list
+    fn argument(&mut self) -> anyhow::Result<Expr> {
+        if !self.check(&TokenKind::DotDotDot) {
+            return self.expression();
+        }
+        let start = self.advance().span;
+        let list = self.expression()?;
+        let span = start.to(list.span);
+        Ok(Expr::new(ExprKind::Spread(Box::new(list)), span))
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> anyhow::Result<Expr> {
+        let mut arguments = Vec::new();
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    return Err(self.error_at_current("Can't have more than 255 arguments."));
+                }
+                arguments.push(self.argument()?);
+                if !self.matches(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        let end = self.consume(&TokenKind::RightParen, "Expect ')' after arguments.")?;
+
+        let span = callee.span.to(end);
+        Ok(Expr::new(
+            ExprKind::Call {
+                callee: Box::new(callee),
+                arguments,
+            },
+            span,
+        ))
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Expr> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(self.error_at_current("Expect expression.")),
+        };
+
+        let kind = match token.kind {
+            TokenKind::False => ExprKind::Literal(Literal::Bool(false)),
+            TokenKind::True => ExprKind::Literal(Literal::Bool(true)),
+            TokenKind::Nil => ExprKind::Literal(Literal::Nil),
+            TokenKind::This => ExprKind::This,
+            TokenKind::Number(number) => ExprKind::Literal(Literal::Integer(number)),
+            TokenKind::Float(number) => ExprKind::Literal(Literal::Number(number)),
+            TokenKind::String(string) => ExprKind::Literal(Literal::String(string)),
+            TokenKind::Identifier(name) => ExprKind::Variable(Identifier {
+                name,
+                span: token.span,
+            }),
+            TokenKind::Super => {
+                self.advance();
+                self.consume(&TokenKind::Dot, "Expect '.' after 'super'.")?;
+                let method = self.consume_identifier("Expect superclass method name.")?;
+                let span = token.span.to(method.span);
+                return Ok(Expr::new(ExprKind::Super { method }, span));
+            }
+            TokenKind::LeftParen => {
+                self.advance();
+                let expr = self.expression()?;
+                let end = self.consume(&TokenKind::RightParen, "Expect ')' after expression.")?;
+                return Ok(Expr::new(
+                    ExprKind::Grouping(Box::new(expr)),
+                    token.span.to(end),
+                ));
+            }
+            TokenKind::LeftBracket => {
+                self.advance();
+                let mut elements = Vec::new();
+                if !self.check(&TokenKind::RightBracket) {
+                    loop {
+                        elements.push(self.argument()?);
+                        if !self.matches(&TokenKind::Comma) {
+                            break;
+                        }
+                    }
+                }
+                let end =
+                    self.consume(&TokenKind::RightBracket, "Expect ']' after list elements.")?;
+                return Ok(Expr::new(ExprKind::List(elements), token.span.to(end)));
+            }
+            TokenKind::LeftBrace => {
+                self.advance();
+                let mut entries = Vec::new();
+                if !self.check(&TokenKind::RightBrace) {
+                    loop {
+                        let key = self.expression()?;
+                        self.consume(&TokenKind::Colon, "Expect ':' after map key.")?;
+                        entries.push((key, self.expression()?));
+                        if !self.matches(&TokenKind::Comma) {
+                            break;
+                        }
+                    }
+                }
+                let end = self.consume(&TokenKind::RightBrace, "Expect '}' after map entries.")?;
+                return Ok(Expr::new(ExprKind::Map(entries), token.span.to(end)));
+            }
+            _ => return Err(self.error_at_current("Expect expression.")),
+        };
+
+        self.advance();
+        Ok(Expr::new(kind, token.span))
+    }
+
+    // Helpers
+
+    // one level deeper into the tree being parsed
+    fn nest(&mut self) -> anyhow::Result<()> {
+        self.depth += 1;
+        match self.depth > MAX_NESTING {
+            true => Err(self.error_at_current("Too much nesting.")),
+            false => Ok(()),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current)
+    }
+
+    fn peek_kind(&self) -> Option<&TokenKind> {
+        self.peek().map(|token| &token.kind)
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.tokens.len()
+    }
+
+    fn advance(&mut self) -> &Token {
+        let token = &self.tokens[self.current];
+        self.current += 1;
+        token
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        self.peek_kind() == Some(kind)
+    }
+
+    fn matches(&mut self, kind: &TokenKind) -> bool {
+        if self.check(kind) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume(&mut self, kind: &TokenKind, message: &str) -> anyhow::Result<Span> {
+        if self.check(kind) {
+            Ok(self.advance().span)
+        } else {
+            Err(self.error_at_current(message))
+        }
+    }
+
+    fn consume_identifier(&mut self, message: &str) -> anyhow::Result<Identifier> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Identifier(name),
+                span,
+            }) => {
+                let identifier = Identifier {
+                    name: name.clone(),
+                    span: *span,
+                };
+                self.current += 1;
+                Ok(identifier)
+            }
+            _ => Err(self.error_at_current(message)),
+        }
+    }
+
+    fn error_at(&self, token: &Token, message: &str) -> anyhow::Error {
+        let lexeme = &self.source[token.span.start..token.span.end];
+        SyntaxError::new(message, token.span)
+            .at(format!("'{}'", lexeme))
+            .into()
+    }
+
+    fn error_at_current(&self, message: &str) -> anyhow::Error {
+        match self.peek() {
+            Some(token) => self.error_at(token, message),
+            None => SyntaxError::new(message, self.eof).at("end").into(),
+        }
+    }
+}
+
+/// Lex and parse a whole program.
+pub fn parse(source: &str) -> anyhow::Result<Vec<Stmt>> {
+    Parser::new(Lexer::new(source.to_string()))?.parse()
+}
+
+fn binary(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+    let span = left.span.to(right.span);
+    Expr::new(
+        ExprKind::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        },
+        span,
+    )
+}
+
+fn logical(left: Expr, op: LogicalOp, right: Expr) -> Expr {
+    let span = left.span.to(right.span);
+    Expr::new(
+        ExprKind::Logical {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        },
+        span,
+    )
+}
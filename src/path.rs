@@ -0,0 +1,67 @@
+//! The natives for working with paths the way the system does:
+//! `joinPath(path, ...)`, `dirname(path)`, `basename(path)`,
+//! `absolutePath(path)`, `cwd()`, `platform()` and `pathSeparator()`.
+//!
+//! They only look at paths, without touching the files, so they don't need
+//! the `fs` capability. Paths are split on `/` everywhere, and on `\` too on
+//! Windows. `cwd` and `absolutePath` depend on where the program runs, so
+//! they're recorded and replayed like `clock`.
+
+use std::env;
+use std::path::{self, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::files::path;
+use crate::function::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::value::Value;
+
+/// Register the path natives with an interpreter.
+pub fn register(interpreter: &mut Interpreter) {
+    let absolute = NativeFunction::new("absolutePath", 1, |arguments| {
+        let path = path(&arguments[0])?;
+        let absolute = path::absolute(path).map_err(|error| {
+            RuntimeError::msg(format!("Could not resolve '{}': {}.", path, error))
+        })?;
+        Ok(string(&absolute))
+    });
+    interpreter.define_global(
+        "absolutePath",
+        Value::Native(Rc::new(absolute.nondeterministic())),
+    );
+    // nothing for the root or a path ending in `..`
+    interpreter.define_native("basename", 1, |arguments| {
+        let name = Path::new(path(&arguments[0])?).file_name();
+        Ok(string(name.map_or(Path::new(""), Path::new)))
+    });
+    let cwd = NativeFunction::new("cwd", 0, |_| {
+        let cwd = env::current_dir().map_err(|error| {
+            RuntimeError::msg(format!("Could not get the working directory: {}.", error))
+        })?;
+        Ok(string(&cwd))
+    });
+    interpreter.define_global("cwd", Value::Native(Rc::new(cwd.nondeterministic())));
+    // the root is its own directory, and a bare name is in the empty one
+    interpreter.define_native("dirname", 1, |arguments| {
+        let path = Path::new(path(&arguments[0])?);
+        Ok(string(path.parent().unwrap_or(path)))
+    });
+    // an absolute path replaces what comes before it
+    let join = NativeFunction::new("joinPath", 1, |arguments| {
+        let mut joined = PathBuf::new();
+        for argument in arguments {
+            joined.push(path(argument)?);
+        }
+        Ok(string(&joined))
+    });
+    interpreter.define_global("joinPath", Value::Native(Rc::new(join.variadic())));
+    interpreter.define_native("pathSeparator", 0, |_| {
+        Ok(Value::from(path::MAIN_SEPARATOR.to_string()))
+    });
+    // "linux", "macos", "windows" and so on
+    interpreter.define_native("platform", 0, |_| Ok(Value::from(env::consts::OS)));
+}
+
+fn string(path: &Path) -> Value {
+    Value::from(path.to_string_lossy().as_ref())
+}
@@ -0,0 +1,168 @@
+//! The natives about the process running the program: `env(name)` and
+//! `args()`, `setEnv(name, value)` with the `env` capability, and
+//! `exec(command, args)` and `spawnProcess(command, args)` with the
+//! `process` capability.
+//!
+//! `args()` is the arguments the program was given, the ones after the
+//! script on the command line, or what the embedder set with
+//! `Interpreter::set_args`. `env` gives nil for unset variables, and is
+//! recorded and replayed like `clock`. Setting a variable to nil with
+//! `setEnv` unsets it.
+//!
+//! Commands run directly, not through a shell, with the list of arguments
+//! as they are. `exec` waits for the command and gives a map of its exit
+//! `code`, `nil` when a signal killed it, and what it wrote to `stdout` and
+//! `stderr`. `spawnProcess` starts it sharing the standard streams of the
+//! interpreter, giving its process id without waiting for it; the name
+//! `spawn` is already the one of threads.
+
+use std::env;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+
+use crate::function::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::list;
+use crate::map::{self, MapKey};
+use crate::ordered_map::OrderedMap;
+use crate::value::Value;
+
+/// Register the natives every interpreter has.
+pub fn register(interpreter: &mut Interpreter) {
+    let args = NativeFunction::with_callbacks("args", 0, |interpreter, _, _| {
+        let args = interpreter
+            .args()
+            .iter()
+            .map(|arg| Value::from(arg.as_str()));
+        Ok(list::new(args.collect()))
+    });
+    interpreter.define_global("args", Value::Native(Rc::new(args)));
+    let env = NativeFunction::new("env", 1, |arguments| {
+        let value = env::var_os(name(&arguments[0])?);
+        Ok(value.map_or(Value::Nil, |value| {
+            Value::from(value.to_string_lossy().as_ref())
+        }))
+    });
+    interpreter.define_global("env", Value::Native(Rc::new(env.nondeterministic())));
+}
+
+/// Register the natives of the `env` capability, see
+/// `Interpreter::enable`.
+pub fn register_env(interpreter: &mut Interpreter) {
+    interpreter.define_native("setEnv", 2, |arguments| {
+        let name = name(&arguments[0])?;
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return Err(RuntimeError::msg(format!(
+                "Invalid environment variable name '{}'.",
+                name
+            )));
+        }
+        match &arguments[1] {
+            Value::Nil => env::remove_var(name),
+            Value::String(value) if !value.contains('\0') => env::set_var(name, &**value),
+            Value::String(_) => {
+                return Err(RuntimeError::msg(
+                    "Environment variables can't hold NUL characters.",
+                ))
+            }
+            other => {
+                return Err(RuntimeError::msg(format!(
+                    "Expected a string or nil but got {}.",
+                    other.type_name()
+                )))
+            }
+        }
+        Ok(Value::Nil)
+    });
+}
+
+/// Register the natives of the `process` capability, see
+/// `Interpreter::enable`.
+pub fn register_process(interpreter: &mut Interpreter) {
+    let mut exec = NativeFunction::new("exec", 2, |arguments| {
+        let (program, mut command) = command(arguments)?;
+        let output = command
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|error| failed(program, error))?;
+        let code = output.status.code();
+        let code = code.map_or(Value::Nil, |code| Value::Integer(i64::from(code)));
+        let mut entries = OrderedMap::new();
+        let mut entry = |key: &str, value| entries.insert(MapKey::String(Rc::from(key)), value);
+        entry("code", code);
+        entry("stdout", text(&output.stdout));
+        entry("stderr", text(&output.stderr));
+        Ok(map::new(entries))
+    });
+    // the arguments are optional
+    exec.arity.min = 1;
+    interpreter.define_global("exec", Value::Native(Rc::new(exec)));
+    let mut spawn = NativeFunction::new("spawnProcess", 2, |arguments| {
+        let (program, mut command) = command(arguments)?;
+        let mut child = command.spawn().map_err(|error| failed(program, error))?;
+        let id = child.id();
+        // waited for elsewhere, so it doesn't linger once it exits
+        thread::spawn(move || child.wait());
+        Ok(Value::Integer(i64::from(id)))
+    });
+    spawn.arity.min = 1;
+    interpreter.define_global("spawnProcess", Value::Native(Rc::new(spawn)));
+}
+
+// the program and the command running it with the arguments, which are
+// optional
+fn command(arguments: &[Value]) -> Result<(&str, Command), RuntimeError> {
+    let program = match &arguments[0] {
+        Value::String(program) => program,
+        other => {
+            return Err(RuntimeError::msg(format!(
+                "Expected a command but got {}.",
+                other.type_name()
+            )))
+        }
+    };
+    let mut command = Command::new(&**program);
+    match arguments.get(1) {
+        Some(Value::List(list)) => {
+            for argument in list.borrow().iter() {
+                match argument {
+                    Value::String(argument) => command.arg(&**argument),
+                    other => {
+                        return Err(RuntimeError::msg(format!(
+                            "Command arguments must be strings, not {}.",
+                            other.type_name()
+                        )))
+                    }
+                };
+            }
+        }
+        Some(other) => {
+            return Err(RuntimeError::msg(format!(
+                "Expected a list of arguments but got {}.",
+                other.type_name()
+            )))
+        }
+        None => {}
+    }
+    Ok((program, command))
+}
+
+fn failed(program: &str, error: std::io::Error) -> RuntimeError {
+    RuntimeError::msg(format!("Could not run '{}': {}.", program, error))
+}
+
+// what a command wrote, which may not be UTF-8
+fn text(bytes: &[u8]) -> Value {
+    Value::from(String::from_utf8_lossy(bytes).as_ref())
+}
+
+fn name(value: &Value) -> Result<&str, RuntimeError> {
+    match value {
+        Value::String(name) => Ok(name),
+        _ => Err(RuntimeError::msg(format!(
+            "Expected a variable name but got {}.",
+            value.type_name()
+        ))),
+    }
+}
@@ -0,0 +1,60 @@
+//! Promises, handed back by async functions and timers.
+//!
+//! A promise starts out pending and settles once: fulfilled with a value, or
+//! rejected with the value thrown. Async functions suspended on an `await`
+//! wait on it, and the event loop of the interpreter resumes them once it
+//! settles.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::gc;
+use crate::generator::GeneratorRef;
+use crate::lexer::Span;
+use crate::value::Value;
+
+pub type PromiseRef = Rc<RefCell<Promise>>;
+
+pub struct Promise {
+    pub(crate) state: State,
+    // something awaited it, so a rejection doesn't go unnoticed
+    pub(crate) handled: bool,
+}
+
+pub(crate) enum State {
+    Pending(Vec<Waiter>),
+    Fulfilled(Value),
+    Rejected(Value),
+}
+
+/// What to do once a promise settles.
+pub(crate) enum Waiter {
+    /// Resume an async function suspended on the promise, settling `promise`,
+    /// the one its call returned, once the function is done.
+    Resume {
+        coroutine: GeneratorRef,
+        promise: PromiseRef,
+        // where the async function was called
+        span: Span,
+    },
+    /// Settle another promise the same way, for an async function returning
+    /// a promise.
+    Forward(PromiseRef),
+}
+
+pub fn new() -> PromiseRef {
+    let promise = Rc::new(RefCell::new(Promise {
+        state: State::Pending(Vec::new()),
+        handled: false,
+    }));
+    // the async function waiting on it can hold the promise itself
+    gc::track(&promise);
+    promise
+}
+
+impl fmt::Debug for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<promise>")
+    }
+}
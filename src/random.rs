@@ -0,0 +1,136 @@
+//! The random natives, `random()`, `randomInt(lo, hi)` and
+//! `seedRandom(seed)`.
+//!
+//! Every interpreter has a generator of its own, xoshiro256** seeded with
+//! SplitMix64, the same on every platform, so a seed always gives the same
+//! numbers. Without one it's seeded differently on every run. Embedders and
+//! test runners can pin the seed with `Interpreter::seed_random` before
+//! running a program, and the CLI with `--seed`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
+
+use crate::function::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::value::{self, Value};
+
+/// A deterministic pseudorandom number generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Random {
+    state: [u64; 4],
+}
+
+impl Random {
+    /// A generator giving the same numbers for the same seed.
+    pub fn seeded(seed: u64) -> Self {
+        // SplitMix64 spreads the seed over the state, which must not be
+        // all zeros
+        let mut seed = seed;
+        let mut next = || {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Self {
+            state: [next(), next(), next(), next()],
+        }
+    }
+
+    /// A generator seeded differently every time.
+    pub fn unseeded() -> Self {
+        // the standard library seeds the keys of its hash maps randomly
+        Self::seeded(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let [a, b, c, d] = &mut self.state;
+        let result = b.wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = *b << 17;
+        *c ^= *a;
+        *d ^= *b;
+        *b ^= *c;
+        *a ^= *d;
+        *c ^= t;
+        *d = d.rotate_left(45);
+        result
+    }
+
+    /// A float in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        // the 53 bits a float holds exactly
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer in `lo..=hi`, each as likely as the others.
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        let span = hi.wrapping_sub(lo) as u64;
+        if span == u64::MAX {
+            return self.next_u64() as i64;
+        }
+        // drop the numbers past the last whole multiple of the range, which
+        // would make the first ones more likely
+        let count = span + 1;
+        let limit = u64::MAX - u64::MAX % count;
+        loop {
+            let n = self.next_u64();
+            if n < limit {
+                return lo.wrapping_add((n % count) as i64);
+            }
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::unseeded()
+    }
+}
+
+/// Register the random natives with an interpreter.
+pub fn register(interpreter: &mut Interpreter) {
+    let random = NativeFunction::with_callbacks("random", 0, |interpreter, _, _| {
+        Ok(Value::Number(interpreter.random().next_f64()))
+    });
+    interpreter.define_global("random", Value::Native(Rc::new(random.nondeterministic())));
+    let random_int =
+        NativeFunction::with_callbacks("randomInt", 2, |interpreter, arguments, span| {
+            let lo = integer(&arguments[0]).map_err(|error| error.at(span))?;
+            let hi = integer(&arguments[1]).map_err(|error| error.at(span))?;
+            if lo > hi {
+                return Err(RuntimeError::new(
+                    format!("Can't pick an integer between {} and {}.", lo, hi),
+                    span,
+                ));
+            }
+            Ok(Value::Integer(interpreter.random().range(lo, hi)))
+        });
+    interpreter.define_global(
+        "randomInt",
+        Value::Native(Rc::new(random_int.nondeterministic())),
+    );
+    let seed = NativeFunction::with_callbacks("seedRandom", 1, |interpreter, arguments, span| {
+        let seed = integer(&arguments[0]).map_err(|error| error.at(span))?;
+        interpreter.seed_random(seed as u64);
+        Ok(Value::Nil)
+    });
+    interpreter.define_global("seedRandom", Value::Native(Rc::new(seed)));
+}
+
+pub(crate) fn integer(value: &Value) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Integer(integer) => Ok(*integer),
+        Value::Number(number) => value::exact_integer(*number).ok_or_else(|| {
+            RuntimeError::msg(format!(
+                "Expected an integer but got {}.",
+                value::format_number(*number)
+            ))
+        }),
+        other => Err(RuntimeError::msg(format!(
+            "Expected an integer but got {}.",
+            other.type_name()
+        ))),
+    }
+}
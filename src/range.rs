@@ -0,0 +1,68 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::function::NativeFunction;
+use crate::interpreter::RuntimeError;
+use crate::list;
+use crate::value::{self, Value};
+
+/// The integers from `start` up to, but not including, `end`, which is empty
+/// when `end` isn't past `start`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Range {
+    /// The range `start..end`, both of which must be integers.
+    pub fn new(start: &Value, end: &Value) -> Result<Self, RuntimeError> {
+        match (bound(start), bound(end)) {
+            (Some(start), Some(end)) => Ok(Range { start, end }),
+            _ => Err(RuntimeError::msg("Range bounds must be integers.")),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        (self.end as i128 - self.start as i128).clamp(0, usize::MAX as i128) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        bound(value).is_some_and(|integer| (self.start..self.end).contains(&integer))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Value> {
+        (self.start..self.end).map(Value::Integer)
+    }
+}
+
+// integral floats can be bounds as well
+fn bound(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(integer) => Some(*integer),
+        Value::Number(number) => value::exact_integer(*number),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Look up one of the methods every range has, bound to `range`.
+pub fn get_method(range: Range, name: &str) -> Option<Value> {
+    let method = match name {
+        "contains" => NativeFunction::new(name, 1, move |arguments| {
+            Ok(Value::Bool(range.contains(&arguments[0])))
+        }),
+        "toList" => NativeFunction::new(name, 0, move |_| Ok(list::new(range.iter().collect()))),
+        _ => return None,
+    };
+    Some(Value::Native(Rc::new(method)))
+}
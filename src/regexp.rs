@@ -0,0 +1,115 @@
+//! The regular expression natives, with the `regex` feature:
+//! `regexMatch(pattern, text)`, `regexFindAll(pattern, text)` and
+//! `regexReplace(pattern, text, replacement)`.
+//!
+//! Patterns have the syntax of the `regex` crate, which has no
+//! backreferences or lookaround, but always runs in linear time. A match is
+//! a map of its `text`, where it `start`s and `end`s in characters, like
+//! the string natives count, its `groups` in a list, `nil` for the ones
+//! that didn't take part, and its `named` groups in a map. Replacements
+//! refer to the groups with `$1` or `${name}`.
+
+use std::rc::Rc;
+
+use regex::{Captures, Regex};
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::list;
+use crate::map::{self, MapKey};
+use crate::ordered_map::OrderedMap;
+use crate::string::string;
+use crate::value::Value;
+
+/// Register the regular expression natives with an interpreter.
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.define_native("regexFindAll", 2, |arguments| {
+        let (regex, text) = (regex(&arguments[0])?, string(&arguments[1])?);
+        let mut chars = CharIndex::new(text);
+        let matches = regex
+            .captures_iter(text)
+            .map(|captures| found(&regex, &captures, &mut chars))
+            .collect();
+        Ok(list::new(matches))
+    });
+    interpreter.define_native("regexMatch", 2, |arguments| {
+        let (regex, text) = (regex(&arguments[0])?, string(&arguments[1])?);
+        Ok(match regex.captures(text) {
+            Some(captures) => found(&regex, &captures, &mut CharIndex::new(text)),
+            None => Value::Nil,
+        })
+    });
+    interpreter.define_native("regexReplace", 3, |arguments| {
+        let (regex, text) = (regex(&arguments[0])?, string(&arguments[1])?);
+        let replacement = string(&arguments[2])?;
+        Ok(Value::from(
+            regex.replace_all(text, &**replacement).into_owned(),
+        ))
+    });
+}
+
+fn regex(pattern: &Value) -> Result<Regex, RuntimeError> {
+    let pattern = string(pattern)?;
+    Regex::new(pattern).map_err(|error| {
+        // syntax errors show the pattern over several lines, ending with
+        // what's wrong
+        let error = error.to_string();
+        let problem = error.lines().last().unwrap_or_default();
+        RuntimeError::msg(format!(
+            "Invalid regular expression '{}': {}.",
+            pattern,
+            problem.trim_start_matches("error: ")
+        ))
+    })
+}
+
+// the map of a match
+fn found(regex: &Regex, captures: &Captures, chars: &mut CharIndex) -> Value {
+    let whole = captures.get(0).expect("a match has a whole group");
+    let groups = captures
+        .iter()
+        .skip(1)
+        .map(|group| group.map_or(Value::Nil, |group| Value::from(group.as_str())))
+        .collect();
+    let mut named = OrderedMap::new();
+    for name in regex.capture_names().flatten() {
+        let group = captures.name(name);
+        let group = group.map_or(Value::Nil, |group| Value::from(group.as_str()));
+        named.insert(MapKey::String(Rc::from(name)), group);
+    }
+
+    let mut entries = OrderedMap::new();
+    let mut entry = |key: &str, value| entries.insert(MapKey::String(Rc::from(key)), value);
+    entry("text", Value::from(whole.as_str()));
+    entry("start", Value::Integer(chars.at(whole.start())));
+    entry("end", Value::Integer(chars.at(whole.end())));
+    entry("groups", list::new(groups));
+    entry("named", map::new(named));
+    map::new(entries)
+}
+
+/// Turns the byte offsets of matches into character indices, counting from
+/// the previous one as matches come in order.
+struct CharIndex<'a> {
+    text: &'a str,
+    byte: usize,
+    chars: usize,
+}
+
+impl<'a> CharIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            byte: 0,
+            chars: 0,
+        }
+    }
+
+    fn at(&mut self, byte: usize) -> i64 {
+        if byte < self.byte {
+            *self = Self::new(self.text);
+        }
+        self.chars += self.text[self.byte..byte].chars().count();
+        self.byte = byte;
+        self.chars as i64
+    }
+}
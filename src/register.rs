@@ -0,0 +1,1488 @@
+//! An experimental register machine, compiling and running programs next to
+//! the stack machine of `vm`, to find out whether it's worth replacing it.
+//! It's only built with the `register-vm` feature.
+//!
+//! Locals live in registers, a window of the registers of the machine
+//! starting at the first parameter of the function being run, and
+//! instructions name the registers they read and write: `a = b + c` is a
+//! single `Add`, where the stack machine gets two locals, adds them and sets
+//! a third. Temporaries take the registers above the locals, and are freed
+//! once the statement using them is done. A call puts the callee and its
+//! arguments in consecutive registers, the arguments becoming the first
+//! registers of the callee without being copied.
+//!
+//! It only compiles functions without closures: programs can't make cycles
+//! without classes or closures, so strings and functions are counted
+//! references rather than objects of a collected heap. What else it can't
+//! compile fails the way it does on the stack machine. Globals are numbered
+//! as they're compiled, so running looks them up by index rather than by
+//! name.
+//!
+//! `cargo bench --bench vm --features register-vm` runs the scripts it can
+//! compile on both machines. It dispatches 35% fewer instructions on fib
+//! and strings and 40% fewer on loop, but takes about as long: strings is
+//! 5% faster, fib the same and loop 10% slower, each instruction decoding
+//! more operands and writing values through a register window rather than
+//! the top of the stack. Not enough to replace the stack machine, which
+//! runs everything, so this stays an experiment.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ast::*;
+use crate::interpreter::{
+    self, InterruptHandle, RuntimeError, RuntimeErrorKind, TraceFrame, DEFAULT_MAX_CALL_DEPTH,
+};
+use crate::lexer::{Lexer, Span, SyntaxError};
+use crate::math;
+use crate::parser::Parser;
+use crate::resolver;
+use crate::string;
+use crate::value;
+
+// registers are a byte
+const MAX_REGISTERS: usize = 256;
+
+/// The instructions, naming the registers of the running function they read
+/// and write.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Constant {
+        dst: u8,
+        index: u16,
+    },
+    Nil {
+        dst: u8,
+    },
+    Bool {
+        dst: u8,
+        value: bool,
+    },
+    Move {
+        dst: u8,
+        src: u8,
+    },
+    GetGlobal {
+        dst: u8,
+        slot: u16,
+    },
+    SetGlobal {
+        src: u8,
+        slot: u16,
+    },
+    DefineGlobal {
+        src: u8,
+        slot: u16,
+    },
+    Add {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    Subtract {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    Multiply {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    Divide {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    Modulo {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    Equal {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    NotEqual {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    Less {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    LessEqual {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    Greater {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    GreaterEqual {
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    /// The rarer binary operators.
+    Binary {
+        op: BinaryOp,
+        dst: u8,
+        left: u8,
+        right: u8,
+    },
+    /// The right operand is a constant, as in `n - 1`.
+    AddConstant {
+        dst: u8,
+        left: u8,
+        index: u16,
+    },
+    SubtractConstant {
+        dst: u8,
+        left: u8,
+        index: u16,
+    },
+    LessConstant {
+        dst: u8,
+        left: u8,
+        index: u16,
+    },
+    Not {
+        dst: u8,
+        src: u8,
+    },
+    Negate {
+        dst: u8,
+        src: u8,
+    },
+    BitNot {
+        dst: u8,
+        src: u8,
+    },
+    Jump {
+        target: u32,
+    },
+    JumpIfFalse {
+        src: u8,
+        target: u32,
+    },
+    JumpIfTrue {
+        src: u8,
+        target: u32,
+    },
+    /// Call the callee in `base` with the `count` arguments after it,
+    /// leaving the result in `base`.
+    Call {
+        base: u8,
+        count: u8,
+    },
+    Return {
+        src: u8,
+    },
+    Print {
+        src: u8,
+    },
+}
+
+/// A compiled function, or the top-level script.
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    /// How many registers a call needs, the parameters first.
+    pub registers: usize,
+    pub code: Vec<Instruction>,
+    /// The line of every instruction.
+    pub lines: Vec<usize>,
+    pub constants: Vec<Value>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(Rc<String>),
+    Function(Rc<Function>),
+    Native(Native),
+}
+
+/// A function written in Rust, failing with an error message.
+#[derive(Debug, Clone, Copy)]
+pub struct Native {
+    name: &'static str,
+    arity: usize,
+    function: fn(&[Value]) -> Result<Value, String>,
+}
+
+impl Value {
+    fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Integer(integer) => write!(f, "{}", integer),
+            Value::Number(number) => write!(f, "{}", value::format_number(*number)),
+            Value::String(string) => write!(f, "{}", string),
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+            Value::Native(_) => write!(f, "<native fn>"),
+        }
+    }
+}
+
+// numbered by the compiler, in the order it meets them
+#[derive(Default)]
+struct Globals {
+    slots: HashMap<String, u16>,
+    names: Vec<String>,
+    // `None` until defined
+    values: Vec<Option<Value>>,
+}
+
+impl Globals {
+    fn slot(&mut self, name: &Identifier) -> Result<u16, SyntaxError> {
+        if let Some(slot) = self.slots.get(&name.name) {
+            return Ok(*slot);
+        }
+        let slot = u16::try_from(self.names.len())
+            .map_err(|_| SyntaxError::new("Too many global variables.", name.span))?;
+        self.slots.insert(name.name.clone(), slot);
+        self.names.push(name.name.clone());
+        self.values.push(None);
+        Ok(slot)
+    }
+}
+
+pub struct Vm {
+    registers: Vec<Value>,
+    // the running function last
+    frames: Vec<Frame>,
+    globals: Globals,
+    // how many instructions ran
+    instructions: u64,
+    output: Box<dyn Write>,
+    interrupt: InterruptHandle,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Frame {
+    function: Rc<Function>,
+    ip: usize,
+    // the index of the first register
+    base: usize,
+}
+
+impl Frame {
+    // the line of the instruction being run
+    fn line(&self) -> usize {
+        self.function
+            .lines
+            .get(self.ip.saturating_sub(1))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Self {
+            registers: Vec::new(),
+            frames: Vec::new(),
+            globals: Globals::default(),
+            instructions: 0,
+            output: Box::new(io::stdout()),
+            interrupt: InterruptHandle::default(),
+        };
+        vm.define_native("clock", 0, clock);
+        vm.define_native("abs", 1, |arguments| math(arguments, math::abs));
+        vm.define_native("acos", 1, |arguments| math(arguments, math::acos));
+        vm.define_native("asin", 1, |arguments| math(arguments, math::asin));
+        vm.define_native("atan", 1, |arguments| math(arguments, math::atan));
+        vm.define_native("atan2", 2, |arguments| math(arguments, math::atan2));
+        vm.define_native("ceil", 1, |arguments| math(arguments, math::ceil));
+        vm.define_native("cos", 1, |arguments| math(arguments, math::cos));
+        vm.define_native("floor", 1, |arguments| math(arguments, math::floor));
+        vm.define_native("max", 2, |arguments| math(arguments, math::max));
+        vm.define_native("min", 2, |arguments| math(arguments, math::min));
+        vm.define_native("pow", 2, |arguments| math(arguments, math::pow));
+        vm.define_native("round", 1, |arguments| math(arguments, math::round));
+        vm.define_native("sin", 1, |arguments| math(arguments, math::sin));
+        vm.define_native("sqrt", 1, |arguments| math(arguments, math::sqrt));
+        vm.define_native("tan", 1, |arguments| math(arguments, math::tan));
+        vm.define_native("charAt", 2, |arguments| string(arguments, string::char_at));
+        vm.define_native("contains", 2, |arguments| {
+            string(arguments, string::contains)
+        });
+        vm.define_native("endsWith", 2, |arguments| {
+            string(arguments, string::ends_with)
+        });
+        vm.define_native("indexOf", 2, |arguments| {
+            string(arguments, string::index_of)
+        });
+        vm.define_native("len", 1, len);
+        vm.define_native("lower", 1, |arguments| string(arguments, string::lower));
+        vm.define_native("replace", 3, |arguments| string(arguments, string::replace));
+        vm.define_native("startsWith", 2, |arguments| {
+            string(arguments, string::starts_with)
+        });
+        vm.define_native("trim", 1, |arguments| string(arguments, string::trim));
+        vm.define_native("upper", 1, |arguments| string(arguments, string::upper));
+        vm.define_global("pi", Value::Number(std::f64::consts::PI));
+        vm
+    }
+
+    fn define_native(
+        &mut self,
+        name: &'static str,
+        arity: usize,
+        function: fn(&[Value]) -> Result<Value, String>,
+    ) {
+        self.define_global(
+            name,
+            Value::Native(Native {
+                name,
+                arity,
+                function,
+            }),
+        );
+    }
+
+    fn define_global(&mut self, name: &str, value: Value) {
+        let identifier = Identifier {
+            name: name.to_string(),
+            span: Span::new(0, 0, 0),
+        };
+        let slot = self
+            .globals
+            .slot(&identifier)
+            .expect("natives are the first globals");
+        self.globals.values[slot as usize] = Some(value);
+    }
+
+    /// Send everything the program prints to `output` instead of stdout.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+
+    /// Lex, parse, resolve and compile a program, numbering the globals it
+    /// uses.
+    pub fn compile(&mut self, source: &str) -> anyhow::Result<Rc<Function>> {
+        let program = Parser::new(Lexer::new(source.to_string()))?.parse()?;
+        resolver::resolve(&program).check()?;
+        Ok(Rc::new(compile_program(&program, &mut self.globals)?))
+    }
+
+    /// Compile and run a program.
+    pub fn run(&mut self, source: &str) -> anyhow::Result<()> {
+        let script = self.compile(source)?;
+        self.execute(script)?;
+        Ok(())
+    }
+
+    /// Look a global up by name, as a value of the interpreter. Functions
+    /// are left out.
+    pub fn get_global(&self, name: &str) -> Option<value::Value> {
+        let slot = *self.globals.slots.get(name)?;
+        let value = match self.globals.values[slot as usize].as_ref()? {
+            Value::Nil => value::Value::Nil,
+            Value::Bool(b) => value::Value::Bool(*b),
+            Value::Integer(integer) => value::Value::Integer(*integer),
+            Value::Number(number) => value::Value::Number(*number),
+            Value::String(string) => value::Value::from(string.as_str()),
+            Value::Function(_) | Value::Native(_) => return None,
+        };
+        Some(value)
+    }
+
+    /// How many instructions ran so far.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// A handle stopping whatever program the machine is running when it's
+    /// interrupted, at its next call or backward jump.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn execute(&mut self, script: Rc<Function>) -> Result<(), RuntimeError> {
+        self.interrupt.clear();
+        if self.registers.len() < script.registers {
+            self.registers.resize(script.registers, Value::Nil);
+        }
+        self.frames.push(Frame {
+            function: script,
+            ip: 0,
+            base: 0,
+        });
+        let result = self.run_frames();
+        if result.is_err() {
+            self.frames.clear();
+        }
+        result
+    }
+
+    fn run_frames(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            self.instructions += 1;
+            let frame = self.frames.last_mut().expect("a function is running");
+            let instruction = frame.function.code[frame.ip];
+            frame.ip += 1;
+            let base = frame.base;
+            let register = |register: u8| base + register as usize;
+            match instruction {
+                Instruction::Constant { dst, index } => {
+                    self.registers[register(dst)] =
+                        frame.function.constants[index as usize].clone();
+                }
+                Instruction::Nil { dst } => self.registers[register(dst)] = Value::Nil,
+                Instruction::Bool { dst, value } => {
+                    self.registers[register(dst)] = Value::Bool(value)
+                }
+                Instruction::Move { dst, src } => {
+                    self.registers[register(dst)] = self.registers[register(src)].clone();
+                }
+                Instruction::GetGlobal { dst, slot } => {
+                    let value = match &self.globals.values[slot as usize] {
+                        Some(value) => value.clone(),
+                        None => return Err(self.undefined(slot)),
+                    };
+                    self.registers[register(dst)] = value;
+                }
+                Instruction::SetGlobal { src, slot } => {
+                    if self.globals.values[slot as usize].is_none() {
+                        return Err(self.undefined(slot));
+                    }
+                    self.globals.values[slot as usize] =
+                        Some(self.registers[register(src)].clone());
+                }
+                Instruction::DefineGlobal { src, slot } => {
+                    self.globals.values[slot as usize] =
+                        Some(self.registers[register(src)].clone());
+                }
+                Instruction::Add { dst, left, right } => {
+                    let (left, right) = (
+                        &self.registers[register(left)],
+                        &self.registers[register(right)],
+                    );
+                    self.registers[register(dst)] = self.add(left, right)?;
+                }
+                Instruction::Subtract { dst, left, right } => self.binary(
+                    BinaryOp::Subtract,
+                    register(dst),
+                    register(left),
+                    register(right),
+                )?,
+                Instruction::Multiply { dst, left, right } => self.binary(
+                    BinaryOp::Multiply,
+                    register(dst),
+                    register(left),
+                    register(right),
+                )?,
+                Instruction::Divide { dst, left, right } => self.binary(
+                    BinaryOp::Divide,
+                    register(dst),
+                    register(left),
+                    register(right),
+                )?,
+                Instruction::Modulo { dst, left, right } => self.binary(
+                    BinaryOp::Modulo,
+                    register(dst),
+                    register(left),
+                    register(right),
+                )?,
+                Instruction::Less { dst, left, right } => self.binary(
+                    BinaryOp::Less,
+                    register(dst),
+                    register(left),
+                    register(right),
+                )?,
+                Instruction::LessEqual { dst, left, right } => self.binary(
+                    BinaryOp::LessEqual,
+                    register(dst),
+                    register(left),
+                    register(right),
+                )?,
+                Instruction::Greater { dst, left, right } => self.binary(
+                    BinaryOp::Greater,
+                    register(dst),
+                    register(left),
+                    register(right),
+                )?,
+                Instruction::GreaterEqual { dst, left, right } => self.binary(
+                    BinaryOp::GreaterEqual,
+                    register(dst),
+                    register(left),
+                    register(right),
+                )?,
+                Instruction::Binary {
+                    op,
+                    dst,
+                    left,
+                    right,
+                } => self.binary(op, register(dst), register(left), register(right))?,
+                Instruction::Equal { dst, left, right } => {
+                    let equal = values_equal(
+                        &self.registers[register(left)],
+                        &self.registers[register(right)],
+                    );
+                    self.registers[register(dst)] = Value::Bool(equal);
+                }
+                Instruction::NotEqual { dst, left, right } => {
+                    let equal = values_equal(
+                        &self.registers[register(left)],
+                        &self.registers[register(right)],
+                    );
+                    self.registers[register(dst)] = Value::Bool(!equal);
+                }
+                Instruction::AddConstant { dst, left, index } => {
+                    let right = frame.function.constants[index as usize].clone();
+                    self.registers[register(dst)] =
+                        self.add(&self.registers[register(left)], &right)?;
+                }
+                Instruction::SubtractConstant { dst, left, index } => {
+                    let right = frame.function.constants[index as usize].clone();
+                    self.registers[register(dst)] = self.arithmetic(
+                        BinaryOp::Subtract,
+                        &self.registers[register(left)],
+                        &right,
+                    )?;
+                }
+                Instruction::LessConstant { dst, left, index } => {
+                    let right = frame.function.constants[index as usize].clone();
+                    self.registers[register(dst)] =
+                        self.arithmetic(BinaryOp::Less, &self.registers[register(left)], &right)?;
+                }
+                Instruction::Not { dst, src } => {
+                    self.registers[register(dst)] =
+                        Value::Bool(self.registers[register(src)].is_falsey());
+                }
+                Instruction::Negate { dst, src } => {
+                    let value = match self.registers[register(src)] {
+                        // only -i64::MIN doesn't fit
+                        Value::Integer(integer) => match integer.checked_neg() {
+                            Some(negated) => Value::Integer(negated),
+                            None => Value::Number(-(integer as f64)),
+                        },
+                        Value::Number(number) => Value::Number(-number),
+                        _ => return Err(self.error("Operand must be a number.")),
+                    };
+                    self.registers[register(dst)] = value;
+                }
+                Instruction::BitNot { dst, src } => {
+                    let integer = match self.registers[register(src)] {
+                        Value::Integer(integer) => integer,
+                        Value::Number(number) => {
+                            let span = Span::new(0, 0, self.line());
+                            interpreter::integer(number, span)
+                                .map_err(|failure| self.error(failure.message))?
+                        }
+                        _ => return Err(self.error("Operand must be a number.")),
+                    };
+                    self.registers[register(dst)] = Value::Integer(!integer);
+                }
+                Instruction::Jump { target } => {
+                    let backward = (target as usize) < frame.ip;
+                    frame.ip = target as usize;
+                    if backward {
+                        self.check_interrupt()?;
+                    }
+                }
+                Instruction::JumpIfFalse { src, target } => {
+                    if self.registers[register(src)].is_falsey() {
+                        frame.ip = target as usize;
+                    }
+                }
+                Instruction::JumpIfTrue { src, target } => {
+                    if !self.registers[register(src)].is_falsey() {
+                        frame.ip = target as usize;
+                    }
+                }
+                Instruction::Call { base, count } => self.call(register(base), count as usize)?,
+                Instruction::Return { src } => {
+                    let result = self.registers[register(src)].clone();
+                    self.frames.pop();
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    // the callee was in the register before the first
+                    // argument
+                    self.registers[base - 1] = result;
+                }
+                Instruction::Print { src } => {
+                    let text = self.registers[register(src)].to_string();
+                    if let Err(error) = writeln!(self.output, "{}", text) {
+                        return Err(self.error(format!("Could not print: {}.", error)));
+                    }
+                }
+            }
+        }
+    }
+
+    // call the callee in `slot` with the arguments in the registers after it
+    fn call(&mut self, slot: usize, count: usize) -> Result<(), RuntimeError> {
+        match &self.registers[slot] {
+            Value::Function(function) => {
+                if count != function.arity {
+                    return Err(self.error(format!(
+                        "Expected {} arguments but got {}.",
+                        function.arity, count
+                    )));
+                }
+                if self.frames.len() >= DEFAULT_MAX_CALL_DEPTH {
+                    return Err(self.error("Stack overflow."));
+                }
+                self.check_interrupt()?;
+                let function = function.clone();
+                let base = slot + 1;
+                if self.registers.len() < base + function.registers {
+                    self.registers.resize(base + function.registers, Value::Nil);
+                }
+                self.frames.push(Frame {
+                    function,
+                    ip: 0,
+                    base,
+                });
+                Ok(())
+            }
+            Value::Native(native) => {
+                if count != native.arity {
+                    return Err(self.error(format!(
+                        "Expected {} arguments but got {}.",
+                        native.arity, count
+                    )));
+                }
+                let result = (native.function)(&self.registers[slot + 1..slot + 1 + count])
+                    .map_err(|message| self.error(message))?;
+                self.registers[slot] = result;
+                Ok(())
+            }
+            _ => Err(self.error("Can only call functions and classes.")),
+        }
+    }
+
+    fn add(&self, left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+        if let Some(sum) = fast_arithmetic(BinaryOp::Add, left, right) {
+            return Ok(sum);
+        }
+        if let (Value::String(left), Value::String(right)) = (left, right) {
+            let mut joined = String::with_capacity(left.len() + right.len());
+            joined.push_str(left);
+            joined.push_str(right);
+            return Ok(Value::String(Rc::new(joined)));
+        }
+        if let (Some(_), Some(_)) = (number(left), number(right)) {
+            return self.arithmetic(BinaryOp::Add, left, right);
+        }
+        let (left, right) = (type_name(left), type_name(right));
+        if matches!((left, right), ("string", "number") | ("number", "string")) {
+            return Err(self.error(format!(
+                "Can't add {} and {}, operands must be two numbers or two strings.",
+                left, right
+            )));
+        }
+        Err(self.error("Operands must be two numbers or two strings."))
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        dst: usize,
+        left: usize,
+        right: usize,
+    ) -> Result<(), RuntimeError> {
+        let result = self.arithmetic(op, &self.registers[left], &self.registers[right])?;
+        self.registers[dst] = result;
+        Ok(())
+    }
+
+    // the operators working on numbers, which the interpreter implements
+    fn arithmetic(&self, op: BinaryOp, left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+        if let Some(result) = fast_arithmetic(op, left, right) {
+            return Ok(result);
+        }
+        let (left, right) = match (number(left), number(right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return Err(self.error("Operands must be numbers.")),
+        };
+        let span = Span::new(0, 0, self.line());
+        let result = interpreter::arithmetic(op, &left, &right, span)
+            .map_err(|failure| self.error(failure.message))?;
+        Ok(match result {
+            value::Value::Integer(integer) => Value::Integer(integer),
+            value::Value::Number(number) => Value::Number(number),
+            value::Value::Bool(b) => Value::Bool(b),
+            _ => unreachable!("arithmetic only gives numbers and booleans"),
+        })
+    }
+
+    fn line(&self) -> usize {
+        self.frames.last().map_or(0, Frame::line)
+    }
+
+    fn undefined(&self, slot: u16) -> RuntimeError {
+        let name = &self.globals.names[slot as usize];
+        self.error(format!("Undefined variable '{}'.", name))
+    }
+
+    // an error of the running instruction, with the frames calling it
+    // loops jump backward, so checking there and on calls stops any program
+    fn check_interrupt(&self) -> Result<(), RuntimeError> {
+        match self.interrupt.take() {
+            true => Err(self
+                .error("Interrupted.")
+                .with_kind(RuntimeErrorKind::Interrupted)),
+            false => Ok(()),
+        }
+    }
+
+    fn error<M: Into<String>>(&self, message: M) -> RuntimeError {
+        let mut error = RuntimeError::new(message, Span::new(0, 0, self.line()));
+        for (depth, frame) in self.frames.iter().enumerate().rev() {
+            error.trace.push(TraceFrame {
+                // the script is at the bottom
+                function: Some(frame.function.name.clone()).filter(|_| depth > 0),
+                line: frame.line(),
+            });
+        }
+        error
+    }
+}
+
+// a number as the interpreter has it
+fn number(value: &Value) -> Option<value::Value> {
+    match value {
+        Value::Integer(integer) => Some(value::Value::Integer(*integer)),
+        Value::Number(number) => Some(value::Value::Number(*number)),
+        _ => None,
+    }
+}
+
+// the common cases of `arithmetic`: `None` for everything else, including
+// integers overflowing
+fn fast_arithmetic(op: BinaryOp, left: &Value, right: &Value) -> Option<Value> {
+    match (left, right) {
+        (Value::Integer(left), Value::Integer(right)) => match op {
+            BinaryOp::Add => Some(Value::Integer(left.checked_add(*right)?)),
+            BinaryOp::Subtract => Some(Value::Integer(left.checked_sub(*right)?)),
+            BinaryOp::Multiply => Some(Value::Integer(left.checked_mul(*right)?)),
+            BinaryOp::Less => Some(Value::Bool(left < right)),
+            BinaryOp::LessEqual => Some(Value::Bool(left <= right)),
+            BinaryOp::Greater => Some(Value::Bool(left > right)),
+            BinaryOp::GreaterEqual => Some(Value::Bool(left >= right)),
+            _ => None,
+        },
+        (Value::Number(left), Value::Number(right)) => match op {
+            BinaryOp::Add => Some(Value::Number(left + right)),
+            BinaryOp::Subtract => Some(Value::Number(left - right)),
+            BinaryOp::Multiply => Some(Value::Number(left * right)),
+            BinaryOp::Divide => Some(Value::Number(left / right)),
+            BinaryOp::Less => Some(Value::Bool(left < right)),
+            BinaryOp::LessEqual => Some(Value::Bool(left <= right)),
+            BinaryOp::Greater => Some(Value::Bool(left > right)),
+            BinaryOp::GreaterEqual => Some(Value::Bool(left >= right)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Bool(left), Value::Bool(right)) => left == right,
+        (Value::Integer(left), Value::Integer(right)) => left == right,
+        (Value::Number(left), Value::Number(right)) => left == right,
+        (Value::Integer(integer), Value::Number(number))
+        | (Value::Number(number), Value::Integer(integer)) => {
+            value::exact_integer(*number) == Some(*integer)
+        }
+        (Value::String(left), Value::String(right)) => left == right,
+        (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+        (Value::Native(left), Value::Native(right)) => left.name == right.name,
+        _ => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Bool(_) => "boolean",
+        Value::Integer(_) | Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Function(_) | Value::Native(_) => "function",
+    }
+}
+
+fn clock(_: &[Value]) -> Result<Value, String> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| "System clock is set before the epoch.".to_string())?;
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+// a math native, on the numbers of the interpreter
+fn math(
+    arguments: &[Value],
+    native: fn(&[value::Value]) -> Result<value::Value, RuntimeError>,
+) -> Result<Value, String> {
+    let arguments = arguments
+        .iter()
+        .map(|argument| {
+            number(argument)
+                .ok_or_else(|| format!("Expected a number but got {}.", type_name(argument)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match native(&arguments).map_err(|error| error.message)? {
+        value::Value::Integer(integer) => Ok(Value::Integer(integer)),
+        value::Value::Number(number) => Ok(Value::Number(number)),
+        _ => unreachable!("math natives only give numbers"),
+    }
+}
+
+// a string native, on the values of the interpreter
+fn string(
+    arguments: &[Value],
+    native: fn(&[value::Value]) -> Result<value::Value, RuntimeError>,
+) -> Result<Value, String> {
+    let arguments = arguments
+        .iter()
+        .map(|argument| match argument {
+            Value::Nil => Ok(value::Value::Nil),
+            Value::Bool(b) => Ok(value::Value::Bool(*b)),
+            Value::Integer(integer) => Ok(value::Value::Integer(*integer)),
+            Value::Number(number) => Ok(value::Value::Number(*number)),
+            Value::String(string) => Ok(value::Value::from(string.as_str())),
+            _ => Err(format!(
+                "Expected a string but got {}.",
+                type_name(argument)
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match native(&arguments).map_err(|error| error.message)? {
+        value::Value::String(string) => Ok(Value::String(Rc::new(string.to_string()))),
+        value::Value::Integer(integer) => Ok(Value::Integer(integer)),
+        value::Value::Bool(b) => Ok(Value::Bool(b)),
+        _ => unreachable!("string natives give strings, integers and booleans"),
+    }
+}
+
+// the only values with a length the machine has are strings
+fn len(arguments: &[Value]) -> Result<Value, String> {
+    match &arguments[0] {
+        Value::String(string) => Ok(Value::Integer(string::len(string) as i64)),
+        other => Err(format!(
+            "Expected a list, map, string or range but got {}.",
+            type_name(other)
+        )),
+    }
+}
+
+fn compile_program(program: &[Stmt], globals: &mut Globals) -> Result<Function, SyntaxError> {
+    let mut compiler = Compiler {
+        globals,
+        functions: vec![State {
+            function: Function {
+                name: "script".to_string(),
+                ..Function::default()
+            },
+            ..State::default()
+        }],
+    };
+    for statement in program {
+        compiler.statement(statement)?;
+    }
+    let line = program.last().map_or(1, |statement| statement.span.line);
+    compiler.emit_return_nil(line, Span::new(0, 0, line))?;
+    Ok(compiler
+        .functions
+        .pop()
+        .expect("the script is compiled")
+        .function)
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+}
+
+// the jumps of `break` and `continue` statements, patched at the end of the
+// loop they're in
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// a function being compiled
+#[derive(Default)]
+struct State {
+    function: Function,
+    // innermost last, the index being the register
+    locals: Vec<Local>,
+    // the first register neither a local nor a temporary holds
+    free: usize,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    // the local whose initializer is being compiled, which has no register
+    // yet
+    initializing: Option<String>,
+}
+
+struct Compiler<'a> {
+    globals: &'a mut Globals,
+    // the function being compiled last, after the ones it's declared in
+    functions: Vec<State>,
+}
+
+impl Compiler<'_> {
+    fn state(&mut self) -> &mut State {
+        self.functions
+            .last_mut()
+            .expect("a function is being compiled")
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), SyntaxError> {
+        let line = stmt.span.line;
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                self.expression(expr, None)?;
+            }
+            StmtKind::Print(expr) => {
+                let src = self.expression(expr, None)?;
+                self.emit(Instruction::Print { src }, line);
+            }
+            StmtKind::Var { name, initializer } => self.define(name, initializer.as_ref())?,
+            StmtKind::Const { name, initializer } => self.define(name, Some(initializer))?,
+            StmtKind::Function(declaration) => {
+                let function = self.function(declaration)?;
+                let index = self.constant(Value::Function(Rc::new(function)), stmt.span)?;
+                let name = &declaration.name;
+                if self.state().scope_depth == 0 {
+                    let src = self.temp(stmt.span)?;
+                    self.emit(Instruction::Constant { dst: src, index }, line);
+                    let slot = self.globals.slot(name)?;
+                    self.emit(Instruction::DefineGlobal { src, slot }, line);
+                } else {
+                    let dst = self.temp(name.span)?;
+                    self.emit(Instruction::Constant { dst, index }, line);
+                    self.add_local(name);
+                }
+            }
+            StmtKind::Return(value) => match value {
+                Some(value) => {
+                    let src = self.expression(value, None)?;
+                    self.emit(Instruction::Return { src }, line);
+                }
+                None => self.emit_return_nil(line, stmt.span)?,
+            },
+            StmtKind::Class(_) => return Err(unsupported("classes", stmt.span)),
+            StmtKind::Block(statements) => {
+                self.state().scope_depth += 1;
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                let state = self.state();
+                state.scope_depth -= 1;
+                let depth = state.scope_depth;
+                while state.locals.last().is_some_and(|local| local.depth > depth) {
+                    state.locals.pop();
+                }
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let src = self.expression(condition, None)?;
+                let then_jump = self.emit(Instruction::JumpIfFalse { src, target: 0 }, line);
+                self.statement(then_branch)?;
+                match else_branch {
+                    Some(else_branch) => {
+                        let else_jump = self.emit(Instruction::Jump { target: 0 }, line);
+                        self.patch(then_jump);
+                        self.statement(else_branch)?;
+                        self.patch(else_jump);
+                    }
+                    None => self.patch(then_jump),
+                }
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let start = self.state().function.code.len();
+                let src = self.expression(condition, None)?;
+                let exit = self.emit(Instruction::JumpIfFalse { src, target: 0 }, line);
+                self.free_temporaries();
+
+                self.state().loops.push(Loop::default());
+                let body = self.statement(body);
+                let innermost = self.state().loops.pop().expect("the loop was just pushed");
+                body?;
+
+                for jump in innermost.continues {
+                    self.patch(jump);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment, None)?;
+                }
+                self.emit(
+                    Instruction::Jump {
+                        target: start as u32,
+                    },
+                    line,
+                );
+                self.patch(exit);
+                for jump in innermost.breaks {
+                    self.patch(jump);
+                }
+            }
+            // locals stay in their registers, so leaving a loop is a jump
+            StmtKind::Break | StmtKind::Continue => {
+                if self.state().loops.is_empty() {
+                    return Err(SyntaxError::new("Can't jump outside of a loop.", stmt.span));
+                }
+                let jump = self.emit(Instruction::Jump { target: 0 }, line);
+                let innermost = self.state().loops.last_mut().expect("inside a loop");
+                match stmt.kind {
+                    StmtKind::Break => innermost.breaks.push(jump),
+                    _ => innermost.continues.push(jump),
+                }
+            }
+            StmtKind::ForIn { .. } => return Err(unsupported("for-in loops", stmt.span)),
+            StmtKind::Yield(_) => return Err(unsupported("generators", stmt.span)),
+            StmtKind::Defer(_) => return Err(unsupported("defer statements", stmt.span)),
+            StmtKind::Throw(_) | StmtKind::Try { .. } => {
+                return Err(unsupported("exceptions", stmt.span))
+            }
+            StmtKind::Import(_) => return Err(unsupported("imports", stmt.span)),
+        }
+        self.free_temporaries();
+        Ok(())
+    }
+
+    // a global at the top level of the script, a local everywhere else
+    fn define(&mut self, name: &Identifier, initializer: Option<&Expr>) -> Result<(), SyntaxError> {
+        let line = name.span.line;
+        if self.state().scope_depth == 0 {
+            let src = match initializer {
+                Some(initializer) => self.expression(initializer, None)?,
+                None => {
+                    let dst = self.temp(name.span)?;
+                    self.emit(Instruction::Nil { dst }, line);
+                    dst
+                }
+            };
+            let slot = self.globals.slot(name)?;
+            self.emit(Instruction::DefineGlobal { src, slot }, line);
+            return Ok(());
+        }
+        // the register of the local, where the initializer puts the value
+        let dst = self.temp(name.span)?;
+        match initializer {
+            Some(initializer) => {
+                self.state().initializing = Some(name.name.clone());
+                let compiled = self.expression(initializer, Some(dst));
+                self.state().initializing = None;
+                compiled?;
+            }
+            None => {
+                self.emit(Instruction::Nil { dst }, line);
+            }
+        }
+        self.add_local(name);
+        Ok(())
+    }
+
+    fn function(&mut self, declaration: &FunctionDecl) -> Result<Function, SyntaxError> {
+        let span = declaration.span;
+        if !declaration.defaults.is_empty() {
+            return Err(unsupported("default parameters", span));
+        }
+        if declaration.rest.is_some() {
+            return Err(unsupported("rest parameters", span));
+        }
+        if declaration.is_async {
+            return Err(unsupported("async functions", span));
+        }
+        self.functions.push(State {
+            function: Function {
+                name: declaration.name.name.clone(),
+                arity: declaration.params.len(),
+                ..Function::default()
+            },
+            scope_depth: 1,
+            ..State::default()
+        });
+        let body = self.function_body(declaration);
+        let compiled = self.functions.pop().expect("the function was just pushed");
+        body?;
+        Ok(compiled.function)
+    }
+
+    fn function_body(&mut self, declaration: &FunctionDecl) -> Result<(), SyntaxError> {
+        // the arguments are in the first registers
+        for param in &declaration.params {
+            self.temp(param.span)?;
+            self.add_local(param);
+        }
+        for statement in &declaration.body {
+            self.statement(statement)?;
+        }
+        let line = declaration
+            .body
+            .last()
+            .map_or(declaration.span.line, |statement| statement.span.line);
+        self.emit_return_nil(line, declaration.span)
+    }
+
+    // compile an expression, into `dst` when given, returning the register
+    // holding its value: reading a local is using its register
+    fn expression(&mut self, expr: &Expr, dst: Option<u8>) -> Result<u8, SyntaxError> {
+        let line = expr.span.line;
+        match &expr.kind {
+            ExprKind::Literal(literal) => {
+                let dst = self.target(dst, expr.span)?;
+                let instruction = match literal {
+                    Literal::Nil => Instruction::Nil { dst },
+                    Literal::Bool(value) => Instruction::Bool { dst, value: *value },
+                    _ => {
+                        let index = self.literal(literal, expr.span)?;
+                        Instruction::Constant { dst, index }
+                    }
+                };
+                self.emit(instruction, line);
+                Ok(dst)
+            }
+            ExprKind::Grouping(inner) => self.expression(inner, dst),
+            ExprKind::Unary { op, right } => {
+                let mark = self.state().free;
+                let src = self.expression(right, None)?;
+                self.state().free = mark;
+                let dst = self.target(dst, expr.span)?;
+                let instruction = match op {
+                    UnaryOp::Negate => Instruction::Negate { dst, src },
+                    UnaryOp::Not => Instruction::Not { dst, src },
+                    UnaryOp::BitNot => Instruction::BitNot { dst, src },
+                };
+                self.emit(instruction, line);
+                Ok(dst)
+            }
+            ExprKind::Binary { left, op, right } => self.binary(*op, left, right, dst, expr.span),
+            ExprKind::Logical { left, op, right } => {
+                // the left operand is written to the result before the right
+                // one runs, which mustn't see it in a local it reads
+                let result = match dst {
+                    Some(dst) if !self.is_local(dst) => dst,
+                    _ => self.temp(expr.span)?,
+                };
+                self.expression(left, Some(result))?;
+                let short_circuit = match op {
+                    LogicalOp::And => Instruction::JumpIfFalse {
+                        src: result,
+                        target: 0,
+                    },
+                    LogicalOp::Or => Instruction::JumpIfTrue {
+                        src: result,
+                        target: 0,
+                    },
+                };
+                let short_circuit = self.emit(short_circuit, line);
+                self.expression(right, Some(result))?;
+                self.patch(short_circuit);
+                Ok(self.move_to(result, dst, line))
+            }
+            ExprKind::Variable(name) => {
+                if let Some(register) = self.local(&name.name) {
+                    return Ok(self.move_to(register, dst, line));
+                }
+                let slot = self.global(name)?;
+                let dst = self.target(dst, expr.span)?;
+                self.emit(Instruction::GetGlobal { dst, slot }, line);
+                Ok(dst)
+            }
+            ExprKind::Assign { name, value } => {
+                if self.state().initializing.as_ref() == Some(&name.name) {
+                    // the value is the result, and goes to the register of
+                    // the local anyway
+                    return self.expression(value, dst);
+                }
+                if let Some(register) = self.local(&name.name) {
+                    let mark = self.state().free;
+                    self.expression(value, Some(register))?;
+                    self.state().free = mark;
+                    return Ok(self.move_to(register, dst, line));
+                }
+                let slot = self.global(name)?;
+                let src = self.expression(value, dst)?;
+                self.emit(Instruction::SetGlobal { src, slot }, line);
+                Ok(src)
+            }
+            ExprKind::Call { callee, arguments } => {
+                let count = u8::try_from(arguments.len()).map_err(|_| {
+                    SyntaxError::new("Can't have more than 255 arguments.", expr.span)
+                })?;
+                let base = self.temp(expr.span)?;
+                self.expression(callee, Some(base))?;
+                for argument in arguments {
+                    let register = self.temp(argument.span)?;
+                    self.expression(argument, Some(register))?;
+                }
+                self.emit(Instruction::Call { base, count }, line);
+                self.state().free = base as usize + 1;
+                let result = self.move_to(base, dst, line);
+                if result != base {
+                    self.state().free = base as usize;
+                }
+                Ok(result)
+            }
+            ExprKind::Get { .. }
+            | ExprKind::Set { .. }
+            | ExprKind::This
+            | ExprKind::Super { .. } => Err(unsupported("classes", expr.span)),
+            ExprKind::List(_) | ExprKind::Spread(_) => Err(unsupported("lists", expr.span)),
+            ExprKind::Map(_) => Err(unsupported("maps", expr.span)),
+            ExprKind::Index { .. } | ExprKind::Slice { .. } | ExprKind::SetIndex { .. } => {
+                Err(unsupported("indexing", expr.span))
+            }
+            ExprKind::Await(_) => Err(unsupported("async functions", expr.span)),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        dst: Option<u8>,
+        span: Span,
+    ) -> Result<u8, SyntaxError> {
+        let line = span.line;
+        if op == BinaryOp::Range {
+            return Err(unsupported("ranges", span));
+        }
+        let mark = self.state().free;
+        let mut left = self.expression(left, None)?;
+        // the right operand can't change the left one once it's read
+        if self.is_local(left) && assigns(right) {
+            let copy = self.temp(span)?;
+            self.emit(
+                Instruction::Move {
+                    dst: copy,
+                    src: left,
+                },
+                line,
+            );
+            left = copy;
+        }
+        let constant = match (op, &right.kind) {
+            (
+                BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Less,
+                ExprKind::Literal(literal @ (Literal::Integer(_) | Literal::Number(_))),
+            ) => Some(self.literal(literal, right.span)?),
+            _ => None,
+        };
+        let right = match constant {
+            Some(_) => 0,
+            None => self.expression(right, None)?,
+        };
+        self.state().free = mark;
+        let dst = self.target(dst, span)?;
+
+        let instruction = match (op, constant) {
+            (BinaryOp::Add, Some(index)) => Instruction::AddConstant { dst, left, index },
+            (BinaryOp::Subtract, Some(index)) => Instruction::SubtractConstant { dst, left, index },
+            (_, Some(index)) => Instruction::LessConstant { dst, left, index },
+            (BinaryOp::Add, None) => Instruction::Add { dst, left, right },
+            (BinaryOp::Subtract, None) => Instruction::Subtract { dst, left, right },
+            (BinaryOp::Multiply, None) => Instruction::Multiply { dst, left, right },
+            (BinaryOp::Divide, None) => Instruction::Divide { dst, left, right },
+            (BinaryOp::Modulo, None) => Instruction::Modulo { dst, left, right },
+            (BinaryOp::Equal, None) => Instruction::Equal { dst, left, right },
+            (BinaryOp::NotEqual, None) => Instruction::NotEqual { dst, left, right },
+            (BinaryOp::Less, None) => Instruction::Less { dst, left, right },
+            (BinaryOp::LessEqual, None) => Instruction::LessEqual { dst, left, right },
+            (BinaryOp::Greater, None) => Instruction::Greater { dst, left, right },
+            (BinaryOp::GreaterEqual, None) => Instruction::GreaterEqual { dst, left, right },
+            (op, None) => Instruction::Binary {
+                op,
+                dst,
+                left,
+                right,
+            },
+        };
+        self.emit(instruction, line);
+        Ok(dst)
+    }
+
+    // `dst`, or a new temporary when there's none
+    fn target(&mut self, dst: Option<u8>, span: Span) -> Result<u8, SyntaxError> {
+        match dst {
+            Some(dst) => Ok(dst),
+            None => self.temp(span),
+        }
+    }
+
+    // the register holding a value, moving it to `dst` when given
+    fn move_to(&mut self, src: u8, dst: Option<u8>, line: usize) -> u8 {
+        match dst {
+            Some(dst) if dst != src => {
+                self.emit(Instruction::Move { dst, src }, line);
+                dst
+            }
+            _ => src,
+        }
+    }
+
+    fn temp(&mut self, span: Span) -> Result<u8, SyntaxError> {
+        let state = self.state();
+        if state.free == MAX_REGISTERS {
+            return Err(SyntaxError::new("Too many registers in function.", span));
+        }
+        let register = state.free as u8;
+        state.free += 1;
+        state.function.registers = state.function.registers.max(state.free);
+        Ok(register)
+    }
+
+    // the temporaries of a statement are done with once it's compiled
+    fn free_temporaries(&mut self) {
+        let state = self.state();
+        state.free = state.locals.len();
+    }
+
+    // the local takes the last register taken
+    fn add_local(&mut self, name: &Identifier) {
+        let state = self.state();
+        state.locals.push(Local {
+            name: name.name.clone(),
+            depth: state.scope_depth,
+        });
+        debug_assert_eq!(
+            state.locals.len(),
+            state.free,
+            "locals come before temporaries"
+        );
+    }
+
+    fn is_local(&mut self, register: u8) -> bool {
+        (register as usize) < self.state().locals.len()
+    }
+
+    fn local(&mut self, name: &str) -> Option<u8> {
+        let locals = &self.state().locals;
+        locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|register| register as u8)
+    }
+
+    // the slot of a global, failing for the locals of the functions around
+    // the one being compiled, which would have to be captured
+    fn global(&mut self, name: &Identifier) -> Result<u16, SyntaxError> {
+        let (_, enclosing) = self
+            .functions
+            .split_last()
+            .expect("a function is being compiled");
+        let captured = enclosing
+            .iter()
+            .any(|state| state.locals.iter().any(|local| local.name == name.name));
+        if captured {
+            return Err(unsupported("closures", name.span));
+        }
+        self.globals.slot(name)
+    }
+
+    fn literal(&mut self, literal: &Literal, span: Span) -> Result<u16, SyntaxError> {
+        let value = match literal {
+            Literal::Integer(integer) => Value::Integer(*integer),
+            Literal::Number(number) => Value::Number(*number),
+            Literal::String(string) => Value::String(Rc::new(string.clone())),
+            Literal::Nil | Literal::Bool(_) => unreachable!("nil and booleans aren't constants"),
+        };
+        self.constant(value, span)
+    }
+
+    fn constant(&mut self, value: Value, span: Span) -> Result<u16, SyntaxError> {
+        let constants = &mut self.state().function.constants;
+        // the same numbers and strings share a constant
+        let existing = constants
+            .iter()
+            .position(|constant| match (constant, &value) {
+                (Value::Integer(left), Value::Integer(right)) => left == right,
+                (Value::Number(left), Value::Number(right)) => left.to_bits() == right.to_bits(),
+                (Value::String(left), Value::String(right)) => left == right,
+                _ => false,
+            });
+        let index = existing.unwrap_or_else(|| {
+            constants.push(value);
+            constants.len() - 1
+        });
+        u16::try_from(index).map_err(|_| SyntaxError::new("Too many constants in one chunk.", span))
+    }
+
+    fn emit(&mut self, instruction: Instruction, line: usize) -> usize {
+        let function = &mut self.state().function;
+        function.code.push(instruction);
+        function.lines.push(line);
+        function.code.len() - 1
+    }
+
+    fn emit_return_nil(&mut self, line: usize, span: Span) -> Result<(), SyntaxError> {
+        let src = self.temp(span)?;
+        self.emit(Instruction::Nil { dst: src }, line);
+        self.emit(Instruction::Return { src }, line);
+        Ok(())
+    }
+
+    // point a jump at the next instruction
+    fn patch(&mut self, jump: usize) {
+        let code = &mut self.state().function.code;
+        let next = code.len() as u32;
+        match &mut code[jump] {
+            Instruction::Jump { target }
+            | Instruction::JumpIfFalse { target, .. }
+            | Instruction::JumpIfTrue { target, .. } => *target = next,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+}
+
+// whether an expression assigns a variable
+fn assigns(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Assign { .. } => true,
+        ExprKind::Grouping(inner) | ExprKind::Unary { right: inner, .. } => assigns(inner),
+        ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+            assigns(left) || assigns(right)
+        }
+        ExprKind::Call { callee, arguments } => assigns(callee) || arguments.iter().any(assigns),
+        _ => false,
+    }
+}
+
+fn unsupported(what: &str, span: Span) -> SyntaxError {
+    SyntaxError::new(format!("Can't compile {} to bytecode yet.", what), span)
+}
@@ -0,0 +1,78 @@
+use std::io::{self, BufRead, Write};
+
+use crate::interpreter::{Interpreter, RuntimeError, RuntimeErrorKind};
+
+const PROMPT: &str = "> ";
+const RESET: &str = ":reset";
+const ENV: &str = ":env";
+
+/// Read lines from `input` and run them one by one, echoing the value of
+/// bare expressions. Globals stay defined from one line to the next, until
+/// `:reset` brings them back to how they were when the session started.
+/// `:env` lists the globals defined or changed since then. The session ends
+/// with the input, giving 0, or when the program calls `exit`, giving its
+/// status.
+pub fn run<R, W>(interpreter: &mut Interpreter, input: R, output: &mut W) -> io::Result<i32>
+where
+    R: BufRead,
+    W: Write,
+{
+    let start = interpreter.snapshot();
+    let mut lines = input.lines();
+    loop {
+        write!(output, "{}", PROMPT)?;
+        output.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.trim() == RESET {
+            interpreter.restore(&start);
+            continue;
+        }
+        if line.trim() == ENV {
+            for binding in interpreter.scopes().pop().unwrap_or_default() {
+                let unchanged = start
+                    .get_global(&binding.name)
+                    .is_some_and(|value| value.is_identical(&binding.value));
+                if !unchanged {
+                    let keyword = if binding.constant { "const" } else { "var" };
+                    writeln!(output, "{} {} = {}", keyword, binding.name, binding.value)?;
+                }
+            }
+            continue;
+        }
+
+        match interpreter.eval(&complete_line(&line)) {
+            Ok(Some(value)) => writeln!(output, "{}", value)?,
+            Ok(None) => {}
+            Err(error) => {
+                if let Some(RuntimeError {
+                    kind: RuntimeErrorKind::Exit(code),
+                    ..
+                }) = error.downcast_ref()
+                {
+                    return Ok(*code);
+                }
+                writeln!(output, "{}", error)?
+            }
+        }
+    }
+
+    writeln!(output)?;
+    Ok(0)
+}
+
+// let expressions and statements be typed without their trailing semicolon
+fn complete_line(line: &str) -> String {
+    let trimmed = line.trim_end();
+    if trimmed.ends_with(';') || trimmed.ends_with('}') {
+        trimmed.to_string()
+    } else {
+        format!("{};", trimmed)
+    }
+}
@@ -0,0 +1,163 @@
+//! Recording what the nondeterministic natives return, like `clock`, to play
+//! it back later and run a program again exactly the same way.
+//!
+//! A log has one result per line, in the order the natives were called:
+//!
+//! ```text
+//! clock number 1760000000.25
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayMode {
+    Record,
+    Replay,
+}
+
+/// The results of the nondeterministic natives a program called, oldest
+/// first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayLog {
+    entries: VecDeque<(String, Value)>,
+}
+
+impl ReplayLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Could not read replay log '{}'.", path.display()))?;
+        Self::parse(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string())
+            .with_context(|| format!("Could not write replay log '{}'.", path.display()))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut log = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let entry = parse_entry(line)
+                .ok_or_else(|| anyhow!("Invalid replay log line {}.", number + 1))?;
+            log.entries.push_back(entry);
+        }
+        Ok(log)
+    }
+
+    /// Add the result of a call to `native`. Only nil, booleans, numbers and
+    /// strings can be recorded.
+    pub fn push(&mut self, native: &str, value: Value) -> Result<(), String> {
+        match value {
+            Value::Nil
+            | Value::Bool(_)
+            | Value::Number(_)
+            | Value::Integer(_)
+            | Value::String(_) => {
+                self.entries.push_back((native.to_string(), value));
+                Ok(())
+            }
+            other => Err(format!(
+                "Can't record the {} returned by '{}'.",
+                other.type_name(),
+                native
+            )),
+        }
+    }
+
+    /// Take the next result, which must come from a call to `native`.
+    pub fn pop(&mut self, native: &str) -> Result<Value, String> {
+        match self.entries.front() {
+            None => Err(format!("Replay log has no result left for '{}'.", native)),
+            Some((name, _)) if name != native => Err(format!(
+                "Replay log expected a call to '{}', not '{}'.",
+                name, native
+            )),
+            Some((_, value)) => {
+                let value = value.clone();
+                self.entries.pop_front();
+                Ok(value)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ReplayLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (native, value) in &self.entries {
+            match value {
+                Value::Nil => writeln!(f, "{} nil", native)?,
+                Value::Bool(b) => writeln!(f, "{} {}", native, b)?,
+                // debug formatting reads back as the same number
+                Value::Number(n) => writeln!(f, "{} number {:?}", native, n)?,
+                Value::Integer(i) => writeln!(f, "{} integer {}", native, i)?,
+                Value::String(s) => writeln!(f, "{} string {}", native, escape(s))?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Option<(String, Value)> {
+    let (native, rest) = line.split_once(' ')?;
+    let (kind, payload) = rest.split_once(' ').unwrap_or((rest, ""));
+    let value = match kind {
+        "nil" => Value::Nil,
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        "number" => Value::Number(payload.parse().ok()?),
+        "integer" => Value::Integer(payload.parse().ok()?),
+        "string" => Value::from(unescape(payload)?),
+        _ => return None,
+    };
+    Some((native.to_string(), value))
+}
+
+// keep every entry on its own line
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut output = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        output.push(match chars.next()? {
+            '\\' => '\\',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(output)
+}
@@ -0,0 +1,934 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::lexer::{Span, SyntaxError};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct ScopeId(pub usize);
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct DefinitionId(pub usize);
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ScopeKind {
+    Global,
+    Block,
+    Function,
+    Class,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Scope {
+    pub id: ScopeId,
+    pub kind: ScopeKind,
+    pub parent: Option<ScopeId>,
+    pub span: Span,
+    pub definitions: Vec<DefinitionId>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DefinitionKind {
+    Variable,
+    Constant,
+    Parameter,
+    Function,
+    Class,
+    This,
+    Super,
+}
+
+/// A name introduced by the program.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Definition {
+    pub id: DefinitionId,
+    pub name: String,
+    pub kind: DefinitionKind,
+    // the span of the name itself
+    pub span: Span,
+    // the node that declared the name
+    pub node: NodeId,
+    pub scope: ScopeId,
+    // position among the definitions of its scope, which is where the
+    // interpreter stores local variables
+    pub slot: usize,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ReferenceKind {
+    Read,
+    Write,
+}
+
+/// A use of a name by a variable, assignment, `this` or `super` expression.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Reference {
+    pub node: NodeId,
+    pub name: String,
+    pub kind: ReferenceKind,
+    pub span: Span,
+    pub definition: Option<DefinitionId>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Resolution {
+    /// Found in slot `slot` of the scope `depth` scopes away from the scope
+    /// of the expression.
+    Local {
+        depth: usize,
+        slot: usize,
+        definition: DefinitionId,
+    },
+    /// Looked up dynamically in the global environment. `definition` is the
+    /// global declaration with that name, if the program has one.
+    Global { definition: Option<DefinitionId> },
+}
+
+/// Everything the resolver learned about a program: scopes, definitions,
+/// references and how each reference node resolves.
+#[derive(Debug, Clone, Default)]
+pub struct SemanticModel {
+    scopes: Vec<Scope>,
+    definitions: Vec<Definition>,
+    references: Vec<Reference>,
+    resolutions: HashMap<NodeId, Resolution>,
+    reference_index: HashMap<NodeId, usize>,
+    tail_calls: HashSet<NodeId>,
+    // the class declaration around each access to a private member
+    private_accesses: HashMap<NodeId, NodeId>,
+    generators: HashSet<NodeId>,
+    // statements with a `yield` somewhere inside, outside of nested functions
+    yielding: HashSet<NodeId>,
+    captures: HashSet<NodeId>,
+    errors: Vec<SyntaxError>,
+}
+
+impl SemanticModel {
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    pub fn scope(&self, id: ScopeId) -> &Scope {
+        &self.scopes[id.0]
+    }
+
+    pub fn global_scope(&self) -> &Scope {
+        &self.scopes[0]
+    }
+
+    pub fn definitions(&self) -> &[Definition] {
+        &self.definitions
+    }
+
+    pub fn definition(&self, id: DefinitionId) -> &Definition {
+        &self.definitions[id.0]
+    }
+
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    pub fn reference(&self, node: NodeId) -> Option<&Reference> {
+        self.reference_index
+            .get(&node)
+            .map(|index| &self.references[*index])
+    }
+
+    pub fn resolution(&self, node: NodeId) -> Option<Resolution> {
+        self.resolutions.get(&node).copied()
+    }
+
+    /// The definition a reference node points to.
+    pub fn definition_of(&self, node: NodeId) -> Option<&Definition> {
+        self.reference(node)
+            .and_then(|reference| reference.definition)
+            .map(|id| self.definition(id))
+    }
+
+    /// Every reference that resolves to the given definition.
+    pub fn references_to(&self, definition: DefinitionId) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(move |reference| reference.definition == Some(definition))
+    }
+
+    /// The definition of the name found at a byte offset of the source,
+    /// whether the offset points to the definition itself or to a use of it.
+    pub fn definition_at(&self, offset: usize) -> Option<&Definition> {
+        let reference = self
+            .references
+            .iter()
+            .find(|reference| reference.span.contains(offset));
+        match reference {
+            Some(reference) => reference.definition.map(|id| self.definition(id)),
+            None => self
+                .definitions
+                .iter()
+                .find(|definition| definition.span.contains(offset)),
+        }
+    }
+
+    /// The innermost scope containing a byte offset of the source.
+    pub fn scope_at(&self, offset: usize) -> &Scope {
+        self.scopes
+            .iter()
+            .skip(1)
+            .filter(|scope| scope.span.start <= offset && offset <= scope.span.end)
+            .min_by_key(|scope| scope.span.end - scope.span.start)
+            .unwrap_or_else(|| self.global_scope())
+    }
+
+    /// Whether a call expression is the value of a `return`, with nothing
+    /// left to do in its function once the call is done.
+    pub fn is_tail_call(&self, call: NodeId) -> bool {
+        self.tail_calls.contains(&call)
+    }
+
+    pub fn tail_calls(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.tail_calls.iter().copied()
+    }
+
+    /// Accesses to private members, named with a leading `_`, along with
+    /// the class declaration they're written in. Accesses outside of any
+    /// class are left out.
+    pub fn private_accesses(&self) -> impl Iterator<Item = (NodeId, NodeId)> + '_ {
+        self.private_accesses
+            .iter()
+            .map(|(access, class)| (*access, *class))
+    }
+
+    /// The declarations of the functions with a `yield` in their body,
+    /// which make generators when called.
+    pub fn generators(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.generators.iter().copied()
+    }
+
+    /// The statements a generator can be suspended in: `yield` statements,
+    /// and the blocks, branches and loops around them.
+    pub fn yielding_statements(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.yielding.iter().copied()
+    }
+
+    /// The declarations of the functions using the locals of the functions
+    /// or blocks around them, other than themselves.
+    pub fn captures(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.captures.iter().copied()
+    }
+
+    /// Errors such as reading a local in its own initializer.
+    pub fn errors(&self) -> &[SyntaxError] {
+        &self.errors
+    }
+
+    /// Fail with the first error found, if any.
+    pub fn check(&self) -> anyhow::Result<()> {
+        match self.errors.first() {
+            Some(error) => Err(error.clone().into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+struct ScopeFrame {
+    id: ScopeId,
+    // name -> (definition, whether its initializer finished)
+    names: HashMap<String, (DefinitionId, bool)>,
+}
+
+pub struct Resolver {
+    model: SemanticModel,
+    scopes: Vec<ScopeFrame>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    // the declaration of `current_class`
+    class_node: Option<NodeId>,
+    loop_depth: usize,
+    // a `try` still has work to do after a return, so calls inside aren't
+    // tail calls
+    try_depth: usize,
+    // globals provided by the host, set when running in strict mode
+    strict_globals: Option<HashSet<String>>,
+    // the current function was declared `async`
+    is_async: bool,
+    // `yield` statements, or `await`s in async functions, found so far in the
+    // current function
+    yields: usize,
+    // `return value;` statements of the current function, an error if it
+    // turns out to be a generator
+    value_returns: Vec<Span>,
+    // the functions being resolved, innermost last, with how many scopes
+    // were around each
+    functions: Vec<(usize, NodeId)>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        let mut model = SemanticModel::default();
+        model.scopes.push(Scope {
+            id: ScopeId(0),
+            kind: ScopeKind::Global,
+            parent: None,
+            span: Span::default(),
+            definitions: Vec::new(),
+        });
+
+        Self {
+            model,
+            scopes: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            class_node: None,
+            loop_depth: 0,
+            try_depth: 0,
+            strict_globals: None,
+            is_async: false,
+            yields: 0,
+            value_returns: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+
+    /// Report uses of globals that the program never declares, catching typos
+    /// before anything runs. `known_globals` are names defined by the host,
+    /// like native functions or globals from previous runs.
+    pub fn strict<I, S>(mut self, known_globals: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.strict_globals = Some(known_globals.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn resolve(mut self, program: &[Stmt]) -> SemanticModel {
+        if let (Some(first), Some(last)) = (program.first(), program.last()) {
+            self.model.scopes[0].span = first.span.to(last.span);
+        }
+
+        self.resolve_statements(program);
+        self.link_globals();
+        self.check_constants();
+        self.model
+    }
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, stmt: &Stmt) {
+        let yields = self.yields;
+        self.resolve_statement_kind(stmt);
+        if self.yields > yields {
+            self.model.yielding.insert(stmt.id);
+        }
+    }
+
+    fn resolve_statement_kind(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Block(statements) => {
+                self.begin_scope(ScopeKind::Block, stmt.span);
+                self.resolve_statements(statements);
+                self.end_scope();
+            }
+            StmtKind::Var { name, initializer } => {
+                self.declare(name, DefinitionKind::Variable, stmt.id);
+                if let Some(initializer) = initializer {
+                    self.resolve_awaitable(initializer);
+                }
+                self.define(&name.name);
+            }
+            StmtKind::Const { name, initializer } => {
+                self.declare(name, DefinitionKind::Constant, stmt.id);
+                self.resolve_expression(initializer);
+                self.define(&name.name);
+            }
+            StmtKind::Function(function) => {
+                self.declare(&function.name, DefinitionKind::Function, function.id);
+                self.define(&function.name.name);
+                self.resolve_function(function, FunctionType::Function);
+            }
+            StmtKind::Class(class) => self.resolve_class(stmt, class),
+            StmtKind::Expression(expr) => self.resolve_awaitable(expr),
+            StmtKind::Print(expr) => self.resolve_expression(expr),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+            } => {
+                self.resolve_expression(condition);
+                self.loop_depth += 1;
+                self.resolve_statement(body);
+                self.loop_depth -= 1;
+                if let Some(increment) = increment {
+                    self.resolve_expression(increment);
+                }
+            }
+            StmtKind::ForIn {
+                name,
+                iterable,
+                body,
+            } => {
+                self.resolve_expression(iterable);
+                self.begin_scope(ScopeKind::Block, stmt.span);
+                self.declare(name, DefinitionKind::Variable, stmt.id);
+                self.define(&name.name);
+                self.loop_depth += 1;
+                self.resolve_statement(body);
+                self.loop_depth -= 1;
+                self.end_scope();
+            }
+            StmtKind::Break if self.loop_depth == 0 => {
+                self.error("Can't use 'break' outside of a loop.", "'break'", stmt.span);
+            }
+            StmtKind::Continue if self.loop_depth == 0 => {
+                self.error(
+                    "Can't use 'continue' outside of a loop.",
+                    "'continue'",
+                    stmt.span,
+                );
+            }
+            StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::Return(value) => {
+                if self.current_function == FunctionType::None {
+                    self.error("Can't return from top-level code.", "'return'", stmt.span);
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(
+                            "Can't return a value from an initializer.",
+                            "'return'",
+                            stmt.span,
+                        );
+                    }
+                    // an async function settles its promise after returning
+                    let tail = self.try_depth == 0 && !self.is_async;
+                    if matches!(value.kind, ExprKind::Call { .. }) && tail {
+                        self.model.tail_calls.insert(value.id);
+                    }
+                    self.value_returns.push(stmt.span);
+                    self.resolve_expression(value);
+                }
+            }
+            StmtKind::Yield(value) => {
+                match self.current_function {
+                    FunctionType::None => {
+                        self.error("Can't yield from top-level code.", "'yield'", stmt.span)
+                    }
+                    FunctionType::Initializer => {
+                        self.error("Can't yield from an initializer.", "'yield'", stmt.span)
+                    }
+                    _ if self.is_async => {
+                        self.error("Can't yield from an async function.", "'yield'", stmt.span)
+                    }
+                    _ if self.try_depth > 0 => {
+                        self.error("Can't yield inside a try statement.", "'yield'", stmt.span)
+                    }
+                    _ => {}
+                }
+                self.yields += 1;
+                if let Some(value) = value {
+                    self.resolve_expression(value);
+                }
+            }
+            StmtKind::Defer(expr) => {
+                if self.current_function == FunctionType::None {
+                    self.error("Can't defer from top-level code.", "'defer'", stmt.span);
+                }
+                self.resolve_expression(expr);
+            }
+            StmtKind::Throw(value) => self.resolve_expression(value),
+            // imports define globals
+            StmtKind::Import(_) if !self.scopes.is_empty() => {
+                self.error("Can only import at the top level.", "'import'", stmt.span);
+            }
+            StmtKind::Import(_) => {}
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.try_depth += 1;
+                self.resolve_statement(body);
+                if let Some(catch) = catch {
+                    self.begin_scope(ScopeKind::Block, catch.span);
+                    self.declare(&catch.name, DefinitionKind::Variable, stmt.id);
+                    self.define(&catch.name.name);
+                    self.resolve_statement(&catch.body);
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.resolve_statement(finally);
+                }
+                self.try_depth -= 1;
+            }
+        }
+    }
+
+    fn resolve_class(&mut self, stmt: &Stmt, class: &ClassDecl) {
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+        let enclosing_node = self.class_node.replace(stmt.id);
+
+        self.declare(&class.name, DefinitionKind::Class, stmt.id);
+        self.define(&class.name.name);
+
+        if let Some(superclass) = &class.superclass {
+            if let ExprKind::Variable(superclass_name) = &superclass.kind {
+                if superclass_name.name == class.name.name {
+                    self.error(
+                        "A class can't inherit from itself.",
+                        &format!("'{}'", class.name.name),
+                        superclass.span,
+                    );
+                }
+            }
+
+            self.current_class = ClassType::Subclass;
+            self.resolve_expression(superclass);
+
+            self.begin_scope(ScopeKind::Class, stmt.span);
+            self.define_implicit("super", DefinitionKind::Super, class.name.span, stmt.id);
+        }
+
+        self.begin_scope(ScopeKind::Class, stmt.span);
+        self.define_implicit("this", DefinitionKind::This, class.name.span, stmt.id);
+
+        for method in &class.methods {
+            let kind = if method.name.name == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, kind);
+        }
+        // `this` is the class itself in class methods
+        let members = class
+            .getters
+            .iter()
+            .chain(&class.setters)
+            .chain(&class.class_methods);
+        for member in members {
+            self.resolve_function(member, FunctionType::Method);
+        }
+
+        self.end_scope();
+        if class.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class = enclosing_class;
+        self.class_node = enclosing_node;
+    }
+
+    // `await` suspends the whole statement, so it can only be all of one
+    fn resolve_awaitable(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Await(promise) => {
+                if !self.is_async {
+                    self.error(
+                        "Can't use 'await' outside of an async function.",
+                        "'await'",
+                        expr.span,
+                    );
+                }
+                self.yields += 1;
+                self.resolve_expression(promise);
+            }
+            _ => self.resolve_expression(expr),
+        }
+    }
+
+    // the interpreter checks private members are only used inside their class
+    fn resolve_member(&mut self, node: NodeId, name: &Identifier) {
+        if let (true, Some(class)) = (name.name.starts_with('_'), self.class_node) {
+            self.model.private_accesses.insert(node, class);
+        }
+    }
+
+    fn resolve_function(&mut self, function: &FunctionDecl, kind: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = kind;
+        // loops don't reach into the functions declared inside them
+        let enclosing_loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let enclosing_try_depth = std::mem::replace(&mut self.try_depth, 0);
+        let enclosing_async = std::mem::replace(&mut self.is_async, function.is_async);
+        let enclosing_yields = std::mem::replace(&mut self.yields, 0);
+        let enclosing_returns = std::mem::take(&mut self.value_returns);
+
+        if function.is_async && kind == FunctionType::Initializer {
+            self.error(
+                "Can't make an initializer async.",
+                "'init'",
+                function.name.span,
+            );
+        }
+
+        self.functions.push((self.scopes.len(), function.id));
+        self.begin_scope(ScopeKind::Function, function.span);
+        // defaults see the parameters before them
+        let required = function.params.len() - function.defaults.len();
+        for (i, param) in function.params.iter().chain(&function.rest).enumerate() {
+            if let Some(default) = i
+                .checked_sub(required)
+                .and_then(|i| function.defaults.get(i))
+            {
+                self.resolve_expression(default);
+            }
+            self.declare(param, DefinitionKind::Parameter, function.id);
+            self.define(&param.name);
+        }
+        self.resolve_statements(&function.body);
+        self.end_scope();
+        self.functions.pop();
+
+        if self.yields > 0 && !function.is_async {
+            self.model.generators.insert(function.id);
+            for span in std::mem::take(&mut self.value_returns) {
+                self.error("Can't return a value from a generator.", "'return'", span);
+            }
+        }
+
+        self.loop_depth = enclosing_loop_depth;
+        self.try_depth = enclosing_try_depth;
+        self.is_async = enclosing_async;
+        self.yields = enclosing_yields;
+        self.value_returns = enclosing_returns;
+
+        self.current_function = enclosing_function;
+    }
+
+    fn resolve_expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::Grouping(inner) => self.resolve_expression(inner),
+            ExprKind::Await(promise) => {
+                let message = if self.is_async {
+                    "Can only await as a whole statement or variable initializer."
+                } else {
+                    "Can't use 'await' outside of an async function."
+                };
+                self.error(message, "'await'", expr.span);
+                self.resolve_expression(promise);
+            }
+            ExprKind::Unary { right, .. } => self.resolve_expression(right),
+            ExprKind::Spread(list) => self.resolve_expression(list),
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            ExprKind::Variable(name) => {
+                if let Some(frame) = self.scopes.last() {
+                    if let Some((_, false)) = frame.names.get(&name.name) {
+                        self.error(
+                            "Can't read local variable in its own initializer.",
+                            &format!("'{}'", name.name),
+                            name.span,
+                        );
+                    }
+                }
+                self.resolve_local(expr.id, &name.name, name.span, ReferenceKind::Read);
+            }
+            ExprKind::Assign { name, value } => {
+                self.resolve_expression(value);
+                self.resolve_local(expr.id, &name.name, name.span, ReferenceKind::Write);
+            }
+            ExprKind::Call { callee, arguments } => {
+                self.resolve_expression(callee);
+                for argument in arguments {
+                    self.resolve_expression(argument);
+                }
+            }
+            ExprKind::Get { object, name } => {
+                self.resolve_expression(object);
+                self.resolve_member(expr.id, name);
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+                self.resolve_member(expr.id, name);
+            }
+            ExprKind::List(elements) => {
+                for element in elements {
+                    self.resolve_expression(element);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+            }
+            ExprKind::Slice { object, start, end } => {
+                self.resolve_expression(object);
+                for bound in start.iter().chain(end) {
+                    self.resolve_expression(bound);
+                }
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+                self.resolve_expression(value);
+            }
+            ExprKind::This => {
+                if self.current_class == ClassType::None {
+                    self.error("Can't use 'this' outside of a class.", "'this'", expr.span);
+                    return;
+                }
+                self.resolve_local(expr.id, "this", expr.span, ReferenceKind::Read);
+            }
+            ExprKind::Super { .. } => {
+                match self.current_class {
+                    ClassType::None => {
+                        self.error(
+                            "Can't use 'super' outside of a class.",
+                            "'super'",
+                            expr.span,
+                        );
+                        return;
+                    }
+                    ClassType::Class => {
+                        self.error(
+                            "Can't use 'super' in a class with no superclass.",
+                            "'super'",
+                            expr.span,
+                        );
+                        return;
+                    }
+                    ClassType::Subclass => {}
+                }
+                self.resolve_local(expr.id, "super", expr.span, ReferenceKind::Read);
+            }
+        }
+    }
+
+    fn resolve_local(&mut self, node: NodeId, name: &str, span: Span, kind: ReferenceKind) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, frame)| frame.names.get(name).map(|(id, _)| (depth, *id)));
+
+        let (resolution, definition) = match found {
+            Some((depth, definition)) => {
+                let outside = self.scopes.len() - 1 - depth;
+                let node = self.model.definition(definition).node;
+                for &(_, function) in self
+                    .functions
+                    .iter()
+                    .rev()
+                    .take_while(|(scopes, _)| *scopes > outside)
+                {
+                    if function != node {
+                        self.model.captures.insert(function);
+                    }
+                }
+                let slot = self.model.definition(definition).slot;
+                let resolution = Resolution::Local {
+                    depth,
+                    slot,
+                    definition,
+                };
+                (resolution, Some(definition))
+            }
+            // globals are linked once the whole program was seen
+            None => (Resolution::Global { definition: None }, None),
+        };
+
+        self.model.resolutions.insert(node, resolution);
+        self.model
+            .reference_index
+            .insert(node, self.model.references.len());
+        self.model.references.push(Reference {
+            node,
+            name: name.to_string(),
+            kind,
+            span,
+            definition,
+        });
+    }
+
+    fn link_globals(&mut self) {
+        let mut globals: HashMap<&str, DefinitionId> = HashMap::new();
+        for id in &self.model.scopes[0].definitions {
+            let definition = &self.model.definitions[id.0];
+            globals.entry(&definition.name).or_insert(*id);
+        }
+
+        for reference in &mut self.model.references {
+            let resolution = self.model.resolutions.get_mut(&reference.node);
+            if let Some(Resolution::Global { definition }) = resolution {
+                *definition = globals.get(reference.name.as_str()).copied();
+                reference.definition = *definition;
+
+                let known = match &self.strict_globals {
+                    Some(known_globals) => known_globals.contains(&reference.name),
+                    None => true,
+                };
+                if definition.is_none() && !known {
+                    self.model.errors.push(
+                        SyntaxError::new(
+                            format!("Undefined variable '{}'.", reference.name),
+                            reference.span,
+                        )
+                        .at(format!("'{}'", reference.name)),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_constants(&mut self) {
+        for reference in &self.model.references {
+            let constant = reference
+                .definition
+                .is_some_and(|id| self.model.definitions[id.0].kind == DefinitionKind::Constant);
+            if constant && reference.kind == ReferenceKind::Write {
+                self.model.errors.push(
+                    SyntaxError::new(
+                        format!("Can't assign to constant '{}'.", reference.name),
+                        reference.span,
+                    )
+                    .at(format!("'{}'", reference.name)),
+                );
+            }
+        }
+    }
+
+    fn begin_scope(&mut self, kind: ScopeKind, span: Span) {
+        let id = ScopeId(self.model.scopes.len());
+        let parent = Some(self.current_scope());
+        self.model.scopes.push(Scope {
+            id,
+            kind,
+            parent,
+            span,
+            definitions: Vec::new(),
+        });
+        self.scopes.push(ScopeFrame {
+            id,
+            names: HashMap::new(),
+        });
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn current_scope(&self) -> ScopeId {
+        self.scopes
+            .last()
+            .map(|frame| frame.id)
+            .unwrap_or(ScopeId(0))
+    }
+
+    fn add_definition(
+        &mut self,
+        name: &str,
+        kind: DefinitionKind,
+        span: Span,
+        node: NodeId,
+    ) -> DefinitionId {
+        let id = DefinitionId(self.model.definitions.len());
+        let scope = self.current_scope();
+        let slot = self.model.scopes[scope.0].definitions.len();
+        self.model.definitions.push(Definition {
+            id,
+            name: name.to_string(),
+            kind,
+            span,
+            node,
+            scope,
+            slot,
+        });
+        self.model.scopes[scope.0].definitions.push(id);
+        id
+    }
+
+    fn declare(&mut self, name: &Identifier, kind: DefinitionKind, node: NodeId) {
+        let id = self.add_definition(&name.name, kind, name.span, node);
+
+        if let Some(frame) = self.scopes.last_mut() {
+            if frame.names.contains_key(&name.name) {
+                self.error(
+                    "Already a variable with this name in this scope.",
+                    &format!("'{}'", name.name),
+                    name.span,
+                );
+                return;
+            }
+            frame.names.insert(name.name.clone(), (id, false));
+        }
+    }
+
+    fn define(&mut self, name: &str) {
+        if let Some(frame) = self.scopes.last_mut() {
+            if let Some((_, defined)) = frame.names.get_mut(name) {
+                *defined = true;
+            }
+        }
+    }
+
+    fn define_implicit(&mut self, name: &str, kind: DefinitionKind, span: Span, node: NodeId) {
+        let id = self.add_definition(name, kind, span, node);
+        if let Some(frame) = self.scopes.last_mut() {
+            frame.names.insert(name.to_string(), (id, true));
+        }
+    }
+
+    fn error(&mut self, message: &str, location: &str, span: Span) {
+        self.model
+            .errors
+            .push(SyntaxError::new(message, span).at(location));
+    }
+}
+
+/// Resolve a whole program.
+pub fn resolve(program: &[Stmt]) -> SemanticModel {
+    Resolver::new().resolve(program)
+}
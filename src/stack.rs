@@ -0,0 +1,71 @@
+//! How much native stack the current thread has left, so that deep
+//! recursion in the interpreter fails with a "Stack overflow." error instead
+//! of aborting the whole process.
+//!
+//! On Linux the stack of each thread is looked up once. Elsewhere it's
+//! assumed to end `FALLBACK_SIZE` bytes below where the thread first asked,
+//! which the default stack of a thread spawned by Rust has room for.
+
+use std::cell::Cell;
+
+// what a call of the interpreter can take before the next check, with room
+// to spare for reporting the error: debug builds need the most, up to about
+// 100KB for a call running a loop
+const RESERVE: usize = 256 * 1024;
+
+const FALLBACK_SIZE: usize = 1024 * 1024;
+
+thread_local! {
+    // the lowest address the stack of the thread can grow down to
+    static LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Whether the stack of the current thread is too close to its end for one
+/// more call.
+pub(crate) fn exhausted() -> bool {
+    let marker = 0u8;
+    let here = &marker as *const u8 as usize;
+    let limit = LIMIT.with(|limit| {
+        let found = limit
+            .get()
+            .unwrap_or_else(|| find_limit().unwrap_or(here.saturating_sub(FALLBACK_SIZE)));
+        limit.set(Some(found));
+        found
+    });
+    here.saturating_sub(limit) < RESERVE
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn find_limit() -> Option<usize> {
+    // bigger than `pthread_attr_t` on every architecture
+    #[repr(C, align(16))]
+    struct Attributes([u8; 128]);
+
+    extern "C" {
+        fn pthread_self() -> usize;
+        fn pthread_getattr_np(thread: usize, attributes: *mut Attributes) -> i32;
+        fn pthread_attr_getstack(
+            attributes: *const Attributes,
+            address: *mut usize,
+            size: *mut usize,
+        ) -> i32;
+        fn pthread_attr_destroy(attributes: *mut Attributes) -> i32;
+    }
+
+    let mut attributes = Attributes([0; 128]);
+    let (mut address, mut size) = (0, 0);
+    // the attributes are only read after glibc filled them in
+    unsafe {
+        if pthread_getattr_np(pthread_self(), &mut attributes) != 0 {
+            return None;
+        }
+        let found = pthread_attr_getstack(&attributes, &mut address, &mut size);
+        pthread_attr_destroy(&mut attributes);
+        (found == 0 && size > 0).then_some(address)
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn find_limit() -> Option<usize> {
+    None
+}
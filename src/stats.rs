@@ -0,0 +1,97 @@
+//! Counting what a program does, to see where it spends its time.
+//!
+//! `Stats` is a hook: set it with `Interpreter::set_hook`, keeping a handle
+//! to read it once the program ran. Like any hook, it turns off tail call
+//! optimization while it's set.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::Stmt;
+use crate::environment::EnvRef;
+use crate::gc;
+use crate::hook::InterpreterHook;
+use crate::lexer::Span;
+use crate::value::Value;
+
+#[derive(Debug)]
+pub struct Stats {
+    pub statements: u64,
+    pub calls: u64,
+    /// Calls by the name of the function, native function or class called.
+    pub calls_by_function: HashMap<String, u64>,
+    // the allocation totals of the thread when counting started
+    start: (u64, u64),
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            statements: 0,
+            calls: 0,
+            calls_by_function: HashMap::new(),
+            start: gc::allocation_totals(),
+        }
+    }
+
+    /// Objects allocated since counting started, on this thread.
+    pub fn objects_allocated(&self) -> u64 {
+        gc::allocation_totals().0 - self.start.0
+    }
+
+    /// Bytes allocated for strings, elements and fields since counting
+    /// started, on this thread.
+    pub fn bytes_allocated(&self) -> u64 {
+        gc::allocation_totals().1 - self.start.1
+    }
+}
+
+impl InterpreterHook for Stats {
+    fn on_statement(&mut self, _statement: &Stmt, _environment: &EnvRef) {
+        self.statements += 1;
+    }
+
+    fn on_call(&mut self, callee: &Value, _arguments: &[Value], _span: Span) {
+        self.calls += 1;
+        let name = match callee {
+            Value::Function(function) => function.name().to_string(),
+            Value::Native(native) => native.name.clone(),
+            Value::Class(class) => class.name.clone(),
+            other => other.to_string(),
+        };
+        *self.calls_by_function.entry(name).or_insert(0) += 1;
+    }
+}
+
+/// A report of the counts, with the functions called the most first.
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "statements executed: {}", self.statements)?;
+        writeln!(f, "calls: {}", self.calls)?;
+        writeln!(f, "objects allocated: {}", self.objects_allocated())?;
+        write!(f, "bytes allocated: {}", self.bytes_allocated())?;
+
+        let mut functions = self.calls_by_function.iter().collect::<Vec<_>>();
+        functions.sort_by(|(a, a_calls), (b, b_calls)| b_calls.cmp(a_calls).then(a.cmp(b)));
+        if !functions.is_empty() {
+            write!(f, "\ncalls by function:")?;
+        }
+        let width = functions.iter().map(|(name, _)| name.len()).max();
+        for (name, calls) in functions {
+            write!(
+                f,
+                "\n  {:width$}  {}",
+                name,
+                calls,
+                width = width.unwrap_or(0)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::assert;
+use crate::channel::{self, Channel};
+use crate::class::LoxInstance;
+use crate::format;
+use crate::function::NativeFunction;
+use crate::gc;
+use crate::interpreter::{Interpreter, RuntimeError, RuntimeErrorKind};
+use crate::json;
+use crate::list;
+use crate::math;
+use crate::number;
+use crate::path;
+use crate::process;
+use crate::random;
+#[cfg(feature = "regex")]
+use crate::regexp;
+use crate::string;
+use crate::time;
+use crate::value::{self, Value};
+use crate::weak;
+
+// the parts of the standard library written in Lox itself
+const PRELUDE: &str = r#"
+class Error {
+  init(message) {
+    this.message = message;
+  }
+}
+
+class OutOfMemoryError < Error {}
+
+class AssertionError < Error {}
+
+class JsonError < Error {}
+"#;
+
+/// Register the natives every interpreter starts with.
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.define_native("Channel", 0, |_| Ok(Value::Channel(Channel::new())));
+    interpreter.define_native("WeakRef", 1, |arguments| weak::new(&arguments[0]));
+    let clock = NativeFunction::new("clock", 0, |_| clock()).nondeterministic();
+    interpreter.define_global("clock", Value::Native(Rc::new(clock)));
+    let clear_timeout =
+        NativeFunction::with_callbacks("clearTimeout", 1, |interpreter, arguments, span| {
+            match &arguments[0] {
+                Value::Integer(id) => Ok(Value::Bool(interpreter.clear_timeout(*id))),
+                other => Err(RuntimeError::new(
+                    format!("Expected a timeout id but got {}.", other.type_name()),
+                    span,
+                )),
+            }
+        });
+    interpreter.define_global("clearTimeout", Value::Native(Rc::new(clear_timeout)));
+    let mut exit = NativeFunction::new("exit", 1, |arguments| {
+        let code = match arguments.first() {
+            None => 0,
+            Some(code) => exit_code(code)?,
+        };
+        Err(RuntimeError::msg(format!("Exited with code {}.", code))
+            .with_kind(RuntimeErrorKind::Exit(code)))
+    });
+    // exiting with 0 by default
+    exit.arity.min = 0;
+    interpreter.define_global("exit", Value::Native(Rc::new(exit)));
+    interpreter.define_native("fields", 1, |arguments| fields(&arguments[0]));
+    let format = NativeFunction::with_callbacks("format", 1, format::format).variadic();
+    interpreter.define_global("format", Value::Native(Rc::new(format)));
+    interpreter.define_native("getField", 2, |arguments| {
+        let name = field_name(&arguments[1])?;
+        let value = instance(&arguments[0])?.borrow().fields.get(name).cloned();
+        value.ok_or_else(|| RuntimeError::msg(format!("Undefined property '{}'.", name)))
+    });
+    interpreter.define_native("hasField", 2, |arguments| {
+        let name = field_name(&arguments[1])?;
+        let found = instance(&arguments[0])?.borrow().fields.contains_key(name);
+        Ok(Value::Bool(found))
+    });
+    interpreter.define_native("identical", 2, |arguments| {
+        Ok(Value::Bool(arguments[0].is_identical(&arguments[1])))
+    });
+    let mut input = NativeFunction::with_callbacks("input", 1, |interpreter, arguments, span| {
+        let prompt = match arguments.first() {
+            Some(prompt) => interpreter.stringify(prompt.clone(), span)?,
+            None => String::new(),
+        };
+        interpreter.read_line(&prompt, span)
+    });
+    // the prompt is optional
+    input.arity.min = 0;
+    interpreter.define_global("input", Value::Native(Rc::new(input)));
+    interpreter.define_native("isInstance", 2, |arguments| {
+        is_instance(&arguments[0], &arguments[1])
+    });
+    interpreter.define_native("len", 1, |arguments| len(&arguments[0]));
+    interpreter.define_native("memoryUsed", 0, |_| {
+        Ok(Value::Integer(gc::memory_used() as i64))
+    });
+    interpreter.define_native("setField", 3, |arguments| {
+        let name = field_name(&arguments[1])?;
+        let value = arguments[2].clone();
+        instance(&arguments[0])?
+            .borrow_mut()
+            .fields
+            .insert(name.to_string(), value.clone());
+        Ok(value)
+    });
+    let set_timeout =
+        NativeFunction::with_callbacks("setTimeout", 2, |interpreter, arguments, span| {
+            let duration = milliseconds(&arguments[1]).map_err(|error| error.at(span))?;
+            let id = interpreter.set_timeout(arguments[0].clone(), duration, span);
+            Ok(Value::Integer(id))
+        });
+    interpreter.define_global("setTimeout", Value::Native(Rc::new(set_timeout)));
+    let sleep = NativeFunction::with_callbacks("sleep", 1, |interpreter, arguments, span| {
+        let duration = milliseconds(&arguments[0]).map_err(|error| error.at(span))?;
+        interpreter.sleep(duration, span)?;
+        Ok(Value::Nil)
+    });
+    interpreter.define_global("sleep", Value::Native(Rc::new(sleep)));
+    let sleep_async =
+        NativeFunction::with_callbacks("sleepAsync", 1, |interpreter, arguments, span| {
+            let duration = milliseconds(&arguments[0]).map_err(|error| error.at(span))?;
+            Ok(interpreter.sleep_async(duration, span))
+        });
+    interpreter.define_global("sleepAsync", Value::Native(Rc::new(sleep_async)));
+    let spawn =
+        NativeFunction::with_callbacks("spawn", 1, |interpreter, arguments, span| match &arguments
+            [0]
+        {
+            Value::Function(function) => channel::spawn(function, interpreter, span),
+            other => Err(RuntimeError::new(
+                format!("Expected a function but got {}.", other.type_name()),
+                span,
+            )),
+        });
+    interpreter.define_global("spawn", Value::Native(Rc::new(spawn)));
+    interpreter.define_native("type", 1, |arguments| Ok(type_of(&arguments[0])));
+    assert::register(interpreter);
+    json::register(interpreter);
+    math::register(interpreter);
+    number::register(interpreter);
+    path::register(interpreter);
+    process::register(interpreter);
+    random::register(interpreter);
+    #[cfg(feature = "regex")]
+    regexp::register(interpreter);
+    string::register(interpreter);
+    time::register(interpreter);
+    interpreter
+        .run(PRELUDE)
+        .expect("the prelude should run without errors");
+}
+
+// seconds since the epoch, with sub-second precision
+fn clock() -> Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| RuntimeError::msg("System clock is set before the epoch."))?;
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+fn milliseconds(value: &Value) -> Result<Duration, RuntimeError> {
+    match value.as_number() {
+        Some(ms) if ms.is_finite() && ms >= 0.0 => Ok(Duration::from_secs_f64(ms / 1000.0)),
+        _ => Err(RuntimeError::msg(format!(
+            "Expected a number of milliseconds but got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+fn exit_code(value: &Value) -> Result<i32, RuntimeError> {
+    let code = match value {
+        Value::Integer(code) => Some(*code),
+        Value::Number(code) => value::exact_integer(*code),
+        _ => None,
+    };
+    let code = code.ok_or_else(|| {
+        RuntimeError::msg(format!(
+            "Expected an exit code but got {}.",
+            value.type_name()
+        ))
+    })?;
+    // the system keeps the low byte of anything else
+    if !(0..=255).contains(&code) {
+        return Err(RuntimeError::msg(format!(
+            "Exit code must be between 0 and 255 but got {}.",
+            code
+        )));
+    }
+    Ok(code as i32)
+}
+
+fn len(value: &Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::List(list) => Ok(Value::Integer(list.borrow().len() as i64)),
+        Value::Map(map) => Ok(Value::Integer(map.borrow().len() as i64)),
+        Value::String(string) => Ok(Value::Integer(string::len(string) as i64)),
+        Value::Range(range) => Ok(Value::Integer(range.len() as i64)),
+        _ => Err(RuntimeError::msg(format!(
+            "Expected a list, map, string or range but got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+// the names of the fields of an instance, in the order they were first set,
+// leaving methods and getters out
+fn fields(value: &Value) -> Result<Value, RuntimeError> {
+    let names = instance(value)?
+        .borrow()
+        .fields
+        .keys()
+        .map(|name| Value::from(name.as_str()))
+        .collect();
+    Ok(list::new(names))
+}
+
+fn instance(value: &Value) -> Result<&Rc<RefCell<LoxInstance>>, RuntimeError> {
+    match value {
+        Value::Instance(instance) => Ok(instance),
+        _ => Err(RuntimeError::msg(format!(
+            "Expected an instance but got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+fn field_name(value: &Value) -> Result<&str, RuntimeError> {
+    match value {
+        Value::String(name) => Ok(name),
+        _ => Err(RuntimeError::msg(format!(
+            "Expected a field name but got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+// the class of instances, the name of the type of anything else
+fn type_of(value: &Value) -> Value {
+    match value {
+        Value::Instance(instance) => Value::Class(instance.borrow().class.clone()),
+        _ => Value::from(value.type_name()),
+    }
+}
+
+// whether `value` is an instance of `class` or of one of its subclasses
+fn is_instance(value: &Value, class: &Value) -> Result<Value, RuntimeError> {
+    let class = match class {
+        Value::Class(class) => class,
+        _ => {
+            return Err(RuntimeError::msg(format!(
+                "Expected a class but got {}.",
+                class.type_name()
+            )))
+        }
+    };
+
+    let mut current = match value {
+        Value::Instance(instance) => Some(instance.borrow().class.clone()),
+        _ => None,
+    };
+    while let Some(ancestor) = current {
+        if Rc::ptr_eq(&ancestor, class) {
+            return Ok(Value::Bool(true));
+        }
+        current = ancestor.superclass.clone();
+    }
+    Ok(Value::Bool(false))
+}
@@ -0,0 +1,185 @@
+//! Indexing and slicing strings, and the string natives.
+//!
+//! Strings are UTF-8, but indices count characters (Unicode scalar values)
+//! rather than bytes, so `"héllo"[1]` is `"é"` and `len("héllo")` is `5`.
+//! Finding a character walks the string from its start.
+//!
+//! The natives follow the same rule: `indexOf` and `charAt` count
+//! characters, and `split` with an empty separator splits a string into
+//! them. Characters are compared as they are, without normalizing, so an
+//! `é` written as `e` and a combining accent is two characters that don't
+//! match the single one. `upper` and `lower` map case the Unicode way,
+//! which may change the length, as `upper("ß")` is `"SS"`.
+
+use std::rc::Rc;
+
+use crate::function::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::list;
+use crate::value::Value;
+
+type Native = fn(&[Value]) -> Result<Value, RuntimeError>;
+
+// by name, with their arities, all but those taking or giving lists, which
+// the VMs have too
+const NATIVES: &[(&str, usize, Native)] = &[
+    ("charAt", 2, char_at),
+    ("contains", 2, contains),
+    ("endsWith", 2, ends_with),
+    ("indexOf", 2, index_of),
+    ("lower", 1, lower),
+    ("replace", 3, replace),
+    ("startsWith", 2, starts_with),
+    ("trim", 1, trim),
+    ("upper", 1, upper),
+];
+
+/// Register the string natives with an interpreter. `len` is registered
+/// with the others, as it takes lists and maps too.
+pub fn register(interpreter: &mut Interpreter) {
+    for &(name, arity, native) in NATIVES {
+        interpreter.define_native(name, arity, native);
+    }
+    interpreter.define_native("join", 2, |arguments| {
+        join(&arguments[0], string(&arguments[1])?)
+    });
+    interpreter.define_native("split", 2, |arguments| {
+        let (string, separator) = (string(&arguments[0])?, string(&arguments[1])?);
+        Ok(list::new(split(string, separator)))
+    });
+}
+
+pub(crate) fn char_at(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    index(string(&arguments[0])?, &arguments[1])
+}
+
+pub(crate) fn contains(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let (string, part) = (string(&arguments[0])?, string(&arguments[1])?);
+    Ok(Value::Bool(string.contains(&**part)))
+}
+
+pub(crate) fn ends_with(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let (string, suffix) = (string(&arguments[0])?, string(&arguments[1])?);
+    Ok(Value::Bool(string.ends_with(&**suffix)))
+}
+
+// the index of the character the part starts at, or -1 when it isn't there
+pub(crate) fn index_of(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let (string, part) = (string(&arguments[0])?, string(&arguments[1])?);
+    Ok(Value::Integer(match string.find(&**part) {
+        Some(byte) => len(&string[..byte]) as i64,
+        None => -1,
+    }))
+}
+
+pub(crate) fn lower(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(string(&arguments[0])?.to_lowercase()))
+}
+
+pub(crate) fn replace(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let (string, from, to) = (
+        string(&arguments[0])?,
+        string(&arguments[1])?,
+        string(&arguments[2])?,
+    );
+    Ok(Value::from(string.replace(&**from, to)))
+}
+
+pub(crate) fn starts_with(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    let (string, prefix) = (string(&arguments[0])?, string(&arguments[1])?);
+    Ok(Value::Bool(string.starts_with(&**prefix)))
+}
+
+pub(crate) fn trim(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(string(&arguments[0])?.trim()))
+}
+
+pub(crate) fn upper(arguments: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(string(&arguments[0])?.to_uppercase()))
+}
+
+/// How many characters `string` has.
+pub fn len(string: &str) -> usize {
+    string.chars().count()
+}
+
+/// The character of `string` at `index`, as a string of its own.
+pub fn index(string: &str, index: &Value) -> Result<Value, RuntimeError> {
+    let index = list::position(index, len(string), "String")?;
+    let character = string.chars().nth(index).unwrap_or_default();
+    Ok(Value::from(character.to_string()))
+}
+
+/// The characters of `string` from `start` up to, but not including, `end`.
+pub fn slice(
+    string: &str,
+    start: Option<&Value>,
+    end: Option<&Value>,
+) -> Result<Value, RuntimeError> {
+    let (start, end) = list::bounds(start, end, len(string), "String")?;
+    let slice = string
+        .chars()
+        .skip(start)
+        .take(end - start)
+        .collect::<String>();
+    Ok(Value::from(slice))
+}
+
+/// Look up one of the methods every string has, bound to `string`.
+pub fn get_method(string: &Rc<str>, name: &str) -> Option<Value> {
+    let string = string.clone();
+    let method = match name {
+        "chars" => NativeFunction::new(name, 0, move |_| {
+            let characters = string.chars().map(|c| Value::from(c.to_string())).collect();
+            Ok(list::new(characters))
+        }),
+        _ => return None,
+    };
+    Some(Value::Native(Rc::new(method)))
+}
+
+pub(crate) fn string(value: &Value) -> Result<&Rc<str>, RuntimeError> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(RuntimeError::msg(format!(
+            "Expected a string but got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+// an empty separator splits between characters
+fn split(string: &str, separator: &str) -> Vec<Value> {
+    if separator.is_empty() {
+        return string.chars().map(|c| Value::from(c.to_string())).collect();
+    }
+    string.split(separator).map(Value::from).collect()
+}
+
+fn join(list: &Value, separator: &str) -> Result<Value, RuntimeError> {
+    let list = match list {
+        Value::List(list) => list.borrow(),
+        _ => {
+            return Err(RuntimeError::msg(format!(
+                "Expected a list but got {}.",
+                list.type_name()
+            )))
+        }
+    };
+    let mut joined = String::new();
+    for (i, element) in list.iter().enumerate() {
+        if i > 0 {
+            joined.push_str(separator);
+        }
+        match element {
+            Value::String(string) => joined.push_str(string),
+            _ => {
+                return Err(RuntimeError::msg(format!(
+                    "Can only join strings but got {}.",
+                    element.type_name()
+                )))
+            }
+        }
+    }
+    Ok(Value::from(joined))
+}
@@ -0,0 +1,168 @@
+//! The time natives next to `clock`: `now`, `elapsed` and `formatTime`.
+//!
+//! `now()` is the wall clock in milliseconds since the Unix epoch, as an
+//! integer, for timestamps. `elapsed()` is the seconds since the
+//! interpreter started on a monotonic clock, which never goes back when the
+//! system time is changed, for measuring. Both are recorded and replayed
+//! like `clock`.
+//!
+//! `formatTime(template, timestamp)` formats a timestamp of `now` in UTC,
+//! there being no time zone database to know any other, with these
+//! specifiers:
+//!
+//! - `%Y` the year, `%m` the month, `%d` the day and `%j` the day of the
+//!   year, all but the year padded with zeros;
+//! - `%H`, `%M` and `%S` the hours, minutes and seconds, and `%L` the
+//!   milliseconds;
+//! - `%a` and `%A` the day of the week, `%b` and `%B` the month, by their
+//!   English names, short and long;
+//! - `%%` a `%`.
+
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::function::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::value::{self, Value};
+
+const DAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const MS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Register the time natives with an interpreter.
+pub fn register(interpreter: &mut Interpreter) {
+    let start = Instant::now();
+    let elapsed = NativeFunction::new("elapsed", 0, move |_| {
+        Ok(Value::Number(start.elapsed().as_secs_f64()))
+    });
+    interpreter.define_global(
+        "elapsed",
+        Value::Native(Rc::new(elapsed.nondeterministic())),
+    );
+    interpreter.define_native("formatTime", 2, |arguments| {
+        let template = match &arguments[0] {
+            Value::String(template) => template,
+            other => {
+                return Err(RuntimeError::msg(format!(
+                    "Expected a template but got {}.",
+                    other.type_name()
+                )))
+            }
+        };
+        Ok(Value::from(format_time(
+            template,
+            timestamp(&arguments[1])?,
+        )?))
+    });
+    let now = NativeFunction::new("now", 0, |_| now());
+    interpreter.define_global("now", Value::Native(Rc::new(now.nondeterministic())));
+}
+
+fn now() -> Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| RuntimeError::msg("System clock is set before the epoch."))?;
+    Ok(Value::Integer(elapsed.as_millis() as i64))
+}
+
+// milliseconds since the epoch, dropping any fraction of one
+fn timestamp(value: &Value) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Integer(ms) => Ok(*ms),
+        Value::Number(ms) => value::exact_integer(ms.floor())
+            .ok_or_else(|| RuntimeError::msg("Timestamp is out of range.")),
+        other => Err(RuntimeError::msg(format!(
+            "Expected a timestamp but got {}.",
+            other.type_name()
+        ))),
+    }
+}
+
+// a timestamp in milliseconds since the epoch, in UTC
+fn format_time(template: &str, timestamp: i64) -> Result<String, RuntimeError> {
+    let days = timestamp.div_euclid(MS_PER_DAY);
+    let ms = timestamp.rem_euclid(MS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    // the epoch was on a Thursday
+    let weekday = (days + 4).rem_euclid(7) as usize;
+
+    let mut formatted = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            formatted.push(c);
+            continue;
+        }
+        // writing to a string can't fail
+        let _ = match chars.next() {
+            Some('Y') => write!(formatted, "{:04}", year),
+            Some('m') => write!(formatted, "{:02}", month),
+            Some('d') => write!(formatted, "{:02}", day),
+            Some('j') => write!(formatted, "{:03}", day_of_year(year, month, day)),
+            Some('H') => write!(formatted, "{:02}", ms / 3_600_000),
+            Some('M') => write!(formatted, "{:02}", ms / 60_000 % 60),
+            Some('S') => write!(formatted, "{:02}", ms / 1000 % 60),
+            Some('L') => write!(formatted, "{:03}", ms % 1000),
+            Some('a') => write!(formatted, "{}", &DAYS[weekday][..3]),
+            Some('A') => write!(formatted, "{}", DAYS[weekday]),
+            Some('b') => write!(formatted, "{}", &MONTHS[month as usize - 1][..3]),
+            Some('B') => write!(formatted, "{}", MONTHS[month as usize - 1]),
+            Some('%') => write!(formatted, "%"),
+            Some(other) => {
+                return Err(RuntimeError::msg(format!(
+                    "Unknown time specifier '%{}'.",
+                    other
+                )))
+            }
+            None => return Err(RuntimeError::msg("Time template ends in '%'.")),
+        };
+    }
+    Ok(formatted)
+}
+
+// the date of a day counted from the epoch, in the proleptic Gregorian
+// calendar, see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // months counted from March, so February and its leap day come last
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn day_of_year(year: i64, month: i64, day: i64) -> i64 {
+    const BEFORE: [i64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    BEFORE[month as usize - 1] + day + i64::from(leap && month > 2)
+}
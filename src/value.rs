@@ -0,0 +1,292 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::channel::{Channel, ThreadRef};
+use crate::class::{LoxClass, LoxInstance};
+use crate::function::{LoxFunction, NativeFunction};
+use crate::gc;
+use crate::generator::GeneratorRef;
+use crate::list::ListRef;
+use crate::map::MapRef;
+use crate::promise::PromiseRef;
+use crate::range::Range;
+use crate::weak::WeakRef;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(ListRef),
+    Map(MapRef),
+    Range(Range),
+    Generator(GeneratorRef),
+    Promise(PromiseRef),
+    Channel(Channel),
+    Thread(ThreadRef),
+    WeakRef(Rc<WeakRef>),
+}
+
+impl Value {
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// Whether both are the same object, unlike `==` which compares lists
+    /// and maps element by element. Values without an identity, like
+    /// numbers and strings, are identical when they're equal.
+    pub fn is_identical(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(left), Value::List(right)) => Rc::ptr_eq(left, right),
+            (Value::Map(left), Value::Map(right)) => Rc::ptr_eq(left, right),
+            (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+            _ => self == other,
+        }
+    }
+
+    /// The value of a number as a float, whichever kind it is.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Integer(integer) => Some(*integer as f64),
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Integer(_) | Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Range(_) => "range",
+            Value::Generator(_) => "generator",
+            Value::Promise(_) => "promise",
+            Value::Channel(_) => "channel",
+            Value::Thread(_) => "thread",
+            Value::WeakRef(_) => "weakref",
+        }
+    }
+}
+
+// values of different types are never equal, there is no implicit coercion,
+// but integers and floats are both numbers and equal when they're the same
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(left), Value::Bool(right)) => left == right,
+            (Value::Integer(left), Value::Integer(right)) => left == right,
+            (Value::Number(left), Value::Number(right)) => left == right,
+            (Value::Integer(integer), Value::Number(number))
+            | (Value::Number(number), Value::Integer(integer)) => {
+                exact_integer(*number) == Some(*integer)
+            }
+            (Value::String(left), Value::String(right)) => left == right,
+            (Value::Function(left), Value::Function(right)) => left.same(right),
+            (Value::Native(left), Value::Native(right)) => Rc::ptr_eq(left, right),
+            (Value::Class(left), Value::Class(right)) => Rc::ptr_eq(left, right),
+            (Value::Instance(left), Value::Instance(right)) => Rc::ptr_eq(left, right),
+            // lists with the same elements are equal
+            (Value::List(left), Value::List(right)) => {
+                Rc::ptr_eq(left, right)
+                    || compare_nested(
+                        Rc::as_ptr(left) as *const (),
+                        Rc::as_ptr(right) as *const (),
+                        || *left.borrow() == *right.borrow(),
+                    )
+            }
+            (Value::Map(left), Value::Map(right)) => {
+                Rc::ptr_eq(left, right)
+                    || compare_nested(
+                        Rc::as_ptr(left) as *const (),
+                        Rc::as_ptr(right) as *const (),
+                        || *left.borrow() == *right.borrow(),
+                    )
+            }
+            (Value::Range(left), Value::Range(right)) => left == right,
+            (Value::Generator(left), Value::Generator(right)) => Rc::ptr_eq(left, right),
+            (Value::Promise(left), Value::Promise(right)) => Rc::ptr_eq(left, right),
+            (Value::Channel(left), Value::Channel(right)) => left.same(right),
+            (Value::Thread(left), Value::Thread(right)) => Rc::ptr_eq(left, right),
+            (Value::WeakRef(left), Value::WeakRef(right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(boolean) => write!(f, "{}", boolean),
+            Value::Integer(integer) => write!(f, "{}", integer),
+            Value::Number(number) => write!(f, "{}", format_number(*number)),
+            Value::String(string) => write!(f, "{}", string),
+            Value::Function(function) => write!(f, "<fn {}>", function.name()),
+            Value::Native(_) => write!(f, "<native fn>"),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Value::List(list) => fmt_nested(Rc::as_ptr(list) as *const (), "[...]", f, |f| {
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }),
+            Value::Map(map) => fmt_nested(Rc::as_ptr(map) as *const (), "{...}", f, |f| {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }),
+            Value::Range(range) => write!(f, "{}", range),
+            Value::Generator(generator) => {
+                write!(
+                    f,
+                    "<generator {}>",
+                    generator.borrow().declaration.name.name
+                )
+            }
+            Value::Promise(_) => write!(f, "<promise>"),
+            Value::Channel(_) => write!(f, "<channel>"),
+            Value::Thread(thread) => write!(f, "<thread {}>", thread.borrow().name),
+            Value::WeakRef(_) => write!(f, "<weakref>"),
+        }
+    }
+}
+
+thread_local! {
+    // collections being displayed, so one holding itself doesn't recurse
+    // forever
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+    // pairs of collections being compared, likewise
+    static COMPARING: RefCell<Vec<(*const (), *const ())>> = const { RefCell::new(Vec::new()) };
+}
+
+// compare two collections with `contents`, taking them to be equal when
+// they're already being compared further up, so cycles end: a difference
+// anywhere else still makes them unequal
+fn compare_nested<F>(left: *const (), right: *const (), contents: F) -> bool
+where
+    F: FnOnce() -> bool,
+{
+    let pair = (left, right);
+    if COMPARING.with(|comparing| comparing.borrow().contains(&pair)) {
+        return true;
+    }
+
+    COMPARING.with(|comparing| comparing.borrow_mut().push(pair));
+    let equal = contents();
+    COMPARING.with(|comparing| comparing.borrow_mut().pop());
+    equal
+}
+
+// display a collection with `contents`, or `placeholder` inside of itself
+fn fmt_nested<F>(
+    address: *const (),
+    placeholder: &str,
+    f: &mut fmt::Formatter<'_>,
+    contents: F,
+) -> fmt::Result
+where
+    F: FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result,
+{
+    if DISPLAYING.with(|displaying| displaying.borrow().contains(&address)) {
+        return write!(f, "{}", placeholder);
+    }
+
+    DISPLAYING.with(|displaying| displaying.borrow_mut().push(address));
+    let result = contents(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+    result
+}
+
+/// Format a number like jlox does, which is Java's `Double.toString` without
+/// a trailing `.0`: `5`, `2.5`, `-0`, and scientific notation outside of
+/// `[0.001, 10000000)`, like `1.0E7` or `1.5E-4`.
+pub fn format_number(number: f64) -> String {
+    if number.is_nan() {
+        return "NaN".to_string();
+    }
+    if number.is_infinite() {
+        let sign = if number.is_sign_negative() { "-" } else { "" };
+        return format!("{}Infinity", sign);
+    }
+
+    let magnitude = number.abs();
+    if number == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        // the shortest representation, already without `.0`
+        return format!("{}", number);
+    }
+
+    // Java keeps a digit after the point, so `1e7` is `1.0E7`
+    let scientific = format!("{:e}", number);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    if mantissa.contains('.') {
+        format!("{}E{}", mantissa, exponent)
+    } else {
+        format!("{}.0E{}", mantissa, exponent)
+    }
+}
+
+/// The integer `number` is exactly, if any.
+pub fn exact_integer(number: f64) -> Option<i64> {
+    // i64::MAX rounds up to 2^63 as a float, which is out of range
+    if number.fract() != 0.0 || !(-(2f64.powi(63))..2f64.powi(63)).contains(&number) {
+        return None;
+    }
+    Some(number as i64)
+}
+
+impl From<bool> for Value {
+    fn from(boolean: bool) -> Self {
+        Value::Bool(boolean)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(integer: i64) -> Self {
+        Value::Integer(integer)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(number: f64) -> Self {
+        Value::Number(number)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(string: &str) -> Self {
+        gc::allocate(string.len());
+        Value::String(string.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(string: String) -> Self {
+        gc::allocate(string.len());
+        Value::String(string.into())
+    }
+}
@@ -0,0 +1,462 @@
+//! Checks on chunks that don't come straight from the compiler, like those
+//! of `.loxc` files, before the VM runs them.
+//!
+//! The VM trusts its bytecode: it indexes constants, locals and inline
+//! caches with operands as they are, and pops what instructions expect to
+//! find on the stack, so a broken or crafted file would make it panic.
+//! `verify` follows every path through the code of each function the way
+//! the VM would run it, and checks that:
+//!
+//! - every byte belongs to an instruction with all its operands, and jumps
+//!   land on one;
+//! - operands name constants of the right kind, inline caches, upvalues of
+//!   the function and locals on the stack;
+//! - the stack holds what every instruction pops, and is as high wherever
+//!   paths meet;
+//! - locals captured by closures stay on the stack until they are closed;
+//! - no path runs past the end of the code.
+//!
+//! What values are is only known when running, so the instructions
+//! expecting classes or closures on the stack still check them.
+
+use std::collections::BTreeSet;
+
+use anyhow::bail;
+
+use crate::chunk::{Chunk, Constant, OpCode};
+
+/// Fail unless the VM can run `chunk` as a script, and the functions among
+/// its constants as functions.
+pub fn verify(chunk: &Chunk) -> anyhow::Result<()> {
+    // the script has no slot of its own, functions have the callee's
+    verify_function("script", chunk, 0, 0)
+}
+
+fn verify_function(
+    name: &str,
+    chunk: &Chunk,
+    height: usize,
+    upvalues: usize,
+) -> anyhow::Result<()> {
+    let verifier = Verifier {
+        name,
+        chunk,
+        upvalues,
+    };
+    verifier.run(height)?;
+    for constant in &chunk.constants {
+        if let Constant::Function(function) = constant {
+            verify_function(
+                &function.name,
+                &function.chunk,
+                function.arity + 1,
+                function.upvalues,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+struct Verifier<'a> {
+    name: &'a str,
+    chunk: &'a Chunk,
+    // how many upvalues the closures of the function have
+    upvalues: usize,
+}
+
+// an instruction with its operands
+struct Instruction<'a> {
+    op: OpCode,
+    operands: &'a [u8],
+    next: usize,
+}
+
+impl Instruction<'_> {
+    fn byte(&self, at: usize) -> usize {
+        self.operands[at] as usize
+    }
+
+    fn u16(&self, at: usize) -> usize {
+        u16::from_be_bytes([self.operands[at], self.operands[at + 1]]) as usize
+    }
+
+    fn u24(&self) -> usize {
+        u32::from_be_bytes([0, self.operands[0], self.operands[1], self.operands[2]]) as usize
+    }
+}
+
+impl Verifier<'_> {
+    fn run(&self, height: usize) -> anyhow::Result<()> {
+        let code = &self.chunk.code;
+        if self.chunk.lines.len() != code.len() {
+            bail!(
+                "Invalid bytecode in {}: its lines don't match its code.",
+                self.name
+            );
+        }
+        // every inline cache belongs to an instruction
+        if self.chunk.caches > code.len() {
+            bail!(
+                "Invalid bytecode in {}: it has more caches than code.",
+                self.name
+            );
+        }
+
+        let mut starts = vec![false; code.len()];
+        let mut offset = 0;
+        while offset < code.len() {
+            starts[offset] = true;
+            offset = self.decode(offset)?.next;
+        }
+
+        // the height of the stack before each instruction reached so far,
+        // and the slots closures may have captured on the way there
+        let mut states: Vec<Option<(usize, BTreeSet<usize>)>> = vec![None; code.len()];
+        let mut pending = vec![(0, height, BTreeSet::new())];
+        while let Some((offset, height, mut captured)) = pending.pop() {
+            if offset >= code.len() {
+                bail!(
+                    "Invalid bytecode in {}: it runs past the end of its code.",
+                    self.name
+                );
+            }
+            match &mut states[offset] {
+                Some((known, _)) if *known != height => {
+                    return Err(self.error(offset, "the stack isn't as high on every path"))
+                }
+                // what some path captured must be closed on all of them
+                Some((_, known)) if captured.is_subset(known) => continue,
+                Some((_, known)) => {
+                    known.extend(captured);
+                    captured = known.clone();
+                }
+                None => states[offset] = Some((height, captured.clone())),
+            }
+            let instruction = self.decode(offset)?;
+            let (pops, pushes) = self.check(offset, &instruction, height)?;
+            if height < pops {
+                return Err(self.error(offset, "it pops more than the stack holds"));
+            }
+            let after = height - pops + pushes;
+            match instruction.op {
+                OpCode::Closure | OpCode::ClosureLong => {
+                    captured.extend(self.captures(&instruction));
+                }
+                OpCode::CloseUpvalue => {
+                    captured.remove(&after);
+                }
+                // the frame closes all of them
+                OpCode::Return => {}
+                _ if captured.range(after..).next().is_some() => {
+                    return Err(self.error(offset, "it pops a local a closure captured"));
+                }
+                _ => {}
+            }
+            let next = instruction.next;
+            match instruction.op {
+                OpCode::Return => {}
+                OpCode::Jump => pending.push((
+                    self.target(offset, &starts, next + instruction.u16(0))?,
+                    after,
+                    captured,
+                )),
+                OpCode::JumpIfFalse => {
+                    let target = self.target(offset, &starts, next + instruction.u16(0))?;
+                    pending.push((target, after, captured.clone()));
+                    pending.push((next, after, captured));
+                }
+                OpCode::Loop => {
+                    let target = next.checked_sub(instruction.u16(0)).unwrap_or(usize::MAX);
+                    pending.push((self.target(offset, &starts, target)?, after, captured));
+                }
+                _ => pending.push((next, after, captured)),
+            }
+        }
+        Ok(())
+    }
+
+    // the instruction at `offset`, failing when its operands are cut off
+    fn decode(&self, offset: usize) -> anyhow::Result<Instruction<'_>> {
+        let code = &self.chunk.code;
+        let byte = code[offset];
+        let op = match OpCode::from_byte(byte) {
+            Some(op) => op,
+            None => return Err(self.error(offset, &format!("unknown opcode {}", byte))),
+        };
+        let size = match op {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::SetProperty
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::SetGlobalPop
+            | OpCode::AddConstant
+            | OpCode::SubtractConstant
+            | OpCode::LessConstant
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call
+            | OpCode::SetLocalPop
+            | OpCode::Closure => 1,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop | OpCode::GetLocals => 2,
+            OpCode::ConstantLong
+            | OpCode::GetGlobalLong
+            | OpCode::DefineGlobalLong
+            | OpCode::SetGlobalLong
+            | OpCode::GetProperty
+            | OpCode::GetSuper
+            | OpCode::ClosureLong => 3,
+            OpCode::Invoke | OpCode::SuperInvoke => 4,
+            _ => 0,
+        };
+        let truncated = || self.error(offset, "its operands are cut off");
+        let mut next = offset + 1 + size;
+        let mut operands = code.get(offset + 1..next).ok_or_else(truncated)?;
+        // the upvalues of a closure follow, two bytes each
+        if let OpCode::Closure | OpCode::ClosureLong = op {
+            let instruction = Instruction { op, operands, next };
+            let index = match op {
+                OpCode::Closure => instruction.byte(0),
+                _ => instruction.u24(),
+            };
+            let upvalues = match self.chunk.constants.get(index) {
+                Some(Constant::Function(function)) => function.upvalues,
+                _ => return Err(self.error(offset, "closures are made of functions")),
+            };
+            next = upvalues
+                .checked_mul(2)
+                .and_then(|size| next.checked_add(size))
+                .filter(|&next| next <= code.len())
+                .ok_or_else(truncated)?;
+            operands = &code[offset + 1..next];
+        }
+        Ok(Instruction { op, operands, next })
+    }
+
+    // check the operands of an instruction run with the stack this high,
+    // returning how many values it pops and pushes
+    fn check(
+        &self,
+        offset: usize,
+        instruction: &Instruction<'_>,
+        height: usize,
+    ) -> anyhow::Result<(usize, usize)> {
+        let local = |slot: usize, height: usize| match slot < height {
+            true => Ok(()),
+            false => Err(self.error(offset, "it uses a local that isn't on the stack")),
+        };
+        Ok(match instruction.op {
+            OpCode::Constant | OpCode::ConstantLong => {
+                let index = match instruction.op {
+                    OpCode::Constant => instruction.byte(0),
+                    _ => instruction.u24(),
+                };
+                self.value(offset, index)?;
+                (0, 1)
+            }
+            OpCode::Nil | OpCode::True | OpCode::False => (0, 1),
+            OpCode::Pop | OpCode::Print => (1, 0),
+            OpCode::GetLocal => {
+                local(instruction.byte(0), height)?;
+                (0, 1)
+            }
+            OpCode::SetLocal => {
+                local(instruction.byte(0), height)?;
+                (1, 1)
+            }
+            OpCode::SetLocalPop => {
+                // the slot is stored to once the value is popped
+                local(instruction.byte(0), height.saturating_sub(1))?;
+                (1, 0)
+            }
+            OpCode::GetLocals => {
+                // the second is read once the first is pushed, which may
+                // have made it
+                local(instruction.byte(0), height)?;
+                local(instruction.byte(1), height + 1)?;
+                (0, 2)
+            }
+            OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                self.name_of(offset, instruction)?;
+                (0, 1)
+            }
+            OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                self.name_of(offset, instruction)?;
+                (1, 0)
+            }
+            OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                self.name_of(offset, instruction)?;
+                (1, 1)
+            }
+            OpCode::SetGlobalPop => {
+                self.name_of(offset, instruction)?;
+                (1, 0)
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                if instruction.byte(0) >= self.upvalues {
+                    return Err(self.error(offset, "it uses an upvalue the function doesn't have"));
+                }
+                match instruction.op {
+                    OpCode::GetUpvalue => (0, 1),
+                    _ => (1, 1),
+                }
+            }
+            OpCode::GetProperty => {
+                self.name_of(offset, instruction)?;
+                self.cache(offset, instruction.u16(1))?;
+                (1, 1)
+            }
+            OpCode::GetSuper => {
+                self.name_of(offset, instruction)?;
+                self.cache(offset, instruction.u16(1))?;
+                (2, 1)
+            }
+            OpCode::SetProperty => {
+                self.name_of(offset, instruction)?;
+                (2, 1)
+            }
+            OpCode::Class => {
+                self.name_of(offset, instruction)?;
+                (0, 1)
+            }
+            OpCode::Method => {
+                self.name_of(offset, instruction)?;
+                (2, 1)
+            }
+            OpCode::Inherit => (2, 1),
+            OpCode::Invoke | OpCode::SuperInvoke => {
+                self.name_of(offset, instruction)?;
+                self.cache(offset, instruction.u16(2))?;
+                let count = instruction.byte(1);
+                match instruction.op {
+                    // the receiver and the arguments
+                    OpCode::Invoke => (count + 1, 1),
+                    // and the superclass above them
+                    _ => (count + 2, 1),
+                }
+            }
+            OpCode::Call => (instruction.byte(0) + 1, 1),
+            OpCode::Closure | OpCode::ClosureLong => {
+                let start = match instruction.op {
+                    OpCode::Closure => 1,
+                    _ => 3,
+                };
+                for pair in instruction.operands[start..].chunks(2) {
+                    let index = pair[1] as usize;
+                    match pair[0] {
+                        1 => local(index, height)?,
+                        0 if index < self.upvalues => {}
+                        0 => {
+                            return Err(self
+                                .error(offset, "it captures an upvalue the function doesn't have"))
+                        }
+                        _ => {
+                            return Err(
+                                self.error(offset, "it captures neither a local nor an upvalue")
+                            )
+                        }
+                    }
+                }
+                (0, 1)
+            }
+            OpCode::CloseUpvalue => (1, 0),
+            OpCode::AddConstant | OpCode::SubtractConstant | OpCode::LessConstant => {
+                self.value(offset, instruction.byte(0))?;
+                (1, 1)
+            }
+            OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::FloorDivide
+            | OpCode::Modulo
+            | OpCode::Range
+            | OpCode::BitAnd
+            | OpCode::BitOr
+            | OpCode::BitXor
+            | OpCode::ShiftLeft
+            | OpCode::ShiftRight => (2, 1),
+            OpCode::Not | OpCode::Negate | OpCode::BitNot => (1, 1),
+            OpCode::Jump | OpCode::Loop => (0, 0),
+            OpCode::JumpIfFalse => (1, 1),
+            OpCode::Return => (1, 0),
+        })
+    }
+
+    // the locals a closure instruction captures, its operands being checked
+    fn captures(&self, instruction: &Instruction<'_>) -> Vec<usize> {
+        let start = match instruction.op {
+            OpCode::Closure => 1,
+            _ => 3,
+        };
+        instruction.operands[start..]
+            .chunks(2)
+            .filter(|pair| pair[0] == 1)
+            .map(|pair| pair[1] as usize)
+            .collect()
+    }
+
+    // a constant the instruction pushes as a value, which functions aren't
+    // until they're made closures
+    fn value(&self, offset: usize, index: usize) -> anyhow::Result<()> {
+        match self.chunk.constants.get(index) {
+            Some(Constant::Function(_)) => {
+                Err(self.error(offset, "it loads a function as a value"))
+            }
+            Some(_) => Ok(()),
+            None => Err(self.error(offset, "it uses a constant the chunk doesn't have")),
+        }
+    }
+
+    // the constant naming a global, a property or a class must be a string
+    fn name_of(&self, offset: usize, instruction: &Instruction<'_>) -> anyhow::Result<()> {
+        let index = match instruction.op {
+            OpCode::GetGlobalLong | OpCode::DefineGlobalLong | OpCode::SetGlobalLong => {
+                instruction.u24()
+            }
+            _ => instruction.byte(0),
+        };
+        match self.chunk.constants.get(index) {
+            Some(Constant::String(_)) => Ok(()),
+            Some(_) => Err(self.error(offset, "its name isn't a string")),
+            None => Err(self.error(offset, "it uses a constant the chunk doesn't have")),
+        }
+    }
+
+    fn cache(&self, offset: usize, index: usize) -> anyhow::Result<()> {
+        match index < self.chunk.caches {
+            true => Ok(()),
+            false => Err(self.error(offset, "it uses an inline cache the chunk doesn't have")),
+        }
+    }
+
+    // where a jump lands, which must be the start of an instruction
+    fn target(&self, offset: usize, starts: &[bool], target: usize) -> anyhow::Result<usize> {
+        match starts.get(target) {
+            Some(true) => Ok(target),
+            _ => Err(self.error(
+                offset,
+                "it jumps outside of the code or into an instruction",
+            )),
+        }
+    }
+
+    fn error(&self, offset: usize, problem: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Invalid bytecode in {} at {:04}: {}.",
+            self.name,
+            offset,
+            problem
+        )
+    }
+}
@@ -0,0 +1,1409 @@
+//! A stack machine running the bytecode of the compiler, the second half of
+//! the bytecode backend.
+//!
+//! It runs what the compiler can compile, and behaves like the tree-walking
+//! interpreter there: same arithmetic, same output, same error messages.
+//!
+//! Every call pushes a frame whose locals start at the slot of the function
+//! being called. Closures reach the locals they capture through upvalues:
+//! open ones point to a slot of the stack, and are closed, moving the value
+//! into the upvalue, when the local goes out of scope, as in clox.
+//!
+//! Frames are as in clox too: all of them are allocated with the VM, as many
+//! as calls may nest, and calling one level deeper is a stack overflow.
+//! Pushing one copies a few words, pointing to the function instead of
+//! counting a reference to it, which took `fib(35)` from 2.2 to 1.85
+//! seconds.
+//!
+//! Instructions looking methods up have an inline cache each, remembering
+//! the class of the last instance and the method found in it: the same
+//! instruction mostly sees instances of the same class, and methods don't
+//! change once the class is declared.
+//!
+//! Most of the time of the loop goes to what instructions do rather than to
+//! dispatching them: arithmetic on two integers or two floats skips the
+//! values of the interpreter, and globals, fields and methods are found in
+//! maps hashing the addresses of their interned names. With
+//! `cargo bench --bench vm`, that took fib from 80 to 124 million
+//! instructions per second, loop from 101 to 152 and zoo from 51 to 92.
+//! Skipping the bounds checks on the stack and the code made no
+//! measurable difference, so they stay.
+//!
+//! Globals have since moved to slots: the first time an instruction names
+//! one, the function remembers its slot, so running it again skips the map.
+//! A global used before it's defined has a slot all the same, which stays
+//! empty until then. Hashing addresses was cheap already, and the globals
+//! script only went from 145 to 140 milliseconds.
+
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::mem;
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ast::BinaryOp;
+use crate::cache::Cache;
+use crate::chunk::{self, Chunk, Constant, Function, OpCode};
+use crate::compiler;
+use crate::interpreter::{
+    self, InterruptHandle, RuntimeError, RuntimeErrorKind, TraceFrame, DEFAULT_MAX_CALL_DEPTH,
+};
+use crate::lexer::Span;
+use crate::math;
+use crate::object::{Cached, Heap, Obj, ObjMap, ObjRef, Upvalue};
+use crate::string;
+use crate::value;
+use crate::vm_value::{Unpacked, Value};
+
+pub use crate::object::{GcConfig, GcStats};
+
+pub struct Vm {
+    stack: Vec<Value>,
+    // the running function last
+    frames: Vec<Frame>,
+    // the values of the globals by slot, `None` until they're defined
+    globals: Vec<Option<Value>>,
+    // the slots of the globals by their interned names
+    global_slots: ObjMap<usize>,
+    // the upvalues still pointing to the stack
+    open_upvalues: Vec<ObjRef>,
+    // the name of initializers, interned once
+    init_string: ObjRef,
+    inline_caches: bool,
+    options: compiler::Options,
+    // how many instructions ran, counting each superinstruction once
+    instructions: u64,
+    // where to trace the instructions run, when tracing
+    trace: Option<Box<dyn Write>>,
+    heap: Heap,
+    output: Box<dyn Write>,
+    // where `run` finds the programs it compiled before
+    cache: Option<Cache>,
+    interrupt: InterruptHandle,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// as many frames as calls may nest, all allocated when the VM is made
+const FRAMES_MAX: usize = DEFAULT_MAX_CALL_DEPTH;
+
+// a function being run
+#[derive(Clone, Copy)]
+struct Frame {
+    // owned by `object`, which the frame keeps alive
+    function: NonNull<Function>,
+    // the `Obj::Function` holding the constants
+    object: ObjRef,
+    // `None` for the top-level script
+    closure: Option<ObjRef>,
+    ip: usize,
+    // the slot of the first local
+    base: usize,
+}
+
+impl Frame {
+    fn function(&self) -> &Function {
+        unsafe { self.function.as_ref() }
+    }
+
+    // the line of the instruction being run
+    fn line(&self) -> usize {
+        self.function()
+            .chunk
+            .lines
+            .get(self.ip.saturating_sub(1))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn constant(&self, index: usize) -> Value {
+        match self.object.get() {
+            Obj::Function { constants, .. } => constants[index],
+            _ => unreachable!("frames run functions"),
+        }
+    }
+
+    fn cache(&self, index: usize) -> &Cell<Option<Cached>> {
+        match self.object.get() {
+            Obj::Function { caches, .. } => &caches[index],
+            _ => unreachable!("frames run functions"),
+        }
+    }
+
+    // the slot of the global named by a constant, once known
+    fn global(&self, index: usize) -> &Cell<Option<usize>> {
+        match self.object.get() {
+            Obj::Function { globals, .. } => &globals[index],
+            _ => unreachable!("frames run functions"),
+        }
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut heap = Heap::new();
+        let init_string = heap.alloc(Obj::String("init".to_string()));
+        let mut vm = Self {
+            stack: Vec::new(),
+            frames: Vec::with_capacity(FRAMES_MAX),
+            globals: Vec::new(),
+            global_slots: ObjMap::default(),
+            open_upvalues: Vec::new(),
+            init_string,
+            inline_caches: true,
+            options: compiler::Options::default(),
+            instructions: 0,
+            trace: None,
+            heap,
+            output: Box::new(io::stdout()),
+            cache: None,
+            interrupt: InterruptHandle::default(),
+        };
+        vm.define_native("clock", 0, clock);
+        vm.define_native("gcStats", 0, gc_stats);
+        vm.define_native("abs", 1, |vm, arguments| math(vm, arguments, math::abs));
+        vm.define_native("acos", 1, |vm, arguments| math(vm, arguments, math::acos));
+        vm.define_native("asin", 1, |vm, arguments| math(vm, arguments, math::asin));
+        vm.define_native("atan", 1, |vm, arguments| math(vm, arguments, math::atan));
+        vm.define_native("atan2", 2, |vm, arguments| math(vm, arguments, math::atan2));
+        vm.define_native("ceil", 1, |vm, arguments| math(vm, arguments, math::ceil));
+        vm.define_native("cos", 1, |vm, arguments| math(vm, arguments, math::cos));
+        vm.define_native("floor", 1, |vm, arguments| math(vm, arguments, math::floor));
+        vm.define_native("max", 2, |vm, arguments| math(vm, arguments, math::max));
+        vm.define_native("min", 2, |vm, arguments| math(vm, arguments, math::min));
+        vm.define_native("pow", 2, |vm, arguments| math(vm, arguments, math::pow));
+        vm.define_native("round", 1, |vm, arguments| math(vm, arguments, math::round));
+        vm.define_native("sin", 1, |vm, arguments| math(vm, arguments, math::sin));
+        vm.define_native("sqrt", 1, |vm, arguments| math(vm, arguments, math::sqrt));
+        vm.define_native("tan", 1, |vm, arguments| math(vm, arguments, math::tan));
+        vm.define_native("charAt", 2, |vm, arguments| {
+            string(vm, arguments, string::char_at)
+        });
+        vm.define_native("contains", 2, |vm, arguments| {
+            string(vm, arguments, string::contains)
+        });
+        vm.define_native("endsWith", 2, |vm, arguments| {
+            string(vm, arguments, string::ends_with)
+        });
+        vm.define_native("indexOf", 2, |vm, arguments| {
+            string(vm, arguments, string::index_of)
+        });
+        vm.define_native("len", 1, len);
+        vm.define_native("lower", 1, |vm, arguments| {
+            string(vm, arguments, string::lower)
+        });
+        vm.define_native("replace", 3, |vm, arguments| {
+            string(vm, arguments, string::replace)
+        });
+        vm.define_native("startsWith", 2, |vm, arguments| {
+            string(vm, arguments, string::starts_with)
+        });
+        vm.define_native("trim", 1, |vm, arguments| {
+            string(vm, arguments, string::trim)
+        });
+        vm.define_native("upper", 1, |vm, arguments| {
+            string(vm, arguments, string::upper)
+        });
+        let pi = vm.string("pi".to_string());
+        let slot = vm.global_slot(pi);
+        vm.globals[slot] = Some(Value::number(std::f64::consts::PI));
+        vm
+    }
+
+    fn define_native(
+        &mut self,
+        name: &'static str,
+        arity: usize,
+        function: fn(&mut Vm, &[Value]) -> Result<Value, String>,
+    ) {
+        // on the stack while allocating the native, out of reach of the
+        // collector
+        let interned = self.string(name.to_string());
+        self.push(Value::object(interned));
+        let native = self.alloc(Obj::Native { arity, function });
+        self.pop();
+        let slot = self.global_slot(interned);
+        self.globals[slot] = Some(Value::object(native));
+    }
+
+    /// Send everything the program prints to `output` instead of stdout.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+
+    /// Compile and run a program.
+    pub fn run(&mut self, source: &str) -> anyhow::Result<()> {
+        let chunk = match &mut self.cache {
+            Some(cache) => cache.compile(source, self.options)?,
+            None => compiler::compile_with(source, self.options)?,
+        };
+        self.execute(&chunk)?;
+        Ok(())
+    }
+
+    /// Compile the programs given to `run` only the first time `cache`
+    /// sees them.
+    pub fn set_cache(&mut self, cache: Cache) {
+        self.cache = Some(cache);
+    }
+
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// Look a global up by name, as a value of the interpreter. Functions
+    /// only the VM can call are left out.
+    pub fn get_global(&self, name: &str) -> Option<value::Value> {
+        let name = self.heap.find_string(name)?;
+        let slot = self.global_slots.get(&name)?;
+        self.globals[*slot].and_then(export)
+    }
+
+    /// How many objects are alive on the heap of the VM.
+    pub fn objects(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Look every method up in its class, ignoring the inline caches, to
+    /// measure what they save.
+    pub fn set_inline_caches(&mut self, enabled: bool) {
+        self.inline_caches = enabled;
+    }
+
+    /// Compile programs given to `run` without superinstructions, to measure
+    /// what they save.
+    pub fn set_superinstructions(&mut self, enabled: bool) {
+        self.options.superinstructions = enabled;
+    }
+
+    /// How many instructions the VM dispatched so far.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Trace every instruction to `trace` before running it, after the
+    /// values on the stack, as clox does with `DEBUG_TRACE_EXECUTION`.
+    /// Instructions are listed as `chunk::disassemble` lists them, after the
+    /// name of the function running them.
+    pub fn set_trace<W: Write + 'static>(&mut self, trace: W) {
+        self.trace = Some(Box::new(trace));
+    }
+
+    /// Stop tracing instructions.
+    pub fn stop_trace(&mut self) {
+        self.trace = None;
+    }
+
+    /// Collect garbage before every allocation instead of when the heap
+    /// grows.
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    /// Trace what the garbage collector does to `log`.
+    pub fn set_gc_log<W: Write + 'static>(&mut self, log: W) {
+        self.heap.set_log(log);
+    }
+
+    /// Collect garbage as `config` says: more often for shorter pauses,
+    /// less often for throughput. Outgrowing the maximum heap is a runtime
+    /// error.
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.heap.set_config(config);
+    }
+
+    /// What the garbage collector did so far, also returned by the
+    /// `gcStats()` native.
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Free the objects the program can't reach anymore, returning how many
+    /// there were.
+    pub fn collect_garbage(&mut self) -> usize {
+        let roots = self
+            .stack
+            .iter()
+            .chain(self.globals.iter().flatten())
+            .filter_map(|value| value.as_object())
+            .chain(self.global_slots.keys().copied())
+            .chain(self.frames.iter().map(|frame| frame.object))
+            .chain(self.frames.iter().filter_map(|frame| frame.closure))
+            .chain(self.open_upvalues.iter().copied())
+            .chain(Some(self.init_string))
+            .collect::<Vec<_>>();
+        self.heap.mark_roots(roots);
+        self.heap.sweep()
+    }
+
+    /// A handle stopping whatever program the VM is running when it's
+    /// interrupted, at its next call or backward jump.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Run a chunk as a script. Chunks that don't come from the compiler
+    /// must pass [`verifier::verify`](crate::verifier::verify) first.
+    pub fn execute(&mut self, chunk: &Chunk) -> Result<(), RuntimeError> {
+        self.interrupt.clear();
+        let script = Rc::new(Function {
+            name: "script".to_string(),
+            arity: 0,
+            upvalues: 0,
+            chunk: chunk.clone(),
+        });
+        let object = self.load(&script);
+        self.frames.push(Frame {
+            function: NonNull::from(&*script),
+            object,
+            closure: None,
+            ip: 0,
+            base: self.stack.len(),
+        });
+        let result = self.run_frames();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn run_frames(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            self.instructions += 1;
+            if self.trace.is_some() {
+                self.trace_instruction();
+            }
+            let byte = self.read_byte();
+            let op = OpCode::from_byte(byte)
+                .ok_or_else(|| self.error(format!("Unknown opcode {}.", byte)))?;
+            match op {
+                OpCode::Constant => {
+                    let index = self.read_byte() as usize;
+                    self.push(self.frame().constant(index));
+                }
+                OpCode::ConstantLong => {
+                    let index = self.read_u24();
+                    self.push(self.frame().constant(index));
+                }
+                OpCode::Nil => self.push(Value::NIL),
+                OpCode::True => self.push(Value::bool(true)),
+                OpCode::False => self.push(Value::bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.push(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0);
+                }
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let (slot, index) = self.read_global(op)?;
+                    match self.globals[slot] {
+                        Some(value) => self.push(value),
+                        None => return Err(self.undefined(index)),
+                    }
+                }
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                    let (slot, _) = self.read_global(op)?;
+                    self.globals[slot] = Some(self.pop());
+                }
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let (slot, index) = self.read_global(op)?;
+                    let value = self.peek(0);
+                    match &mut self.globals[slot] {
+                        Some(global) => *global = value,
+                        None => return Err(self.undefined(index)),
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let upvalue = self.upvalue();
+                    let value = match cell(&upvalue).get() {
+                        Upvalue::Open(slot) => *self.stack.get(slot).ok_or_else(|| self.lost())?,
+                        Upvalue::Closed(value) => value,
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let upvalue = self.upvalue();
+                    let value = self.peek(0);
+                    match cell(&upvalue).get() {
+                        Upvalue::Open(slot) => match self.stack.get_mut(slot) {
+                            Some(local) => *local = value,
+                            None => return Err(self.lost()),
+                        },
+                        Upvalue::Closed(_) => cell(&upvalue).set(Upvalue::Closed(value)),
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let cache = self.read_u16();
+                    let instance = match instance(self.peek(0)) {
+                        Some(instance) => instance,
+                        None => return Err(self.error("Only instances have properties.")),
+                    };
+                    let (class, field) = match instance.get() {
+                        Obj::Instance { class, fields } => {
+                            (*class, fields.borrow().get(&name).copied())
+                        }
+                        _ => unreachable!("the object is an instance"),
+                    };
+                    match field {
+                        Some(field) => {
+                            self.pop();
+                            self.push(field);
+                        }
+                        None => self.bind_method(class, name, cache)?,
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let instance = match instance(self.peek(1)) {
+                        Some(instance) => instance,
+                        None => return Err(self.error("Only instances have fields.")),
+                    };
+                    let value = self.peek(0);
+                    let added = match instance.get() {
+                        Obj::Instance { fields, .. } => fields.borrow_mut().insert(name, value),
+                        _ => unreachable!("the object is an instance"),
+                    };
+                    if added.is_none() {
+                        let size = mem::size_of::<ObjRef>() + mem::size_of::<Value>();
+                        self.heap.grow(instance, size);
+                    }
+                    let value = self.pop();
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let cache = self.read_u16();
+                    let superclass = self.pop();
+                    let superclass = self.class(superclass)?;
+                    self.bind_method(superclass, name, cache)?;
+                }
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.push(Value::bool(values_equal(left, right)));
+                }
+                OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.push(Value::bool(!values_equal(left, right)));
+                }
+                OpCode::Add => self.add()?,
+                OpCode::Greater => self.arithmetic(BinaryOp::Greater)?,
+                OpCode::GreaterEqual => self.arithmetic(BinaryOp::GreaterEqual)?,
+                OpCode::Less => self.arithmetic(BinaryOp::Less)?,
+                OpCode::LessEqual => self.arithmetic(BinaryOp::LessEqual)?,
+                OpCode::Subtract => self.arithmetic(BinaryOp::Subtract)?,
+                OpCode::Multiply => self.arithmetic(BinaryOp::Multiply)?,
+                OpCode::Divide => self.arithmetic(BinaryOp::Divide)?,
+                OpCode::FloorDivide => self.arithmetic(BinaryOp::FloorDivide)?,
+                OpCode::Modulo => self.arithmetic(BinaryOp::Modulo)?,
+                OpCode::BitAnd => self.arithmetic(BinaryOp::BitAnd)?,
+                OpCode::BitOr => self.arithmetic(BinaryOp::BitOr)?,
+                OpCode::BitXor => self.arithmetic(BinaryOp::BitXor)?,
+                OpCode::ShiftLeft => self.arithmetic(BinaryOp::ShiftLeft)?,
+                OpCode::ShiftRight => self.arithmetic(BinaryOp::ShiftRight)?,
+                OpCode::Range => return Err(self.error("Can't run ranges in the VM yet.")),
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::bool(value.is_falsey()));
+                }
+                OpCode::Negate => {
+                    let value = match self.pop().unpack() {
+                        // only -i64::MIN doesn't fit
+                        Unpacked::Integer(integer) => match integer.checked_neg() {
+                            Some(negated) => self.integer(negated),
+                            None => Value::number(-(integer as f64)),
+                        },
+                        Unpacked::Number(number) => Value::number(-number),
+                        _ => return Err(self.error("Operand must be a number.")),
+                    };
+                    self.push(value);
+                }
+                OpCode::BitNot => {
+                    let integer = match self.pop().unpack() {
+                        Unpacked::Integer(integer) => integer,
+                        Unpacked::Number(number) => {
+                            let span = Span::new(0, 0, self.frame().line());
+                            interpreter::integer(number, span)
+                                .map_err(|failure| self.error(failure.message))?
+                        }
+                        _ => return Err(self.error("Operand must be a number.")),
+                    };
+                    let value = self.integer(!integer);
+                    self.push(value);
+                }
+                OpCode::Print => {
+                    let text = stringify(self.pop());
+                    if let Err(error) = writeln!(self.output, "{}", text) {
+                        return Err(self.error(format!("Could not print: {}.", error)));
+                    }
+                }
+                OpCode::Jump => {
+                    let distance = self.read_u16();
+                    self.frame_mut().ip += distance;
+                }
+                OpCode::JumpIfFalse => {
+                    let distance = self.read_u16();
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += distance;
+                    }
+                }
+                OpCode::Loop => {
+                    let distance = self.read_u16();
+                    self.frame_mut().ip -= distance;
+                    self.check_interrupt()?;
+                    self.check_memory()?;
+                }
+                OpCode::Call => {
+                    let count = self.read_byte() as usize;
+                    self.call(self.peek(count), count)?;
+                }
+                OpCode::Closure | OpCode::ClosureLong => {
+                    let index = match op {
+                        OpCode::Closure => self.read_byte() as usize,
+                        _ => self.read_u24(),
+                    };
+                    let function = self
+                        .frame()
+                        .constant(index)
+                        .as_object()
+                        .expect("closures are made of functions");
+                    let closure = self.closure(function);
+                    self.push(Value::object(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1)?;
+                    self.pop();
+                }
+                OpCode::Invoke => {
+                    let name = self.read_string();
+                    let count = self.read_byte() as usize;
+                    let cache = self.read_u16();
+                    self.invoke(name, count, cache)?;
+                }
+                OpCode::SuperInvoke => {
+                    let name = self.read_string();
+                    let count = self.read_byte() as usize;
+                    let cache = self.read_u16();
+                    let superclass = self.pop();
+                    let superclass = self.class(superclass)?;
+                    self.invoke_from_class(superclass, name, count, cache)?;
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = self.alloc(Obj::Class {
+                        name,
+                        methods: RefCell::new(ObjMap::default()),
+                    });
+                    self.push(Value::object(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1).as_object() {
+                        Some(superclass) if matches!(superclass.get(), Obj::Class { .. }) => {
+                            superclass
+                        }
+                        _ => return Err(self.error("Superclass must be a class.")),
+                    };
+                    let subclass = self.class(self.peek(0))?;
+                    let inherited = methods(&superclass).borrow().clone();
+                    let size = inherited.len() * 2 * mem::size_of::<ObjRef>();
+                    methods(&subclass).borrow_mut().extend(inherited);
+                    self.heap.grow(subclass, size);
+                    self.pop();
+                }
+                OpCode::GetLocals => {
+                    let base = self.frame().base;
+                    let first = base + self.read_byte() as usize;
+                    let second = base + self.read_byte() as usize;
+                    self.push(self.stack[first]);
+                    self.push(self.stack[second]);
+                }
+                OpCode::SetLocalPop => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack[slot] = self.pop();
+                }
+                OpCode::SetGlobalPop => {
+                    let (slot, index) = self.read_global(op)?;
+                    let value = self.pop();
+                    match &mut self.globals[slot] {
+                        Some(global) => *global = value,
+                        None => return Err(self.undefined(index)),
+                    }
+                }
+                OpCode::AddConstant | OpCode::SubtractConstant | OpCode::LessConstant => {
+                    let index = self.read_byte() as usize;
+                    self.push(self.frame().constant(index));
+                    match op {
+                        OpCode::AddConstant => self.add()?,
+                        OpCode::SubtractConstant => self.arithmetic(BinaryOp::Subtract)?,
+                        _ => self.arithmetic(BinaryOp::Less)?,
+                    }
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let method = match self.peek(0).as_object() {
+                        Some(method) if matches!(method.get(), Obj::Closure { .. }) => method,
+                        _ => return Err(self.error("Methods must be closures.")),
+                    };
+                    let class = self.class(self.peek(1))?;
+                    methods(&class).borrow_mut().insert(name, method);
+                    self.heap.grow(class, 2 * mem::size_of::<ObjRef>());
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    self.close_upvalues(self.frame().base)?;
+                    let frame = self.frames.pop().expect("a frame is running");
+                    // the function called goes too
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.push(result);
+                }
+            }
+        }
+    }
+
+    fn trace_instruction(&mut self) {
+        let frame = self.frame();
+        let mut stack = " ".repeat(10);
+        for value in &self.stack {
+            stack.push_str(&format!("[ {} ]", stringify(*value)));
+        }
+        let (instruction, _) = chunk::disassemble_instruction(&frame.function().chunk, frame.ip);
+        let line = format!("{}\n{:<10}{}", stack, frame.function().name, instruction);
+        if let Some(trace) = &mut self.trace {
+            // the trace is for debugging, losing it is no reason to stop
+            let _ = writeln!(trace, "{}", line);
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("a frame is running")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("a frame is running")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        frame.ip += 1;
+        frame.function().chunk.code[frame.ip - 1]
+    }
+
+    fn read_u16(&mut self) -> usize {
+        let high = self.read_byte();
+        let low = self.read_byte();
+        u16::from_be_bytes([high, low]) as usize
+    }
+
+    fn read_u24(&mut self) -> usize {
+        let bytes = [0, self.read_byte(), self.read_byte(), self.read_byte()];
+        u32::from_be_bytes(bytes) as usize
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler balances the stack")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn replace_top(&mut self, value: Value) {
+        *self
+            .stack
+            .last_mut()
+            .expect("the compiler balances the stack") = value;
+    }
+
+    // the compiler only leaves classes where instructions expect them, but
+    // verified bytecode may have anything there
+    fn class(&self, value: Value) -> Result<ObjRef, RuntimeError> {
+        match value.as_object() {
+            Some(class) if matches!(class.get(), Obj::Class { .. }) => Ok(class),
+            _ => Err(self.error("Expected a class.")),
+        }
+    }
+
+    // the string in the constant the operand points to
+    fn read_string(&mut self) -> ObjRef {
+        let index = self.read_byte() as usize;
+        self.frame()
+            .constant(index)
+            .as_object()
+            .expect("names are strings")
+    }
+
+    fn call(&mut self, callee: Value, count: usize) -> Result<(), RuntimeError> {
+        let object = match callee.as_object() {
+            Some(object) => object,
+            None => return Err(self.error("Can only call functions and classes.")),
+        };
+        let slot = self.stack.len() - count - 1;
+        match object.get() {
+            Obj::Closure { .. } => self.call_closure(object, count),
+            Obj::BoundMethod { receiver, method } => {
+                self.stack[slot] = *receiver;
+                self.call_closure(*method, count)
+            }
+            Obj::Native {
+                arity, function, ..
+            } => {
+                if count != *arity {
+                    return Err(
+                        self.error(format!("Expected {} arguments but got {}.", arity, count))
+                    );
+                }
+                // the arguments stay on the stack, out of reach of the
+                // collector, while the native runs
+                let arguments = self.stack[slot + 1..].to_vec();
+                let result = function(self, &arguments).map_err(|message| self.error(message))?;
+                self.stack.truncate(slot);
+                self.push(result);
+                Ok(())
+            }
+            Obj::Class { methods, .. } => {
+                let initializer = methods.borrow().get(&self.init_string).copied();
+                // the class stays in its slot while allocating, out of reach
+                // of the collector
+                let instance = self.alloc(Obj::Instance {
+                    class: object,
+                    fields: RefCell::new(ObjMap::default()),
+                });
+                self.stack[slot] = Value::object(instance);
+                match initializer {
+                    Some(initializer) => self.call_closure(initializer, count),
+                    None if count != 0 => {
+                        Err(self.error(format!("Expected 0 arguments but got {}.", count)))
+                    }
+                    None => Ok(()),
+                }
+            }
+            _ => Err(self.error("Can only call functions and classes.")),
+        }
+    }
+
+    fn call_closure(&mut self, closure: ObjRef, count: usize) -> Result<(), RuntimeError> {
+        let function = match closure.get() {
+            Obj::Closure { function, .. } => *function,
+            _ => unreachable!("the callee is a closure"),
+        };
+        let compiled = match function.get() {
+            Obj::Function { function, .. } => function,
+            _ => unreachable!("closures are made of functions"),
+        };
+        if count != compiled.arity {
+            return Err(self.error(format!(
+                "Expected {} arguments but got {}.",
+                compiled.arity, count
+            )));
+        }
+        // the frames never outgrow what was allocated for them
+        if self.frames.len() >= FRAMES_MAX {
+            return Err(self.error("Stack overflow."));
+        }
+        self.check_interrupt()?;
+        self.check_memory()?;
+        self.frames.push(Frame {
+            function: NonNull::from(&**compiled),
+            object: function,
+            closure: Some(closure),
+            ip: 0,
+            base: self.stack.len() - count - 1,
+        });
+        Ok(())
+    }
+
+    // call the method `name` of the instance below the arguments
+    fn invoke(&mut self, name: ObjRef, count: usize, cache: usize) -> Result<(), RuntimeError> {
+        let instance = match instance(self.peek(count)) {
+            Some(instance) => instance,
+            None => return Err(self.error("Only instances have properties.")),
+        };
+        let (class, field) = match instance.get() {
+            Obj::Instance { class, fields } => (*class, fields.borrow().get(&name).copied()),
+            _ => unreachable!("the receiver is an instance"),
+        };
+        // fields shadow methods
+        if let Some(field) = field {
+            let slot = self.stack.len() - count - 1;
+            self.stack[slot] = field;
+            return self.call(field, count);
+        }
+        self.invoke_from_class(class, name, count, cache)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        count: usize,
+        cache: usize,
+    ) -> Result<(), RuntimeError> {
+        match self.find_method(class, name, cache) {
+            Some(method) => self.call_closure(method, count),
+            None => Err(self.undefined_property(name)),
+        }
+    }
+
+    // replace the instance on top of the stack with its method `name`
+    fn bind_method(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        cache: usize,
+    ) -> Result<(), RuntimeError> {
+        let method = match self.find_method(class, name, cache) {
+            Some(method) => method,
+            None => return Err(self.undefined_property(name)),
+        };
+        let receiver = self.peek(0);
+        let bound = self.alloc(Obj::BoundMethod { receiver, method });
+        self.pop();
+        self.push(Value::object(bound));
+        Ok(())
+    }
+
+    // the method `name` of `class`, from the inline cache of the running
+    // instruction when it saw the class last
+    fn find_method(&self, class: ObjRef, name: ObjRef, cache: usize) -> Option<ObjRef> {
+        let cache = self.frame().cache(cache);
+        if let Some(cached) = cache.get() {
+            if self.inline_caches && cached.class == class {
+                return Some(cached.method);
+            }
+        }
+        let method = methods(&class).borrow().get(&name).copied()?;
+        cache.set(Some(Cached { class, method }));
+        Some(method)
+    }
+
+    // make a closure of `function`, capturing the upvalues the operands of
+    // the instruction describe
+    fn closure(&mut self, function: ObjRef) -> ObjRef {
+        let count = match function.get() {
+            Obj::Function { function, .. } => function.upvalues,
+            _ => unreachable!("closures are made of functions"),
+        };
+        // the new upvalues are open, which keeps them alive until the
+        // closure holds them
+        let mut upvalues = Vec::with_capacity(count);
+        for _ in 0..count {
+            let is_local = self.read_byte() == 1;
+            let index = self.read_byte() as usize;
+            let upvalue = if is_local {
+                self.capture_upvalue(self.frame().base + index)
+            } else {
+                self.enclosing_upvalue(index)
+            };
+            upvalues.push(upvalue);
+        }
+        self.alloc(Obj::Closure { function, upvalues })
+    }
+
+    // an upvalue of the running closure
+    fn enclosing_upvalue(&self, index: usize) -> ObjRef {
+        let closure = self.frame().closure.expect("only functions have upvalues");
+        match closure.get() {
+            Obj::Closure { upvalues, .. } => upvalues[index],
+            _ => unreachable!("frames run closures"),
+        }
+    }
+
+    // the upvalue of the running closure the operand points to
+    fn upvalue(&mut self) -> ObjRef {
+        let index = self.read_byte() as usize;
+        self.enclosing_upvalue(index)
+    }
+
+    // the upvalue pointing to `slot`, shared by every closure capturing it
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(cell(upvalue).get(), Upvalue::Open(open) if open == slot));
+        if let Some(existing) = existing {
+            return *existing;
+        }
+        let upvalue = self.alloc(Obj::Upvalue(Cell::new(Upvalue::Open(slot))));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    // move the locals from `slot` up off the stack, into the upvalues
+    // capturing them
+    fn close_upvalues(&mut self, slot: usize) -> Result<(), RuntimeError> {
+        let stack = &self.stack;
+        let lost = self.open_upvalues.iter().any(
+            |upvalue| matches!(cell(upvalue).get(), Upvalue::Open(open) if open >= stack.len()),
+        );
+        if lost {
+            return Err(self.lost());
+        }
+        self.open_upvalues
+            .retain(|upvalue| match cell(upvalue).get() {
+                Upvalue::Open(open) if open >= slot => {
+                    cell(upvalue).set(Upvalue::Closed(stack[open]));
+                    false
+                }
+                _ => true,
+            });
+        Ok(())
+    }
+
+    // only unverified bytecode pops a local before closing its upvalues
+    fn lost(&self) -> RuntimeError {
+        self.error("Captured a local that is no longer on the stack.")
+    }
+
+    // the function and its constants as an object, loading the functions
+    // among them too
+    fn load(&mut self, function: &Rc<Function>) -> ObjRef {
+        let base = self.stack.len();
+        for constant in &function.chunk.constants {
+            let value = self.constant(constant);
+            self.push(value);
+        }
+        // the constants stay on the stack, where the collector sees them,
+        // until the function holds them
+        let constants = self.stack[base..].to_vec();
+        let object = self.alloc(Obj::Function {
+            function: function.clone(),
+            constants,
+            caches: (0..function.chunk.caches)
+                .map(|_| Cell::new(None))
+                .collect(),
+            globals: (0..function.chunk.constants.len())
+                .map(|_| Cell::new(None))
+                .collect(),
+        });
+        self.stack.truncate(base);
+        object
+    }
+
+    fn constant(&mut self, constant: &Constant) -> Value {
+        match constant {
+            Constant::Integer(integer) => self.integer(*integer),
+            Constant::Number(number) => Value::number(*number),
+            Constant::String(string) => Value::object(self.string(string.clone())),
+            Constant::Function(function) => Value::object(self.load(function)),
+        }
+    }
+
+    // the heap only outgrows its maximum for good by looping or calling, so
+    // that's where it's checked
+    // programs only loop through backward jumps and calls, so checking
+    // there is enough to stop any of them
+    fn check_interrupt(&self) -> Result<(), RuntimeError> {
+        match self.interrupt.take() {
+            true => Err(self
+                .error("Interrupted.")
+                .with_kind(RuntimeErrorKind::Interrupted)),
+            false => Ok(()),
+        }
+    }
+
+    fn check_memory(&mut self) -> Result<(), RuntimeError> {
+        if self.heap.over_limit() {
+            self.collect_garbage();
+            if self.heap.over_limit() {
+                return Err(self.error("Out of memory."));
+            }
+        }
+        Ok(())
+    }
+
+    // every allocation goes through here, the only place collections start
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(obj)
+    }
+
+    // the slot of a global, named by the constant the operand points to,
+    // along with the index of the constant
+    fn read_global(&mut self, op: OpCode) -> Result<(usize, usize), RuntimeError> {
+        let index = match op {
+            OpCode::GetGlobalLong | OpCode::DefineGlobalLong | OpCode::SetGlobalLong => {
+                self.read_u24()
+            }
+            _ => self.read_byte() as usize,
+        };
+        if let Some(slot) = self.frame().global(index).get() {
+            return Ok((slot, index));
+        }
+        let slot = match self.frame().constant(index).unpack() {
+            Unpacked::Object(name) if matches!(name.get(), Obj::String(_)) => {
+                self.global_slot(name)
+            }
+            _ => return Err(self.error("Global names must be strings.")),
+        };
+        self.frame().global(index).set(Some(slot));
+        Ok((slot, index))
+    }
+
+    // the slot of the global with this name, a new one if it has none yet
+    fn global_slot(&mut self, name: ObjRef) -> usize {
+        let next = self.globals.len();
+        let slot = *self.global_slots.entry(name).or_insert(next);
+        if slot == next {
+            self.globals.push(None);
+        }
+        slot
+    }
+
+    // the interned string with these contents
+    fn string(&mut self, string: String) -> ObjRef {
+        match self.heap.find_string(&string) {
+            Some(interned) => interned,
+            None => self.alloc(Obj::String(string)),
+        }
+    }
+
+    fn integer(&mut self, integer: i64) -> Value {
+        Value::integer(integer).unwrap_or_else(|| Value::object(self.alloc(Obj::Integer(integer))))
+    }
+
+    fn add(&mut self) -> Result<(), RuntimeError> {
+        let (left, right) = (self.peek(1), self.peek(0));
+        if let Some(sum) = fast_arithmetic(BinaryOp::Add, left, right) {
+            self.pop();
+            self.replace_top(sum);
+            return Ok(());
+        }
+        if let (Unpacked::Object(left), Unpacked::Object(right)) = (left.unpack(), right.unpack()) {
+            if let (Obj::String(left), Obj::String(right)) = (left.get(), right.get()) {
+                // the operands stay on the stack, where the collector sees them
+                let joined = format!("{}{}", left, right);
+                let joined = self.string(joined);
+                self.pop();
+                self.pop();
+                self.push(Value::object(joined));
+                return Ok(());
+            }
+        }
+        if let (Some(_), Some(_)) = (number(left), number(right)) {
+            return self.arithmetic(BinaryOp::Add);
+        }
+        let (left, right) = (type_name(left), type_name(right));
+        if matches!((left, right), ("string", "number") | ("number", "string")) {
+            return Err(self.error(format!(
+                "Can't add {} and {}, operands must be two numbers or two strings.",
+                left, right
+            )));
+        }
+        Err(self.error("Operands must be two numbers or two strings."))
+    }
+
+    // the operators working on numbers, which the interpreter implements
+    fn arithmetic(&mut self, op: BinaryOp) -> Result<(), RuntimeError> {
+        let right = self.pop();
+        if let Some(result) = fast_arithmetic(op, self.peek(0), right) {
+            self.replace_top(result);
+            return Ok(());
+        }
+        let left = self.pop();
+        let (left, right) = match (number(left), number(right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return Err(self.error("Operands must be numbers.")),
+        };
+        let span = Span::new(0, 0, self.frame().line());
+        let result = interpreter::arithmetic(op, &left, &right, span)
+            .map_err(|failure| self.error(failure.message))?;
+        let result = match result {
+            value::Value::Integer(integer) => self.integer(integer),
+            value::Value::Number(number) => Value::number(number),
+            value::Value::Bool(b) => Value::bool(b),
+            _ => unreachable!("arithmetic only gives numbers and booleans"),
+        };
+        self.push(result);
+        Ok(())
+    }
+
+    fn undefined_property(&self, name: ObjRef) -> RuntimeError {
+        self.error(format!("Undefined property '{}'.", name.as_str()))
+    }
+
+    // the global named by a constant of the running function isn't defined
+    fn undefined(&self, index: usize) -> RuntimeError {
+        let name = stringify(self.frame().constant(index));
+        self.error(format!("Undefined variable '{}'.", name))
+    }
+
+    // an error of the running instruction, with the frames calling it
+    fn error<M: Into<String>>(&self, message: M) -> RuntimeError {
+        let line = self.frames.last().map_or(0, Frame::line);
+        let mut error = RuntimeError::new(message, Span::new(0, 0, line));
+        for frame in self.frames.iter().rev() {
+            error.trace.push(TraceFrame {
+                function: frame.closure.map(|_| frame.function().name.clone()),
+                line: frame.line(),
+            });
+        }
+        error
+    }
+}
+
+fn instance(value: Value) -> Option<ObjRef> {
+    value
+        .as_object()
+        .filter(|object| matches!(object.get(), Obj::Instance { .. }))
+}
+
+fn methods(class: &ObjRef) -> &RefCell<ObjMap<ObjRef>> {
+    match class.get() {
+        Obj::Class { methods, .. } => methods,
+        _ => unreachable!("only classes have methods"),
+    }
+}
+
+fn cell(upvalue: &ObjRef) -> &Cell<Upvalue> {
+    match upvalue.get() {
+        Obj::Upvalue(upvalue) => upvalue,
+        _ => unreachable!("closures capture upvalues"),
+    }
+}
+
+// a number as the interpreter has it
+fn number(value: Value) -> Option<value::Value> {
+    match value.unpack() {
+        Unpacked::Integer(integer) => Some(value::Value::Integer(integer)),
+        Unpacked::Number(number) => Some(value::Value::Number(number)),
+        _ => None,
+    }
+}
+
+// the common cases of `arithmetic`, without going through the values of
+// the interpreter: `None` for everything else, including integers
+// overflowing or too big to be values
+fn fast_arithmetic(op: BinaryOp, left: Value, right: Value) -> Option<Value> {
+    match (left.unpack(), right.unpack()) {
+        (Unpacked::Integer(left), Unpacked::Integer(right)) => match op {
+            BinaryOp::Add => Value::integer(left.checked_add(right)?),
+            BinaryOp::Subtract => Value::integer(left.checked_sub(right)?),
+            BinaryOp::Multiply => Value::integer(left.checked_mul(right)?),
+            BinaryOp::Less => Some(Value::bool(left < right)),
+            BinaryOp::LessEqual => Some(Value::bool(left <= right)),
+            BinaryOp::Greater => Some(Value::bool(left > right)),
+            BinaryOp::GreaterEqual => Some(Value::bool(left >= right)),
+            _ => None,
+        },
+        (Unpacked::Number(left), Unpacked::Number(right)) => match op {
+            BinaryOp::Add => Some(Value::number(left + right)),
+            BinaryOp::Subtract => Some(Value::number(left - right)),
+            BinaryOp::Multiply => Some(Value::number(left * right)),
+            BinaryOp::Divide => Some(Value::number(left / right)),
+            BinaryOp::Less => Some(Value::bool(left < right)),
+            BinaryOp::LessEqual => Some(Value::bool(left <= right)),
+            BinaryOp::Greater => Some(Value::bool(left > right)),
+            BinaryOp::GreaterEqual => Some(Value::bool(left >= right)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn values_equal(left: Value, right: Value) -> bool {
+    match (left.unpack(), right.unpack()) {
+        (Unpacked::Nil, Unpacked::Nil) => true,
+        (Unpacked::Bool(left), Unpacked::Bool(right)) => left == right,
+        (Unpacked::Integer(left), Unpacked::Integer(right)) => left == right,
+        (Unpacked::Number(left), Unpacked::Number(right)) => left == right,
+        (Unpacked::Integer(integer), Unpacked::Number(number))
+        | (Unpacked::Number(number), Unpacked::Integer(integer)) => {
+            value::exact_integer(number) == Some(integer)
+        }
+        // strings are interned, and the same method bound to the same
+        // receiver twice is the same
+        (Unpacked::Object(left), Unpacked::Object(right)) => match (left.get(), right.get()) {
+            (
+                Obj::BoundMethod { receiver, method },
+                Obj::BoundMethod {
+                    receiver: other_receiver,
+                    method: other_method,
+                },
+            ) => method == other_method && values_equal(*receiver, *other_receiver),
+            _ => left == right,
+        },
+        _ => false,
+    }
+}
+
+fn type_name(value: Value) -> &'static str {
+    match value.unpack() {
+        Unpacked::Nil => "nil",
+        Unpacked::Bool(_) => "boolean",
+        Unpacked::Integer(_) | Unpacked::Number(_) => "number",
+        Unpacked::Object(object) => match object.get() {
+            Obj::String(_) => "string",
+            Obj::Integer(_) => "number",
+            Obj::Function { .. }
+            | Obj::Closure { .. }
+            | Obj::BoundMethod { .. }
+            | Obj::Native { .. } => "function",
+            Obj::Upvalue(_) => "upvalue",
+            Obj::Class { .. } => "class",
+            Obj::Instance { .. } => "instance",
+        },
+    }
+}
+
+fn clock(_: &mut Vm, _: &[Value]) -> Result<Value, String> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| "System clock is set before the epoch.".to_string())?;
+    Ok(Value::number(elapsed.as_secs_f64()))
+}
+
+// a math native, on the numbers of the interpreter
+fn math(
+    vm: &mut Vm,
+    arguments: &[Value],
+    native: fn(&[value::Value]) -> Result<value::Value, RuntimeError>,
+) -> Result<Value, String> {
+    let arguments = arguments
+        .iter()
+        .map(|&argument| {
+            number(argument)
+                .ok_or_else(|| format!("Expected a number but got {}.", type_name(argument)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match native(&arguments).map_err(|error| error.message)? {
+        value::Value::Integer(integer) => Ok(vm.integer(integer)),
+        value::Value::Number(number) => Ok(Value::number(number)),
+        _ => unreachable!("math natives only give numbers"),
+    }
+}
+
+// a string native, on the values of the interpreter
+fn string(
+    vm: &mut Vm,
+    arguments: &[Value],
+    native: fn(&[value::Value]) -> Result<value::Value, RuntimeError>,
+) -> Result<Value, String> {
+    let arguments = arguments
+        .iter()
+        .map(|&argument| {
+            export(argument)
+                .ok_or_else(|| format!("Expected a string but got {}.", type_name(argument)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match native(&arguments).map_err(|error| error.message)? {
+        value::Value::String(string) => Ok(Value::object(vm.string(string.to_string()))),
+        value::Value::Integer(integer) => Ok(vm.integer(integer)),
+        value::Value::Bool(b) => Ok(Value::bool(b)),
+        _ => unreachable!("string natives give strings, integers and booleans"),
+    }
+}
+
+// the only values with a length the VM has are strings
+fn len(vm: &mut Vm, arguments: &[Value]) -> Result<Value, String> {
+    if let Some(object) = arguments[0].as_object() {
+        if let Obj::String(string) = object.get() {
+            let length = string::len(string) as i64;
+            return Ok(vm.integer(length));
+        }
+    }
+    Err(format!(
+        "Expected a list, map, string or range but got {}.",
+        type_name(arguments[0])
+    ))
+}
+
+// an instance of a class of its own, with the stats in fields
+fn gc_stats(vm: &mut Vm, _: &[Value]) -> Result<Value, String> {
+    let stats = vm.heap.stats();
+    let name = vm.string("GcStats".to_string());
+    vm.push(Value::object(name));
+    let class = vm.alloc(Obj::Class {
+        name,
+        methods: RefCell::new(ObjMap::default()),
+    });
+    vm.pop();
+    vm.push(Value::object(class));
+    let instance = vm.alloc(Obj::Instance {
+        class,
+        fields: RefCell::new(ObjMap::default()),
+    });
+    vm.pop();
+    vm.push(Value::object(instance));
+    for (field, count) in [
+        ("collections", stats.collections),
+        ("objectsFreed", stats.objects_freed),
+        ("bytesFreed", stats.bytes_freed),
+    ] {
+        let value = vm.integer(count as i64);
+        set_field(vm, instance, field, value);
+    }
+    for (field, pause) in [
+        ("totalPause", stats.total_pause),
+        ("maxPause", stats.max_pause),
+    ] {
+        set_field(vm, instance, field, Value::number(pause.as_secs_f64()));
+    }
+    Ok(vm.pop())
+}
+
+fn set_field(vm: &mut Vm, instance: ObjRef, field: &str, value: Value) {
+    // on the stack while allocating the name of the field, out of reach of
+    // the collector
+    vm.push(value);
+    let field = vm.string(field.to_string());
+    let value = vm.pop();
+    if let Obj::Instance { fields, .. } = instance.get() {
+        fields.borrow_mut().insert(field, value);
+    }
+    let size = mem::size_of::<ObjRef>() + mem::size_of::<Value>();
+    vm.heap.grow(instance, size);
+}
+
+fn stringify(value: Value) -> String {
+    match value.unpack() {
+        Unpacked::Nil => "nil".to_string(),
+        Unpacked::Bool(b) => b.to_string(),
+        Unpacked::Integer(integer) => integer.to_string(),
+        Unpacked::Number(number) => value::format_number(number),
+        Unpacked::Object(object) => match object.get() {
+            Obj::String(string) => string.clone(),
+            _ => format!("{:?}", object),
+        },
+    }
+}
+
+// the same value for the interpreter, copying strings
+fn export(value: Value) -> Option<value::Value> {
+    let value = match value.unpack() {
+        Unpacked::Nil => value::Value::Nil,
+        Unpacked::Bool(b) => value::Value::Bool(b),
+        Unpacked::Integer(integer) => value::Value::Integer(integer),
+        Unpacked::Number(number) => value::Value::Number(number),
+        Unpacked::Object(object) => match object.get() {
+            Obj::String(string) => value::Value::from(string.as_str()),
+            Obj::Integer(integer) => value::Value::Integer(*integer),
+            _ => return None,
+        },
+    };
+    Some(value)
+}
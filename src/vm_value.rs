@@ -0,0 +1,164 @@
+//! The values of the bytecode VM, in one of two representations.
+//!
+//! By default a value is an enum. With the `nan-boxing` feature, it's the
+//! 64 bits of a float instead: numbers are themselves, and everything else
+//! hides in the bits of quiet NaNs, which arithmetic never produces. Values
+//! are looked at through `unpack`, so the VM doesn't care which it gets.
+
+#[cfg(feature = "nan-boxing")]
+use crate::object::Obj;
+use crate::object::ObjRef;
+
+/// A value taken apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unpacked {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    Object(ObjRef),
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Debug, Clone, Copy)]
+pub struct Value(Unpacked);
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+    pub const NIL: Value = Value(Unpacked::Nil);
+
+    pub fn bool(b: bool) -> Self {
+        Value(Unpacked::Bool(b))
+    }
+
+    pub fn number(number: f64) -> Self {
+        Value(Unpacked::Number(number))
+    }
+
+    /// The integer as a value, or `None` when it has to go to the heap as an
+    /// `Obj::Integer`.
+    pub fn integer(integer: i64) -> Option<Self> {
+        Some(Value(Unpacked::Integer(integer)))
+    }
+
+    pub fn object(object: ObjRef) -> Self {
+        Value(Unpacked::Object(object))
+    }
+
+    pub fn unpack(self) -> Unpacked {
+        self.0
+    }
+
+    /// The object the value points to, for the collector to mark.
+    pub fn as_object(self) -> Option<ObjRef> {
+        match self.0 {
+            Unpacked::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+}
+
+// a quiet NaN with one more bit set than the ones floats can produce
+#[cfg(feature = "nan-boxing")]
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const SIGN: u64 = 0x8000_0000_0000_0000;
+// tags in the bits below the NaN, for the values that aren't pointers
+#[cfg(feature = "nan-boxing")]
+const TAG_NIL: u64 = 1;
+#[cfg(feature = "nan-boxing")]
+const TAG_FALSE: u64 = 2;
+#[cfg(feature = "nan-boxing")]
+const TAG_TRUE: u64 = 3;
+#[cfg(feature = "nan-boxing")]
+const TAG_INTEGER: u64 = 1 << 48;
+// integers take the 48 bits left, the bigger ones go to the heap
+#[cfg(feature = "nan-boxing")]
+const INTEGER_BITS: u32 = 48;
+#[cfg(feature = "nan-boxing")]
+const PAYLOAD: u64 = (1 << INTEGER_BITS) - 1;
+
+/// Pointers have the sign bit set, and fit in the 48 bits x86-64 and
+/// AArch64 give addresses.
+#[cfg(feature = "nan-boxing")]
+#[derive(Clone, Copy)]
+pub struct Value(u64);
+
+#[cfg(feature = "nan-boxing")]
+impl Value {
+    pub const NIL: Value = Value(QNAN | TAG_NIL);
+
+    pub fn bool(b: bool) -> Self {
+        Value(QNAN | if b { TAG_TRUE } else { TAG_FALSE })
+    }
+
+    pub fn number(number: f64) -> Self {
+        // the NaNs of arithmetic could set the bits tagging other values
+        if number.is_nan() {
+            return Value(f64::NAN.to_bits());
+        }
+        Value(number.to_bits())
+    }
+
+    pub fn integer(integer: i64) -> Option<Self> {
+        let min = -(1 << (INTEGER_BITS - 1));
+        let max = (1 << (INTEGER_BITS - 1)) - 1;
+        if (min..=max).contains(&integer) {
+            Some(Value(QNAN | TAG_INTEGER | (integer as u64 & PAYLOAD)))
+        } else {
+            None
+        }
+    }
+
+    pub fn object(object: ObjRef) -> Self {
+        Value(SIGN | QNAN | object.address())
+    }
+
+    /// The object the value points to, for the collector to mark, including
+    /// the integers `unpack` takes out of the heap.
+    pub fn as_object(self) -> Option<ObjRef> {
+        if self.0 & (SIGN | QNAN) != SIGN | QNAN {
+            return None;
+        }
+        // only `object` sets the sign bit, with a live object
+        Some(unsafe { ObjRef::from_address(self.0 & !(SIGN | QNAN)) })
+    }
+
+    pub fn unpack(self) -> Unpacked {
+        let bits = self.0;
+        if bits & QNAN != QNAN {
+            return Unpacked::Number(f64::from_bits(bits));
+        }
+        if bits & SIGN != 0 {
+            // only `object` sets the sign bit, with a live object
+            let object = unsafe { ObjRef::from_address(bits & !(SIGN | QNAN)) };
+            return match object.get() {
+                Obj::Integer(integer) => Unpacked::Integer(*integer),
+                _ => Unpacked::Object(object),
+            };
+        }
+        if bits & TAG_INTEGER != 0 {
+            // shift the sign of the payload back into place
+            let shift = 64 - INTEGER_BITS;
+            return Unpacked::Integer(((bits & PAYLOAD) << shift) as i64 >> shift);
+        }
+        match bits & 0b11 {
+            TAG_FALSE => Unpacked::Bool(false),
+            TAG_TRUE => Unpacked::Bool(true),
+            _ => Unpacked::Nil,
+        }
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.unpack())
+    }
+}
+
+impl Value {
+    pub fn is_falsey(self) -> bool {
+        matches!(self.unpack(), Unpacked::Nil | Unpacked::Bool(false))
+    }
+}
@@ -0,0 +1,74 @@
+//! Weak references, made with `WeakRef(object)`.
+//!
+//! They don't keep their target alive: `get` hands it back until nothing
+//! else holds it, then `nil`. The collector doesn't count them either, so a
+//! target only kept alive by a cycle goes away once the cycle is collected.
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use crate::class::{LoxClass, LoxInstance};
+use crate::function::{LoxFunction, NativeFunction};
+use crate::generator::Generator;
+use crate::interpreter::RuntimeError;
+use crate::map::MapKey;
+use crate::ordered_map::OrderedMap;
+use crate::promise::Promise;
+use crate::value::Value;
+
+#[derive(Debug)]
+pub enum WeakRef {
+    Function(Weak<LoxFunction>),
+    Class(Weak<LoxClass>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<OrderedMap<MapKey, Value>>>),
+    Generator(Weak<RefCell<Generator>>),
+    Promise(Weak<RefCell<Promise>>),
+}
+
+pub fn new(target: &Value) -> Result<Value, RuntimeError> {
+    let weak = match target {
+        Value::Function(function) => WeakRef::Function(Rc::downgrade(function)),
+        Value::Class(class) => WeakRef::Class(Rc::downgrade(class)),
+        Value::Instance(instance) => WeakRef::Instance(Rc::downgrade(instance)),
+        Value::List(list) => WeakRef::List(Rc::downgrade(list)),
+        Value::Map(map) => WeakRef::Map(Rc::downgrade(map)),
+        Value::Generator(generator) => WeakRef::Generator(Rc::downgrade(generator)),
+        Value::Promise(promise) => WeakRef::Promise(Rc::downgrade(promise)),
+        other => {
+            return Err(RuntimeError::msg(format!(
+                "Can only make weak references to objects, not {}.",
+                other.type_name()
+            )))
+        }
+    };
+    Ok(Value::WeakRef(Rc::new(weak)))
+}
+
+impl WeakRef {
+    /// The target, unless it's gone.
+    pub fn get(&self) -> Option<Value> {
+        Some(match self {
+            WeakRef::Function(weak) => Value::Function(weak.upgrade()?),
+            WeakRef::Class(weak) => Value::Class(weak.upgrade()?),
+            WeakRef::Instance(weak) => Value::Instance(weak.upgrade()?),
+            WeakRef::List(weak) => Value::List(weak.upgrade()?),
+            WeakRef::Map(weak) => Value::Map(weak.upgrade()?),
+            WeakRef::Generator(weak) => Value::Generator(weak.upgrade()?),
+            WeakRef::Promise(weak) => Value::Promise(weak.upgrade()?),
+        })
+    }
+}
+
+/// Look up one of the methods every weak reference has, bound to `weak`.
+pub fn get_method(weak: &Rc<WeakRef>, name: &str) -> Option<Value> {
+    let method = match name {
+        "get" => NativeFunction::new(name, 0, {
+            let weak = weak.clone();
+            move |_| Ok(weak.get().unwrap_or(Value::Nil))
+        }),
+        _ => return None,
+    };
+    Some(Value::Native(Rc::new(method)))
+}
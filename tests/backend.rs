@@ -0,0 +1,77 @@
+mod common;
+
+use lox_rs::backend::{Backend, Engine};
+use lox_rs::value::Value;
+
+use common::SharedOutput;
+
+#[test]
+fn parses_backend_names() {
+    for backend in Backend::ALL {
+        assert_eq!(backend.to_string().parse::<Backend>().unwrap(), backend);
+    }
+    assert_eq!(Backend::default(), Backend::Ast);
+    let expected = if cfg!(feature = "register-vm") {
+        "Unknown backend 'jit', expected 'ast', 'vm' or 'register'."
+    } else {
+        "Unknown backend 'jit', expected 'ast' or 'vm'."
+    };
+    assert_eq!("jit".parse::<Backend>().unwrap_err().to_string(), expected);
+}
+
+#[test]
+fn runs_programs_on_either_backend() {
+    for backend in Backend::ALL {
+        let output = SharedOutput::default();
+        let mut engine = Engine::new(backend);
+        assert_eq!(engine.backend(), backend);
+        engine.set_output(output.clone());
+        engine.run("var greeting = \"hi\";").unwrap();
+        // globals are kept from one run to the next
+        engine.run("print greeting + \"!\";").unwrap();
+        assert_eq!(output.take(), "hi!\n");
+        assert_eq!(engine.get_global("greeting"), Some(Value::from("hi")));
+
+        let error = engine.run("print nope;").unwrap_err();
+        assert_eq!(
+            error.to_string().lines().next(),
+            Some("Undefined variable 'nope'.")
+        );
+        let error = engine.run_file("tests/lox/missing.lox").unwrap_err();
+        assert!(error.to_string().starts_with("Could not read"));
+    }
+}
+
+#[test]
+fn rejects_what_the_vm_cant_compile() {
+    let mut engine = Engine::new(Backend::Vm);
+    let error = engine.run("var list = [1, 2];").unwrap_err();
+    assert!(error.to_string().contains("to bytecode yet"), "{}", error);
+    Engine::new(Backend::Ast).run("var list = [1, 2];").unwrap();
+}
+
+#[test]
+fn interrupts_programs_on_every_backend() {
+    use lox_rs::interpreter::{RuntimeError, RuntimeErrorKind};
+
+    for backend in Backend::ALL {
+        for source in ["while (true) {}", "fun spin() {} while (true) spin();"] {
+            let mut engine = Engine::new(backend);
+            let handle = engine.interrupt_handle();
+            let interrupter = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                handle.interrupt();
+            });
+            let error = engine.run(source).unwrap_err();
+            interrupter.join().unwrap();
+            let error = error.downcast::<RuntimeError>().unwrap();
+            assert_eq!(error.kind, RuntimeErrorKind::Interrupted, "{}", backend);
+            assert_eq!(error.message, "Interrupted.");
+
+            // an interrupt only stops the run it was meant for
+            engine.interrupt_handle().interrupt();
+            engine.run("var done = true;").unwrap();
+            assert_eq!(engine.get_global("done"), Some(Value::Bool(true)));
+        }
+    }
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use lox_rs::cache::Cache;
+use lox_rs::chunk;
+use lox_rs::compiler::{self, Options};
+use lox_rs::value::Value;
+use lox_rs::vm::Vm;
+
+use common::SharedOutput;
+
+// a directory of its own for each test
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lox-cache-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn reuses_chunks_in_memory() {
+    let mut cache = Cache::in_memory();
+    let options = Options::default();
+    let first = cache.compile("print 1 + 2;", options).unwrap();
+    let again = cache.compile("print 1 + 2;", options).unwrap();
+    assert_eq!(first, again);
+    assert_eq!(first, compiler::compile("print 1 + 2;").unwrap());
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    assert_eq!(cache.path("print 1 + 2;", options), None);
+
+    // errors aren't cached
+    assert!(cache.compile("print 1 +;", options).is_err());
+    assert!(cache.compile("print 1 +;", options).is_err());
+    assert_eq!((cache.hits(), cache.misses()), (1, 3));
+}
+
+#[test]
+fn compiles_again_when_the_source_or_options_change() {
+    let mut cache = Cache::in_memory();
+    let options = Options::default();
+    cache.compile("print 1;", options).unwrap();
+    let changed = cache.compile("print 2;", options).unwrap();
+    assert_eq!(changed, compiler::compile("print 2;").unwrap());
+    let unfused = Options {
+        superinstructions: false,
+        ..options
+    };
+    cache.compile("print 1;", unfused).unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (0, 3));
+    cache.compile("print 1;", options).unwrap();
+    assert_eq!(cache.hits(), 1);
+}
+
+#[test]
+fn keeps_chunks_in_a_directory() {
+    let dir = dir("saved");
+    let source = "fun twice(n) { return n * 2; } print twice(21);";
+    let options = Options::default();
+    let mut cache = Cache::in_dir(&dir);
+    let chunk = cache.compile(source, options).unwrap();
+    let path = cache.path(source, options).unwrap();
+    assert!(path.starts_with(&dir));
+    // the source it was compiled from, then the chunk
+    let bytes = fs::read(&path).unwrap();
+    assert_eq!(&bytes[8..8 + source.len()], source.as_bytes());
+    assert_eq!(
+        chunk::deserialize(&bytes[8 + source.len()..]).unwrap(),
+        chunk
+    );
+
+    // another cache, as in the next run of the program, finds it
+    let mut cache = Cache::in_dir(&dir);
+    assert_eq!(cache.compile(source, options).unwrap(), chunk);
+    assert_eq!((cache.hits(), cache.misses()), (1, 0));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn replaces_files_it_cant_load() {
+    let dir = dir("broken");
+    let source = "print \"cached\";";
+    let options = Options::default();
+    let mut cache = Cache::in_dir(&dir);
+    let chunk = cache.compile(source, options).unwrap();
+    let path = cache.path(source, options).unwrap();
+
+    // broken
+    fs::write(&path, b"LOXC").unwrap();
+    assert_eq!(cache.compile(source, options).unwrap(), chunk);
+    assert_eq!((cache.hits(), cache.misses()), (0, 2));
+    assert_eq!(cache.compile(source, options).unwrap(), chunk);
+    assert_eq!(cache.hits(), 1);
+
+    // from another version of the format
+    let mut bytes = fs::read(&path).unwrap();
+    let version = 8 + source.len() + chunk::MAGIC.len();
+    bytes[version..version + 2].copy_from_slice(&(chunk::FORMAT_VERSION + 1).to_le_bytes());
+    fs::write(&path, bytes).unwrap();
+    assert_eq!(cache.compile(source, options).unwrap(), chunk);
+    assert_eq!(cache.misses(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checks_the_source_of_files_it_loads() {
+    let dir = dir("source");
+    let options = Options::default();
+    let mut cache = Cache::in_dir(&dir);
+    cache.compile("print 1;", options).unwrap();
+    let first = cache.path("print 1;", options).unwrap();
+
+    // as if both sources had the same hash
+    let second = cache.path("print 2;", options).unwrap();
+    fs::copy(&first, &second).unwrap();
+    assert_eq!(
+        cache.compile("print 2;", options).unwrap(),
+        compiler::compile("print 2;").unwrap()
+    );
+    assert_eq!((cache.hits(), cache.misses()), (0, 2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn runs_cached_programs_on_the_vm() {
+    let mut vm = Vm::new();
+    vm.set_output(SharedOutput::default());
+    vm.set_cache(Cache::in_memory());
+    vm.run("var count = 0;").unwrap();
+    for _ in 0..3 {
+        vm.run("count = count + 1;").unwrap();
+    }
+    assert_eq!(vm.get_global("count"), Some(Value::Integer(3)));
+    let cache = vm.cache().unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (2, 2));
+}
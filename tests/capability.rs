@@ -0,0 +1,215 @@
+use std::fs;
+use std::path::PathBuf;
+
+use lox_rs::capability::Capability;
+use lox_rs::interpreter::Interpreter;
+use lox_rs::value::Value;
+
+// a directory of its own for each test
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lox-capability-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn error(interpreter: &mut Interpreter, source: &str) -> String {
+    let error = interpreter.run(source).unwrap_err().to_string();
+    error.lines().next().unwrap_or_default().to_string()
+}
+
+#[test]
+fn leaves_files_alone_by_default() {
+    let mut interpreter = Interpreter::new();
+    for native in ["readFile", "writeFile", "appendFile", "exists", "listDir"] {
+        assert_eq!(
+            error(&mut interpreter, &format!("{}(\"x\");", native)),
+            format!("Undefined variable '{}'.", native)
+        );
+    }
+}
+
+#[test]
+fn reads_and_writes_files() {
+    let dir = dir("files");
+    let path = |name: &str| dir.join(name).display().to_string();
+    let mut interpreter = Interpreter::new();
+    interpreter.enable(Capability::Fs);
+    interpreter
+        .run(&format!(
+            r#"
+            var notes = "{notes}";
+            var before = exists(notes);
+            writeFile(notes, "héllo ");
+            appendFile(notes, "wörld");
+            appendFile("{log}", "started");
+            var text = readFile(notes);
+            var after = exists(notes);
+            var names = listDir("{dir}");
+            "#,
+            notes = path("notes.txt"),
+            log = path("log.txt"),
+            dir = dir.display(),
+        ))
+        .unwrap();
+
+    assert_eq!(interpreter.get_global("before"), Some(Value::Bool(false)));
+    assert_eq!(interpreter.get_global("after"), Some(Value::Bool(true)));
+    assert_eq!(
+        interpreter.get_global("text"),
+        Some(Value::from("héllo wörld"))
+    );
+    assert_eq!(fs::read_to_string(path("log.txt")).unwrap(), "started");
+    let names = interpreter.get_global("names").unwrap().to_string();
+    assert_eq!(names, "[log.txt, notes.txt]");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn fails_with_the_reason() {
+    let dir = dir("errors");
+    let missing = dir.join("missing.txt").display().to_string();
+    let mut interpreter = Interpreter::new();
+    interpreter.enable(Capability::Fs);
+
+    let message = error(&mut interpreter, &format!("readFile(\"{}\");", missing));
+    assert!(
+        message.starts_with(&format!("Could not read '{}': ", missing)),
+        "{}",
+        message
+    );
+    assert!(message.ends_with('.'));
+    let message = error(&mut interpreter, &format!("listDir(\"{}\");", missing));
+    assert!(
+        message.starts_with(&format!("Could not list '{}': ", missing)),
+        "{}",
+        message
+    );
+    let message = error(
+        &mut interpreter,
+        &format!("writeFile(\"{}\", \"\");", dir.display()),
+    );
+    assert!(message.starts_with("Could not write"), "{}", message);
+
+    assert_eq!(
+        error(&mut interpreter, "readFile(1);"),
+        "Expected a path but got number."
+    );
+    assert_eq!(
+        error(&mut interpreter, "writeFile(\"x\", nil);"),
+        "Expected a string but got nil."
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parses_capabilities() {
+    assert_eq!("fs".parse::<Capability>().unwrap(), Capability::Fs);
+    assert_eq!("env".parse::<Capability>().unwrap(), Capability::Env);
+    assert_eq!(
+        "process".parse::<Capability>().unwrap(),
+        Capability::Process
+    );
+    assert_eq!(Capability::Fs.to_string(), "fs");
+    let error = "gpu".parse::<Capability>().unwrap_err();
+    #[cfg(not(feature = "http"))]
+    let expected = "Unknown capability 'gpu', expected 'fs' or 'env' or 'process'.";
+    #[cfg(feature = "http")]
+    let expected = "Unknown capability 'gpu', expected 'fs' or 'env' or 'process' or 'net'.";
+    assert_eq!(error.to_string(), expected);
+}
+
+#[test]
+fn sets_environment_variables() {
+    let mut interpreter = Interpreter::new();
+    assert_eq!(
+        error(&mut interpreter, "setEnv(\"x\", \"y\");"),
+        "Undefined variable 'setEnv'."
+    );
+
+    let name = format!("LOX_CAPABILITY_TEST_{}", std::process::id());
+    interpreter.enable(Capability::Env);
+    interpreter
+        .run(&format!(
+            r#"setEnv("{name}", "héllo"); var set = env("{name}");"#,
+            name = name
+        ))
+        .unwrap();
+    assert_eq!(std::env::var(&name).unwrap(), "héllo");
+    assert_eq!(interpreter.get_global("set"), Some(Value::from("héllo")));
+    interpreter
+        .run(&format!(
+            r#"setEnv("{name}", nil); var unset = env("{name}");"#,
+            name = name
+        ))
+        .unwrap();
+    assert!(std::env::var_os(&name).is_none());
+    assert_eq!(interpreter.get_global("unset"), Some(Value::Nil));
+
+    assert_eq!(
+        error(&mut interpreter, "setEnv(\"a=b\", \"c\");"),
+        "Invalid environment variable name 'a=b'."
+    );
+    assert_eq!(
+        error(&mut interpreter, "setEnv(\"a\", 1);"),
+        "Expected a string or nil but got number."
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_processes() {
+    let mut interpreter = Interpreter::new();
+    for native in ["exec", "spawnProcess"] {
+        assert_eq!(
+            error(&mut interpreter, &format!("{}(\"true\");", native)),
+            format!("Undefined variable '{}'.", native)
+        );
+    }
+
+    interpreter.enable(Capability::Process);
+    interpreter
+        .run(
+            r#"
+            var result = exec("sh", ["-c", "echo out; echo err >&2; exit 3"]);
+            var code = result["code"];
+            var stdout = result["stdout"];
+            var stderr = result["stderr"];
+            var plain = exec("true")["code"];
+            "#,
+        )
+        .unwrap();
+    assert_eq!(interpreter.get_global("code"), Some(Value::Integer(3)));
+    assert_eq!(interpreter.get_global("stdout"), Some(Value::from("out\n")));
+    assert_eq!(interpreter.get_global("stderr"), Some(Value::from("err\n")));
+    assert_eq!(interpreter.get_global("plain"), Some(Value::Integer(0)));
+
+    // started without waiting for it
+    let dir = dir("process");
+    let marker = dir.join("marker");
+    interpreter
+        .run(&format!(
+            r#"var pid = spawnProcess("sh", ["-c", "sleep 0.05; echo done > {}"]);"#,
+            marker.display()
+        ))
+        .unwrap();
+    assert!(matches!(interpreter.get_global("pid"), Some(Value::Integer(pid)) if pid > 0));
+    let mut waited = 0;
+    while !marker.exists() && waited < 100 {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        waited += 1;
+    }
+    assert!(marker.exists());
+
+    assert!(error(&mut interpreter, "exec(\"lox-no-such-command\");")
+        .starts_with("Could not run 'lox-no-such-command': "));
+    assert_eq!(
+        error(&mut interpreter, "exec(\"echo\", \"hi\");"),
+        "Expected a list of arguments but got string."
+    );
+    assert_eq!(
+        error(&mut interpreter, "exec(\"echo\", [1]);"),
+        "Command arguments must be strings, not number."
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,44 @@
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use lox_rs::interpreter::Interpreter;
+
+/// A writer whose contents can still be read after handing it over to the
+/// interpreter.
+#[derive(Clone, Default)]
+pub struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl SharedOutput {
+    pub fn take(&self) -> String {
+        String::from_utf8(self.0.borrow_mut().split_off(0)).unwrap()
+    }
+}
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn capturing_interpreter() -> (Interpreter, SharedOutput) {
+    let output = SharedOutput::default();
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(output.clone());
+    (interpreter, output)
+}
+
+/// Run a program, returning what it printed or the error it failed with.
+pub fn run(source: &str) -> Result<String, String> {
+    let (mut interpreter, output) = capturing_interpreter();
+    match interpreter.run(source) {
+        Ok(()) => Ok(output.take()),
+        Err(error) => Err(error.to_string()),
+    }
+}
@@ -0,0 +1,564 @@
+use lox_rs::chunk::{self, Chunk, Constant, OpCode};
+use lox_rs::compiler::{compile, compile_with, Options};
+use lox_rs::lexer::Span;
+
+use OpCode::*;
+
+// the code of a chunk, operands as plain bytes
+fn code(ops: &[Result<OpCode, u8>]) -> Vec<u8> {
+    ops.iter()
+        .map(|op| match op {
+            Ok(op) => *op as u8,
+            Err(byte) => *byte,
+        })
+        .collect()
+}
+
+// the instructions as they are before fusing them into superinstructions
+fn unfused(source: &str) -> anyhow::Result<Chunk> {
+    compile_with(
+        source,
+        Options {
+            superinstructions: false,
+            ..Options::default()
+        },
+    )
+}
+
+// the instructions as they are before working out constant expressions
+fn unfolded(source: &str) -> anyhow::Result<Chunk> {
+    compile_with(
+        source,
+        Options {
+            constant_folding: false,
+            ..Options::default()
+        },
+    )
+}
+
+#[test]
+fn compiles_expressions() {
+    let chunk = unfolded("print -1 + 2 * 3.5;").unwrap();
+    assert_eq!(
+        chunk.code,
+        code(&[
+            Ok(Constant),
+            Err(0),
+            Ok(Negate),
+            Ok(Constant),
+            Err(1),
+            Ok(Constant),
+            Err(2),
+            Ok(Multiply),
+            Ok(Add),
+            Ok(Print),
+            Ok(Nil),
+            Ok(Return),
+        ])
+    );
+    assert_eq!(
+        chunk.constants,
+        vec![
+            Constant::Integer(1),
+            Constant::Integer(2),
+            Constant::Number(3.5)
+        ]
+    );
+}
+
+#[test]
+fn compiles_globals_and_locals() {
+    let chunk = unfused("var a = 1;\n{\n  var b = a;\n  b = 2;\n}").unwrap();
+    assert_eq!(
+        chunk.code,
+        code(&[
+            Ok(Constant),
+            Err(0),
+            Ok(DefineGlobal),
+            Err(1),
+            // the name is stored once
+            Ok(GetGlobal),
+            Err(1),
+            Ok(Constant),
+            Err(2),
+            Ok(SetLocal),
+            Err(0),
+            Ok(Pop),
+            Ok(Pop),
+            Ok(Nil),
+            Ok(Return),
+        ])
+    );
+    assert_eq!(chunk.constants[1], Constant::String("a".to_string()));
+    assert_eq!(chunk.lines, [1, 1, 1, 1, 3, 3, 4, 4, 4, 4, 4, 2, 2, 2]);
+}
+
+#[test]
+fn compiles_jumps() {
+    let chunk = unfolded("if (true and false) print 1; else print 2;").unwrap();
+    assert_eq!(
+        chunk.code,
+        code(&[
+            Ok(True),
+            Ok(JumpIfFalse),
+            Err(0),
+            Err(2),
+            Ok(Pop),
+            Ok(False),
+            // to the else branch
+            Ok(JumpIfFalse),
+            Err(0),
+            Err(7),
+            Ok(Pop),
+            Ok(Constant),
+            Err(0),
+            Ok(Print),
+            // over the else branch
+            Ok(Jump),
+            Err(0),
+            Err(4),
+            Ok(Pop),
+            Ok(Constant),
+            Err(1),
+            Ok(Print),
+            Ok(Nil),
+            Ok(Return),
+        ])
+    );
+}
+
+#[test]
+fn compiles_loops() {
+    let chunk =
+        unfused("for (var i = 0; i < 3; i = i + 1) { var j = i; if (j == 1) continue; break; }")
+            .unwrap();
+    assert_eq!(
+        chunk.code,
+        code(&[
+            // var i = 0, a local of the loop's scope
+            Ok(Constant),
+            Err(0),
+            // i < 3
+            Ok(GetLocal),
+            Err(0),
+            Ok(Constant),
+            Err(1),
+            Ok(Less),
+            Ok(JumpIfFalse),
+            Err(0),
+            Err(32),
+            Ok(Pop),
+            // var j = i
+            Ok(GetLocal),
+            Err(0),
+            // if (j == 1) continue, popping j, with no else to jump over
+            Ok(GetLocal),
+            Err(1),
+            Ok(Constant),
+            Err(2),
+            Ok(Equal),
+            Ok(JumpIfFalse),
+            Err(0),
+            Err(5),
+            Ok(Pop),
+            Ok(Pop),
+            Ok(Jump),
+            Err(0),
+            Err(5),
+            Ok(Pop),
+            // break, popping j, which the end of the block doesn't again
+            Ok(Pop),
+            Ok(Jump),
+            Err(0),
+            Err(12),
+            // i = i + 1
+            Ok(GetLocal),
+            Err(0),
+            Ok(Constant),
+            Err(2),
+            Ok(Add),
+            Ok(SetLocal),
+            Err(0),
+            Ok(Pop),
+            Ok(Loop),
+            Err(0),
+            Err(40),
+            // the condition, then i
+            Ok(Pop),
+            Ok(Pop),
+            Ok(Nil),
+            Ok(Return),
+        ])
+    );
+}
+
+#[test]
+fn rejects_what_bytecode_cant_express() {
+    let error = compile("var x = 1;\nfor (y in x) {}").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 2] Error: Can't compile for-in loops to bytecode yet."
+    );
+    let error = compile("class A { get x { return 1; } }").unwrap_err();
+    assert!(error.to_string().contains("getters"));
+    // errors the resolver finds come first
+    let error = compile("{ var a = a; }").unwrap_err();
+    assert!(error.to_string().contains("own initializer"));
+}
+
+#[test]
+fn opcodes_round_trip_through_bytes() {
+    for byte in 0..=u8::MAX {
+        if let Some(op) = OpCode::from_byte(byte) {
+            assert_eq!(op as u8, byte);
+        }
+    }
+    assert_eq!(OpCode::from_byte(Return as u8), Some(Return));
+}
+
+#[test]
+fn disassembles_chunks() {
+    let chunk = unfused("var a = \"hi\";\n{ var b = a; while (b) b = nil; }").unwrap();
+    assert_eq!(
+        chunk::disassemble(&chunk, "test"),
+        "== test ==
+0000    1 OP_CONSTANT         0 'hi'
+0002    | OP_DEFINE_GLOBAL    1 'a'
+0004    2 OP_GET_GLOBAL       1 'a'
+0006    | OP_GET_LOCAL        0
+0008    | OP_JUMP_IF_FALSE    8 -> 19
+0011    | OP_POP
+0012    | OP_NIL
+0013    | OP_SET_LOCAL        0
+0015    | OP_POP
+0016    | OP_LOOP            16 -> 6
+0019    | OP_POP
+0020    | OP_POP
+0021    | OP_NIL
+0022    | OP_RETURN"
+    );
+
+    // broken code doesn't stop the listing
+    let mut chunk = Chunk::new();
+    chunk.write(255, 1);
+    chunk.write(OpCode::Constant, 1);
+    assert_eq!(
+        chunk::disassemble(&chunk, "broken"),
+        "== broken ==\n0000    1 Unknown opcode 255\n0001    | OP_CONSTANT      ?"
+    );
+}
+
+#[test]
+fn dedupes_constants() {
+    let chunk =
+        unfolded("print 1; print 1.0; print 1; print -0.0; print 0.0; print \"1\"; print \"1\";")
+            .unwrap();
+    assert_eq!(
+        chunk.constants,
+        vec![
+            Constant::Integer(1),
+            Constant::Number(1.0),
+            Constant::Number(0.0),
+            Constant::String("1".to_string()),
+        ]
+    );
+    // `-0.0` is `0.0` negated, nothing to mix up there
+    let mut chunk = Chunk::new();
+    assert_eq!(chunk.add_constant(Constant::Number(0.0)), 0);
+    assert_eq!(chunk.add_constant(Constant::Number(-0.0)), 1);
+    assert_eq!(chunk.add_constant(Constant::Number(f64::NAN)), 2);
+    assert_eq!(chunk.add_constant(Constant::Number(f64::NAN)), 2);
+}
+
+#[test]
+fn compiles_enormous_scripts_with_long_constants() {
+    let count = 70_000;
+    let source = (0..count)
+        .map(|i| format!("var v{} = {};\n", i, i))
+        .collect::<String>();
+    let chunk = compile(&source).unwrap();
+    // a name and a number for every variable
+    assert_eq!(chunk.constants.len(), 2 * count);
+
+    let mut offset = 0;
+    let mut defined = 0;
+    while offset < chunk.len() {
+        let op = OpCode::from_byte(chunk.code[offset]).unwrap();
+        let (index, size) = match op {
+            ConstantLong | DefineGlobalLong => {
+                let bytes = &chunk.code[offset + 1..offset + 4];
+                let index = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize;
+                (Some(index), 4)
+            }
+            Constant | DefineGlobal => (Some(chunk.code[offset + 1] as usize), 2),
+            _ => (None, 1),
+        };
+        if let Some(index) = index {
+            // the index points at the value or name of this very line
+            let line = chunk.lines[offset] - 1;
+            let expected = match op {
+                Constant | ConstantLong => chunk::Constant::Integer(line as i64),
+                _ => chunk::Constant::String(format!("v{}", line)),
+            };
+            assert_eq!(chunk.constants[index], expected, "at offset {}", offset);
+            assert_eq!(matches!(op, ConstantLong | DefineGlobalLong), index >= 256);
+            if matches!(op, DefineGlobal | DefineGlobalLong) {
+                defined += 1;
+            }
+        }
+        offset += size;
+    }
+    assert_eq!(defined, count);
+    assert!(chunk::disassemble(&chunk, "big").contains("OP_CONSTANT_LONG 139998 '69999'"));
+}
+
+#[test]
+fn compiles_closures() {
+    let source = "fun outer() {\n  var x = 1;\n  fun inner() { return x; }\n  return inner;\n}";
+    let chunk = compile(source).unwrap();
+    assert_eq!(
+        chunk::disassemble(&chunk, "test"),
+        "== test ==
+0000    1 OP_CLOSURE          0 '<fn outer>'
+0002    | OP_DEFINE_GLOBAL    1 'outer'
+0004    | OP_NIL
+0005    | OP_RETURN
+== outer ==
+0000    2 OP_CONSTANT         0 '1'
+0002    3 OP_CLOSURE          1 '<fn inner>'
+0004      |                     local 1
+0006    4 OP_GET_LOCAL        2
+0008    | OP_RETURN
+== inner ==
+0000    3 OP_GET_UPVALUE      0
+0002    | OP_RETURN"
+    );
+
+    // captured locals are closed instead of popped
+    let chunk = compile("{ var a = 1; var b = 2; fun f() { return a; } }").unwrap();
+    assert_eq!(
+        chunk.code[chunk.len() - 5..],
+        code(&[Ok(Pop), Ok(Pop), Ok(CloseUpvalue), Ok(Nil), Ok(Return),])[..]
+    );
+}
+
+#[test]
+fn compiles_classes() {
+    let source = "class A {\n  init(x) { this.x = x; }\n  get() { return this.x; }\n}\nA(1).get();";
+    let chunk = unfused(source).unwrap();
+    assert_eq!(
+        chunk::disassemble(&chunk, "test"),
+        "== test ==
+0000    1 OP_CLASS            0 'A'
+0002    | OP_DEFINE_GLOBAL    0 'A'
+0004    | OP_GET_GLOBAL       0 'A'
+0006    2 OP_CLOSURE          1 '<fn init>'
+0008    1 OP_METHOD           2 'init'
+0010    3 OP_CLOSURE          3 '<fn get>'
+0012    1 OP_METHOD           4 'get'
+0014    | OP_POP
+0015    5 OP_GET_GLOBAL       0 'A'
+0017    | OP_CONSTANT         5 '1'
+0019    | OP_CALL             1
+0021    | OP_INVOKE        (0 args)    4 'get'
+0026    | OP_POP
+0027    | OP_NIL
+0028    | OP_RETURN
+== init ==
+0000    2 OP_GET_LOCAL        0
+0002    | OP_GET_LOCAL        1
+0004    | OP_SET_PROPERTY     0 'x'
+0006    | OP_POP
+0007    | OP_GET_LOCAL        0
+0009    | OP_RETURN
+== get ==
+0000    3 OP_GET_LOCAL        0
+0002    | OP_GET_PROPERTY     0 'x'
+0006    | OP_RETURN"
+    );
+    // the cache of the invoke, `get` counting its own
+    assert_eq!(chunk.caches, 1);
+}
+
+#[test]
+fn serializes_chunks() {
+    let source = "var big = 9223372036854775807;
+        var pi = 3.5;
+        fun greet(name) { var prefix = \"hi \"; fun inner() { return prefix + name; } return inner; }
+        class A { init() { this.x = -0.0; } }";
+    let chunk = compile(source).unwrap();
+    let bytes = chunk::serialize(&chunk);
+    // the header is the same on every machine
+    assert_eq!(bytes[..4], *b"LOXC");
+    assert_eq!(bytes[4..6], chunk::FORMAT_VERSION.to_le_bytes());
+    let loaded = chunk::deserialize(&bytes).unwrap();
+    assert_eq!(loaded, chunk);
+    assert_eq!(
+        chunk::disassemble(&loaded, "test"),
+        chunk::disassemble(&chunk, "test")
+    );
+    // constants added later still dedupe against the loaded ones
+    let pi = Constant::String("pi".to_string());
+    let index = chunk.constants.iter().position(|constant| *constant == pi);
+    let mut loaded = loaded;
+    assert_eq!(Some(loaded.add_constant(pi)), index);
+}
+
+#[test]
+fn rejects_broken_compiled_files() {
+    let bytes = chunk::serialize(&compile("print \"hello\";").unwrap());
+    let error = |bytes: &[u8]| chunk::deserialize(bytes).unwrap_err().to_string();
+
+    assert_eq!(error(b"print 1;"), "Not a compiled Lox file.");
+    let mut newer = bytes.clone();
+    newer[4] = 99;
+    assert_eq!(
+        error(&newer),
+        format!(
+            "Compiled with version 99 of the bytecode format, expected {}.",
+            chunk::FORMAT_VERSION
+        )
+    );
+    assert_eq!(
+        error(&bytes[..bytes.len() - 1]),
+        "The compiled file is truncated."
+    );
+    let mut longer = bytes;
+    longer.push(0);
+    assert_eq!(
+        error(&longer),
+        "Unexpected bytes at the end of the compiled file."
+    );
+}
+
+#[test]
+fn fuses_superinstructions() {
+    let source = "fun f(a, b) {\n  var c = a + 1;\n  c = a - 2;\n  return a < 3 and b;\n}";
+    let chunk = compile(source).unwrap();
+    let function = match &chunk.constants[0] {
+        Constant::Function(function) => function,
+        constant => panic!("expected a function, got {:?}", constant),
+    };
+    assert_eq!(
+        chunk::disassemble(&function.chunk, "f"),
+        "== f ==
+0000    2 OP_GET_LOCAL        1
+0002    | OP_ADD_CONSTANT     0 '1'
+0004    3 OP_GET_LOCAL        1
+0006    | OP_SUBTRACT_CONSTANT    1 '2'
+0008    | OP_SET_LOCAL_POP    3
+0010    4 OP_GET_LOCAL        1
+0012    | OP_LESS_CONSTANT    2 '3'
+0014    | OP_JUMP_IF_FALSE   14 -> 20
+0017    | OP_POP
+0018    | OP_GET_LOCAL        2
+0020    | OP_RETURN"
+    );
+    // the jump of `or` lands on the add, which can't be fused with the
+    // constant before it
+    let chunk = compile("var x; print 5 + (x or 1);").unwrap();
+    assert_eq!(
+        chunk.code[chunk.len() - 4..],
+        code(&[Ok(Add), Ok(Print), Ok(Nil), Ok(Return)])[..]
+    );
+    assert_eq!(
+        unfused("var x = 1; x = x + 1;").unwrap().code,
+        code(&[
+            Ok(Constant),
+            Err(0),
+            Ok(DefineGlobal),
+            Err(1),
+            Ok(GetGlobal),
+            Err(1),
+            Ok(Constant),
+            Err(0),
+            Ok(Add),
+            Ok(SetGlobal),
+            Err(1),
+            Ok(Pop),
+            Ok(Nil),
+            Ok(Return),
+        ])
+    );
+}
+
+#[test]
+fn folds_constants() {
+    let chunk = compile("print 60 * 60 + 0.5;").unwrap();
+    assert_eq!(
+        chunk.code,
+        code(&[Ok(Constant), Err(0), Ok(Print), Ok(Nil), Ok(Return)])
+    );
+    assert_eq!(chunk.constants, vec![Constant::Number(3600.5)]);
+
+    // only the branch a known condition picks is compiled
+    let chunk =
+        compile("while (false) print 1; if (1 < 2 and !nil) print \"yes\"; else print \"no\";")
+            .unwrap();
+    assert_eq!(
+        chunk.code,
+        code(&[Ok(Constant), Err(0), Ok(Print), Ok(Nil), Ok(Return)])
+    );
+    assert_eq!(chunk.constants, vec![Constant::String("yes".to_string())]);
+
+    // local constants are known where they're read
+    let chunk = compile("{ const n = 2; print n * -3; }").unwrap();
+    assert_eq!(
+        chunk.code,
+        code(&[
+            Ok(Constant),
+            Err(0),
+            Ok(Constant),
+            Err(1),
+            Ok(Print),
+            Ok(Pop),
+            Ok(Nil),
+            Ok(Return)
+        ])
+    );
+    assert_eq!(
+        chunk.constants,
+        vec![Constant::Integer(2), Constant::Integer(-6)]
+    );
+
+    // a loop that's always entered has no condition to check
+    let chunk = compile("while (true) break;").unwrap();
+    assert!(!chunk::disassemble(&chunk, "loop").contains("OP_JUMP_IF_FALSE"));
+
+    // a known left operand of `and` and `or` decides, or leaves the right one
+    let chunk = compile("var x; print false and x; print nil or x;").unwrap();
+    let listing = chunk::disassemble(&chunk, "logical");
+    assert!(listing.contains("OP_FALSE"), "{}", listing);
+    assert_eq!(listing.matches("OP_GET_GLOBAL").count(), 1, "{}", listing);
+
+    // what would fail is left to fail when it runs
+    let chunk = compile("print 1 + nil; print 1 << 64;").unwrap();
+    assert_eq!(chunk.code[3], OpCode::Add as u8);
+    assert!(chunk::disassemble(&chunk, "fails").contains("OP_SHIFT_LEFT"));
+}
+
+#[test]
+fn eliminates_dead_code() {
+    let source = "fun f(x) {\n  if (x) return 1; else { return 2; }\n  print \"never\";\n  x = 3;\n}\nif (false) print \"no\";";
+    let chunk = compile(source).unwrap();
+    let function = match &chunk.constants[0] {
+        Constant::Function(function) => function,
+        constant => panic!("not a function: {:?}", constant),
+    };
+    let dead = source.find("print \"never\"").unwrap();
+    let end = source.find("3;").unwrap() + 2;
+    assert_eq!(function.chunk.eliminated, [Span::new(dead, end, 3)]);
+    let listing = chunk::disassemble(&function.chunk, "f");
+    assert!(listing.contains(&format!("----    3 eliminated, bytes {}..{}", dead, end)));
+    // neither the statements nor the implicit return are compiled
+    assert!(!listing.contains("OP_PRINT"), "{}", listing);
+    assert!(!listing.contains("OP_NIL"), "{}", listing);
+    assert_eq!(listing.matches("OP_JUMP").count(), 1, "{}", listing);
+
+    let branch = source.find("print \"no\"").unwrap();
+    assert_eq!(chunk.eliminated, [Span::new(branch, source.len(), 6)]);
+    assert_eq!(
+        chunk::deserialize(&chunk::serialize(&chunk)).unwrap(),
+        chunk
+    );
+}
@@ -0,0 +1,44 @@
+use lox_rs::diagnostic;
+use lox_rs::interpreter::Interpreter;
+use lox_rs::lexer::Span;
+
+fn render(source: &str) -> String {
+    let error = Interpreter::new().run(source).unwrap_err();
+    diagnostic::render(&error, source)
+}
+
+#[test]
+fn diagnostic_runtime_errors() {
+    let source = "fun check(count) {\n  return count < nil;\n}\nprint check(1);";
+    assert_eq!(
+        render(source),
+        "Operands must be numbers.\n 2 |   return count < nil;\n   |          ^^^^^^^^^^^\n\
+         [line 2] in check()\n[line 4] in script"
+    );
+}
+
+#[test]
+fn diagnostic_syntax_errors() {
+    assert_eq!(
+        render("print 1;\nvar a = ;"),
+        "[line 2] Error at ';': Expect expression.\n 2 | var a = ;\n   |         ^"
+    );
+}
+
+#[test]
+fn diagnostic_snippets() {
+    // carets count characters and keep tabs, and spans over several lines
+    // only underline the first
+    assert_eq!(
+        diagnostic::snippet("\t\"é\" + 1", Span::new(1, 9, 1)),
+        [" 1 | \t\"é\" + 1", "   | \t^^^^^^^"]
+    );
+    assert_eq!(
+        diagnostic::snippet("f(1,\n2);", Span::new(0, 7, 1)),
+        [" 1 | f(1,", "   | ^^^^"]
+    );
+    // spans that don't point into the source, like ones from another file
+    assert!(diagnostic::snippet("print 1;", Span::new(0, 5, 3)).is_empty());
+    assert!(diagnostic::snippet("print 1;", Span::new(40, 45, 1)).is_empty());
+    assert!(diagnostic::snippet("print 1;", Span::default()).is_empty());
+}
@@ -0,0 +1,152 @@
+use lox_rs::gc;
+use lox_rs::interpreter::Interpreter;
+use lox_rs::value::Value;
+
+// every test runs on its own thread, and so has its own collector
+
+#[test]
+fn gc_frees_cycles() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(
+            r#"
+            class Node {
+                method() { return this; }
+            }
+            fun closure() {
+                var f;
+                fun g() { return f; }
+                f = g;
+            }
+            fun instances() {
+                var a = Node();
+                var b = Node();
+                a.other = b;
+                b.other = a;
+            }
+            fun list() {
+                var list = [];
+                list.push(list);
+            }
+            fun map() {
+                var map = {};
+                map["map"] = map;
+            }
+            fun method() {
+                var node = Node();
+                node.method = node.method;
+            }
+            fun each(items) {
+                for (item in items) yield item;
+            }
+            fun generator() {
+                var items = [];
+                var suspended = each(items);
+                items.push(suspended);
+                suspended.next();
+            }
+            async fun first(box) {
+                await box[0];
+            }
+            fun promise() {
+                // awaits its own promise, so it never settles
+                var box = [];
+                box.push(first(box));
+            }
+            "#,
+        )
+        .unwrap();
+    gc::collect();
+    let before = gc::tracked();
+
+    interpreter
+        .run("for (var i = 0; i < 100; i = i + 1) { closure(); instances(); list(); map(); method(); generator(); promise(); }")
+        .unwrap();
+    assert!(gc::tracked() >= before + 700);
+
+    assert!(gc::collect() >= 700);
+    assert_eq!(gc::tracked(), before);
+}
+
+#[test]
+fn gc_keeps_reachable_cycles() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(
+            r#"
+            class Node {}
+            var kept = Node();
+            kept.self = kept;
+            fun counter() {
+                var count = 0;
+                fun increment() {
+                    count = count + 1;
+                    return count;
+                }
+                return increment;
+            }
+            var increment = counter();
+            increment();
+            fun make() {
+                var node = Node();
+                node.self = node;
+                return node;
+            }
+            "#,
+        )
+        .unwrap();
+
+    // only the host holds this one
+    let held = interpreter.eval("make();").unwrap().unwrap();
+    gc::collect();
+
+    interpreter.define_global("held", held);
+    interpreter
+        .run("var same = kept.self == kept and held.self == held; var count = increment();")
+        .unwrap();
+    assert_eq!(interpreter.get_global("same"), Some(Value::Bool(true)));
+    assert_eq!(interpreter.get_global("count"), Some(Value::Number(2.0)));
+}
+
+#[test]
+fn gc_runs_while_executing() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(
+            r#"
+            class Node {}
+            for (var i = 0; i < 50000; i = i + 1) {
+                var node = Node();
+                node.self = node;
+            }
+            "#,
+        )
+        .unwrap();
+
+    // without collecting there would be one instance per iteration left
+    assert!(gc::tracked() < 50000);
+}
+
+#[test]
+fn gc_clears_weak_refs() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(
+            r#"
+            class Node {}
+            var node = Node();
+            node.self = node;
+            var weak = WeakRef(node);
+            node = nil;
+            "#,
+        )
+        .unwrap();
+
+    // the cycle keeps it alive until collected
+    interpreter.run("var alive = weak.get() != nil;").unwrap();
+    assert_eq!(interpreter.get_global("alive"), Some(Value::Bool(true)));
+
+    gc::collect();
+    interpreter.run("alive = weak.get() != nil;").unwrap();
+    assert_eq!(interpreter.get_global("alive"), Some(Value::Bool(false)));
+}
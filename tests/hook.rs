@@ -0,0 +1,186 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use lox_rs::ast::Stmt;
+use lox_rs::environment::{Binding, EnvRef, Environment};
+use lox_rs::hook::InterpreterHook;
+use lox_rs::interpreter::Interpreter;
+use lox_rs::lexer::Span;
+use lox_rs::stats::Stats;
+use lox_rs::value::Value;
+
+#[derive(Default)]
+struct Recorder {
+    lines: BTreeSet<usize>,
+    events: Vec<String>,
+    depth: usize,
+}
+
+impl InterpreterHook for Recorder {
+    fn on_statement(&mut self, statement: &Stmt, _environment: &EnvRef) {
+        self.lines.insert(statement.span.line);
+    }
+
+    fn on_call(&mut self, callee: &Value, arguments: &[Value], span: Span) {
+        self.events.push(format!(
+            "{}call {} with {} argument(s) on line {}",
+            "  ".repeat(self.depth),
+            callee,
+            arguments.len(),
+            span.line
+        ));
+        self.depth += 1;
+    }
+
+    fn on_return(&mut self, callee: &Value, value: &Value) {
+        self.depth -= 1;
+        self.events.push(format!(
+            "{}{} returned {}",
+            "  ".repeat(self.depth),
+            callee,
+            value
+        ));
+    }
+}
+
+#[test]
+fn hook_sees_statements_and_calls() {
+    let source = r#"
+        fun countdown(n) {
+            if (n == 0) return "done";
+            return countdown(n - 1);
+        }
+        var result = countdown(1);
+        if (false) {
+            print "never";
+        }
+    "#;
+
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut interpreter = Interpreter::new();
+    interpreter.set_hook(recorder.clone());
+    interpreter.run(source).unwrap();
+
+    let recorder = recorder.borrow();
+    // line 8 never runs
+    assert_eq!(
+        recorder.lines.iter().copied().collect::<Vec<_>>(),
+        vec![2, 3, 4, 6, 7]
+    );
+    // tail calls still get their own return
+    assert_eq!(
+        recorder.events,
+        vec![
+            "call <fn countdown> with 1 argument(s) on line 6",
+            "  call <fn countdown> with 1 argument(s) on line 4",
+            "  <fn countdown> returned done",
+            "<fn countdown> returned done",
+        ]
+    );
+}
+
+#[test]
+fn hook_skips_return_of_failed_calls() {
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut interpreter = Interpreter::new();
+    interpreter.set_hook(recorder.clone());
+    interpreter
+        .run("fun f() { return nil + 1; }\nf();")
+        .unwrap_err();
+    assert_eq!(
+        recorder.borrow().events,
+        vec!["call <fn f> with 0 argument(s) on line 2"]
+    );
+
+    assert!(interpreter.take_hook().is_some());
+    interpreter.run("\n\nf;").unwrap();
+    assert_eq!(
+        recorder.borrow().lines.iter().copied().collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+}
+
+#[test]
+fn stats_count_statements_calls_and_allocations() {
+    let stats = Rc::new(RefCell::new(Stats::new()));
+    let mut interpreter = Interpreter::new();
+    interpreter.set_hook(stats.clone());
+    interpreter
+        .run(
+            r#"
+            class Point {}
+            fun fib(n) {
+                if (n < 2) return n;
+                return fib(n - 1) + fib(n - 2);
+            }
+            var points = [Point(), Point()];
+            var result = len(points) + fib(4);
+            "#,
+        )
+        .unwrap();
+
+    let stats = stats.borrow();
+    // 9 calls to fib with an if each, 4 of them returning early
+    assert_eq!(stats.statements, 4 + 9 * 2);
+    assert_eq!(stats.calls, 12);
+    assert_eq!(stats.calls_by_function["fib"], 9);
+    assert_eq!(stats.calls_by_function["Point"], 2);
+    assert_eq!(stats.calls_by_function["len"], 1);
+    assert!(stats.objects_allocated() >= 3);
+    assert!(stats.bytes_allocated() > 0);
+
+    let report = stats.to_string();
+    assert!(report.starts_with("statements executed: 22\ncalls: 12\n"));
+    assert!(report.ends_with("calls by function:\n  fib    9\n  Point  2\n  len    1"));
+}
+
+// the scopes as they were at the statement on a given line
+struct Inspector {
+    line: usize,
+    scopes: Vec<Vec<Binding>>,
+}
+
+impl InterpreterHook for Inspector {
+    fn on_statement(&mut self, statement: &Stmt, environment: &EnvRef) {
+        if statement.span.line == self.line {
+            self.scopes = Environment::scopes(environment);
+        }
+    }
+}
+
+#[test]
+fn hook_inspects_scopes() {
+    let inspector = Rc::new(RefCell::new(Inspector {
+        line: 5,
+        scopes: Vec::new(),
+    }));
+    let mut interpreter = Interpreter::new();
+    interpreter.set_hook(inspector.clone());
+    interpreter
+        .run(
+            r#"
+            const limit = 10;
+            fun f(a) {
+                { const b = a + 1;
+                    print b; }
+            }
+            f(1);
+            "#,
+        )
+        .unwrap();
+
+    let binding = |name: &str, value: Value, constant| Binding {
+        name: name.to_string(),
+        value,
+        constant,
+    };
+    let scopes = &inspector.borrow().scopes;
+    assert_eq!(scopes.len(), 3);
+    assert_eq!(scopes[0], vec![binding("b", Value::Integer(2), true)]);
+    assert_eq!(scopes[1], vec![binding("a", Value::Integer(1), false)]);
+    assert!(scopes[2].contains(&binding("limit", Value::Integer(10), true)));
+
+    // between runs only the globals are in scope
+    assert_eq!(interpreter.scopes().len(), 1);
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+use lox_rs::capability::Capability;
+
+// a server answering one request with `response`, giving back the request
+// it got
+fn serve(response: &'static str) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        request.push_str(&String::from_utf8(body).unwrap());
+        reader.get_mut().write_all(response.as_bytes()).unwrap();
+        request
+    });
+    (url, server)
+}
+
+fn run(source: &str) -> Result<String, String> {
+    let (mut interpreter, output) = common::capturing_interpreter();
+    interpreter.enable(Capability::Net);
+    match interpreter.run(source) {
+        Ok(_) => Ok(output.take()),
+        Err(error) => Err(error.to_string().lines().next().unwrap().to_string()),
+    }
+}
+
+#[test]
+fn gets() {
+    let (url, server) = serve(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nX-Tag: a\r\nX-Tag: b\r\n\
+         Content-Length: 5\r\n\r\nhello",
+    );
+    let output = run(&format!(
+        r#"
+        var response = httpGet("{}/greeting");
+        print response["status"];
+        print response["headers"]["content-type"];
+        print response["headers"]["x-tag"];
+        print response["body"];
+        "#,
+        url
+    ));
+    assert_eq!(output, Ok("200\ntext/plain\na, b\nhello\n".to_string()));
+    assert!(server
+        .join()
+        .unwrap()
+        .starts_with("GET /greeting HTTP/1.1\r\n"));
+}
+
+#[test]
+fn posts_with_headers() {
+    let (url, server) = serve("HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\ngone");
+    let output = run(&format!(
+        r#"
+        var response = httpPost("{}/items", "{{}}", {{"X-Token": "secret"}});
+        print response["status"];
+        print response["body"];
+        "#,
+        url
+    ));
+    // errors are responses too
+    assert_eq!(output, Ok("404\ngone\n".to_string()));
+    let request = server.join().unwrap();
+    assert!(request.starts_with("POST /items HTTP/1.1\r\n"));
+    assert!(request.to_lowercase().contains("x-token: secret\r\n"));
+    assert!(request.ends_with("\r\n\r\n{}"));
+}
+
+#[test]
+fn reports_failures() {
+    // nothing listens on a port once its listener is gone
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let error = run(&format!("httpGet(\"http://127.0.0.1:{}\");", port)).unwrap_err();
+    assert!(
+        error.starts_with(&format!("Could not reach 'http://127.0.0.1:{}': ", port)),
+        "{}",
+        error
+    );
+    assert_eq!(
+        run("httpPost(\"http://localhost\", \"\", [1]);"),
+        Err("Expected a map of headers but got list.".to_string())
+    );
+    assert_eq!(
+        run("httpPost(\"http://localhost\", \"\", {\"a\": 1});"),
+        Err("Header values must be strings, not number.".to_string())
+    );
+}
+
+#[test]
+fn needs_the_net_capability() {
+    assert_eq!(
+        common::run("httpGet(\"http://localhost\");"),
+        Err("Undefined variable 'httpGet'.\n[line 1] in script".to_string())
+    );
+}
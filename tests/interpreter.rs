@@ -0,0 +1,1158 @@
+mod common;
+
+use lox_rs::interpreter::{Interpreter, RuntimeError};
+use lox_rs::value::Value;
+
+#[test]
+fn run_control_flow() {
+    let source = std::fs::read_to_string("tests/lox/control_flow.lox").unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&source).unwrap();
+
+    let global = |name| interpreter.get_global(name).unwrap();
+    assert_eq!(global("sum"), Value::Number(55.0));
+    assert_eq!(global("product"), Value::Number(120.0));
+    assert_eq!(global("parity"), Value::from("odd"));
+    assert_eq!(global("shadowed"), Value::from("outer"));
+    assert_eq!(global("logic"), Value::from("default"));
+    assert_eq!(global("short"), Value::Bool(false));
+}
+
+#[test]
+fn run_functions() {
+    let source = std::fs::read_to_string("tests/lox/fibonacci.lox").unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&source).unwrap();
+    assert_eq!(interpreter.get_global("result"), Some(Value::Number(610.0)));
+    assert_eq!(
+        interpreter.get_global("fibonacci").unwrap().to_string(),
+        "<fn fibonacci>"
+    );
+}
+
+#[test]
+fn run_closures() {
+    let source = std::fs::read_to_string("tests/lox/closures.lox").unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&source).unwrap();
+
+    let global = |name| interpreter.get_global(name).unwrap();
+    assert_eq!(global("a"), Value::Number(3.0));
+    assert_eq!(global("b"), Value::Number(1.0));
+    assert_eq!(global("before"), Value::from("global"));
+    assert_eq!(global("after"), Value::from("global"));
+    assert_eq!(global("nothing"), Value::Nil);
+}
+
+#[test]
+fn run_classes() {
+    let source = std::fs::read_to_string("tests/lox/classes.lox").unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&source).unwrap();
+
+    let run = |interpreter: &mut Interpreter, source: &str| {
+        interpreter.run(source).unwrap();
+        interpreter.get_global("result").unwrap()
+    };
+    assert_eq!(
+        run(&mut interpreter, "var result = sum.x;"),
+        Value::Number(0.0)
+    );
+    assert_eq!(
+        run(&mut interpreter, "var result = scaled.y;"),
+        Value::Number(3.0)
+    );
+    assert_eq!(
+        run(&mut interpreter, "var result = bound.x;"),
+        Value::Number(14.0)
+    );
+    assert_eq!(
+        run(&mut interpreter, "var result = reinit == sum;"),
+        Value::Bool(true)
+    );
+    assert_eq!(
+        run(&mut interpreter, "var result = sum.add;"),
+        Value::from("field")
+    );
+    assert_eq!(
+        run(&mut interpreter, "var result = scaled;").to_string(),
+        "Point instance"
+    );
+    assert_eq!(
+        run(&mut interpreter, "var result = Point;").to_string(),
+        "Point"
+    );
+}
+
+#[test]
+fn run_inheritance() {
+    let source = std::fs::read_to_string("tests/lox/inheritance.lox").unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&source).unwrap();
+
+    let global = |name| interpreter.get_global(name).unwrap();
+    assert_eq!(global("inherited"), Value::from("B method"));
+    assert_eq!(global("viaSuper"), Value::from("A method"));
+    assert_eq!(global("derived"), Value::Number(42.0));
+}
+
+#[test]
+fn run_keeps_globals() {
+    let mut interpreter = Interpreter::new();
+    interpreter.run("var a = 1;").unwrap();
+    interpreter.run("{ var b = a + 1; a = b * 10; }").unwrap();
+    assert_eq!(interpreter.get_global("a"), Some(Value::Number(20.0)));
+}
+
+#[test]
+fn run_keeps_constants() {
+    let mut interpreter = Interpreter::new();
+    interpreter.run("const a = 1;").unwrap();
+    let error = interpreter.run("fun f() { a = 2; }\nf();").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Can't assign to constant 'a'.\n[line 1] in f()\n[line 2] in script"
+    );
+    assert_eq!(interpreter.get_global("a"), Some(Value::Number(1.0)));
+
+    // declaring it again as a variable lifts the restriction
+    interpreter.run("var a = 3;\na = 4;").unwrap();
+    assert_eq!(interpreter.get_global("a"), Some(Value::Number(4.0)));
+}
+
+#[test]
+fn run_errors() {
+    let cases = [
+        (
+            "print undefined;",
+            "Undefined variable 'undefined'.\n[line 1] in script",
+        ),
+        (
+            "missing = 1;",
+            "Undefined variable 'missing'.\n[line 1] in script",
+        ),
+        (
+            "var a = \"a\";\nprint -a;",
+            "Operand must be a number.\n[line 2] in script",
+        ),
+        (
+            "print 1 < nil;",
+            "Operands must be numbers.\n[line 1] in script",
+        ),
+        (
+            "\"not a function\"();",
+            "Can only call functions and classes.\n[line 1] in script",
+        ),
+        (
+            "fun f(a, b) {}\nf(1);",
+            "Expected 2 arguments but got 1.\n[line 2] in script",
+        ),
+        (
+            "class A { init(a) {} }\nA();",
+            "Expected 1 arguments but got 0.\n[line 2] in script",
+        ),
+        (
+            "class A {}\nprint A().missing;",
+            "Undefined property 'missing'.\n[line 2] in script",
+        ),
+        (
+            "var a = 1;\nprint a.field;",
+            "Only instances have properties.\n[line 2] in script",
+        ),
+        (
+            "var a = 1;\na.field = 2;",
+            "Only instances have fields.\n[line 2] in script",
+        ),
+        (
+            "var a = 1;\nprint a[0];",
+            "Only lists, maps and strings can be indexed.\n[line 2] in script",
+        ),
+        (
+            "var a = {};\nprint a[1..];",
+            "Only lists and strings can be sliced.\n[line 2] in script",
+        ),
+        (
+            "print \"abc\"[2..1];",
+            "String slice 2..1 out of bounds for length 3.\n[line 1] in script",
+        ),
+        (
+            "print \"abc\"[0.5];",
+            "String index must be an integer.\n[line 1] in script",
+        ),
+        (
+            "var a = [1];\na[0.5] = 2;",
+            "List index must be an integer.\n[line 2] in script",
+        ),
+        (
+            "var a = [];\na.insert(-1, 2);",
+            "List index -1 out of bounds for length 0.\n[line 2] in script",
+        ),
+        (
+            "[].pop();",
+            "Can't pop from an empty list.\n[line 1] in script",
+        ),
+        (
+            "[].missing();",
+            "Undefined property 'missing'.\n[line 1] in script",
+        ),
+        (
+            "var m = {};\nm[nil] = 1;",
+            "Map keys must be strings, numbers or booleans, not nil.\n[line 2] in script",
+        ),
+        (
+            "print {0 / 0: 1};",
+            "Map keys can't be NaN.\n[line 1] in script",
+        ),
+        (
+            "var m = {};\nm.missing();",
+            "Undefined property 'missing'.\n[line 2] in script",
+        ),
+        (
+            "class A {}\nfor (a in A()) print a;",
+            "Undefined property 'iter'.\n[line 2] in script",
+        ),
+        (
+            "class A { iter() { return 1; } }\nfor (a in A()) print a;",
+            "Iterators must be instances.\n[line 2] in script",
+        ),
+        (
+            "class A { lt(other) { return 1; } }\nprint A() < A();",
+            "Method 'lt' must return a boolean, not number.\n[line 2] in script",
+        ),
+        (
+            "class A { str() { return nil; } }\nprint A();",
+            "Method 'str' must return a string, not nil.\n[line 2] in script",
+        ),
+        (
+            "class A {}\nvar m = {A(): 1};",
+            "Instances need a 'hash' method to be map keys.\n[line 2] in script",
+        ),
+        (
+            "class A { hash() { return \"a\"; } }\nvar m = {};\nm[A()] = 1;",
+            "Method 'hash' must return a number, not string.\n[line 3] in script",
+        ),
+        (
+            "class A { lt(other) { return 1 < nil; } }\nvar l = [A(), A()];\nl.sort();",
+            "Operands must be numbers.\n[line 1] in lt()\n[line 3] in script",
+        ),
+        (
+            "[1, \"a\"].sort();",
+            "Operands must be numbers.\n[line 1] in script",
+        ),
+        (
+            "fun less(a, b) { return a < b; }\nvar l = [2, 1];\nl.sort(less);",
+            "Comparison functions must return a number, not boolean.\n[line 3] in script",
+        ),
+        (
+            "fun f(n) {\n  return n.x;\n}\n[1].map(f);",
+            "Only instances have properties.\n[line 2] in f()\n[line 4] in script",
+        ),
+        (
+            "class A {}\nprint getField(A(), \"b\");",
+            "Undefined property 'b'.\n[line 2] in script",
+        ),
+        (
+            "print fields(1);",
+            "Expected an instance but got number.\n[line 1] in script",
+        ),
+        (
+            "class A {}\nsetField(A(), 1, 2);",
+            "Expected a field name but got number.\n[line 2] in script",
+        ),
+        (
+            "class A { class _b() {} }\nA._b();",
+            "Can't access private property '_b' outside of its class.\n[line 2] in script",
+        ),
+        (
+            "class A {}\nA()._b = 1;",
+            "Can't access private property '_b' outside of its class.\n[line 2] in script",
+        ),
+        (
+            "fun f() { yield g.next(); }\nvar g = f();\ng.next();",
+            "Generator is already running.\n[line 1] in f()\n[line 3] in script",
+        ),
+        (
+            "fun f() {\n  yield 1;\n  yield nil + 1;\n}\nvar g = f();\ng.next();\ng.next();",
+            "Operands must be two numbers or two strings.\n[line 3] in f()\n[line 7] in script",
+        ),
+        (
+            "async fun f() {\n  await sleepAsync(1);\n  throw \"boom\";\n}\nf();",
+            "Uncaught exception: boom.\n[line 3] in f()\n[line 5] in script",
+        ),
+        (
+            "async fun f() { throw \"boom\"; }\nasync fun g() {\n  await f();\n}\ng();",
+            "Uncaught exception: boom.\n[line 3] in g()\n[line 5] in script",
+        ),
+        (
+            "async fun f() { return nil + 1; }\nvar p = f();",
+            "Operands must be two numbers or two strings.\n[line 1] in f()\n[line 2] in script",
+        ),
+        (
+            "sleepAsync(-1);",
+            "Expected a number of milliseconds but got number.\n[line 1] in script",
+        ),
+        (
+            "fun f() {\n  return nil + 1;\n}\nvar t = spawn(f);\nt.join();",
+            "Operands must be two numbers or two strings.\n[line 5] in script",
+        ),
+        (
+            "fun f(a, b) {}\nspawn(f);",
+            "Can only spawn functions taking 0 or 1 arguments, not 2.\n[line 2] in script",
+        ),
+        (
+            "class A { m() {} }\nspawn(A().m);",
+            "Can't spawn a method.\n[line 2] in script",
+        ),
+        (
+            "fun outer() {\n  var n = 1;\n  fun inner() { return n; }\n  return spawn(inner);\n}\nouter();",
+            "Can't spawn a function using the locals around it.\n[line 4] in outer()\n[line 6] in script",
+        ),
+        (
+            "{\n  var n = 1;\n  fun f() { return n; }\n  spawn(f);\n}",
+            "Can't spawn a function using the locals around it.\n[line 4] in script",
+        ),
+        (
+            "fun work(parent) { undefinedThing(); }\nvar t = spawn(work);\nt.receive();",
+            "Undefined variable 'undefinedThing'.\n[line 3] in script",
+        ),
+        (
+            "fun work(parent) {}\nvar t = spawn(work);\nt.receive();",
+            "Thread finished without sending a value.\n[line 3] in script",
+        ),
+        (
+            "fun f() { return nil + 1; }\nspawn(f);\nvar done = true;",
+            "Operands must be two numbers or two strings.\n[line 2] in script",
+        ),
+        (
+            "spawn(clock);",
+            "Expected a function but got function.\n[line 1] in script",
+        ),
+        (
+            "Channel().send(Channel);",
+            "Can't send a function between threads.\n[line 1] in script",
+        ),
+        (
+            "var l = [];\nl.push(l);\nChannel().send(l);",
+            "Can't send a list holding itself.\n[line 3] in script",
+        ),
+        (
+            "WeakRef(1);",
+            "Can only make weak references to objects, not number.\n[line 1] in script",
+        ),
+        (
+            "print 1.5 & 1;",
+            "Bitwise operands must be integers.\n[line 1] in script",
+        ),
+        (
+            "print 1 << 64;",
+            "Shift count must be between 0 and 63.\n[line 1] in script",
+        ),
+        (
+            "print ~\"a\";",
+            "Operand must be a number.\n[line 1] in script",
+        ),
+        (
+            "print format(\"{} {}\", 1);",
+            "Format string needs argument 1 but got only 1.\n[line 1] in script",
+        ),
+        (
+            "print format(\"{:x}\", 1);",
+            "Invalid format placeholder '{:x}'.\n[line 1] in script",
+        ),
+        (
+            "print format(\"{\");",
+            "Unmatched '{' in format string.\n[line 1] in script",
+        ),
+        (
+            "print format(\"{:.1}\", \"a\");",
+            "Can only give a precision to numbers, not string.\n[line 1] in script",
+        ),
+        (
+            "print format();",
+            "Expected at least 1 arguments but got 0.\n[line 1] in script",
+        ),
+        (
+            "class A {}\nprint A()[0];",
+            "Can't use '[]' on a A instance without a 'index' method.\n[line 2] in script",
+        ),
+        (
+            "class A { get b { return 1; } }\nA().b = 2;",
+            "Property 'b' has a getter but no setter.\n[line 2] in script",
+        ),
+        (
+            "fun f(a, b) {}\nf(...[1]);",
+            "Expected 2 arguments but got 1.\n[line 2] in script",
+        ),
+        (
+            "fun f(...a) {}\nf(...\"ab\");",
+            "Can only spread lists, not string.\n[line 2] in script",
+        ),
+        (
+            "len(nil);",
+            "Expected a list, map, string or range but got nil.\n[line 1] in script",
+        ),
+    ];
+
+    for (source, message) in cases.iter() {
+        let error = Interpreter::new().run(source).unwrap_err();
+        let error = error.downcast::<RuntimeError>().unwrap();
+        assert_eq!(error.to_string(), *message);
+    }
+}
+
+#[test]
+fn run_error_stack_trace() {
+    let source = r#"
+        fun fib(n) {
+            if (n < 2) return n - nil;
+            return fib(n - 1) + fib(n - 2);
+        }
+
+        class Runner {
+            init(n) {
+                this.result = fib(n);
+            }
+        }
+
+        Runner(3);
+    "#;
+
+    let error = Interpreter::new().run(source).unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.span.line, 3);
+    assert_eq!(
+        error.to_string(),
+        "Operands must be numbers.
+[line 3] in fib()
+[line 4] in fib()
+[line 4] in fib()
+[line 9] in init()
+[line 13] in script"
+    );
+}
+
+#[test]
+fn run_natives() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let calls = Rc::new(Cell::new(0));
+    let mut interpreter = Interpreter::new();
+
+    let counter = calls.clone();
+    interpreter.define_native("double", 1, move |arguments| {
+        counter.set(counter.get() + 1);
+        match arguments[0] {
+            Value::Integer(number) => Ok(Value::Integer(number * 2)),
+            _ => Err(RuntimeError::msg("Argument must be a number.")),
+        }
+    });
+
+    interpreter
+        .run("var result = double(double(10));\nvar native = double;")
+        .unwrap();
+    assert_eq!(interpreter.get_global("result"), Some(Value::Number(40.0)));
+    assert_eq!(
+        interpreter.get_global("native").unwrap().to_string(),
+        "<native fn>"
+    );
+    assert_eq!(calls.get(), 2);
+
+    let error = interpreter.run("\ndouble(1, 2);").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Expected 1 arguments but got 2.\n[line 2] in script"
+    );
+
+    let error = interpreter
+        .run("fun f() {\n  return double(\"a\");\n}\nf();")
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Argument must be a number.\n[line 2] in f()\n[line 4] in script"
+    );
+}
+
+#[test]
+fn run_execution_limits() {
+    use lox_rs::interpreter::{ExecutionLimits, RuntimeErrorKind};
+    use std::time::Duration;
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(ExecutionLimits {
+        max_steps: Some(1_000),
+        timeout: None,
+    });
+
+    // each run gets the whole budget
+    interpreter
+        .run("var i = 0; while (i < 10) i = i + 1;")
+        .unwrap();
+    interpreter
+        .run("var i = 0; while (i < 10) i = i + 1;")
+        .unwrap();
+
+    let error = interpreter.run("while (true) {}").unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::ExecutionLimitExceeded);
+
+    interpreter.set_limits(ExecutionLimits {
+        max_steps: None,
+        timeout: Some(Duration::from_millis(50)),
+    });
+    let error = interpreter
+        .run("fun spin() { while (true) {} }\nspin();")
+        .unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::ExecutionLimitExceeded);
+    assert_eq!(error.trace.len(), 2);
+}
+
+#[test]
+fn run_event_loop_limits() {
+    use lox_rs::interpreter::{ExecutionLimits, RuntimeErrorKind};
+    use std::time::Duration;
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(ExecutionLimits {
+        max_steps: None,
+        timeout: Some(Duration::from_millis(50)),
+    });
+    let error = interpreter
+        .run("async fun f() {\n  await sleepAsync(60000);\n}\nf();")
+        .unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::ExecutionLimitExceeded);
+    assert_eq!(error.span.line, 2);
+
+    // whatever was left waiting is dropped along with the failed run
+    interpreter.set_limits(ExecutionLimits::default());
+    interpreter.run("var done = true;").unwrap();
+    assert_eq!(interpreter.get_global("done"), Some(Value::Bool(true)));
+}
+
+#[test]
+fn run_blocked_on_channel() {
+    use lox_rs::interpreter::{ExecutionLimits, RuntimeErrorKind};
+    use std::time::Duration;
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(ExecutionLimits {
+        max_steps: None,
+        timeout: Some(Duration::from_millis(50)),
+    });
+    let error = interpreter.run("Channel().receive();").unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::ExecutionLimitExceeded);
+
+    let error = interpreter
+        .run("fun forever() { Channel().receive(); }\nspawn(forever).join();")
+        .unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::ExecutionLimitExceeded);
+}
+
+#[test]
+fn run_threads_print_to_the_output() {
+    let (mut interpreter, output) = common::capturing_interpreter();
+    interpreter
+        .run("fun hello() { print \"hello\"; }\nspawn(hello).join();\nprint \"joined\";")
+        .unwrap();
+    assert_eq!(output.take(), "hello\njoined\n");
+
+    // the run waits for the threads it didn't join
+    interpreter
+        .run("fun late() { sleep(50); print \"late\"; }\nspawn(late);\nprint \"early\";")
+        .unwrap();
+    assert_eq!(output.take(), "early\nlate\n");
+}
+
+#[test]
+fn run_threads_inherit_limits() {
+    use lox_rs::interpreter::ExecutionLimits;
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_max_call_depth(10);
+    let error = interpreter
+        .run(
+            "fun f() {\n\
+               fun deep(n) { if (n == 0) return 0; return 1 + deep(n - 1); }\n\
+               return deep(50);\n\
+             }\n\
+             spawn(f).join();",
+        )
+        .unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.message, "Stack overflow.");
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(ExecutionLimits {
+        max_steps: Some(10_000),
+        timeout: None,
+    });
+    let error = interpreter
+        .run("fun count() { for (i in 0..1000000) {} }\nspawn(count).join();")
+        .unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.message, "Execution limit exceeded.");
+}
+
+#[test]
+fn run_interrupted_stops_threads() {
+    use lox_rs::interpreter::RuntimeErrorKind;
+
+    let (mut interpreter, output) = common::capturing_interpreter();
+    let handle = interpreter.interrupt_handle();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+    // the run only fails once the thread stopped
+    let error = interpreter
+        .run("fun forever() { while (true) {} }\nspawn(forever);")
+        .unwrap_err();
+    interrupter.join().unwrap();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+    assert_eq!(error.span.line, 2);
+
+    interpreter.run("print 1;").unwrap();
+    assert_eq!(output.take(), "1\n");
+}
+
+#[test]
+fn run_string_coercion() {
+    let mut interpreter = Interpreter::new();
+    let error = interpreter.run("1 + \"a\";").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Can't add number and string, operands must be two numbers or two strings.\n[line 1] in script"
+    );
+    let error = interpreter.run("nil + \"a\";").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Operands must be two numbers or two strings.\n[line 1] in script"
+    );
+
+    interpreter.set_string_coercion(true);
+    interpreter
+        .run("var a = \"n = \" + 1.5; var b = 2 + \"!\"; var c = \"is \" + nil;")
+        .unwrap();
+    assert_eq!(interpreter.get_global("a"), Some(Value::from("n = 1.5")));
+    assert_eq!(interpreter.get_global("b"), Some(Value::from("2!")));
+    assert_eq!(interpreter.get_global("c"), Some(Value::from("is nil")));
+    // numbers still add up
+    assert_eq!(
+        interpreter.eval("1 + 2;").unwrap(),
+        Some(Value::Number(3.0))
+    );
+
+    // instances are shown the way `print` shows them
+    interpreter
+        .run(
+            "class P { toString() { return \"p\"; } }\n\
+             class Q {}\n\
+             var d = \"is \" + P(); var e = P() + \"!\"; var f = Q() + \"\";",
+        )
+        .unwrap();
+    assert_eq!(interpreter.get_global("d"), Some(Value::from("is p")));
+    assert_eq!(interpreter.get_global("e"), Some(Value::from("p!")));
+    assert_eq!(interpreter.get_global("f"), Some(Value::from("Q instance")));
+}
+
+#[test]
+fn run_exceptions() {
+    let source = r#"
+        fun fail() {
+            throw Error("boom");
+        }
+        fail();
+    "#;
+    let error = Interpreter::new().run(source).unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, lox_rs::interpreter::RuntimeErrorKind::Thrown);
+    assert_eq!(
+        error.to_string(),
+        "Uncaught exception: Error: boom.\n[line 3] in fail()\n[line 5] in script"
+    );
+
+    let error = Interpreter::new().run("throw 1;").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Uncaught exception: 1.\n[line 1] in script"
+    );
+}
+
+#[test]
+fn run_catch_runtime_errors() {
+    use lox_rs::interpreter::ExecutionLimits;
+
+    let source = r#"
+        var message;
+        try {
+            missing;
+        } catch (e) {
+            message = e.message;
+        }
+    "#;
+    let mut interpreter = Interpreter::new();
+    assert!(interpreter.run(source).is_err());
+
+    interpreter.set_catch_runtime_errors(true);
+    interpreter.run(source).unwrap();
+    assert_eq!(
+        interpreter.get_global("message"),
+        Some(Value::from("Undefined variable 'missing'."))
+    );
+
+    // running out of budget can't be caught
+    interpreter.set_limits(ExecutionLimits {
+        max_steps: Some(1_000),
+        timeout: None,
+    });
+    let error = interpreter
+        .run(
+            "var cleaned = false; try { while (true) {} } catch (e) {} finally { cleaned = true; }",
+        )
+        .unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(
+        error.kind,
+        lox_rs::interpreter::RuntimeErrorKind::ExecutionLimitExceeded
+    );
+    assert_eq!(interpreter.get_global("cleaned"), Some(Value::Bool(false)));
+}
+
+#[test]
+fn run_stack_overflow() {
+    let source = r#"
+        fun dive(n) {
+            return 1 + dive(n + 1);
+        }
+        dive(0);
+    "#;
+    let mut interpreter = Interpreter::new();
+    interpreter.set_max_call_depth(25);
+    let error = interpreter.run(source).unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.message, "Stack overflow.");
+    assert_eq!(error.trace.len(), 26);
+
+    // long traces only show both ends
+    let shown = error.to_string();
+    assert_eq!(shown.lines().count(), 22);
+    assert!(shown.contains("\n[6 more frames]\n"));
+    assert!(shown.ends_with("[line 3] in dive()\n[line 5] in script"));
+
+    // and programs can recover from it
+    interpreter.set_catch_runtime_errors(true);
+    interpreter
+        .run("var caught; try { dive(0); } catch (e) { caught = e.message; }")
+        .unwrap();
+    assert_eq!(
+        interpreter.get_global("caught"),
+        Some(Value::from("Stack overflow."))
+    );
+}
+
+#[test]
+fn run_stack_overflow_default_depth() {
+    use lox_rs::interpreter::STACK_SIZE;
+
+    // with the default depth, calls run out before the native stack does
+    let thread = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| {
+            let mut interpreter = Interpreter::new();
+            let error = interpreter
+                .run("fun dive() { return 1 + dive(); } dive();")
+                .unwrap_err();
+            error.downcast::<RuntimeError>().unwrap().message
+        })
+        .unwrap();
+    assert_eq!(thread.join().unwrap(), "Stack overflow.");
+}
+
+#[test]
+fn run_stack_overflow_default_thread() {
+    // the native stack runs out first there, which fails the same way
+    let sources = [
+        "fun dive() { return 1 + dive(); } dive();",
+        "class A { dive() { for (i in [1]) { return [this.dive()]; } } }\nA().dive();",
+        "fun dive() { var g = Generator(); return g.next(); }\n\
+         fun Generator() { yield dive(); }\ndive();",
+    ];
+    let thread = std::thread::spawn(move || {
+        sources
+            .iter()
+            .map(|source| {
+                let error = Interpreter::new().run(source).unwrap_err();
+                error.downcast::<RuntimeError>().unwrap().message
+            })
+            .collect::<Vec<_>>()
+    });
+    for message in thread.join().unwrap() {
+        assert_eq!(message, "Stack overflow.");
+    }
+}
+
+#[test]
+fn run_interrupted() {
+    use lox_rs::interpreter::RuntimeErrorKind;
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_catch_runtime_errors(true);
+    let handle = interpreter.interrupt_handle();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+
+    // catch and finally don't get in the way
+    let error = interpreter
+        .run(
+            "var cleaned = false; try { while (true) {} } catch (e) {} finally { cleaned = true; }",
+        )
+        .unwrap_err();
+    interrupter.join().unwrap();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+    assert_eq!(error.message, "Interrupted.");
+    assert_eq!(interpreter.get_global("cleaned"), Some(Value::Bool(false)));
+
+    // an interrupt only stops the run it was meant for
+    interpreter.interrupt_handle().interrupt();
+    interpreter.run("cleaned = true;").unwrap();
+    assert_eq!(interpreter.get_global("cleaned"), Some(Value::Bool(true)));
+}
+
+#[test]
+fn run_sleep_interrupted() {
+    use lox_rs::interpreter::RuntimeErrorKind;
+    use std::time::{Duration, Instant};
+
+    let mut interpreter = Interpreter::new();
+    let handle = interpreter.interrupt_handle();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+    let start = Instant::now();
+    let error = interpreter.run("print 1;\nsleep(60000);").unwrap_err();
+    interrupter.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+    assert_eq!(error.span.line, 2);
+}
+
+#[test]
+fn run_tail_calls() {
+    let source = r#"
+        fun inner(n) {
+            return n - nil;
+        }
+        fun outer(n) {
+            return inner(n);
+        }
+        fun caller() {
+            var result = outer(1);
+            return result;
+        }
+        caller();
+    "#;
+
+    // `outer` hands its frame over to `inner`
+    let error = Interpreter::new().run(source).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Operands must be numbers.\n[line 3] in inner()\n[line 9] in caller()\n[line 12] in script"
+    );
+}
+
+#[test]
+fn run_imports() {
+    // without a script, paths are relative to the working directory
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(std::io::sink());
+    interpreter
+        .run("import \"tests/lox/modules/math.lox\";")
+        .unwrap();
+    assert_eq!(interpreter.get_global("answer"), Some(Value::Number(42.0)));
+    assert_eq!(interpreter.get_global("hidden"), None);
+
+    let error = interpreter.run("import \"missing.lox\";").unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("Could not import 'missing.lox': "));
+
+    let error = interpreter
+        .run("import \"tests/lox/modules/broken.lox\";")
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Could not import 'tests/lox/modules/broken.lox': [line 1] Error at ';': Expect expression.\n[line 1] in script"
+    );
+
+    let error = interpreter.run_file("missing.lox").unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("Could not read 'missing.lox': "));
+}
+
+#[test]
+fn run_file_exit_codes() {
+    use lox_rs::interpreter::{exit_code, EXIT_COMPILE_ERROR, EXIT_NO_INPUT, EXIT_RUNTIME_ERROR};
+
+    let code = |path| exit_code(&Interpreter::new().run_file(path));
+    assert_eq!(code("tests/lox/numbers.lox"), 0);
+    assert_eq!(code("tests/lox/modules/broken.lox"), EXIT_COMPILE_ERROR);
+    assert_eq!(code("missing.lox"), EXIT_NO_INPUT);
+    assert_eq!(
+        exit_code(&Interpreter::new().run("print 1 < nil;")),
+        EXIT_RUNTIME_ERROR
+    );
+    // failing in a thread nobody joined fails the run too
+    assert_eq!(
+        exit_code(&Interpreter::new().run("fun f() { return nil + 1; }\nspawn(f);")),
+        EXIT_RUNTIME_ERROR
+    );
+}
+
+#[test]
+fn run_exit() {
+    use lox_rs::interpreter::{exit_code, exited};
+
+    let (mut interpreter, output) = common::capturing_interpreter();
+    interpreter.set_catch_runtime_errors(true);
+    let result = interpreter.run(
+        r#"
+        fun log(message) {
+            print message;
+        }
+        fun work() {
+            defer log("deferred");
+            try {
+                exit(3);
+            } catch (e) {
+                print "caught";
+            } finally {
+                print "finally";
+            }
+            print "after";
+        }
+        work();
+        print "not reached";
+        "#,
+    );
+    assert_eq!(output.take(), "finally\ndeferred\n");
+    assert!(exited(&result));
+    assert_eq!(exit_code(&result), 3);
+
+    // the interpreter can still be used after
+    let result = interpreter.run("print 1; exit();");
+    assert_eq!(output.take(), "1\n");
+    assert_eq!(exit_code(&result), 0);
+    assert!(exited(&result));
+    let result = interpreter.run("print 1 < nil;");
+    assert!(!exited(&result));
+
+    let error = interpreter.run("exit(\"1\");").unwrap_err().to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected an exit code but got string.")
+    );
+    // the system would only keep the low byte
+    for code in [300, -1, 1i64 << 40] {
+        let result = interpreter.run(&format!("exit({});", code));
+        assert!(!exited(&result));
+        assert_eq!(
+            result.unwrap_err().to_string().lines().next(),
+            Some(format!("Exit code must be between 0 and 255 but got {}.", code).as_str())
+        );
+    }
+    assert_eq!(exit_code(&interpreter.run("exit(255);")), 255);
+}
+
+#[test]
+fn run_snapshot_restore() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run("var list = [];\nconst limit = 1;\nfun f() { return list; }")
+        .unwrap();
+    let snapshot = interpreter.snapshot();
+
+    interpreter
+        .run("var extra = 1;\nvar limit = 2;\nlist.push(1);\nlist = nil;")
+        .unwrap();
+    interpreter.define_native("host", 0, |_| Ok(Value::Nil));
+    interpreter.restore(&snapshot);
+
+    assert_eq!(interpreter.get_global("extra"), None);
+    assert_eq!(interpreter.get_global("host"), None);
+    // the globals get their values back, but those aren't copies
+    interpreter.run("var pushed = len(f());").unwrap();
+    assert_eq!(interpreter.get_global("pushed"), Some(Value::Integer(1)));
+    let error = interpreter.run("limit = 3;").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Can't assign to constant 'limit'.\n[line 1] in script"
+    );
+
+    // and it can be restored again
+    interpreter.run("var extra = 2;").unwrap();
+    interpreter.restore(&snapshot);
+    assert_eq!(interpreter.get_global("extra"), None);
+}
+
+#[test]
+fn run_memory_limit() {
+    use lox_rs::interpreter::RuntimeErrorKind;
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_memory_limit(Some(1 << 20));
+    interpreter
+        .run("var small = [];\nfor (i in 0..100) small.push(\"item\");")
+        .unwrap();
+
+    let error = interpreter
+        .run("var big = [];\nwhile (true) big.push(\"more\" + \"data\");")
+        .unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::Thrown);
+    assert_eq!(
+        error.to_string(),
+        "Uncaught exception: Out of memory.\n[line 2] in script"
+    );
+
+    // the program can catch it and let go of what it holds
+    interpreter.run("big = nil;").unwrap();
+    interpreter
+        .run(
+            "var caught;\n\
+             try {\n  var text = \"x\";\n  while (true) text = text + text;\n} \
+             catch (error) {\n  caught = isInstance(error, OutOfMemoryError) and error.message;\n}",
+        )
+        .unwrap();
+    assert_eq!(
+        interpreter.get_global("caught"),
+        Some(Value::from("Out of memory."))
+    );
+}
+
+#[test]
+fn run_record_replay() {
+    use lox_rs::replay::ReplayLog;
+
+    let source = "var a = clock();\nvar b = clock();";
+    let mut interpreter = Interpreter::new();
+    interpreter.record();
+    interpreter.run(source).unwrap();
+    let log = interpreter.take_replay_log().unwrap();
+    assert_eq!(log.len(), 2);
+    let recorded = (
+        interpreter.get_global("a").unwrap(),
+        interpreter.get_global("b").unwrap(),
+    );
+
+    // the log survives being written out
+    let log = ReplayLog::parse(&log.to_string()).unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.replay(log);
+    interpreter.run(source).unwrap();
+    assert_eq!(
+        (
+            interpreter.get_global("a").unwrap(),
+            interpreter.get_global("b").unwrap()
+        ),
+        recorded
+    );
+    assert!(interpreter.take_replay_log().unwrap().is_empty());
+
+    interpreter.replay(ReplayLog::parse("clock number 1.5\n").unwrap());
+    let error = interpreter.run("clock();\nclock();").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Replay log has no result left for 'clock'.\n[line 2] in script"
+    );
+}
+
+#[test]
+fn replay_log_keeps_values() {
+    use lox_rs::replay::ReplayLog;
+
+    let text = "env string two\\nlines \\\\ here\nenv nil\nrandom number 0.1\nrandom integer -3\ninput true\n";
+    let mut log = ReplayLog::parse(text).unwrap();
+    assert_eq!(log.to_string(), text);
+    assert_eq!(log.pop("env").unwrap(), Value::from("two\nlines \\ here"));
+    assert_eq!(
+        log.pop("random").unwrap_err(),
+        "Replay log expected a call to 'env', not 'random'."
+    );
+    assert!(ReplayLog::parse("clock number soon").is_err());
+}
+
+#[test]
+fn run_reload_module() {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    let dir = std::env::temp_dir().join(format!("lox-reload-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let module = dir.join("hot.lox");
+    let write = |source: &str, age: u64| {
+        fs::write(&module, source).unwrap();
+        // file systems may not tell writes close in time apart
+        let file = fs::File::options().write(true).open(&module).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(age))
+            .unwrap();
+    };
+    write(
+        "fun greet() { return \"v1\"; }\nclass Box { get() { return 1; } }\nfun shadowed() { return \"module\"; }",
+        20,
+    );
+
+    let mut interpreter = Interpreter::new();
+    let eval = |interpreter: &mut Interpreter, source: &str| {
+        interpreter
+            .eval(&format!("{};", source))
+            .unwrap()
+            .unwrap()
+            .to_string()
+    };
+    interpreter
+        .run(&format!(
+            "import \"{}\";\nvar box = Box();\nfun shadowed() {{ return \"script\"; }}",
+            module.display()
+        ))
+        .unwrap();
+
+    write(
+        "fun greet() { return \"v2\"; }\nclass Box { get() { return 2; } }\nfun shadowed() { return \"module v2\"; }\nfun added() { return \"new\"; }",
+        10,
+    );
+    interpreter.reload_module(&module).unwrap();
+    assert_eq!(
+        eval(
+            &mut interpreter,
+            "[greet(), box.get(), Box().get(), shadowed(), added()]"
+        ),
+        "[v2, 1, 2, script, new]"
+    );
+
+    // a broken module leaves the old one in place
+    write("fun greet( {", 5);
+    assert!(interpreter.reload_module(&module).is_err());
+    assert_eq!(eval(&mut interpreter, "greet()"), "v2");
+
+    // and still counts as changed
+    assert!(interpreter.reload_changed_modules().is_err());
+    write("fun greet() { return \"v3\"; }", 0);
+    assert_eq!(interpreter.reload_changed_modules().unwrap().len(), 1);
+    assert_eq!(interpreter.reload_changed_modules().unwrap().len(), 0);
+    assert_eq!(eval(&mut interpreter, "greet()"), "v3");
+
+    let error = Interpreter::new().reload_module(&module).unwrap_err();
+    assert!(error.to_string().ends_with("was never imported."));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+
+use lox_rs::interpreter::{Interpreter, RuntimeError};
+use lox_rs::isolate::Isolate;
+
+#[test]
+fn isolate_handle_is_shareable() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Isolate>();
+}
+
+#[test]
+fn isolate_runs_programs() {
+    let isolate = Isolate::spawn();
+    isolate.run("var a = 1;").unwrap();
+    assert_eq!(isolate.eval("a + 1;").unwrap(), Some("2".to_string()));
+    assert_eq!(isolate.eval("var b;").unwrap(), None);
+
+    // errors come back to the host
+    let error = isolate.run("print missing;").unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(error.message, "Undefined variable 'missing'.");
+}
+
+#[test]
+fn isolate_setup() {
+    // the output lives on the isolate thread, only the text comes back
+    let isolate = Isolate::spawn_with(|interpreter| {
+        let (capturing, output) = common::capturing_interpreter();
+        *interpreter = capturing;
+        interpreter.define_native("printed", 0, move |_| Ok(output.take().into()));
+    });
+    isolate.run("print \"captured\";").unwrap();
+    assert_eq!(
+        isolate.eval("printed();").unwrap(),
+        Some("captured\n".to_string())
+    );
+}
+
+#[test]
+fn isolates_share_nothing() {
+    let isolates = (0..4)
+        .map(|i| {
+            Isolate::spawn_with(move |interpreter| {
+                interpreter.define_global("id", (i as f64).into());
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // the same names in every isolate, run from threads of the host
+    let isolates = Arc::new(isolates);
+    let threads = (0..4)
+        .map(|i| {
+            let isolates = isolates.clone();
+            thread::spawn(move || {
+                let isolate = &isolates[i];
+                isolate
+                    .run("var total = 0; for (var n = 0; n < 1000; n = n + 1) total = total + id;")
+                    .unwrap();
+                isolate.eval("total;").unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for (i, thread) in threads.into_iter().enumerate() {
+        let total = thread.join().unwrap();
+        assert_eq!(total, Some((i * 1000).to_string()));
+    }
+}
+
+#[test]
+fn interpreters_per_thread() {
+    let threads = (0..4)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut interpreter = Interpreter::new();
+                interpreter
+                    .run(&format!(
+                        "class Box {{ init(v) {{ this.v = v; }} }} var box = Box({});",
+                        i
+                    ))
+                    .unwrap();
+                interpreter.eval("box.v;").unwrap().unwrap().to_string()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for (i, thread) in threads.into_iter().enumerate() {
+        assert_eq!(thread.join().unwrap(), i.to_string());
+    }
+}
@@ -0,0 +1,164 @@
+use lox_rs::interpreter::Interpreter;
+use lox_rs::json::{parse, stringify};
+use lox_rs::value::Value;
+
+fn error(text: &str) -> String {
+    parse(text).unwrap_err().message
+}
+
+#[test]
+fn parses_values() {
+    assert_eq!(parse(" true ").unwrap(), Value::Bool(true));
+    assert_eq!(parse("null").unwrap(), Value::Nil);
+    assert_eq!(parse("-0").unwrap(), Value::Integer(0));
+    assert_eq!(
+        parse("9223372036854775807").unwrap(),
+        Value::Integer(i64::MAX)
+    );
+    assert_eq!(
+        parse("9223372036854775808").unwrap(),
+        Value::Number(9223372036854775808.0)
+    );
+    assert_eq!(parse("1e3").unwrap(), Value::Number(1000.0));
+    assert_eq!(parse("-2.5E-1").unwrap(), Value::Number(-0.25));
+
+    let escaped = r#""quote \" slash \\ \/ \b\f\n\r\t \u00e9 \ud83e\udd80 ü""#;
+    assert_eq!(
+        parse(escaped).unwrap(),
+        Value::from("quote \" slash \\ / \u{8}\u{c}\n\r\t é 🦀 ü")
+    );
+
+    // the last of the same keys wins, where the first one was
+    let object = parse(r#"{"a": 1, "b": {"c": [true, null]}, "a": 2}"#).unwrap();
+    assert_eq!(object.to_string(), "{a: 2, b: {c: [true, nil]}}");
+}
+
+#[test]
+fn points_at_malformed_json() {
+    assert_eq!(
+        error(""),
+        "Invalid JSON at line 1, column 1: unexpected end of the text."
+    );
+    assert_eq!(
+        error("[1 2]"),
+        "Invalid JSON at line 1, column 4: expected ',' or ']' but got '2'."
+    );
+    assert_eq!(
+        error("{\n  \"a\": 1,\n  b: 2\n}"),
+        "Invalid JSON at line 3, column 3: expected a string key but got 'b'."
+    );
+    assert_eq!(
+        error("{\"a\" 1}"),
+        "Invalid JSON at line 1, column 6: expected ':' after the key but got '1'."
+    );
+    assert_eq!(
+        error("{\"é\": tru}"),
+        "Invalid JSON at line 1, column 7: unexpected 't'."
+    );
+    assert_eq!(
+        error("[1] 2"),
+        "Invalid JSON at line 1, column 5: unexpected '2'."
+    );
+    assert_eq!(
+        error("\"open"),
+        "Invalid JSON at line 1, column 6: the string isn't closed."
+    );
+    assert_eq!(
+        error("\"a\nb\""),
+        "Invalid JSON at line 1, column 3: control characters must be escaped in strings."
+    );
+    assert_eq!(
+        error("\"\\x\""),
+        "Invalid JSON at line 1, column 2: invalid escape."
+    );
+    assert_eq!(
+        error("\"\\ud83e\""),
+        "Invalid JSON at line 1, column 2: invalid unicode escape."
+    );
+    assert_eq!(
+        error("\"\\u12\""),
+        "Invalid JSON at line 1, column 2: invalid unicode escape."
+    );
+    assert_eq!(
+        error("01"),
+        "Invalid JSON at line 1, column 2: unexpected '1'."
+    );
+    assert_eq!(
+        error("1."),
+        "Invalid JSON at line 1, column 3: invalid number."
+    );
+    assert_eq!(
+        error("-"),
+        "Invalid JSON at line 1, column 2: invalid number."
+    );
+    assert_eq!(
+        error("[1,"),
+        "Invalid JSON at line 1, column 4: unexpected end of the text."
+    );
+    let deep = "[".repeat(1000);
+    assert_eq!(
+        error(&deep),
+        "Invalid JSON at line 1, column 513: it's nested too deeply."
+    );
+}
+
+#[test]
+fn stringifies_values() {
+    let value = parse(r#"{"text": "tab\there \"quoted\" \u0001", "n": [0.1, -7, 1e21]}"#).unwrap();
+    assert_eq!(
+        stringify(&value, false).unwrap(),
+        r#"{"text":"tab\there \"quoted\" \u0001","n":[0.1,-7,1.0E21]}"#
+    );
+    // what's written parses back the same
+    assert_eq!(parse(&stringify(&value, true).unwrap()).unwrap(), value);
+    assert_eq!(
+        stringify(&parse("[[], {}]").unwrap(), true).unwrap(),
+        "[\n  [],\n  {}\n]"
+    );
+}
+
+#[test]
+fn fails_on_what_json_cant_hold() {
+    let mut interpreter = Interpreter::new();
+    let error = |interpreter: &mut Interpreter, source| {
+        let error = interpreter.run(source).unwrap_err().to_string();
+        error.lines().next().unwrap_or_default().to_string()
+    };
+    assert_eq!(
+        error(&mut interpreter, "jsonStringify(clock);"),
+        "Can't convert function to JSON."
+    );
+    assert_eq!(
+        error(&mut interpreter, "class A {} jsonStringify([A()]);"),
+        "Can't convert instance to JSON."
+    );
+    assert_eq!(
+        error(&mut interpreter, "jsonStringify(0 / 0);"),
+        "Can't convert NaN to JSON."
+    );
+    assert_eq!(
+        error(
+            &mut interpreter,
+            "var l = [1]; l.push(l); jsonStringify(l);"
+        ),
+        "Can't convert a value holding itself to JSON."
+    );
+    assert_eq!(
+        error(&mut interpreter, "jsonStringify(1, 2);"),
+        "Expected a boolean but got number."
+    );
+    assert_eq!(
+        error(&mut interpreter, "jsonParse(1);"),
+        "Expected a string but got number."
+    );
+
+    // the same list twice isn't a cycle
+    interpreter.define_global("text", Value::from(r#"{"a": [1]}"#));
+    interpreter
+        .run("var data = jsonParse(text); var twice = jsonStringify([data[\"a\"], data[\"a\"]]);")
+        .unwrap();
+    assert_eq!(
+        interpreter.get_global("twice"),
+        Some(Value::from("[[1],[1]]"))
+    );
+}
@@ -54,4 +54,45 @@ fn tokenize_all() {
     let lexer = Lexer::new(fibonacci.to_string());
 
     println!("{:?}", lexer.collect::<Vec<TokenKind>>());
-}
\ No newline at end of file
+}
+
+#[test]
+fn tokenize_spans() {
+    let source = "var _count = 1.5; // comment\nprint \"ã\" + _count;";
+    let tokens = Lexer::new(source.to_string()).tokenize().unwrap();
+
+    let kinds = tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::Var,
+            TokenKind::Identifier("_count".to_string()),
+            TokenKind::Equal,
+            TokenKind::Float(1.5),
+            TokenKind::SemiColon,
+            TokenKind::Print,
+            TokenKind::String("ã".to_string()),
+            TokenKind::Plus,
+            TokenKind::Identifier("_count".to_string()),
+            TokenKind::SemiColon,
+        ]
+    );
+
+    let print = &tokens[5];
+    assert_eq!(print.span.line, 2);
+    assert_eq!(&source[print.span.start..print.span.end], "print");
+    let last = &tokens[8];
+    assert_eq!(&source[last.span.start..last.span.end], "_count");
+}
+
+#[test]
+fn tokenize_errors() {
+    let error = Lexer::new("\n\"open".to_string()).tokenize().unwrap_err();
+    assert_eq!(error.to_string(), "[line 2] Error: Unterminated string.");
+
+    let error = Lexer::new("a # b".to_string()).tokenize().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error: Unexpected character '#'."
+    );
+}
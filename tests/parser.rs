@@ -0,0 +1,126 @@
+use lox_rs::ast::{BinaryOp, ExprKind, Literal, StmtKind};
+use lox_rs::lexer::SyntaxError;
+use lox_rs::parser::parse;
+
+#[test]
+fn parse_precedence() {
+    let program = parse("print 1 + 2 * 3;").unwrap();
+
+    let expr = match &program[0].kind {
+        StmtKind::Print(expr) => expr,
+        other => panic!("expected a print statement, got {:?}", other),
+    };
+    match &expr.kind {
+        ExprKind::Binary { left, op, right } => {
+            assert_eq!(*op, BinaryOp::Add);
+            assert_eq!(left.kind, ExprKind::Literal(Literal::Integer(1)));
+            assert!(matches!(
+                right.kind,
+                ExprKind::Binary {
+                    op: BinaryOp::Multiply,
+                    ..
+                }
+            ));
+        }
+        other => panic!("expected a binary expression, got {:?}", other),
+    }
+}
+
+#[test]
+fn parse_for_desugars_to_while() {
+    let program = parse("for (var i = 0; i < 10; i = i + 1) print i;").unwrap();
+
+    match &program[0].kind {
+        StmtKind::Block(statements) => {
+            assert!(matches!(statements[0].kind, StmtKind::Var { .. }));
+            assert!(matches!(statements[1].kind, StmtKind::While { .. }));
+        }
+        other => panic!("expected a block, got {:?}", other),
+    }
+}
+
+#[test]
+fn parse_errors() {
+    let error = parse("var a = 1\nprint a;").unwrap_err();
+    let error = error.downcast::<SyntaxError>().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "[line 2] Error at 'print': Expect ';' after variable declaration."
+    );
+
+    let error = parse("1 + 2 = 3;").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at '=': Invalid assignment target."
+    );
+
+    let error = parse("print (1;").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at ';': Expect ')' after expression."
+    );
+
+    let error = parse("print").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at end: Expect expression."
+    );
+
+    let error = parse("try { } print 1;").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at 'print': Expect 'catch' or 'finally' after try block."
+    );
+
+    let error = parse("class A { set b(c, d) {} }").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at 'b': A setter must have exactly one parameter."
+    );
+
+    let error = parse("fun f(...a, b) {}").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at ',': Expect ')' after rest parameter."
+    );
+
+    let error = parse("fun f(a = 1, b) {}").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at 'b': Expect a default value, like the parameters before."
+    );
+}
+
+#[test]
+fn parse_too_much_nesting() {
+    use lox_rs::interpreter::{Interpreter, STACK_SIZE};
+
+    let nested = |open: &str, inner: &str, close: &str, depth: usize| {
+        format!("{}{}{}", open.repeat(depth), inner, close.repeat(depth))
+    };
+    // the native stack of the interpreter thread holds what may be parsed
+    let thread = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let source = format!("print {};", nested("(", "1", ")", 1000));
+            Interpreter::new().run(&source).unwrap();
+
+            for source in [
+                format!("print {};", nested("(", "1", ")", 10_000)),
+                nested("{", "", "}", 10_000),
+                nested("if (true) ", "print 1;", "", 10_000),
+                format!("print {};", nested("-", "1", "", 10_000)),
+                format!("print 1{};", " + 1".repeat(10_000)),
+                format!("print a{};", ".b".repeat(10_000)),
+            ] {
+                let error = parse(&source).unwrap_err();
+                assert!(
+                    error.to_string().ends_with("Too much nesting."),
+                    "{}",
+                    error
+                );
+            }
+        })
+        .unwrap();
+    thread.join().unwrap();
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "regex")]
+
+mod common;
+
+use lox_rs::interpreter::Interpreter;
+
+#[test]
+fn matches_with_groups() {
+    assert_eq!(
+        common::run(
+            r#"
+            var date = regexMatch("(?P<year>\d{4})-(\d{2})(-(\d{2}))?", "on 2024-05, or so");
+            print date["text"];
+            print date["start"];
+            print date["end"];
+            print date["groups"];
+            print date["named"]["year"];
+            print regexMatch("x", "abc");
+            // indices count characters, like the string natives
+            print regexMatch("l+", "héllo")["start"];
+            "#
+        ),
+        Ok("2024-05\n3\n10\n[2024, 05, nil, nil]\n2024\nnil\n2\n".to_string())
+    );
+}
+
+#[test]
+fn finds_all_matches() {
+    assert_eq!(
+        common::run(
+            r#"
+            var words = regexFindAll("\w+", "één, twee drie");
+            print len(words);
+            for (word in words) print word["text"] + " " + format("{}", word["start"]);
+            print regexFindAll("\d", "none");
+            "#
+        ),
+        Ok("3\néén 0\ntwee 5\ndrie 10\n[]\n".to_string())
+    );
+}
+
+#[test]
+fn replaces_matches() {
+    assert_eq!(
+        common::run(
+            r#"
+            print regexReplace("(\w+)@(\w+)", "ann@home, bob@work", "$2:$1");
+            print regexReplace("(?P<n>\d+)", "1 and 22", "<${n}>");
+            print regexReplace("z", "abc", "y");
+            "#
+        ),
+        Ok("home:ann, work:bob\n<1> and <22>\nabc\n".to_string())
+    );
+}
+
+#[test]
+fn reports_bad_patterns() {
+    let mut interpreter = Interpreter::new();
+    let mut error = |source| {
+        let error = interpreter.run(source).unwrap_err().to_string();
+        error.lines().next().unwrap_or_default().to_string()
+    };
+    assert_eq!(
+        error("regexMatch(\"(a\", \"a\");"),
+        "Invalid regular expression '(a': unclosed group."
+    );
+    assert_eq!(
+        error("regexFindAll(1, \"a\");"),
+        "Expected a string but got number."
+    );
+    assert_eq!(
+        error("regexReplace(\"a\", \"a\", nil);"),
+        "Expected a string but got nil."
+    );
+}
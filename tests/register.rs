@@ -0,0 +1,119 @@
+#![cfg(feature = "register-vm")]
+
+mod common;
+
+use lox_rs::register::{Instruction, Vm};
+use lox_rs::value::Value;
+
+use common::SharedOutput;
+
+fn run(source: &str) -> Result<String, String> {
+    let mut vm = Vm::new();
+    let output = SharedOutput::default();
+    vm.set_output(output.clone());
+    match vm.run(source) {
+        Ok(()) => Ok(output.take()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+// the register machine and the interpreter agree on a program
+fn check(source: &str) {
+    assert_eq!(run(source), common::run(source), "running {:?}", source);
+}
+
+#[test]
+fn runs_like_the_interpreter() {
+    check("print 1 + 2 * 3; print 7 / 2; print -7 % 3; print 6 & 3; print ~5;");
+    check("print 1 < 2; print 1 == 1.0; print !nil; print -(3); print 9223372036854775807 + 1;");
+    check("var greeting = \"hello\"; print greeting + \" world\"; print clock() > 0;");
+    check("var a = 1; { var b = a + 1; a = b * 10; } print a;");
+    check("print nil or 2; print 1 and false; { var x = nil; print x or \"default\"; }");
+    check(
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+         print fib(15); print fib;",
+    );
+    check(
+        "var total = 0;
+         for (var i = 0; i < 10; i = i + 1) {
+           if (i == 2) continue;
+           if (i == 7) break;
+           total = total + i;
+         }
+         print total;",
+    );
+}
+
+#[test]
+fn keeps_locals_in_registers() {
+    let mut vm = Vm::new();
+    let script = vm
+        .compile("{ var a = 1; var b = a + 2; print a * b; }")
+        .unwrap();
+    assert_eq!(
+        script.code,
+        [
+            Instruction::Constant { dst: 0, index: 0 },
+            Instruction::AddConstant {
+                dst: 1,
+                left: 0,
+                index: 1
+            },
+            Instruction::Multiply {
+                dst: 2,
+                left: 0,
+                right: 1
+            },
+            Instruction::Print { src: 2 },
+            Instruction::Nil { dst: 0 },
+            Instruction::Return { src: 0 },
+        ]
+    );
+    assert_eq!(script.registers, 3);
+}
+
+#[test]
+fn reads_locals_before_they_change() {
+    check("{ var a = 1; print a + (a = 10); print a; }");
+    check("{ var a = 1; var b = 2; a = b and a; print a; a = nil or a; print a; }");
+    check("{ var a = 1; var b = (a = 5) + a; print b; }");
+    check("{ var a = \"before\"; var a2 = a; a = a2 + (a2 = \"!\"); print a; }");
+}
+
+#[test]
+fn fails_like_the_interpreter() {
+    check("print nope;");
+    check("nope = 1;");
+    check("print 1 + \"a\"; ");
+    check("print -\"a\";");
+    check("fun f(a) {} f(1, 2);");
+    check("var x = 1; x();");
+    check("fun inner() { return 1 < nil; } fun outer() { inner(); } outer();");
+    // the interpreter needs a bigger native stack to get there
+    let error = run("fun forever() { forever(); } forever();").unwrap_err();
+    assert!(error.starts_with("Stack overflow."), "{}", error);
+}
+
+#[test]
+fn keeps_globals_from_one_run_to_the_next() {
+    let mut vm = Vm::new();
+    vm.set_output(SharedOutput::default());
+    vm.run("var a = 1; fun f() { return a + 1; }").unwrap();
+    vm.run("var b = f();").unwrap();
+    assert_eq!(vm.get_global("b"), Some(Value::Integer(2)));
+    assert_eq!(vm.get_global("f"), None);
+    assert!(vm.instructions() > 0);
+}
+
+#[test]
+fn rejects_what_it_cant_compile() {
+    for source in [
+        "fun outer() { var x = 1; fun inner() { return x; } }",
+        "class A {}",
+        "var list = [1, 2];",
+        "for (x in 1..3) print x;",
+    ] {
+        let error = run(source).unwrap_err();
+        assert!(error.contains("to bytecode yet"), "{}", error);
+    }
+}
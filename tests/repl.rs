@@ -0,0 +1,54 @@
+use lox_rs::interpreter::Interpreter;
+use lox_rs::repl;
+
+fn session(input: &str) -> String {
+    let mut interpreter = Interpreter::new();
+    let mut output = Vec::new();
+    repl::run(&mut interpreter, input.as_bytes(), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn repl_echoes_expressions() {
+    let output = session("var a = 40;\na + 2\n\nfun f() {}\nf;\nvar b = a\nb == a\n");
+    assert_eq!(output, "> > 42\n> > > <fn f>\n> > true\n> \n");
+}
+
+#[test]
+fn repl_reports_errors_and_continues() {
+    let output = session("missing\nvar x = ;\nvar x = 1;\nx\n");
+    assert_eq!(
+        output,
+        "> Undefined variable 'missing'.\n[line 1] in script\n\
+         > [line 1] Error at ';': Expect expression.\n\
+         > > 1\n> \n"
+    );
+}
+
+#[test]
+fn repl_resets_globals() {
+    let output = session("var clock = 1;\nvar x = 2;\n:reset\nx\nclock\n");
+    assert_eq!(
+        output,
+        "> > > > Undefined variable 'x'.\n[line 1] in script\n> <native fn>\n> \n"
+    );
+}
+
+#[test]
+fn repl_lists_changed_globals() {
+    let output = session("var x = 1;\nconst y = \"two\";\nvar clock = 3;\n:env\n");
+    assert_eq!(
+        output,
+        "> > > > var clock = 3\nvar x = 1\nconst y = two\n> \n"
+    );
+}
+
+#[test]
+fn repl_exits() {
+    let mut interpreter = Interpreter::new();
+    let mut output = Vec::new();
+    let input = "1\nexit(4)\n2\n";
+    let code = repl::run(&mut interpreter, input.as_bytes(), &mut output).unwrap();
+    assert_eq!(code, 4);
+    assert_eq!(String::from_utf8(output).unwrap(), "> 1\n> ");
+}
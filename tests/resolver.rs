@@ -0,0 +1,230 @@
+use lox_rs::ast::{ExprKind, NodeId, Stmt, StmtKind};
+use lox_rs::lexer::Lexer;
+use lox_rs::parser::{parse, Parser};
+use lox_rs::resolver::{resolve, DefinitionKind, ReferenceKind, Resolution, Resolver, ScopeKind};
+
+fn find_print_variable(statements: &[Stmt]) -> Option<NodeId> {
+    statements.iter().find_map(|stmt| match &stmt.kind {
+        StmtKind::Print(expr) => match expr.kind {
+            ExprKind::Variable(_) => Some(expr.id),
+            _ => None,
+        },
+        StmtKind::Block(statements) => find_print_variable(statements),
+        StmtKind::Function(function) => find_print_variable(&function.body),
+        _ => None,
+    })
+}
+
+#[test]
+fn resolve_scopes() {
+    let lexer = Lexer::from_file("tests/lox/scopes.lox").unwrap();
+    let program = Parser::new(lexer).unwrap().parse().unwrap();
+    let model = resolve(&program);
+    assert!(model.errors().is_empty());
+
+    // `print message;` reads the innermost `message`, one block away
+    let node = find_print_variable(&program).unwrap();
+    let definition = model.definition_of(node).unwrap();
+    assert_eq!(definition.name, "message");
+    assert_eq!(definition.kind, DefinitionKind::Variable);
+    assert_eq!(model.scope(definition.scope).kind, ScopeKind::Block);
+    assert!(matches!(
+        model.resolution(node),
+        Some(Resolution::Local {
+            depth: 0,
+            slot: 0,
+            ..
+        })
+    ));
+
+    // `greeting` is a global, read once inside `greet` and once at the top level
+    let greeting = &model.definitions()[0];
+    assert_eq!(greeting.name, "greeting");
+    assert_eq!(greeting.scope, model.global_scope().id);
+    let uses = model.references_to(greeting.id).collect::<Vec<_>>();
+    assert_eq!(uses.len(), 2);
+    assert!(uses.iter().all(|r| r.kind == ReferenceKind::Read));
+}
+
+#[test]
+fn resolve_definition_at_offset() {
+    let source = "var a = 1;\nfun f(b) { a = b; return a; }";
+    let program = parse(source).unwrap();
+    let model = resolve(&program);
+
+    let b_use = source.rfind("b;").unwrap();
+    let definition = model.definition_at(b_use).unwrap();
+    assert_eq!(definition.name, "b");
+    assert_eq!(definition.kind, DefinitionKind::Parameter);
+
+    let a_definition = model.definition_at(source.find("a =").unwrap()).unwrap();
+    let kinds = model
+        .references_to(a_definition.id)
+        .map(|r| r.kind)
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, vec![ReferenceKind::Write, ReferenceKind::Read]);
+
+    let scope = model.scope_at(b_use);
+    assert_eq!(scope.kind, ScopeKind::Function);
+}
+
+#[test]
+fn resolve_errors() {
+    let cases = [
+        (
+            "{ var a = a; }",
+            "[line 1] Error at 'a': Can't read local variable in its own initializer.",
+        ),
+        (
+            "fun f() { var a; var a; }",
+            "[line 1] Error at 'a': Already a variable with this name in this scope.",
+        ),
+        (
+            "return 1;",
+            "[line 1] Error at 'return': Can't return from top-level code.",
+        ),
+        (
+            "print this;",
+            "[line 1] Error at 'this': Can't use 'this' outside of a class.",
+        ),
+        (
+            "class A { f() { super.f(); } }",
+            "[line 1] Error at 'super': Can't use 'super' in a class with no superclass.",
+        ),
+        (
+            "class A < A {}",
+            "[line 1] Error at 'A': A class can't inherit from itself.",
+        ),
+        (
+            "fun f() { import \"a.lox\"; }",
+            "[line 1] Error at 'import': Can only import at the top level.",
+        ),
+        (
+            "break;",
+            "[line 1] Error at 'break': Can't use 'break' outside of a loop.",
+        ),
+        (
+            "for (a in []) { fun f() { break; } }",
+            "[line 1] Error at 'break': Can't use 'break' outside of a loop.",
+        ),
+        (
+            "while (true) { fun f() { continue; } }",
+            "[line 1] Error at 'continue': Can't use 'continue' outside of a loop.",
+        ),
+        (
+            "yield 1;",
+            "[line 1] Error at 'yield': Can't yield from top-level code.",
+        ),
+        (
+            "class A { init() { yield 1; } }",
+            "[line 1] Error at 'yield': Can't yield from an initializer.",
+        ),
+        (
+            "fun f() { try { yield 1; } finally {} }",
+            "[line 1] Error at 'yield': Can't yield inside a try statement.",
+        ),
+        (
+            "fun f() { return 1; yield 2; }",
+            "[line 1] Error at 'return': Can't return a value from a generator.",
+        ),
+        (
+            "fun f() { await g(); }",
+            "[line 1] Error at 'await': Can't use 'await' outside of an async function.",
+        ),
+        (
+            "async fun f() { print await g(); }",
+            "[line 1] Error at 'await': Can only await as a whole statement or variable initializer.",
+        ),
+        (
+            "async fun f() { yield 1; }",
+            "[line 1] Error at 'yield': Can't yield from an async function.",
+        ),
+        (
+            "class A { async init() {} }",
+            "[line 1] Error at 'init': Can't make an initializer async.",
+        ),
+        (
+            "defer f();",
+            "[line 1] Error at 'defer': Can't defer from top-level code.",
+        ),
+        (
+            "const a = 1;\na = 2;",
+            "[line 2] Error at 'a': Can't assign to constant 'a'.",
+        ),
+        (
+            "fun f() { const a = 1; fun g() { a = 2; } }",
+            "[line 1] Error at 'a': Can't assign to constant 'a'.",
+        ),
+    ];
+
+    for (source, message) in cases.iter() {
+        let model = resolve(&parse(source).unwrap());
+        assert_eq!(model.check().unwrap_err().to_string(), *message);
+    }
+}
+
+#[test]
+fn resolve_strict_mode() {
+    let source = r#"
+        fun main() {
+            pritn("typo");
+            clock();
+            return later;
+        }
+        var later = 1;
+        undefined = 2;
+    "#;
+    let program = parse(source).unwrap();
+
+    // globals are late bound, so nothing is reported by default
+    assert!(resolve(&program).errors().is_empty());
+
+    let model = Resolver::new().strict(vec!["clock"]).resolve(&program);
+    let errors = model
+        .errors()
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        vec![
+            "[line 3] Error at 'pritn': Undefined variable 'pritn'.",
+            "[line 8] Error at 'undefined': Undefined variable 'undefined'.",
+        ]
+    );
+}
+
+#[test]
+fn resolve_tail_calls() {
+    let source = r#"
+        fun f(n) {
+            if (n > 0) return f(n - 1);
+            try {
+                return f(n);
+            } finally {}
+            return 1 + f(n);
+        }
+    "#;
+    let program = parse(source).unwrap();
+    let model = resolve(&program);
+    // only the first return, the others still have work left
+    assert_eq!(model.tail_calls().count(), 1);
+    let tail_call = model.tail_calls().next().unwrap();
+    assert!(model.is_tail_call(tail_call));
+}
+
+#[test]
+fn resolve_private_accesses() {
+    let source = r#"
+        class A {
+            f() { return this._x + this.y; }
+        }
+        A()._x;
+    "#;
+    let program = parse(source).unwrap();
+    let model = resolve(&program);
+    // the access outside of the class is left for the interpreter to reject
+    let accesses = model.private_accesses().collect::<Vec<_>>();
+    assert_eq!(accesses.len(), 1);
+    assert_eq!(accesses[0].1, program[0].id);
+}
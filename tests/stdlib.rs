@@ -0,0 +1,512 @@
+mod common;
+
+use std::io::Cursor;
+
+use lox_rs::interpreter::Interpreter;
+use lox_rs::value::Value;
+
+#[test]
+fn stdlib_clock() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(
+            r#"
+            var start = clock();
+            var i = 0;
+            while (i < 1000) i = i + 1;
+            var elapsed = clock() - start;
+            "#,
+        )
+        .unwrap();
+
+    match interpreter.get_global("start") {
+        // some time after 2020
+        Some(Value::Number(start)) => assert!(start > 1_577_836_800.0),
+        other => panic!("expected a number, got {:?}", other),
+    }
+    match interpreter.get_global("elapsed") {
+        Some(Value::Number(elapsed)) => assert!(elapsed >= 0.0),
+        other => panic!("expected a number, got {:?}", other),
+    }
+}
+
+#[test]
+fn stdlib_memory_used() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(
+            r#"
+            var before = memoryUsed();
+            var list = [];
+            for (i in 0..10000) list.push(format("item {}", i));
+            var grown = memoryUsed() - before;
+            list = nil;
+            var shrunk = memoryUsed() - before;
+            "#,
+        )
+        .unwrap();
+
+    let global = |name| match interpreter.get_global(name) {
+        Some(Value::Integer(bytes)) => bytes,
+        other => panic!("expected an integer, got {:?}", other),
+    };
+    assert!(global("grown") > 100_000);
+    assert!(global("shrunk") < 1_000);
+}
+
+#[test]
+fn stdlib_math() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(
+            r#"
+            var rounded = round(2.5);
+            var root = sqrt(4);
+            var nan = min(sqrt(-1), 1);
+            "#,
+        )
+        .unwrap();
+
+    assert!(matches!(
+        interpreter.get_global("rounded"),
+        Some(Value::Integer(3))
+    ));
+    assert!(matches!(interpreter.get_global("root"), Some(Value::Number(root)) if root == 2.0));
+    assert!(matches!(interpreter.get_global("nan"), Some(Value::Number(nan)) if nan.is_nan()));
+
+    let error = interpreter.run("pow(2);").unwrap_err();
+    assert_eq!(
+        error.to_string().lines().next(),
+        Some("Expected 2 arguments but got 1.")
+    );
+    let error = interpreter.run("max(1, nil);").unwrap_err();
+    assert_eq!(
+        error.to_string().lines().next(),
+        Some("Expected a number but got nil.")
+    );
+}
+
+#[test]
+fn stdlib_strings() {
+    let mut interpreter = Interpreter::new();
+    let error = |interpreter: &mut Interpreter, source| {
+        let error = interpreter.run(source).unwrap_err().to_string();
+        error.lines().next().unwrap_or_default().to_string()
+    };
+    assert_eq!(
+        error(&mut interpreter, "upper(1);"),
+        "Expected a string but got number."
+    );
+    assert_eq!(
+        error(&mut interpreter, "contains(\"a\", nil);"),
+        "Expected a string but got nil."
+    );
+    assert_eq!(
+        error(&mut interpreter, "join(\"ab\", \"\");"),
+        "Expected a list but got string."
+    );
+    assert_eq!(
+        error(&mut interpreter, "join([\"a\", 1], \"\");"),
+        "Can only join strings but got number."
+    );
+    assert_eq!(
+        error(&mut interpreter, "charAt(\"añb\", 3);"),
+        "String index 3 out of bounds for length 3."
+    );
+    assert_eq!(
+        error(&mut interpreter, "split(\"a\");"),
+        "Expected 2 arguments but got 1."
+    );
+
+    // a decomposed "é" is two characters, which don't match the composed one
+    interpreter
+        .run("var found = indexOf(\"cafe\u{301}\", \"é\"); var length = len(\"e\u{301}\");")
+        .unwrap();
+    assert!(matches!(
+        interpreter.get_global("found"),
+        Some(Value::Integer(-1))
+    ));
+    assert!(matches!(
+        interpreter.get_global("length"),
+        Some(Value::Integer(2))
+    ));
+}
+
+#[test]
+fn stdlib_input() {
+    let (mut interpreter, output) = common::capturing_interpreter();
+    interpreter.set_input(Cursor::new("alice\r\nbob\n\nlast"));
+    interpreter.record();
+    let source = r#"
+        var name = input("name? ");
+        var second = input();
+        var empty = input("> ");
+        var last = input();
+        var done = input();
+        print name + " " + second;
+        "#;
+    interpreter.run(source).unwrap();
+
+    assert_eq!(output.take(), "name? > alice bob\n");
+    assert_eq!(interpreter.get_global("empty"), Some(Value::from("")));
+    assert_eq!(interpreter.get_global("last"), Some(Value::from("last")));
+    assert_eq!(interpreter.get_global("done"), Some(Value::Nil));
+
+    // replayed lines aren't read again, but the prompts are still shown
+    let log = interpreter.take_replay_log().unwrap();
+    assert_eq!(log.len(), 5);
+    let (mut interpreter, output) = common::capturing_interpreter();
+    interpreter.set_input(Cursor::new(""));
+    interpreter.replay(log);
+    interpreter.run(source).unwrap();
+    assert_eq!(output.take(), "name? > alice bob\n");
+    assert_eq!(interpreter.get_global("last"), Some(Value::from("last")));
+
+    let error = interpreter.run("input(1, 2);").unwrap_err().to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected 0 to 1 arguments but got 2.")
+    );
+}
+
+#[test]
+fn stdlib_time() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(
+            r#"
+            var epoch = formatTime("%Y-%m-%d %H:%M:%S.%L", 0);
+            var leap = formatTime("%a %A, %b %B %d, day %j of %Y", 951782400000);
+            var before = formatTime("%Y-%m-%dT%H:%M:%S.%L %a", -1.5);
+            var percent = formatTime("100%%", 0);
+            var start = now();
+            var since = elapsed();
+            var i = 0;
+            while (i < 1000) i = i + 1;
+            var later = elapsed() - since;
+            "#,
+        )
+        .unwrap();
+
+    let global = |name| interpreter.get_global(name).unwrap();
+    assert_eq!(global("epoch"), Value::from("1970-01-01 00:00:00.000"));
+    assert_eq!(
+        global("leap"),
+        Value::from("Tue Tuesday, Feb February 29, day 060 of 2000")
+    );
+    assert_eq!(global("before"), Value::from("1969-12-31T23:59:59.998 Wed"));
+    assert_eq!(global("percent"), Value::from("100%"));
+    match global("start") {
+        // some time after 2020, in milliseconds
+        Value::Integer(start) => assert!(start > 1_577_836_800_000),
+        other => panic!("expected an integer, got {:?}", other),
+    }
+    match global("later") {
+        Value::Number(later) => assert!(later >= 0.0),
+        other => panic!("expected a number, got {:?}", other),
+    }
+
+    let error = |interpreter: &mut Interpreter, source| {
+        let error = interpreter.run(source).unwrap_err().to_string();
+        error.lines().next().unwrap_or_default().to_string()
+    };
+    assert_eq!(
+        error(&mut interpreter, "formatTime(\"%Q\", 0);"),
+        "Unknown time specifier '%Q'."
+    );
+    assert_eq!(
+        error(&mut interpreter, "formatTime(\"%\", 0);"),
+        "Time template ends in '%'."
+    );
+    assert_eq!(
+        error(&mut interpreter, "formatTime(\"\", \"0\");"),
+        "Expected a timestamp but got string."
+    );
+    assert_eq!(
+        error(&mut interpreter, "formatTime(\"\", 1 / 0);"),
+        "Timestamp is out of range."
+    );
+}
+
+#[test]
+fn stdlib_random() {
+    use lox_rs::random::Random;
+
+    // every value of a small range comes up about as often
+    let mut random = Random::seeded(7);
+    let mut counts = [0; 3];
+    for _ in 0..30_000 {
+        counts[(random.range(-1, 1) + 1) as usize] += 1;
+    }
+    assert!(
+        counts.iter().all(|&count| (9_000..11_000).contains(&count)),
+        "{:?}",
+        counts
+    );
+    // the whole range of integers is a range too
+    random.range(i64::MIN, i64::MAX);
+    assert_ne!(Random::unseeded(), Random::unseeded());
+
+    // seeded by the embedder, as by the program
+    let source = "var a = random(); var b = randomInt(0, 1000000);";
+    let run = |seed| {
+        let mut interpreter = Interpreter::new();
+        interpreter.seed_random(seed);
+        interpreter.run(source).unwrap();
+        (interpreter.get_global("a"), interpreter.get_global("b"))
+    };
+    assert_eq!(run(1), run(1));
+    assert_ne!(run(1), run(2));
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run(&format!("seedRandom(1); {}", source))
+        .unwrap();
+    assert_eq!(
+        (interpreter.get_global("a"), interpreter.get_global("b")),
+        run(1)
+    );
+
+    let error = interpreter
+        .run("randomInt(0.5, 1);")
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected an integer but got 0.5.")
+    );
+    let error = interpreter
+        .run("seedRandom(\"x\");")
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected an integer but got string.")
+    );
+}
+
+#[test]
+fn stdlib_process() {
+    let (mut interpreter, output) = common::capturing_interpreter();
+    interpreter.run("print args();").unwrap();
+    assert_eq!(output.take(), "[]\n");
+    interpreter.set_args(vec!["-v".to_string(), "notes.txt".to_string()]);
+    interpreter
+        .run("var a = args(); print a; print len(a);")
+        .unwrap();
+    assert_eq!(output.take(), "[-v, notes.txt]\n2\n");
+
+    // read, but not set, without the env capability
+    let path = std::env::var("PATH").unwrap();
+    interpreter
+        .run("var path = env(\"PATH\"); var missing = env(\"LOX_STDLIB_UNSET\");")
+        .unwrap();
+    assert_eq!(
+        interpreter.get_global("path"),
+        Some(Value::from(path.as_str()))
+    );
+    assert_eq!(interpreter.get_global("missing"), Some(Value::Nil));
+    let error = interpreter.run("env(1);").unwrap_err().to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected a variable name but got number.")
+    );
+}
+
+#[test]
+fn stdlib_assert() {
+    use lox_rs::interpreter::{RuntimeError, RuntimeErrorKind};
+
+    // failures are thrown from the line of the call, through functions
+    let mut interpreter = Interpreter::new();
+    let error = interpreter
+        .run("fun check(n) {\n  assertEq(n, 2);\n}\ncheck(2);\ncheck(3);")
+        .unwrap_err()
+        .downcast::<RuntimeError>()
+        .unwrap();
+    assert_eq!(error.kind, RuntimeErrorKind::Thrown);
+    assert_eq!(error.span.line, 2);
+    assert_eq!(
+        error.to_string(),
+        "Uncaught exception: AssertionError: Expected 3 to equal 2.\n\
+         [line 2] in check()\n\
+         [line 5] in script"
+    );
+
+    let error = interpreter
+        .run("assert(false, 1, 2);")
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected 1 to 2 arguments but got 3.")
+    );
+}
+
+#[test]
+fn stdlib_paths() {
+    let cwd = std::env::current_dir().unwrap();
+    let (mut interpreter, output) = common::capturing_interpreter();
+    interpreter
+        .run("print platform(); print pathSeparator(); print cwd();")
+        .unwrap();
+    assert_eq!(
+        output.take(),
+        format!(
+            "{}\n{}\n{}\n",
+            std::env::consts::OS,
+            std::path::MAIN_SEPARATOR,
+            cwd.display()
+        )
+    );
+
+    #[cfg(unix)]
+    {
+        interpreter
+            .run(
+                r#"
+                print joinPath("a", "b", "c.txt");
+                print joinPath("a/", "/etc", "hosts");
+                print dirname("a/b/c.txt");
+                print dirname("c.txt") == "";
+                print dirname("/");
+                print basename("a/b/c.txt");
+                print basename("a/b/");
+                print basename("/") == "";
+                print absolutePath("/tmp/x");
+                print absolutePath("x") == joinPath(cwd(), "x");
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            output.take(),
+            "a/b/c.txt\n/etc/hosts\na/b\ntrue\n/\nc.txt\nb\ntrue\n/tmp/x\ntrue\n"
+        );
+    }
+
+    let error = interpreter
+        .run("joinPath(\"a\", 1);")
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected a path but got number.")
+    );
+}
+
+#[test]
+fn stdlib_timers() {
+    use std::time::{Duration, Instant};
+
+    let (mut interpreter, output) = common::capturing_interpreter();
+    let start = Instant::now();
+    interpreter.run("sleep(20);").unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    // timeouts run once the program is done, soonest first
+    interpreter
+        .run(
+            r#"
+            fun later() { print "later"; }
+            fun sooner() { print "sooner"; }
+            fun never() { print "never"; }
+            setTimeout(later, 20);
+            setTimeout(sooner, 0);
+            var id = setTimeout(never, 10);
+            print clearTimeout(id);
+            print clearTimeout(id);
+            print "done";
+            "#,
+        )
+        .unwrap();
+    assert_eq!(output.take(), "true\nfalse\ndone\nsooner\nlater\n");
+
+    // a callback failing fails the run where the timeout was set
+    let error = interpreter
+        .run("fun broken() { return nil + 1; }\nsetTimeout(broken, 0);")
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error,
+        "Operands must be two numbers or two strings.\n[line 1] in broken()\n[line 2] in script"
+    );
+    let error = interpreter.run("sleep(-1);").unwrap_err().to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected a number of milliseconds but got number.")
+    );
+    let error = interpreter
+        .run("clearTimeout(\"1\");")
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error.lines().next(),
+        Some("Expected a timeout id but got string.")
+    );
+}
+
+#[test]
+fn stdlib_numbers() {
+    let (mut interpreter, output) = common::capturing_interpreter();
+    interpreter
+        .run(
+            r#"
+            print parseNumber("42") + 1;
+            print type(parseNumber("42"));
+            print parseNumber(" -2.50 ");
+            print parseNumber("1.0E7") == 10000000;
+            print parseNumber("2e-3");
+            print parseNumber("9223372036854775808");
+            print parseNumber("Infinity");
+            print parseNumber("NaN");
+            print parseNumber("");
+            print parseNumber("12abc");
+            print parseNumber(".5");
+            print parseNumber("1.");
+            print parseNumber("inf");
+            print parseNumber("ff", 16);
+            print parseNumber("-101", 2);
+            print parseNumber("12", 2);
+            print toFixed(3.14159, 2);
+            print toFixed(2, 3);
+            print toFixed(-0.5, 0);
+            print toHex(255);
+            print toHex(-4096);
+            print numberToString(10, 2);
+            print numberToString(-35, 36);
+            print numberToString(2.5, 10);
+            "#,
+        )
+        .unwrap();
+    assert_eq!(
+        output.take(),
+        "43\nnumber\n-2.5\ntrue\n0.002\n9.223372036854776E18\nInfinity\nNaN\nnil\nnil\n\
+         nil\nnil\nnil\n255\n-5\nnil\n3.14\n2.000\n-0\nff\n-1000\n1010\n-z\n2.5\n"
+    );
+
+    // what numberToString gives, parseNumber reads back
+    interpreter
+        .run("var n = -9223372036854775807 - 1; print parseNumber(numberToString(n, 7), 7) == n;")
+        .unwrap();
+    assert_eq!(output.take(), "true\n");
+
+    let error = |interpreter: &mut Interpreter, source| {
+        let error = interpreter.run(source).unwrap_err().to_string();
+        error.lines().next().unwrap_or_default().to_string()
+    };
+    assert_eq!(
+        error(&mut interpreter, "toHex(1.5);"),
+        "Expected an integer but got 1.5."
+    );
+    assert_eq!(
+        error(&mut interpreter, "numberToString(1, 37);"),
+        "Radix must be between 2 and 36 but got 37."
+    );
+    assert_eq!(
+        error(&mut interpreter, "toFixed(1, 101);"),
+        "Digits must be between 0 and 100 but got 101."
+    );
+    assert_eq!(
+        error(&mut interpreter, "parseNumber(1);"),
+        "Expected a string but got number."
+    );
+}
@@ -0,0 +1,114 @@
+//! Runs every script under `tests/lox` on both backends, comparing what it
+//! prints with its `// expect: <line>` comments. A
+//! `// expect runtime error: <message>` comment expects the script to fail
+//! with that message. Scripts under a `modules` directory are only there to
+//! be imported.
+//!
+//! The VM skips the scripts using what it can't compile yet, so they stay
+//! a list of what it's missing; everything else has to behave the same on
+//! both.
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lox_rs::backend::{Backend, Engine};
+
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            if !path.ends_with("modules") {
+                scripts(&path, found);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            found.push(path);
+        }
+    }
+}
+
+// `Ok(false)` when the backend can't run the script
+fn check(path: &Path, backend: Backend) -> Result<bool, String> {
+    let source = fs::read_to_string(path).unwrap();
+
+    let mut expected_output = String::new();
+    let mut expected_error = None;
+    for line in source.lines() {
+        if let Some((_, expected)) = line.split_once("// expect: ") {
+            expected_output.push_str(expected);
+            expected_output.push('\n');
+        } else if let Some((_, expected)) = line.split_once("// expect runtime error: ") {
+            expected_error = Some(expected.to_string());
+        }
+    }
+
+    let output = common::SharedOutput::default();
+    let mut engine = Engine::new(backend);
+    engine.set_output(output.clone());
+    let result = engine.run_file(path);
+    let output = output.take();
+    if let Err(error) = &result {
+        if error.to_string().contains("to bytecode yet") {
+            return Ok(false);
+        }
+    }
+    if output != expected_output {
+        return Err(format!(
+            "expected output:\n{}\ngot:\n{}",
+            expected_output, output
+        ));
+    }
+
+    match (result, expected_error) {
+        (Ok(()), None) => Ok(true),
+        (Err(error), Some(expected)) => {
+            let message = error.to_string();
+            let first_line = message.lines().next().unwrap_or_default();
+            if first_line == expected {
+                Ok(true)
+            } else {
+                Err(format!("expected error '{}', got '{}'", expected, message))
+            }
+        }
+        (Ok(()), Some(expected)) => Err(format!("expected error '{}'", expected)),
+        (Err(error), None) => Err(format!("unexpected error: {}", error)),
+    }
+}
+
+fn run_suite(backend: Backend) -> usize {
+    let mut paths = Vec::new();
+    scripts(Path::new("tests/lox"), &mut paths);
+    paths.sort();
+
+    let mut ran = 0;
+    let mut failures = Vec::new();
+    for path in &paths {
+        match check(path, backend) {
+            Ok(true) => ran += 1,
+            Ok(false) => {}
+            Err(error) => failures.push(format!("{} on {}: {}", path.display(), backend, error)),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+    ran
+}
+
+#[test]
+fn suite() {
+    run_suite(Backend::Ast);
+}
+
+#[test]
+fn suite_on_the_vm() {
+    let ran = run_suite(Backend::Vm);
+    // skipping scripts is no way to pass
+    assert!(ran >= 12, "only {} scripts ran on the vm", ran);
+}
+
+#[cfg(feature = "register-vm")]
+#[test]
+fn suite_on_registers() {
+    let ran = run_suite(Backend::Register);
+    assert!(ran >= 5, "only {} scripts ran on the register machine", ran);
+}
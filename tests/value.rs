@@ -0,0 +1,58 @@
+use lox_rs::value::Value;
+
+#[test]
+fn value_truthiness() {
+    assert!(!Value::Nil.is_truthy());
+    assert!(!Value::Bool(false).is_truthy());
+    assert!(Value::Bool(true).is_truthy());
+    assert!(Value::Number(0.0).is_truthy());
+    assert!(Value::from("").is_truthy());
+}
+
+#[test]
+fn value_equality() {
+    assert_eq!(Value::Nil, Value::Nil);
+    assert_eq!(Value::from("a"), Value::from("a".to_string()));
+    assert_eq!(Value::Number(1.0), Value::Number(1.0));
+    assert_eq!(Value::Integer(1), Value::Number(1.0));
+    assert_ne!(Value::Integer(i64::MAX), Value::Number(i64::MAX as f64));
+
+    // no implicit coercion between types
+    assert_ne!(Value::Nil, Value::Bool(false));
+    assert_ne!(Value::Number(1.0), Value::from("1"));
+    assert_ne!(Value::Number(0.0), Value::Bool(false));
+    assert_ne!(Value::Number(f64::NAN), Value::Number(f64::NAN));
+}
+
+#[test]
+fn value_display() {
+    assert_eq!(Value::Nil.to_string(), "nil");
+    assert_eq!(Value::Bool(true).to_string(), "true");
+    assert_eq!(Value::Number(5.0).to_string(), "5");
+    assert_eq!(Value::Number(2.5).to_string(), "2.5");
+    assert_eq!(Value::Integer(10_000_000).to_string(), "10000000");
+    assert_eq!(Value::Number(-0.0).to_string(), "-0");
+    assert_eq!(Value::Number(f64::NAN).to_string(), "NaN");
+    assert_eq!(Value::Number(f64::NEG_INFINITY).to_string(), "-Infinity");
+    assert_eq!(Value::from("text").to_string(), "text");
+}
+
+#[test]
+fn value_number_format() {
+    use lox_rs::value::format_number;
+
+    let cases = [
+        (0.0, "0"),
+        (123.0, "123"),
+        (0.001, "0.001"),
+        (9999999.0, "9999999"),
+        (1e7, "1.0E7"),
+        (123456789.0, "1.23456789E8"),
+        (-1.5e21, "-1.5E21"),
+        (0.0001, "1.0E-4"),
+        (1.5e-10, "1.5E-10"),
+    ];
+    for (number, formatted) in cases.iter() {
+        assert_eq!(format_number(*number), *formatted);
+    }
+}
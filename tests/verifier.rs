@@ -0,0 +1,302 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use lox_rs::chunk::{self, Chunk, Constant, Function, OpCode};
+use lox_rs::compiler::{compile_with, Options};
+use lox_rs::verifier::verify;
+use lox_rs::vm::Vm;
+
+use OpCode::*;
+
+// a chunk of these bytes, all on line 1
+fn chunk(code: &[Result<OpCode, u8>], constants: Vec<Constant>) -> Chunk {
+    let mut chunk = Chunk::new();
+    for byte in code {
+        match byte {
+            Ok(op) => chunk.write(*op, 1),
+            Err(byte) => chunk.write(*byte, 1),
+        }
+    }
+    for constant in constants {
+        chunk.add_constant(constant);
+    }
+    chunk
+}
+
+fn name(name: &str) -> Constant {
+    Constant::String(name.to_string())
+}
+
+fn function(arity: usize, upvalues: usize, chunk: Chunk) -> Constant {
+    Constant::Function(Rc::new(Function {
+        name: "f".to_string(),
+        arity,
+        upvalues,
+        chunk,
+    }))
+}
+
+fn rejects(chunk: &Chunk, problem: &str) {
+    let error = verify(chunk).unwrap_err().to_string();
+    assert!(error.contains(problem), "{:?} for {:?}", error, chunk.code);
+}
+
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            scripts(&path, found);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            found.push(path);
+        }
+    }
+}
+
+#[test]
+fn accepts_what_the_compiler_makes() {
+    let mut found = Vec::new();
+    scripts(Path::new("tests/lox"), &mut found);
+    scripts(Path::new("benches"), &mut found);
+    let mut verified = 0;
+    for path in found {
+        let source = fs::read_to_string(&path).unwrap();
+        for superinstructions in [true, false] {
+            let options = Options {
+                superinstructions,
+                ..Options::default()
+            };
+            if let Ok(chunk) = compile_with(&source, options) {
+                verify(&chunk).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+                verified += 1;
+            }
+        }
+    }
+    assert!(verified > 20, "only {} verified", verified);
+}
+
+#[test]
+fn rejects_broken_code() {
+    let ret = [Ok(Nil), Ok(Return)];
+    let with =
+        |code: &[Result<OpCode, u8>], constants| chunk(&[code, &ret[..]].concat(), constants);
+
+    rejects(&chunk(&[Err(200)], vec![]), "at 0000: unknown opcode 200");
+    rejects(&chunk(&[Ok(Constant)], vec![]), "its operands are cut off");
+    rejects(
+        &with(&[Ok(Constant), Err(0), Ok(Pop)], vec![]),
+        "a constant the chunk doesn't have",
+    );
+    rejects(
+        &chunk(&[Ok(Nil), Ok(Pop)], vec![]),
+        "runs past the end of its code",
+    );
+    rejects(&chunk(&[], vec![]), "runs past the end of its code");
+    rejects(
+        &chunk(&[Ok(Return)], vec![]),
+        "at 0000: it pops more than the stack holds",
+    );
+    rejects(&with(&[Ok(Add)], vec![]), "pops more than the stack holds");
+    rejects(
+        &with(&[Ok(GetLocal), Err(0), Ok(Pop)], vec![]),
+        "a local that isn't on the stack",
+    );
+    rejects(
+        &with(&[Ok(Nil), Ok(SetLocalPop), Err(0)], vec![]),
+        "a local that isn't on the stack",
+    );
+    rejects(
+        &with(&[Ok(GetUpvalue), Err(0), Ok(Pop)], vec![]),
+        "an upvalue the function doesn't have",
+    );
+    rejects(
+        &with(
+            &[Ok(GetGlobal), Err(0), Ok(Pop)],
+            vec![Constant::Integer(1)],
+        ),
+        "its name isn't a string",
+    );
+    rejects(
+        &with(
+            &[Ok(Nil), Ok(GetProperty), Err(0), Err(0), Err(0), Ok(Pop)],
+            vec![name("x")],
+        ),
+        "an inline cache the chunk doesn't have",
+    );
+    rejects(
+        &with(
+            &[Ok(Constant), Err(0), Ok(Pop)],
+            vec![function(0, 0, chunk(&ret, vec![]))],
+        ),
+        "loads a function as a value",
+    );
+    rejects(
+        &with(&[Ok(Closure), Err(0), Ok(Pop)], vec![name("f")]),
+        "closures are made of functions",
+    );
+
+    // jumps land on instructions inside the code
+    rejects(
+        &with(
+            &[Ok(Jump), Err(0), Err(1), Ok(Constant), Err(0), Ok(Pop)],
+            vec![Constant::Integer(1)],
+        ),
+        "jumps outside of the code or into an instruction",
+    );
+    rejects(
+        &chunk(&[Ok(Jump), Err(0), Err(9), Ok(Nil), Ok(Return)], vec![]),
+        "at 0000: it jumps outside",
+    );
+    rejects(
+        &chunk(&[Ok(Loop), Err(0), Err(4)], vec![]),
+        "jumps outside of the code",
+    );
+    // one path pushes a value the other doesn't
+    rejects(
+        &with(
+            &[Ok(True), Ok(JumpIfFalse), Err(0), Err(1), Ok(Nil), Ok(Pop)],
+            vec![],
+        ),
+        "the stack isn't as high on every path",
+    );
+
+    // functions are checked with their own arity and upvalues
+    let body = chunk(&[Ok(GetLocal), Err(2), Ok(Return)], vec![]);
+    rejects(
+        &with(
+            &[Ok(Closure), Err(0), Ok(Pop)],
+            vec![function(1, 0, body.clone())],
+        ),
+        "Invalid bytecode in f at 0000",
+    );
+    verify(&with(
+        &[Ok(Closure), Err(0), Ok(Pop)],
+        vec![function(2, 0, body)],
+    ))
+    .unwrap();
+    // the upvalues a closure captures follow it
+    let body = chunk(&[Ok(GetUpvalue), Err(0), Ok(Return)], vec![]);
+    rejects(
+        &chunk(&[Ok(Closure), Err(0)], vec![function(0, 1, body.clone())]),
+        "its operands are cut off",
+    );
+    rejects(
+        &with(
+            &[
+                Ok(Nil),
+                Ok(Closure),
+                Err(0),
+                Err(1),
+                Err(1),
+                Ok(Pop),
+                Ok(Pop),
+            ],
+            vec![function(0, 1, body.clone())],
+        ),
+        "a local that isn't on the stack",
+    );
+    rejects(
+        &with(
+            &[Ok(Closure), Err(0), Err(0), Err(0), Ok(Pop)],
+            vec![function(0, 1, body.clone())],
+        ),
+        "captures an upvalue the function doesn't have",
+    );
+    rejects(
+        &with(
+            &[
+                Ok(Nil),
+                Ok(Closure),
+                Err(0),
+                Err(1),
+                Err(0),
+                Ok(Pop),
+                Ok(Pop),
+            ],
+            vec![function(0, 1, body.clone())],
+        ),
+        "pops a local a closure captured",
+    );
+    verify(&with(
+        &[
+            Ok(Nil),
+            Ok(Closure),
+            Err(0),
+            Err(1),
+            Err(0),
+            Ok(Pop),
+            Ok(CloseUpvalue),
+        ],
+        vec![function(0, 1, body)],
+    ))
+    .unwrap();
+}
+
+#[test]
+fn rejects_captured_temporaries() {
+    let source = "class A { init(x) { this.x = x; } get() { return this.x; } } \
+                  class B < A { get() { return super.get() * 2; } } print B(4).get();";
+    let chunk = compile_with(source, Options::default()).unwrap();
+    let mut bytes = chunk::serialize(&chunk);
+    let code = bytes
+        .windows(chunk.code.len())
+        .position(|window| window == &chunk.code[..])
+        .unwrap();
+    // B.get captures the class below `super` instead, which is popped
+    // while the closure still points to it
+    assert_eq!(bytes[39], 0);
+    bytes[39] = 1;
+    let error = chunk::deserialize(&bytes).unwrap_err().to_string();
+    assert!(
+        error.contains("pops a local a closure captured"),
+        "{}",
+        error
+    );
+
+    // unverified, the VM fails instead of reading past the stack
+    let mut broken = chunk;
+    broken.code[39 - code] = 1;
+    Vm::new().execute(&broken).unwrap_err();
+}
+
+#[test]
+fn fails_safely_on_broken_files() {
+    let source = fs::read_to_string("benches/zoo.lox").unwrap();
+    let bytes = chunk::serialize(&compile_with(&source, Options::default()).unwrap());
+    // the same bytes changed in the same places every time
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut random = move |below: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as usize % below
+    };
+    let mut rejected = 0;
+    for _ in 0..2000 {
+        let mut broken = bytes.clone();
+        for _ in 0..1 + random(3) {
+            let at = random(broken.len());
+            broken[at] = random(256) as u8;
+        }
+        if chunk::deserialize(&broken).is_err() {
+            rejected += 1;
+        }
+    }
+    assert!(rejected > 100, "only {} rejected", rejected);
+}
+
+#[test]
+fn checks_what_only_running_tells() {
+    // verified, but with values where classes and closures belong
+    let run = |code: &[Result<OpCode, u8>]| {
+        let code = [code, &[Ok(Nil), Ok(Return)][..]].concat();
+        let chunk = chunk(&code, vec![name("A")]);
+        verify(&chunk).unwrap();
+        Vm::new().execute(&chunk).unwrap_err().to_string()
+    };
+    assert!(run(&[Ok(Nil), Ok(Nil), Ok(Method), Err(0), Ok(Pop)])
+        .starts_with("Methods must be closures."));
+    assert!(
+        run(&[Ok(Class), Err(0), Ok(Nil), Ok(Inherit), Ok(Pop)]).starts_with("Expected a class.")
+    );
+}
@@ -0,0 +1,599 @@
+mod common;
+
+use lox_rs::chunk;
+use lox_rs::compiler::compile;
+use lox_rs::value::Value;
+use lox_rs::vm::{GcConfig, Vm};
+
+use common::SharedOutput;
+
+fn run(source: &str) -> Result<String, String> {
+    run_on(Vm::new(), source)
+}
+
+fn run_on(mut vm: Vm, source: &str) -> Result<String, String> {
+    let output = SharedOutput::default();
+    vm.set_output(output.clone());
+    match vm.run(source) {
+        Ok(()) => Ok(output.take()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+// the VM and the interpreter agree on a program
+fn check(source: &str) {
+    assert_eq!(run(source), common::run(source), "running {:?}", source);
+}
+
+#[test]
+fn runs_arithmetic_like_the_interpreter() {
+    check("print 1 + 2 * 3;");
+    check("print 7 / 2; print 7 // 2; print -7 % 3; print 1.5 + 1;");
+    check("print 6 & 3; print 6 | 3; print 6 ^ 3; print 1 << 10; print ~5;");
+    check("print 1 < 2; print 2 <= 1; print 1 == 1.0; print nil == false;");
+    check("print !nil; print -(3); print 0.1 + 0.2;");
+}
+
+#[test]
+fn runs_arithmetic_fast_and_slow() {
+    check("print 2.5 * 2; print 7 - 10; print 0.5 + 0.25 > 0.7; print 1 <= 1;");
+    check("print 1 + 2.5; print 3 / 2; print 2.0 >= 2; print -0.0 < 0;");
+    check("var nan = 0.0 / 0; print nan < 1; print nan >= nan; print nan + 1;");
+    check("print 9223372036854775807 + 1; print -9223372036854775807 - 2;");
+    check("print 4611686018427387904 * 4; print 1 < \"2\";");
+}
+
+#[test]
+fn runs_integers_of_any_size() {
+    // past the 48 bits of a NaN-boxed integer
+    check("print 140737488355327 + 1;");
+    check("print -140737488355328 - 1;");
+    check("print 9223372036854775807; print -9223372036854775807 - 1;");
+    check("print 4611686018427387904 * 2;");
+    check("var big = 1 << 62; print big == 4611686018427387904; print ~big;");
+}
+
+#[test]
+fn runs_strings_and_variables() {
+    check("var greeting = \"hello\"; print greeting + \" world\";");
+    check("var a = 1; { var b = a + 1; a = b * 10; } print a;");
+    check("print \"a\" + \"b\" == \"ab\";");
+    check(
+        "var total = 0;
+        for (var i = 0; i < 10; i = i + 1) {
+          if (i % 2 == 0) continue;
+          total = total + i;
+        }
+        print total;",
+    );
+    check("var i = 0; while (true) { i = i + 1; if (i > 5) break; } print i;");
+    check("print nil or \"default\"; print 1 and 2;");
+}
+
+#[test]
+fn fails_like_the_interpreter() {
+    check("print -\"text\";");
+    check("print \"a\" + 1;");
+    check("print true + nil;");
+    check("print 1 / 0;");
+    check("print nope;");
+    check("nope = 1;");
+}
+
+#[test]
+fn exposes_globals() {
+    let mut vm = Vm::new();
+    vm.run("var answer = 6 * 7; var name = \"vm\"; var big = 1 << 60;")
+        .unwrap();
+    assert_eq!(vm.get_global("answer"), Some(Value::Integer(42)));
+    assert_eq!(vm.get_global("name"), Some(Value::from("vm")));
+    assert_eq!(vm.get_global("big"), Some(Value::Integer(1 << 60)));
+    assert_eq!(vm.get_global("missing"), None);
+}
+
+#[test]
+fn binds_globals_late() {
+    // functions read globals defined after them, and fail on those that
+    // aren't yet
+    check("fun f() { return later; } var later = 1; print f();");
+    check("fun f() { return later; } print f(); var later = 1;");
+    check("fun f() { later = 2; } f(); var later = 1;");
+    check("var a = 1; var a = a + 1; print a; a = a * 10; print a;");
+
+    // across runs and chunks loaded from files
+    let mut vm = Vm::new();
+    vm.set_output(SharedOutput::default());
+    vm.run("fun get() { return value; }").unwrap();
+    assert!(vm.run("get();").is_err());
+    vm.run("var value = \"first\"; var got = get();").unwrap();
+    assert_eq!(vm.get_global("got"), Some(Value::from("first")));
+    let chunk = compile("value = \"second\"; var other = get();").unwrap();
+    let chunk = chunk::deserialize(&chunk::serialize(&chunk)).unwrap();
+    vm.execute(&chunk).unwrap();
+    assert_eq!(vm.get_global("other"), Some(Value::from("second")));
+}
+
+#[test]
+fn collects_unreachable_objects() {
+    let mut vm = Vm::new();
+    vm.run(
+        "var kept = \"\";
+        var long = \"\";
+        for (var i = 0; i < 100; i = i + 1) {
+          long = long + \"a\";
+          if (i < 3) kept = kept + \"ab\";
+        }",
+    )
+    .unwrap();
+    assert!(vm.collect_garbage() >= 100);
+    assert_eq!(vm.collect_garbage(), 0);
+    // the values of the globals, their names, "init", the natives and "pi"
+    assert_eq!(vm.objects(), 60);
+    assert_eq!(vm.get_global("kept"), Some(Value::from("ababab")));
+}
+
+#[test]
+fn interns_strings() {
+    let mut vm = Vm::new();
+    vm.run("var x = \"hello\"; var y = \"hel\" + \"lo\"; var hello = x;")
+        .unwrap();
+    vm.collect_garbage();
+    // "x", "y" and "hello", the value of all three and the name of one,
+    // "init", the natives and "pi"
+    assert_eq!(vm.objects(), 59);
+    check("print \"hel\" + \"lo\" == \"hello\"; print \"a\" != \"a\" + \"\";");
+}
+
+#[test]
+fn runs_under_gc_stress() {
+    let source = "var words = \"\";
+        for (var i = 0; i < 20; i = i + 1) {
+          var word = \"w\" + \"o\";
+          words = words + word;
+          var big = (1 << 60) + i;
+          if (i == 19) print big;
+          // the string of the last iteration was freed, and left the table
+          if (\"x\" + \"y\" != \"x\" + \"y\") print \"not interned\";
+        }
+        print words;";
+    let mut vm = Vm::new();
+    vm.set_gc_stress(true);
+    assert_eq!(run_on(vm, source), common::run(source));
+}
+
+#[test]
+fn logs_collections() {
+    let log = SharedOutput::default();
+    let mut vm = Vm::new();
+    vm.set_gc_stress(true);
+    vm.set_gc_log(log.clone());
+    vm.run("var a = \"x\" + \"y\"; a = a + \"z\";").unwrap();
+    vm.collect_garbage();
+    let log = log.take();
+    assert!(log.contains("-- gc begin"), "{}", log);
+    assert!(log.contains("allocate"), "{}", log);
+    assert!(log.contains(" mark \"xy\""), "{}", log);
+    assert!(log.contains("free type string"), "{}", log);
+    assert!(log.contains("-- gc end"), "{}", log);
+}
+
+#[test]
+fn tunes_the_collector() {
+    let source = "var s = \"\"; for (var i = 0; i < 500; i = i + 1) s = s + \"x\";";
+    let mut vm = Vm::new();
+    vm.run(source).unwrap();
+    assert_eq!(vm.gc_stats().collections, 0);
+
+    let mut vm = Vm::new();
+    vm.set_gc_config(GcConfig {
+        initial_threshold: 4096,
+        ..GcConfig::default()
+    });
+    vm.run(source).unwrap();
+    let eager = vm.gc_stats();
+    assert!(eager.collections > 0);
+    assert!(eager.objects_freed >= eager.collections);
+    assert!(eager.bytes_freed > 0);
+    assert!(eager.max_pause <= eager.total_pause);
+
+    // growing more between collections makes for fewer of them
+    let mut vm = Vm::new();
+    vm.set_gc_config(GcConfig {
+        initial_threshold: 4096,
+        growth_factor: 8.0,
+        ..GcConfig::default()
+    });
+    vm.run(source).unwrap();
+    let lazy = vm.gc_stats();
+    assert!(lazy.collections < eager.collections);
+    // the same stats, to programs
+    let output = run_on(
+        vm,
+        "var stats = gcStats(); print stats; print stats.collections; print stats.maxPause >= 0;",
+    );
+    assert_eq!(
+        output.unwrap(),
+        format!("GcStats instance\n{}\ntrue\n", lazy.collections)
+    );
+}
+
+#[test]
+fn runs_out_of_memory() {
+    let config = GcConfig {
+        initial_threshold: 1024,
+        max_heap: Some(64 * 1024),
+        ..GcConfig::default()
+    };
+    let mut vm = Vm::new();
+    vm.set_gc_config(config);
+    // garbage is collected before running out
+    vm.run("var s = \"\"; for (var i = 0; i < 2000; i = i + 1) s = s + \"x\";")
+        .unwrap();
+    let mut vm = Vm::new();
+    vm.set_gc_config(config);
+    assert_eq!(
+        run_on(
+            vm,
+            "class Node {} var list; while (true) { var node = Node(); node.next = list; list = node; }"
+        )
+        .unwrap_err(),
+        "Out of memory.\n[line 1] in script"
+    );
+}
+
+#[test]
+fn calls_functions() {
+    check("fun add(a, b) { return a + b; } print add(1, 2);");
+    check("fun nothing() {} print nothing(); print nothing;");
+    check(
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        print fib(15);",
+    );
+    check("{ fun local(n) { if (n > 0) return local(n - 1); return \"done\"; } print local(3); }");
+    check("fun f(a) {} f(1, 2);");
+    check("var x = 1; x();");
+    check("fun inner() { return 1 + nil; }\nfun outer() { inner(); }\nouter();");
+    // the interpreter needs a bigger native stack to get there
+    let error = run("fun forever() { forever(); } forever();").unwrap_err();
+    assert!(error.starts_with("Stack overflow."), "{}", error);
+    // every frame but the script's can be used, and reused after failing
+    let mut vm = Vm::new();
+    let deep =
+        "fun down(n) { if (n == 0) return 0; return down(n - 1) + 1; } var depth = down(998);";
+    vm.run(deep).unwrap();
+    assert!(vm.run("down(999);").is_err());
+    vm.run(deep).unwrap();
+    assert_eq!(vm.get_global("depth"), Some(Value::Integer(998)));
+}
+
+#[test]
+fn closes_over_locals() {
+    // the local outlives the call that declared it
+    check(
+        "fun counter() {
+          var count = 0;
+          fun increment() { count = count + 1; return count; }
+          return increment;
+        }
+        var a = counter();
+        var b = counter();
+        a(); a();
+        print a(); print b();",
+    );
+    // closures of the same local share it, open or closed
+    check(
+        "var get; var set;
+        {
+          var shared = \"before\";
+          fun getter() { return shared; }
+          fun setter(value) { shared = value; }
+          get = getter; set = setter;
+          set(\"open\");
+          print shared;
+        }
+        print get();
+        set(\"closed\");
+        print get();",
+    );
+    // each iteration of a block gets a local of its own
+    check(
+        "var first; var second;
+        for (var i = 0; i < 2; i = i + 1) {
+          var j = i;
+          fun capture() { return j; }
+          if (i == 0) first = capture; else second = capture;
+        }
+        print first(); print second();",
+    );
+    // through functions that don't use the local themselves
+    check(
+        "fun outer() {
+          var x = \"outer\";
+          fun middle() {
+            fun inner() { return x; }
+            return inner;
+          }
+          return middle;
+        }
+        print outer()()();",
+    );
+    // closed when leaving a loop early
+    check(
+        "var saved;
+        while (true) {
+          var local = \"kept\";
+          fun keep() { return local; }
+          saved = keep;
+          break;
+        }
+        print saved();",
+    );
+}
+
+#[test]
+fn closures_survive_gc_stress() {
+    let source = "fun make(prefix) {
+          var words = prefix;
+          fun add(word) { words = words + word; return words; }
+          return add;
+        }
+        var add = make(\"a\");
+        for (var i = 0; i < 5; i = i + 1) add(\"b\");
+        print add(\"c\");";
+    let mut vm = Vm::new();
+    vm.set_gc_stress(true);
+    assert_eq!(run_on(vm, source), common::run(source));
+}
+
+#[test]
+fn runs_classes() {
+    check(
+        "class Point {
+          init(x, y) { this.x = x; this.y = y; }
+          sum() { return this.x + this.y; }
+        }
+        var p = Point(1, 2);
+        print p.sum();
+        p.x = 10;
+        print p.sum();
+        print Point;
+        print p;",
+    );
+    // bound methods remember their instance
+    check(
+        "class Counter {
+          init() { this.count = 0; }
+          increment() { this.count = this.count + 1; return this.count; }
+        }
+        var c = Counter();
+        var increment = c.increment;
+        increment();
+        print increment();
+        print c.count;",
+    );
+    // fields shadow methods, and can hold functions
+    check(
+        "class A { m() { return \"method\"; } }
+        fun f() { return \"field\"; }
+        var a = A();
+        print a.m();
+        a.m = f;
+        print a.m();",
+    );
+    // `this` in closures, and initializers returning early
+    check(
+        "class A {
+          init(early) { this.name = \"a\"; if (early) return; this.name = \"b\"; }
+          getter() { fun get() { return this.name; } return get; }
+        }
+        print A(true).getter()();
+        print A(false).getter()();
+        var a = A(false);
+        print a.init(true) == a;",
+    );
+}
+
+#[test]
+fn runs_inheritance() {
+    check(
+        "class A {
+          init(name) { this.name = name; }
+          greet() { return \"hi \" + this.name; }
+          who() { return \"A\"; }
+        }
+        class B < A {
+          init(name) { super.init(name + \"!\"); }
+          who() { return \"B, then \" + super.who(); }
+          bound() { var who = super.who; return who(); }
+        }
+        var b = B(\"b\");
+        print b.greet();
+        print b.who();
+        print b.bound();",
+    );
+}
+
+#[test]
+fn caches_methods_per_class() {
+    // the same instruction sees instances of several classes
+    let source = "class A { name() { return \"A\"; } }
+        class B { name() { return \"B\"; } }
+        class C < A { name() { return \"C\"; } }
+        var objects = 0;
+        for (var i = 0; i < 6; i = i + 1) {
+          var o;
+          if (i % 3 == 0) o = A(); else if (i % 3 == 1) o = B(); else o = C();
+          print o.name();
+          var get = o.name;
+          print get();
+        }";
+    check(source);
+    let mut vm = Vm::new();
+    vm.set_inline_caches(false);
+    assert_eq!(run_on(vm, source), common::run(source));
+}
+
+#[test]
+fn fails_on_classes_like_the_interpreter() {
+    check("class A {} A().nope;");
+    check("class A {} A().nope();");
+    check("var x = 1; x.field;");
+    check("var x = \"s\"; x.field = 1;");
+    check("var x = 1; x.method();");
+    check("var NotAClass = 1; class B < NotAClass {}");
+    check("class A {} A(1);");
+    check("class A { init(a) {} } A();");
+}
+
+#[test]
+fn classes_survive_gc_stress() {
+    let source = "class Node {
+          init(value, next) { this.value = value; this.next = next; }
+          sum() {
+            if (this.next == nil) return this.value;
+            return this.value + this.next.sum();
+          }
+        }
+        class Named < Node {
+          init(value, next) { super.init(value, next); this.name = \"n\" + \"ode\"; }
+        }
+        var list = nil;
+        for (var i = 0; i < 10; i = i + 1) list = Named(i, list);
+        print list.sum();
+        print list.name;";
+    let mut vm = Vm::new();
+    vm.set_gc_stress(true);
+    assert_eq!(run_on(vm, source), common::run(source));
+}
+
+#[test]
+fn runs_deserialized_chunks() {
+    let source = "fun count(n) { var total = 0; for (var i = 1; i <= n; i = i + 1) total = total + i; return total; }
+        class Box { init(value) { this.value = value; } get() { return this.value; } }
+        print Box(count(10)).get();
+        print 1.5 * 2;";
+    let chunk = lox_rs::compiler::compile(source).unwrap();
+    let chunk = lox_rs::chunk::deserialize(&lox_rs::chunk::serialize(&chunk)).unwrap();
+    let output = SharedOutput::default();
+    let mut vm = Vm::new();
+    vm.set_output(output.clone());
+    vm.execute(&chunk).unwrap();
+    assert_eq!(Ok(output.take()), common::run(source));
+}
+
+#[test]
+fn runs_superinstructions() {
+    let source = "var total = 0;
+        for (var i = 0; i < 10; i = i + 1) {
+          var square = i * i;
+          total = total + square - 1;
+        }
+        print total;
+        var x;
+        print 5 + (x or 1);
+        x = 2;
+        print 5 + (x or 1);
+        fun f(a, b) { var c = a + 1; c = a - 2; return a < 3 and b; }
+        print f(1, \"yes\");
+        print f(5, \"yes\");";
+    check(source);
+
+    // the same program runs fewer instructions fused
+    let mut fused = Vm::new();
+    fused.set_output(std::io::sink());
+    fused.run(source).unwrap();
+    let mut unfused = Vm::new();
+    unfused.set_output(std::io::sink());
+    unfused.set_superinstructions(false);
+    unfused.run(source).unwrap();
+    assert!(fused.instructions() < unfused.instructions());
+    check("var s = \"a\"; print s - 1;");
+    check("print nope < 1;");
+}
+
+#[test]
+fn runs_folded_constants_like_the_interpreter() {
+    check("print 60 * 60; print 7 / 2; print 7 ~/ 0; print -7 % 0; print 1 / 0;");
+    check("print -9223372036854775807 - 1; print -(-9223372036854775807 - 1);");
+    check("print ~1.0; print !\"\"; print \"a\" + \"b\" == \"ab\"; print 1 == 1.0;");
+    check("print nil or \"default\"; print 0 and 1; print (1 < 2) != false;");
+    check("{ const n = 3; while (n < 2) print \"never\"; if (n == 3) print \"three\"; }");
+    check("var i = 0; while (true) { i = i + 1; if (i == 3) break; } print i;");
+    check("print 1 + nil;");
+    check("print 1 << 64;");
+    check("print 1.5 & 1;");
+}
+
+#[test]
+fn runs_without_dead_code_like_the_interpreter() {
+    check("fun f(x) { if (x) return 1; else return 2; print \"never\"; } print f(true); print f(nil);");
+    check("fun f(x) { { var a = x; if (a) return a; } return -1; } print f(5); print f(false);");
+    check(
+        "for (var i = 0; i < 4; i = i + 1) { var j = i * 2; if (j == 2) continue; print j; if (j > 4) break; }",
+    );
+    check("var i = 0; while (i < 3) { i = i + 1; { var k = i; continue; print k; } } print i;");
+    check("fun g() { var a = 1; fun h() { return a; } return h; print a; } print g()();");
+    check("if (false) print \"no\"; else { var x = \"yes\"; print x; }");
+}
+
+#[test]
+fn traces_execution() {
+    let output = SharedOutput::default();
+    let mut vm = Vm::new();
+    vm.set_output(std::io::sink());
+    vm.set_trace(output.clone());
+    vm.run("fun twice(n) { return n * 2; }\nprint twice(4);")
+        .unwrap();
+    let trace = output.take();
+    let lines = trace.lines().collect::<Vec<_>>();
+    // the stack before each instruction, then the instruction
+    assert_eq!(lines[0].trim(), "");
+    assert_eq!(
+        lines[1],
+        "script    0000    1 OP_CLOSURE          0 '<fn twice>'"
+    );
+    assert_eq!(lines[2], "          [ <fn twice> ]");
+    assert!(
+        trace.contains("          [ <fn twice> ][ 4 ]\ntwice     0000    1 OP_GET_LOCAL        1"),
+        "{}",
+        trace
+    );
+    assert!(
+        trace.contains("twice     0002    | OP_CONSTANT"),
+        "{}",
+        trace
+    );
+
+    // tracing stops when asked
+    vm.stop_trace();
+    vm.run("print 1;").unwrap();
+    assert_eq!(output.take(), "");
+}
+
+#[test]
+fn runs_natives() {
+    check("print clock;");
+    check("print clock() > 0;");
+    check("clock(1);");
+    check("var c = clock; print c == clock;");
+}
+
+#[test]
+fn compares_bound_methods() {
+    check(
+        "class A { m() {} }
+        var a = A();
+        print a.m == a.m;
+        print a.m == A().m;
+        var b = A();
+        b.m = a.m;
+        print b.m == a.m;",
+    );
+}
+
+#[test]
+fn assigns_locals_in_their_own_initializer() {
+    check("{ var e = (e = 1) + 1; print e; }");
+    check("{ var e = 5 + (e = 1); print e; }");
+    check("{ var e = 1; { var e = (e = 2) * 3; print e; } print e; }");
+}